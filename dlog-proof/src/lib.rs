@@ -6,6 +6,38 @@ use k256::{
 };
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "tokio")]
+mod pool;
+
+#[cfg(feature = "tokio")]
+pub use pool::VerificationPool;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+/// Domain separator prefixed to every v2 transcript.
+const TRANSCRIPT_V2_DOMAIN: &[u8] = b"dlog-proof/transcript/v2";
+
+/// Domain separator for the context digest computed by `DLogProof::context_digest`.
+const CONTEXT_DOMAIN: &[u8] = b"dlog-proof/context";
+
+/// Domain separator prefixed to every prehashed transcript.
+const TRANSCRIPT_PREHASHED_DOMAIN: &[u8] = b"dlog-proof/transcript/prehashed";
+
+/// Digest of the proof context (session and participant), computed once outside the prover.
+pub type ContextDigest = [u8; 32];
+
+/// Layout of the data hashed into the Fiat-Shamir challenge.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptVersion {
+    /// `sid`, `pid` and points concatenated without delimiters.
+    #[default]
+    V1,
+    /// Domain-separated transcript where every field is labeled and length-prefixed, so
+    /// distinct inputs can never produce the same hashed bytes.
+    V2,
+}
+
 /// Non-interactive Schnorr ZK DLOG Proof scheme with a Fiat-Shamir transformation.
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DLogProof {
@@ -30,12 +62,50 @@ impl DLogProof {
         x: Scalar,
         y: ProjectivePoint,
     ) -> Self {
-        let r = Scalar::random(rng);
-        let t = ProjectivePoint::GENERATOR * r;
-        let c = Self::hash_points(sid, pid, &[ProjectivePoint::GENERATOR, y, t]);
-        let s = r + c * x;
+        Self::prove_with_version(rng, TranscriptVersion::V1, sid, pid, x, y)
+    }
 
-        DLogProof { t, s }
+    /// Creates a Schnorr ZK DLOG proof using the given transcript `version`.
+    ///
+    /// The proof only verifies with `verify_with_version` using the same `version`.
+    pub fn prove_with_version(
+        rng: &mut impl CryptoRngCore,
+        version: TranscriptVersion,
+        sid: &str,
+        pid: u32,
+        x: Scalar,
+        y: ProjectivePoint,
+    ) -> Self {
+        Self::prove_with_nonce(Scalar::random(rng), x, y, |points| {
+            Self::hash_points(version, sid, pid, points)
+        })
+    }
+
+    /// Creates a Schnorr ZK DLOG proof bound to a prehashed `context` digest instead of a
+    /// `sid` and `pid`.
+    ///
+    /// The digest can be computed elsewhere (e.g. by an HSM or another service), either with
+    /// `context_digest` or any hash both prover and verifier agree on.
+    pub fn prove_prehashed(
+        rng: &mut impl CryptoRngCore,
+        context: &ContextDigest,
+        x: Scalar,
+        y: ProjectivePoint,
+    ) -> Self {
+        Self::prove_with_nonce(Scalar::random(rng), x, y, |points| {
+            Self::hash_prehashed(context, points)
+        })
+    }
+
+    /// Computes the context digest of `sid` and `pid` for `prove_prehashed` and
+    /// `verify_prehashed`.
+    pub fn context_digest(sid: &str, pid: u32) -> ContextDigest {
+        let mut hasher = Sha256::new();
+        append_field(&mut hasher, b"domain", CONTEXT_DOMAIN);
+        append_field(&mut hasher, b"sid", sid.as_bytes());
+        append_field(&mut hasher, b"pid", &pid.to_be_bytes());
+
+        hasher.finalize().into()
     }
 
     /// Verifies a Schorr ZK DLOG Proof using the discrete logarithm `x` of y = x*G
@@ -45,19 +115,110 @@ impl DLogProof {
     ///
     /// Returns `true` if the proof is valid, `false` otherwise.
     pub fn verify(&self, sid: &str, pid: u32, y: ProjectivePoint) -> bool {
-        let c = Self::hash_points(sid, pid, &[ProjectivePoint::GENERATOR, y, self.t]);
+        self.verify_with_version(TranscriptVersion::V1, sid, pid, y)
+    }
+
+    /// Verifies a Schnorr ZK DLOG proof created with the given transcript `version`.
+    pub fn verify_with_version(
+        &self,
+        version: TranscriptVersion,
+        sid: &str,
+        pid: u32,
+        y: ProjectivePoint,
+    ) -> bool {
+        self.verify_with_challenge(y, |points| Self::hash_points(version, sid, pid, points))
+    }
+
+    /// Verifies a Schnorr ZK DLOG proof created with `prove_prehashed` and the same `context`.
+    pub fn verify_prehashed(&self, context: &ContextDigest, y: ProjectivePoint) -> bool {
+        self.verify_with_challenge(y, |points| Self::hash_prehashed(context, points))
+    }
+
+    /// Encodes the proof with postcard into a freshly allocated buffer.
+    #[cfg(feature = "postcard")]
+    pub fn to_postcard(&self) -> Result<Vec<u8>, postcard::Error> {
+        postcard::to_allocvec(self)
+    }
+
+    /// Encodes the proof with postcard into `buf`, returning the used part of it.
+    ///
+    /// Doesn't allocate, which suits embedded transports with static buffers.
+    #[cfg(feature = "postcard")]
+    pub fn to_postcard_slice<'a>(
+        &self,
+        buf: &'a mut [u8],
+    ) -> Result<&'a mut [u8], postcard::Error> {
+        postcard::to_slice(self, buf)
+    }
+
+    /// Decodes a proof previously encoded with `to_postcard`.
+    #[cfg(feature = "postcard")]
+    pub fn from_postcard(bytes: &[u8]) -> Result<Self, postcard::Error> {
+        postcard::from_bytes(bytes)
+    }
+
+    fn prove_with_nonce(
+        r: Scalar,
+        x: Scalar,
+        y: ProjectivePoint,
+        challenge: impl FnOnce(&[ProjectivePoint]) -> Scalar,
+    ) -> Self {
+        let t = ProjectivePoint::GENERATOR * r;
+        let c = challenge(&[ProjectivePoint::GENERATOR, y, t]);
+        let s = r + c * x;
+
+        DLogProof { t, s }
+    }
+
+    fn verify_with_challenge(
+        &self,
+        y: ProjectivePoint,
+        challenge: impl FnOnce(&[ProjectivePoint]) -> Scalar,
+    ) -> bool {
+        let c = challenge(&[ProjectivePoint::GENERATOR, y, self.t]);
         let lhs = ProjectivePoint::GENERATOR * self.s;
         let rhs = self.t + (y * c);
 
         lhs == rhs
     }
 
-    fn hash_points(sid: &str, pid: u32, points: &[ProjectivePoint]) -> Scalar {
+    fn hash_points(
+        version: TranscriptVersion,
+        sid: &str,
+        pid: u32,
+        points: &[ProjectivePoint],
+    ) -> Scalar {
         let mut hasher = Sha256::new();
-        hasher.update(sid);
-        hasher.update(pid.to_be_bytes());
+        match version {
+            TranscriptVersion::V1 => {
+                hasher.update(sid);
+                hasher.update(pid.to_be_bytes());
+                for point in points {
+                    hasher.update(point.to_bytes());
+                }
+            }
+            TranscriptVersion::V2 => {
+                append_field(&mut hasher, b"domain", TRANSCRIPT_V2_DOMAIN);
+                append_field(&mut hasher, b"sid", sid.as_bytes());
+                append_field(&mut hasher, b"pid", &pid.to_be_bytes());
+                append_field(&mut hasher, b"points", &(points.len() as u64).to_be_bytes());
+                for point in points {
+                    append_field(&mut hasher, b"point", &point.to_bytes());
+                }
+            }
+        }
+        let digest = hasher.finalize();
+
+        Scalar::from_repr(digest).expect("sha256 should be a valid scalar")
+    }
+
+    fn hash_prehashed(context: &ContextDigest, points: &[ProjectivePoint]) -> Scalar {
+        let mut hasher = Sha256::new();
+        append_field(&mut hasher, b"domain", TRANSCRIPT_PREHASHED_DOMAIN);
+        append_field(&mut hasher, b"context", context);
+        append_field(&mut hasher, b"points", &(points.len() as u64).to_be_bytes());
         for point in points {
-            hasher.update(point.to_bytes());
+            append_field(&mut hasher, b"point", &point.to_bytes());
         }
         let digest = hasher.finalize();
 
@@ -65,6 +226,14 @@ impl DLogProof {
     }
 }
 
+/// Appends `label` and `value` to the transcript, each prefixed with its length.
+fn append_field(hasher: &mut Sha256, label: &[u8], value: &[u8]) {
+    hasher.update((label.len() as u64).to_be_bytes());
+    hasher.update(label);
+    hasher.update((value.len() as u64).to_be_bytes());
+    hasher.update(value);
+}
+
 // We use SEC1 encoding format without compression for serialization/deserialization.
 mod projective_serializer {
     use elliptic_curve::{
@@ -143,6 +312,77 @@ mod tests {
         assert!(!proof.verify(sid, 2, y))
     }
 
+    #[test]
+    fn valid_proof_v2() {
+        let mut rng = rand_core::OsRng;
+        let sid = "sid";
+        let pid = 1;
+        let x = Scalar::random(&mut rng);
+        let y = ProjectivePoint::GENERATOR * x;
+
+        let proof = DLogProof::prove_with_version(&mut rng, TranscriptVersion::V2, sid, pid, x, y);
+
+        assert!(proof.verify_with_version(TranscriptVersion::V2, sid, pid, y))
+    }
+
+    #[test]
+    fn invalid_proof_with_different_transcript_versions() {
+        let mut rng = rand_core::OsRng;
+        let sid = "sid";
+        let pid = 1;
+        let x = Scalar::random(&mut rng);
+        let y = ProjectivePoint::GENERATOR * x;
+
+        let proof_v1 = DLogProof::prove(&mut rng, sid, pid, x, y);
+        let proof_v2 =
+            DLogProof::prove_with_version(&mut rng, TranscriptVersion::V2, sid, pid, x, y);
+
+        assert!(!proof_v1.verify_with_version(TranscriptVersion::V2, sid, pid, y));
+        assert!(!proof_v2.verify(sid, pid, y));
+    }
+
+    #[test]
+    fn v2_transcript_separates_ambiguous_inputs() {
+        // With v1, moving the pid and identity point bytes into the sid hashes the exact same bytes
+        let (sid_a, pid_a, points_a) = ("sid", 1, [ProjectivePoint::IDENTITY]);
+        let sid_b = format!("sid\0\0\0\u{1}{}", "\0".repeat(29));
+        let (pid_b, points_b) = (0, []);
+
+        assert_eq!(
+            DLogProof::hash_points(TranscriptVersion::V1, sid_a, pid_a, &points_a),
+            DLogProof::hash_points(TranscriptVersion::V1, &sid_b, pid_b, &points_b)
+        );
+        assert_ne!(
+            DLogProof::hash_points(TranscriptVersion::V2, sid_a, pid_a, &points_a),
+            DLogProof::hash_points(TranscriptVersion::V2, &sid_b, pid_b, &points_b)
+        );
+    }
+
+    #[test]
+    fn valid_prehashed_proof() {
+        let mut rng = rand_core::OsRng;
+        let context = DLogProof::context_digest("sid", 1);
+        let x = Scalar::random(&mut rng);
+        let y = ProjectivePoint::GENERATOR * x;
+
+        let proof = DLogProof::prove_prehashed(&mut rng, &context, x, y);
+
+        assert!(proof.verify_prehashed(&context, y))
+    }
+
+    #[test]
+    fn invalid_prehashed_proof_with_different_contexts() {
+        let mut rng = rand_core::OsRng;
+        let x = Scalar::random(&mut rng);
+        let y = ProjectivePoint::GENERATOR * x;
+
+        let proof =
+            DLogProof::prove_prehashed(&mut rng, &DLogProof::context_digest("sid", 1), x, y);
+
+        assert!(!proof.verify_prehashed(&DLogProof::context_digest("sid", 2), y));
+        assert!(!proof.verify("sid", 1, y));
+    }
+
     #[test]
     fn serialization_roundtrip() {
         let mut rng = rand_core::OsRng;
@@ -162,4 +402,51 @@ mod tests {
         assert_eq!(original_proof, decoded_proof);
         assert!(decoded_proof.verify(sid, pid, y));
     }
+
+    #[test]
+    fn bincode_roundtrip() {
+        let mut rng = rand_core::OsRng;
+        let sid = "sid";
+        let pid = 1;
+        let x = Scalar::random(&mut rng);
+        let y = ProjectivePoint::GENERATOR * x;
+
+        let original_proof = DLogProof::prove(&mut rng, sid, pid, x, y);
+
+        let bin_proof = bincode::serialize(&original_proof).expect("serialization should succeed");
+
+        let decoded_proof: DLogProof =
+            bincode::deserialize(&bin_proof).expect("deserialization should succeed");
+
+        assert_eq!(original_proof, decoded_proof);
+        assert!(decoded_proof.verify(sid, pid, y));
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn postcard_roundtrip() {
+        let mut rng = rand_core::OsRng;
+        let sid = "sid";
+        let pid = 1;
+        let x = Scalar::random(&mut rng);
+        let y = ProjectivePoint::GENERATOR * x;
+
+        let original_proof = DLogProof::prove(&mut rng, sid, pid, x, y);
+
+        let bin_proof = original_proof
+            .to_postcard()
+            .expect("serialization should succeed");
+
+        let mut buf = [0u8; 128];
+        let slice_proof = original_proof
+            .to_postcard_slice(&mut buf)
+            .expect("serialization should succeed");
+        assert_eq!(&bin_proof[..], &slice_proof[..]);
+
+        let decoded_proof =
+            DLogProof::from_postcard(&bin_proof).expect("deserialization should succeed");
+
+        assert_eq!(original_proof, decoded_proof);
+        assert!(decoded_proof.verify(sid, pid, y));
+    }
 }
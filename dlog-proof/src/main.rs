@@ -16,7 +16,7 @@ pub fn main() {
     println!("Randomly chosen x:");
     println!("{:?}", x);
 
-    println!("");
+    println!();
 
     let start_proof = Instant::now();
     let dlog_proof = DLogProof::prove(&mut rng, sid, pid, x, y);
@@ -25,14 +25,14 @@ pub fn main() {
         start_proof.elapsed().as_millis()
     );
 
-    println!("");
+    println!();
 
     println!(
         "Proof: \n{}",
         serde_json::to_string_pretty(&dlog_proof).expect("Serialization failed")
     );
 
-    println!("");
+    println!();
 
     let start_verify = Instant::now();
     let result = dlog_proof.verify(sid, pid, y);
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use k256::ProjectivePoint;
+use tokio::sync::{mpsc, oneshot, Semaphore};
+
+use crate::DLogProof;
+
+/// A verification request waiting to be processed by the pool.
+struct VerificationJob {
+    proof: DLogProof,
+    sid: String,
+    pid: u32,
+    y: ProjectivePoint,
+    respond_to: oneshot::Sender<bool>,
+}
+
+/// Verifies `DLogProof`s on tokio's blocking thread pool.
+///
+/// Proofs are submitted through a channel and dispatched to `spawn_blocking`, so async
+/// handlers don't stall the runtime on elliptic curve arithmetic.
+#[derive(Clone)]
+pub struct VerificationPool {
+    sender: mpsc::Sender<VerificationJob>,
+}
+
+impl VerificationPool {
+    /// Creates a pool buffering up to `capacity` pending proofs and running at most
+    /// `workers` verifications concurrently.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(capacity: usize, workers: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+
+        tokio::spawn(Self::dispatch(receiver, Arc::new(Semaphore::new(workers))));
+
+        VerificationPool { sender }
+    }
+
+    /// Submits a proof for verification.
+    ///
+    /// Returns a receiver resolving to the verification result. If the pool has shut down,
+    /// the receiver resolves to an error.
+    pub async fn submit(
+        &self,
+        proof: DLogProof,
+        sid: impl Into<String>,
+        pid: u32,
+        y: ProjectivePoint,
+    ) -> oneshot::Receiver<bool> {
+        let (respond_to, receiver) = oneshot::channel();
+        let job = VerificationJob {
+            proof,
+            sid: sid.into(),
+            pid,
+            y,
+            respond_to,
+        };
+
+        // On failure the job is dropped along with its sender, which the receiver reports
+        let _ = self.sender.send(job).await;
+
+        receiver
+    }
+
+    async fn dispatch(mut receiver: mpsc::Receiver<VerificationJob>, workers: Arc<Semaphore>) {
+        while let Some(job) = receiver.recv().await {
+            let Ok(permit) = workers.clone().acquire_owned().await else {
+                return;
+            };
+
+            tokio::task::spawn_blocking(move || {
+                let result = job.proof.verify(&job.sid, job.pid, job.y);
+                // The submitter may have stopped waiting for the result
+                let _ = job.respond_to.send(result);
+                drop(permit);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use elliptic_curve::Field;
+    use k256::{elliptic_curve::rand_core, Scalar};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn verifies_submitted_proofs() {
+        let mut rng = rand_core::OsRng;
+        let pool = VerificationPool::new(8, 2);
+        let x = Scalar::random(&mut rng);
+        let y = ProjectivePoint::GENERATOR * x;
+
+        let valid = DLogProof::prove(&mut rng, "sid", 1, x, y);
+        let invalid = DLogProof::prove(&mut rng, "sid", 1, x, y);
+
+        let valid = pool.submit(valid, "sid", 1, y).await;
+        let invalid = pool.submit(invalid, "sid", 2, y).await;
+
+        assert!(valid.await.expect("pool should respond"));
+        assert!(!invalid.await.expect("pool should respond"));
+    }
+}
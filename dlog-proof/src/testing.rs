@@ -0,0 +1,95 @@
+//! Property-testing helpers for downstream crates.
+//!
+//! Provides `arbitrary::Arbitrary` for `DLogProof` and `proptest` strategies for scalars,
+//! points and proofs, so storage and transport layers can be fuzzed with well-formed values.
+
+use arbitrary::{Arbitrary, Unstructured};
+use elliptic_curve::ops::Reduce;
+use k256::{FieldBytes, ProjectivePoint, Scalar, U256};
+use proptest::prelude::*;
+
+use crate::{DLogProof, TranscriptVersion};
+
+/// Reduces 32 arbitrary bytes into a scalar modulo the curve order.
+pub fn scalar_from_bytes(bytes: [u8; 32]) -> Scalar {
+    <Scalar as Reduce<U256>>::reduce_bytes(&FieldBytes::from(bytes))
+}
+
+/// Draws a scalar from unstructured fuzzer input.
+pub fn arbitrary_scalar(u: &mut Unstructured<'_>) -> arbitrary::Result<Scalar> {
+    Ok(scalar_from_bytes(u.arbitrary()?))
+}
+
+/// Draws a curve point `k*G` from unstructured fuzzer input.
+pub fn arbitrary_point(u: &mut Unstructured<'_>) -> arbitrary::Result<ProjectivePoint> {
+    Ok(ProjectivePoint::GENERATOR * arbitrary_scalar(u)?)
+}
+
+impl<'a> Arbitrary<'a> for DLogProof {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(DLogProof {
+            t: arbitrary_point(u)?,
+            s: arbitrary_scalar(u)?,
+        })
+    }
+
+    fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+        (64, Some(64))
+    }
+}
+
+/// Strategy generating scalars uniformly modulo the curve order.
+pub fn scalar() -> impl Strategy<Value = Scalar> {
+    any::<[u8; 32]>().prop_map(scalar_from_bytes)
+}
+
+/// Strategy generating curve points as multiples of the generator.
+pub fn point() -> impl Strategy<Value = ProjectivePoint> {
+    scalar().prop_map(|k| ProjectivePoint::GENERATOR * k)
+}
+
+/// Strategy generating structurally valid proofs that don't verify against any statement.
+pub fn dlog_proof() -> impl Strategy<Value = DLogProof> {
+    (point(), scalar()).prop_map(|(t, s)| DLogProof { t, s })
+}
+
+/// Strategy generating `(proof, sid, pid, y)` tuples where the proof verifies.
+pub fn valid_dlog_proof() -> impl Strategy<Value = (DLogProof, String, u32, ProjectivePoint)> {
+    (scalar(), scalar(), ".*", any::<u32>()).prop_map(|(x, r, sid, pid)| {
+        let y = ProjectivePoint::GENERATOR * x;
+        let proof = DLogProof::prove_with_nonce(r, x, y, |points| {
+            DLogProof::hash_points(TranscriptVersion::V1, &sid, pid, points)
+        });
+
+        (proof, sid, pid, y)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn valid_proofs_verify((proof, sid, pid, y) in valid_dlog_proof()) {
+            prop_assert!(proof.verify(&sid, pid, y));
+        }
+
+        #[test]
+        fn serialization_roundtrip(proof in dlog_proof()) {
+            let json_proof = serde_json::to_string(&proof).expect("serialization should succeed");
+            let decoded_proof: DLogProof =
+                serde_json::from_str(&json_proof).expect("deserialization should succeed");
+
+            prop_assert_eq!(proof, decoded_proof);
+        }
+
+        #[test]
+        fn arbitrary_consumes_fixed_size(bytes in any::<[u8; 64]>()) {
+            let mut u = Unstructured::new(&bytes);
+
+            prop_assert!(DLogProof::arbitrary(&mut u).is_ok());
+            prop_assert!(u.is_empty());
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Load test of a running sync-point instance.
+//!
+//! Fires pairs of parties waiting on the same id from concurrent clients, then reports the
+//! latency of the matches and the rate of timeouts and errors.
+
+use std::{
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use sync_point_client::{Client, Outcome, RetryPolicy};
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Base URL of the instance under test
+    #[arg(long, env = "SYNC_POINT_URL", default_value = "http://localhost:8080")]
+    url: String,
+
+    /// API key sent as a bearer token, for instances requiring one
+    #[arg(long, env = "SYNC_POINT_API_KEY")]
+    api_key: Option<String>,
+
+    /// Number of pairs to match, each on its own id
+    #[arg(long, default_value_t = 1000)]
+    pairs: usize,
+
+    /// Number of clients matching pairs concurrently
+    #[arg(long, default_value_t = 50)]
+    clients: usize,
+
+    /// Seconds over which the clients start, evenly spread, instead of all at once
+    #[arg(long, default_value_t = 0)]
+    ramp_secs: u64,
+
+    /// Timeout of each wait in milliseconds
+    #[arg(long, default_value_t = 10_000)]
+    timeout_ms: u64,
+}
+
+/// How a pair of parties fared.
+enum PairResult {
+    /// Both parties matched, after the given time.
+    Matched(Duration),
+    /// At least one party timed out.
+    Timeout,
+    /// At least one request failed or was turned away, e.g. the instance was overloaded.
+    Error(String),
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if cli.clients == 0 {
+        eprintln!("at least one client is needed");
+        return ExitCode::FAILURE;
+    }
+
+    // Errors are reported rather than hidden by retries
+    let mut client = Client::new(&cli.url).with_retry_policy(RetryPolicy {
+        max_retries: 0,
+        ..RetryPolicy::default()
+    });
+    if let Some(api_key) = &cli.api_key {
+        client = client.with_api_key(api_key);
+    }
+
+    // Ids are unique to the run, so that runs against the same instance don't interfere
+    let run_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let timeout = Duration::from_millis(cli.timeout_ms);
+    let next_pair = Arc::new(AtomicUsize::new(0));
+
+    println!(
+        "Matching {} pairs from {} clients against {}",
+        cli.pairs, cli.clients, cli.url
+    );
+    let started_at = Instant::now();
+    let workers: Vec<_> = (0..cli.clients)
+        .map(|worker| {
+            let client = client.clone();
+            let next_pair = next_pair.clone();
+            let ramp = Duration::from_secs(cli.ramp_secs) * worker as u32 / cli.clients as u32;
+            let pairs = cli.pairs;
+
+            tokio::spawn(async move {
+                tokio::time::sleep(ramp).await;
+
+                let mut results = Vec::new();
+                loop {
+                    let pair = next_pair.fetch_add(1, Ordering::Relaxed);
+                    if pair >= pairs {
+                        return results;
+                    }
+                    let unique_id = format!("bench-{run_id}-{pair}");
+                    results.push(match_pair(&client, &unique_id, timeout).await);
+                }
+            })
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(cli.pairs);
+    for worker in workers {
+        results.extend(worker.await.expect("workers don't panic"));
+    }
+
+    let report = Report::new(results, started_at.elapsed());
+    report.print();
+    if report.matched.len() == report.pairs {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Sends both parties of a pair at once, timing how long until both matched.
+async fn match_pair(client: &Client, unique_id: &str, timeout: Duration) -> PairResult {
+    let started_at = Instant::now();
+    let (party1, party2) = tokio::join!(
+        client.wait(unique_id, Some(timeout)),
+        client.wait(unique_id, Some(timeout)),
+    );
+    let elapsed = started_at.elapsed();
+
+    match (party1, party2) {
+        (Ok(Outcome::Matched { .. }), Ok(Outcome::Matched { .. })) => PairResult::Matched(elapsed),
+        (Err(err), _) | (_, Err(err)) => PairResult::Error(err.to_string()),
+        (Ok(Outcome::Timeout { .. }), _) | (_, Ok(Outcome::Timeout { .. })) => PairResult::Timeout,
+        (Ok(party1), Ok(party2)) => {
+            let unexpected = match party1 {
+                Outcome::Matched { .. } => party2,
+                _ => party1,
+            };
+            PairResult::Error(format!("unexpected outcome {unexpected:?}"))
+        }
+    }
+}
+
+/// Aggregates of the results of a run.
+struct Report {
+    pairs: usize,
+    elapsed: Duration,
+    /// Latencies of the matched pairs, sorted.
+    matched: Vec<Duration>,
+    timeouts: usize,
+    errors: Vec<String>,
+}
+
+impl Report {
+    fn new(results: Vec<PairResult>, elapsed: Duration) -> Self {
+        let mut report = Report {
+            pairs: results.len(),
+            elapsed,
+            matched: Vec::new(),
+            timeouts: 0,
+            errors: Vec::new(),
+        };
+        for result in results {
+            match result {
+                PairResult::Matched(latency) => report.matched.push(latency),
+                PairResult::Timeout => report.timeouts += 1,
+                PairResult::Error(err) => report.errors.push(err),
+            }
+        }
+        report.matched.sort_unstable();
+        report
+    }
+
+    /// Latency under which `percent` of the matched pairs were matched.
+    fn percentile(&self, percent: usize) -> Option<Duration> {
+        let last = self.matched.len().checked_sub(1)?;
+        Some(self.matched[last * percent / 100])
+    }
+
+    fn rate(&self, count: usize) -> f64 {
+        if self.pairs == 0 {
+            return 0.0;
+        }
+        count as f64 * 100.0 / self.pairs as f64
+    }
+
+    fn print(&self) {
+        println!(
+            "{} pairs in {:.2?} ({:.1} pairs/s)",
+            self.pairs,
+            self.elapsed,
+            self.pairs as f64 / self.elapsed.as_secs_f64()
+        );
+        println!(
+            "matched: {} ({:.2}%)",
+            self.matched.len(),
+            self.rate(self.matched.len())
+        );
+        println!(
+            "timeouts: {} ({:.2}%)",
+            self.timeouts,
+            self.rate(self.timeouts)
+        );
+        println!(
+            "errors: {} ({:.2}%)",
+            self.errors.len(),
+            self.rate(self.errors.len())
+        );
+        if let Some(err) = self.errors.first() {
+            println!("  first error: {err}");
+        }
+
+        if let (Some(p50), Some(p95), Some(p99), Some(max)) = (
+            self.percentile(50),
+            self.percentile(95),
+            self.percentile(99),
+            self.percentile(100),
+        ) {
+            println!("match latency: p50 {p50:.2?}, p95 {p95:.2?}, p99 {p99:.2?}, max {max:.2?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_aggregates_results() {
+        let mut results: Vec<_> = (1..=100)
+            .map(|millis| PairResult::Matched(Duration::from_millis(millis)))
+            .collect();
+        results.push(PairResult::Timeout);
+        results.push(PairResult::Error("overloaded".to_owned()));
+
+        let report = Report::new(results, Duration::from_secs(1));
+        assert_eq!(report.pairs, 102);
+        assert_eq!(report.timeouts, 1);
+        assert_eq!(report.errors, ["overloaded"]);
+        assert_eq!(report.percentile(50), Some(Duration::from_millis(50)));
+        assert_eq!(report.percentile(99), Some(Duration::from_millis(99)));
+        assert_eq!(report.percentile(100), Some(Duration::from_millis(100)));
+
+        let report = Report::new(Vec::new(), Duration::from_secs(1));
+        assert_eq!(report.percentile(50), None);
+        assert_eq!(report.rate(0), 0.0);
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret, StaticSecret};
+
+/// Binds the keys derived for an envelope to this format.
+const INFO: &[u8] = b"sync-point envelope v1";
+
+/// X25519 key pair of a party. Its public key is handed to the party it matches, which can then
+/// seal envelopes only this party can open.
+pub struct KeyPair {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl KeyPair {
+    /// Generates a new key pair, best used for a single match.
+    pub fn generate() -> Self {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        KeyPair {
+            public: PublicKey::from(&secret),
+            secret,
+        }
+    }
+
+    /// Public key of the pair, hex encoded as sent to the server.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.public.as_bytes())
+    }
+
+    /// Opens `envelope`, returning the payload sealed to this key pair.
+    pub fn open(&self, envelope: &Envelope) -> Result<Vec<u8>, EnvelopeError> {
+        let ephemeral_key = parse_public_key(&envelope.ephemeral_key)?;
+        let ciphertext = hex::decode(&envelope.ciphertext).map_err(|_| EnvelopeError::Malformed)?;
+
+        let shared_secret = self.secret.diffie_hellman(&ephemeral_key);
+        let (cipher, nonce) = cipher(&shared_secret, &ephemeral_key, &self.public)?;
+        cipher
+            .decrypt(&nonce, ciphertext.as_slice())
+            .map_err(|_| EnvelopeError::Undecryptable)
+    }
+}
+
+/// Payload encrypted to the public key of a party, so that whatever passively observes it on its
+/// way, e.g. the logs of the relay, can't read it.
+///
+/// The payload is encrypted with ChaCha20-Poly1305, under a key agreed with X25519 between a
+/// one-off key pair and the key pair of the recipient. The public keys are handed over by the
+/// server unauthenticated, so a server swapping them for its own can read and forge envelopes:
+/// the parties must compare their keys over another channel to rule it out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Public key of the one-off key pair, hex encoded.
+    pub ephemeral_key: String,
+    /// Encrypted payload followed by its authentication tag, hex encoded.
+    pub ciphertext: String,
+}
+
+impl Envelope {
+    /// Seals `payload` to `public_key`, the hex encoded public key of the recipient, e.g. the
+    /// `public_key` of the peer of a match.
+    pub fn seal(public_key: &str, payload: &[u8]) -> Result<Self, EnvelopeError> {
+        let recipient = parse_public_key(public_key)?;
+        let ephemeral_secret = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_key = PublicKey::from(&ephemeral_secret);
+
+        let shared_secret = ephemeral_secret.diffie_hellman(&recipient);
+        let (cipher, nonce) = cipher(&shared_secret, &ephemeral_key, &recipient)?;
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .expect("payloads fitting in memory can be encrypted");
+
+        Ok(Envelope {
+            ephemeral_key: hex::encode(ephemeral_key.as_bytes()),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+}
+
+/// Failure to seal or open an envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// A public key isn't a valid X25519 public key.
+    InvalidKey,
+    /// The envelope isn't hex encoded.
+    Malformed,
+    /// The envelope was sealed to another key pair or tampered with.
+    Undecryptable,
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::InvalidKey => write!(f, "invalid public key"),
+            EnvelopeError::Malformed => write!(f, "malformed envelope"),
+            EnvelopeError::Undecryptable => write!(f, "envelope can't be opened with this key"),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+fn parse_public_key(public_key: &str) -> Result<PublicKey, EnvelopeError> {
+    let mut bytes = [0; 32];
+    hex::decode_to_slice(public_key, &mut bytes).map_err(|_| EnvelopeError::InvalidKey)?;
+    Ok(PublicKey::from(bytes))
+}
+
+/// Derives the cipher and nonce of an envelope from the secret agreed between its one-off key
+/// pair and the key pair of its recipient.
+///
+/// The key is never reused, since every envelope has its own one-off key pair, so neither is the
+/// nonce derived along with it.
+fn cipher(
+    shared_secret: &SharedSecret,
+    ephemeral_key: &PublicKey,
+    recipient: &PublicKey,
+) -> Result<(ChaCha20Poly1305, Nonce), EnvelopeError> {
+    // Keys of small order would agree on a secret known to anyone
+    if !shared_secret.was_contributory() {
+        return Err(EnvelopeError::InvalidKey);
+    }
+
+    let salt = [ephemeral_key.as_bytes().as_slice(), recipient.as_bytes()].concat();
+    let mut okm = [0; 44];
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret.as_bytes())
+        .expand(INFO, &mut okm)
+        .expect("44 bytes is a valid length for HKDF-SHA256");
+    let (key, nonce) = okm.split_at(32);
+
+    Ok((
+        ChaCha20Poly1305::new(Key::from_slice(key)),
+        *Nonce::from_slice(nonce),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_recipient_opens_envelopes() {
+        let recipient = KeyPair::generate();
+        let envelope = Envelope::seal(&recipient.public_key(), b"session parameters").unwrap();
+        assert_eq!(recipient.open(&envelope).unwrap(), b"session parameters");
+
+        let other = KeyPair::generate();
+        assert_eq!(other.open(&envelope), Err(EnvelopeError::Undecryptable));
+
+        let mut tampered = envelope.clone();
+        let flipped = if tampered.ciphertext.starts_with('0') {
+            "1"
+        } else {
+            "0"
+        };
+        tampered.ciphertext.replace_range(..1, flipped);
+        assert_eq!(recipient.open(&tampered), Err(EnvelopeError::Undecryptable));
+    }
+
+    #[test]
+    fn rejects_invalid_public_keys() {
+        assert_eq!(
+            Envelope::seal("not a key", b"payload"),
+            Err(EnvelopeError::InvalidKey)
+        );
+        // The identity point agrees on an all-zero secret with every key
+        assert_eq!(
+            Envelope::seal(&"00".repeat(32), b"payload"),
+            Err(EnvelopeError::InvalidKey)
+        );
+    }
+}
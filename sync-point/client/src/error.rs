@@ -0,0 +1,38 @@
+use std::fmt;
+
+use reqwest::StatusCode;
+
+/// Failure to get the outcome of a request.
+#[derive(Debug)]
+pub enum Error {
+    /// The server couldn't be reached or its response couldn't be read.
+    Http(reqwest::Error),
+    /// The server rejected the request (e.g. invalid id or missing API key).
+    Rejected { status: StatusCode, message: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(err) => write!(f, "request failed: {err}"),
+            Error::Rejected { status, message } => {
+                write!(f, "request rejected with {status}: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(err) => Some(err),
+            Error::Rejected { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
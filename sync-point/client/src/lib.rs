@@ -0,0 +1,360 @@
+//! Client of the sync-point HTTP API.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), sync_point_client::Error> {
+//! use std::time::Duration;
+//!
+//! use sync_point_client::{Client, Outcome};
+//!
+//! let client = Client::new("http://localhost:8080");
+//! match client.wait("my-session", Some(Duration::from_secs(30))).await? {
+//!     Outcome::Matched { role, .. } => println!("Matched as the {role:?} party"),
+//!     outcome => println!("No match: {outcome:?}"),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use reqwest::{
+    header::{ACCEPT, RETRY_AFTER},
+    RequestBuilder, Response, StatusCode,
+};
+use serde::Deserialize;
+
+pub use crate::{
+    envelope::{Envelope, EnvelopeError, KeyPair},
+    error::Error,
+    outcome::{Outcome, Peer, Role},
+};
+
+mod envelope;
+mod error;
+mod outcome;
+
+/// Header carrying the public key of a party, handed to the party it matches.
+const PUBLIC_KEY_HEADER: &str = "x-party-public-key";
+
+/// How requests turned away before waiting are retried.
+///
+/// Parties are only retried when the server didn't register them (e.g. it was overloaded,
+/// unreachable or shutting down), so a retry never races its own earlier attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt, `0` disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled after each one.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between retries.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Client of a sync-point server, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    public_key: Option<String>,
+    retry_policy: RetryPolicy,
+}
+
+/// JSON body of the rejected requests.
+#[derive(Deserialize)]
+struct Rejection {
+    message: String,
+}
+
+impl Client {
+    /// Creates a client of the server at `base_url` (e.g. `http://localhost:8080`).
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Client {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_owned(),
+            api_key: None,
+            public_key: None,
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// Sends `api_key` as a bearer token, for servers requiring one.
+    pub fn with_api_key(self, api_key: impl Into<String>) -> Self {
+        Client {
+            api_key: Some(api_key.into()),
+            ..self
+        }
+    }
+
+    /// Hands the public key of `key_pair` to the parties this client's waits match, so that they
+    /// can seal [`Envelope`]s to it.
+    pub fn with_key_pair(self, key_pair: &KeyPair) -> Self {
+        Client {
+            public_key: Some(key_pair.public_key()),
+            ..self
+        }
+    }
+
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Client {
+            retry_policy,
+            ..self
+        }
+    }
+
+    /// Waits on `unique_id` until another party arrives, falling back to the server timeout if
+    /// `timeout` is `None`.
+    pub async fn wait(&self, unique_id: &str, timeout: Option<Duration>) -> Result<Outcome, Error> {
+        let url = format!("{}/wait-for-second-party/{unique_id}", self.base_url);
+        self.send_with_retries(|| with_timeout(self.http.post(&url), timeout))
+            .await
+    }
+
+    /// Waits on the barrier of `unique_id` until `parties` parties arrived.
+    pub async fn wait_for_parties(
+        &self,
+        unique_id: &str,
+        parties: usize,
+        timeout: Option<Duration>,
+    ) -> Result<Outcome, Error> {
+        let url = format!("{}/wait-for-parties/{unique_id}/{parties}", self.base_url);
+        self.send_with_retries(|| with_timeout(self.http.post(&url), timeout))
+            .await
+    }
+
+    /// Cancels the wait of the party waiting on `unique_id`.
+    ///
+    /// Returns `false` if no party was waiting.
+    pub async fn cancel(&self, unique_id: &str) -> Result<bool, Error> {
+        let url = format!("{}/wait-for-second-party/{unique_id}", self.base_url);
+        let response = self.authorize(self.http.delete(&url)).send().await?;
+
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(rejection(status, response).await),
+        }
+    }
+
+    async fn send_with_retries(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Result<Outcome, Error> {
+        let mut backoff = self.retry_policy.initial_backoff;
+
+        for retry in 0.. {
+            let (result, retry_after) = self.send(request()).await;
+            let retryable = match &result {
+                Ok(Outcome::Overloaded { .. } | Outcome::ShuttingDown { .. }) => true,
+                Err(Error::Http(err)) => err.is_connect(),
+                Err(Error::Rejected { status, .. }) => matches!(
+                    *status,
+                    StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+                ),
+                Ok(_) => false,
+            };
+            if !retryable || retry >= self.retry_policy.max_retries {
+                return result;
+            }
+
+            tokio::time::sleep(retry_after.unwrap_or(backoff).max(backoff)).await;
+            backoff = (backoff * 2).min(self.retry_policy.max_backoff);
+        }
+
+        unreachable!("the last retry returns")
+    }
+
+    /// Sends a single request, returning its outcome and the delay the server asked to wait
+    /// before retrying.
+    async fn send(&self, request: RequestBuilder) -> (Result<Outcome, Error>, Option<Duration>) {
+        let request = match &self.public_key {
+            Some(public_key) => request.header(PUBLIC_KEY_HEADER, public_key),
+            None => request,
+        };
+        let response = match self
+            .authorize(request)
+            .header(ACCEPT, "application/json")
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(err) => return (Err(err.into()), None),
+        };
+
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|retry_after| retry_after.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs);
+
+        let status = response.status();
+        let result = match status {
+            StatusCode::OK
+            | StatusCode::REQUEST_TIMEOUT
+            | StatusCode::CONFLICT
+            | StatusCode::GONE
+            | StatusCode::SERVICE_UNAVAILABLE => match response.bytes().await {
+                // The store being unavailable is answered with an error instead of an outcome
+                Ok(body) => serde_json::from_slice(&body).map_err(|_| Error::Rejected {
+                    status,
+                    message: rejection_message(&body),
+                }),
+                Err(err) => Err(err.into()),
+            },
+            status => Err(rejection(status, response).await),
+        };
+
+        (result, retry_after)
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+}
+
+fn with_timeout(request: RequestBuilder, timeout: Option<Duration>) -> RequestBuilder {
+    match timeout {
+        Some(timeout) => request.query(&[("timeout_ms", timeout.as_millis() as u64)]),
+        None => request,
+    }
+}
+
+async fn rejection(status: StatusCode, response: Response) -> Error {
+    match response.bytes().await {
+        Ok(body) => Error::Rejected {
+            status,
+            message: rejection_message(&body),
+        },
+        Err(err) => err.into(),
+    }
+}
+
+fn rejection_message(body: &[u8]) -> String {
+    match serde_json::from_slice::<Rejection>(body) {
+        Ok(rejection) => rejection.message,
+        Err(_) => String::from_utf8_lossy(body).trim().to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use axum::{
+        http::{header::RETRY_AFTER, StatusCode},
+        response::IntoResponse,
+        routing::post,
+        Json, Router,
+    };
+    use serde_json::json;
+
+    use super::*;
+
+    /// Serves a wait route answering the given responses in turn, returning its base URL.
+    async fn serve(responses: Vec<(StatusCode, serde_json::Value)>) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let responses = Arc::new(responses);
+        let served = requests.clone();
+        let app = Router::new().route(
+            "/wait-for-second-party/:unique-id",
+            post(move || async move {
+                let (status, body) = responses[served.fetch_add(1, Ordering::Relaxed)].clone();
+                (status, [(RETRY_AFTER, "0")], Json(body)).into_response()
+            }),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (format!("http://{addr}"), requests)
+    }
+
+    #[tokio::test]
+    async fn wait_retries_while_server_is_overloaded() {
+        let (url, requests) = serve(vec![
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({"status": "overloaded", "retry_after_secs": 0}),
+            ),
+            (
+                StatusCode::OK,
+                json!({
+                    "status": "matched",
+                    "role": "first",
+                    "waited_ms": 42,
+                    "peer": {"arrived_at_ms": 1000, "label": "worker-2"},
+                    "token": "4f1c2a",
+                }),
+            ),
+        ])
+        .await;
+
+        let outcome = Client::new(url).wait("1", None).await.unwrap();
+        assert_eq!(
+            outcome,
+            Outcome::Matched {
+                role: Role::First,
+                waited_ms: 42,
+                peer: Some(Peer {
+                    client: None,
+                    arrived_at_ms: 1000,
+                    label: Some("worker-2".to_owned()),
+                    public_key: None,
+                }),
+                token: Some("4f1c2a".to_owned()),
+                relay_url: None,
+            }
+        );
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn wait_returns_timeouts_and_rejections_without_retrying() {
+        let (url, requests) = serve(vec![
+            (
+                StatusCode::REQUEST_TIMEOUT,
+                json!({"status": "timeout", "waited_ms": 100, "retry_after_secs": 1}),
+            ),
+            (
+                StatusCode::BAD_REQUEST,
+                json!({"status": "error", "message": "The unique id is too long"}),
+            ),
+        ])
+        .await;
+        let client = Client::new(url);
+
+        let outcome = client.wait("1", Some(Duration::from_millis(100))).await;
+        assert_eq!(
+            outcome.unwrap(),
+            Outcome::Timeout {
+                waited_ms: 100,
+                retry_after_secs: 1
+            }
+        );
+
+        let err = client.wait("1", None).await.unwrap_err();
+        assert!(matches!(
+            err,
+            Error::Rejected { status: StatusCode::BAD_REQUEST, ref message }
+                if message == "The unique id is too long"
+        ));
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Which side of the rendezvous a party was on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// The party arrived first and waited for the other one.
+    First,
+    /// The party arrived second and found the other one waiting.
+    Second,
+}
+
+/// The party a party matched with or missed, as seen by the server.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Peer {
+    /// Address of the peer, unknown when the server doesn't see it.
+    #[serde(default)]
+    pub client: Option<String>,
+    /// When the peer arrived, in milliseconds since the Unix epoch.
+    pub arrived_at_ms: u64,
+    /// Label the peer sent in its `X-Party-Label` header.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Public key the peer sent in its `X-Party-Public-Key` header, to seal
+    /// [`Envelope`](crate::Envelope)s to it.
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+/// Outcome of a wait, as answered by the server.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    /// Another party arrived with the same id.
+    Matched {
+        role: Role,
+        waited_ms: u64,
+        /// Missing when the server predates peer metadata.
+        #[serde(default)]
+        peer: Option<Peer>,
+        /// Random token shared by both parties, to authenticate a follow-up connection between
+        /// them. Missing when the server predates match tokens.
+        #[serde(default)]
+        token: Option<String>,
+        /// Room of the relay where both parties can open a channel, when the server has one.
+        #[serde(default)]
+        relay_url: Option<String>,
+    },
+    /// Every party expected at the barrier arrived.
+    Released { parties: usize, waited_ms: u64 },
+    /// No other party arrived before the timeout.
+    Timeout {
+        waited_ms: u64,
+        /// Suggested delay before waiting again, growing with the load of the server.
+        #[serde(default)]
+        retry_after_secs: u64,
+    },
+    /// The wait was cancelled through the API.
+    Cancelled { waited_ms: u64 },
+    /// The server shut down while the party was waiting, or before it could.
+    ShuttingDown { waited_ms: u64 },
+    /// A retry sent with the same idempotency key took over the wait.
+    Superseded { waited_ms: u64 },
+    /// The other party timed out shortly before this one arrived, when the server tells so.
+    Missed {
+        /// The party that timed out.
+        peer: Peer,
+        /// When it gave up, in milliseconds since the Unix epoch.
+        left_at_ms: u64,
+    },
+    /// Too many parties were waiting on the server for this one to wait too.
+    Overloaded { retry_after_secs: u64 },
+}
+
+impl Outcome {
+    /// Whether the wait ended with every expected party connected.
+    pub fn is_synchronized(&self) -> bool {
+        matches!(self, Outcome::Matched { .. } | Outcome::Released { .. })
+    }
+
+    /// How long the party waited, if it got to wait.
+    pub fn waited(&self) -> Option<Duration> {
+        match *self {
+            Outcome::Matched { waited_ms, .. }
+            | Outcome::Released { waited_ms, .. }
+            | Outcome::Timeout { waited_ms, .. }
+            | Outcome::Cancelled { waited_ms }
+            | Outcome::ShuttingDown { waited_ms }
+            | Outcome::Superseded { waited_ms } => Some(Duration::from_millis(waited_ms)),
+            Outcome::Missed { .. } | Outcome::Overloaded { .. } => None,
+        }
+    }
+}
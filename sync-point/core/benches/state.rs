@@ -0,0 +1,132 @@
+//! Baseline of the party store operations and of the in-process request round-trips, to compare
+//! changes to the locking of the waiting parties against.
+//!
+//! ```bash
+//! cargo bench -p sync-point-core --bench state
+//! ```
+
+use std::time::Duration;
+
+use axum::{body::Body, http::Request, Router};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures_util::future::join_all;
+use sync_point_core::{make_router, AppState, Arrival, LocalParties, PartyStore, Peer, Settings};
+use tokio::{runtime::Runtime, time::Instant};
+use tower::ServiceExt;
+
+/// Parties waiting on other ids while the operations are measured.
+const BACKLOGS: [usize; 3] = [0, 1_000, 100_000];
+/// Pairs of parties matching at once in the round-trips.
+const CONCURRENCY: [usize; 4] = [1, 16, 256, 1_024];
+/// Shard counts compared, a single lock against the default.
+const SHARDS: [usize; 2] = [1, 64];
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("the benchmark runtime should start")
+}
+
+/// Store with `backlog` parties waiting on ids of their own, kept waiting by the returned waiters.
+async fn store_with_backlog(backlog: usize) -> (LocalParties, Vec<Arrival>) {
+    let parties = LocalParties::default();
+    let deadline = Instant::now() + Duration::from_secs(3600);
+    let peer = Peer::new(None, None);
+    let mut waiters = Vec::with_capacity(backlog);
+    for i in 0..backlog {
+        let arrival = parties
+            .arrive(&format!("backlog-{i}"), deadline, None, &peer)
+            .await
+            .unwrap();
+        waiters.push(arrival);
+    }
+    (parties, waiters)
+}
+
+/// Inserting a waiting party then taking it out, by matching, withdrawing or cancelling it.
+fn store_operations(c: &mut Criterion) {
+    let runtime = runtime();
+    let peer = Peer::new(None, None);
+    let mut group = c.benchmark_group("store");
+
+    for backlog in BACKLOGS {
+        let (parties, _waiters) = runtime.block_on(store_with_backlog(backlog));
+        let deadline = Instant::now() + Duration::from_secs(3600);
+
+        group.bench_with_input(BenchmarkId::new("match", backlog), &backlog, |b, _| {
+            b.to_async(&runtime).iter(|| async {
+                let waiter = parties.arrive("1", deadline, None, &peer).await.unwrap();
+                let matched = parties.arrive("1", deadline, None, &peer).await.unwrap();
+                assert!(matches!(matched, Arrival::Matched(..)));
+                drop(waiter);
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("withdraw", backlog), &backlog, |b, _| {
+            b.to_async(&runtime).iter(|| async {
+                let Arrival::Wait(waiter) =
+                    parties.arrive("1", deadline, None, &peer).await.unwrap()
+                else {
+                    unreachable!("no party waits on the id");
+                };
+                assert!(waiter.withdraw().await.is_none());
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("cancel", backlog), &backlog, |b, _| {
+            b.to_async(&runtime).iter(|| async {
+                let waiter = parties.arrive("1", deadline, None, &peer).await.unwrap();
+                assert!(parties.cancel("1").await.unwrap());
+                drop(waiter);
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Pairs of parties matching through the whole router, called in-process as a tower service.
+fn round_trips(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut group = c.benchmark_group("round_trip");
+
+    for shards in SHARDS {
+        let app = runtime.block_on(async {
+            let state = AppState::new(
+                Settings::new(Duration::from_secs(10)),
+                Box::new(LocalParties::with_shards(shards)),
+            );
+            make_router(state).0
+        });
+
+        for concurrency in CONCURRENCY {
+            let id = BenchmarkId::new(format!("{shards}_shards"), concurrency);
+            group.throughput(Throughput::Elements(2 * concurrency as u64));
+            group.bench_with_input(id, &concurrency, |b, &concurrency| {
+                b.to_async(&runtime).iter(|| {
+                    let parties = (0..concurrency)
+                        .flat_map(|pair| [pair, pair])
+                        .map(|pair| tokio::spawn(wait(app.clone(), pair)));
+                    async move {
+                        for party in join_all(parties).await {
+                            assert!(party.unwrap());
+                        }
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Waits on the id of `pair`, returning whether the party matched.
+async fn wait(app: Router, pair: usize) -> bool {
+    let request = Request::post(format!("/wait-for-second-party/pair-{pair}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    response.status().is_success()
+}
+
+criterion_group!(benches, store_operations, round_trips);
+criterion_main!(benches);
@@ -0,0 +1,266 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, LineWriter, Write},
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, Request, State},
+    http::{
+        header::{CONTENT_LENGTH, REFERER, USER_AGENT},
+        HeaderMap, HeaderName,
+    },
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    Router,
+};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::{lock::lock, trace::request_id, AppState};
+
+/// Path of the access log standing for the standard output.
+const STDOUT: &str = "-";
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Format of the lines of the access log.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Common Log Format, as written by most web servers.
+    #[default]
+    Common,
+    /// Common Log Format followed by the referer and user agent.
+    Combined,
+    /// One JSON object per line.
+    Json,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "common" => Ok(AccessLogFormat::Common),
+            "combined" => Ok(AccessLogFormat::Combined),
+            "json" => Ok(AccessLogFormat::Json),
+            _ => Err(format!(
+                "{format} isn't an access log format, expected common, combined or json"
+            )),
+        }
+    }
+}
+
+/// Request as recorded in the access log.
+#[derive(Serialize)]
+struct AccessRecord<'a> {
+    /// When the response was sent, in milliseconds since the Unix epoch.
+    at_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client: Option<SocketAddr>,
+    method: &'a str,
+    uri: &'a str,
+    protocol: String,
+    status: u16,
+    /// Size of the response body, unknown when it's streamed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
+    /// How long the response took, waits included.
+    duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    referer: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_agent: Option<&'a str>,
+}
+
+/// `AccessLog` writes a line per request to its own file in the usual formats of web servers,
+/// apart from the diagnostic logs, for the pipelines ingesting access logs.
+pub struct AccessLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+    format: AccessLogFormat,
+    /// Prefixes of the paths left out of the log, e.g. those of the health checks.
+    exclude_paths: Vec<String>,
+}
+
+impl AccessLog {
+    /// Opens the access log at `path` for appending, creating it if needed, or the standard
+    /// output for `-`.
+    pub fn open(
+        path: &str,
+        format: AccessLogFormat,
+        exclude_paths: Vec<String>,
+    ) -> io::Result<Self> {
+        let writer: Box<dyn Write + Send> = if path == STDOUT {
+            Box::new(io::stdout())
+        } else {
+            Box::new(OpenOptions::new().create(true).append(true).open(path)?)
+        };
+
+        Ok(AccessLog {
+            // Flushed line by line, so that lines are never lost in a buffer nor split
+            writer: Mutex::new(Box::new(LineWriter::new(writer))),
+            format,
+            exclude_paths,
+        })
+    }
+
+    fn logs(&self, path: &str) -> bool {
+        !self
+            .exclude_paths
+            .iter()
+            .any(|excluded| path.starts_with(excluded.as_str()))
+    }
+
+    fn record(&self, at: OffsetDateTime, record: &AccessRecord) {
+        let mut line = match self.format {
+            AccessLogFormat::Common => common_line(at, record),
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                common_line(at, record),
+                escape(record.referer),
+                escape(record.user_agent)
+            ),
+            AccessLogFormat::Json => {
+                serde_json::to_string(record).expect("access records always serialize")
+            }
+        };
+        line.push('\n');
+
+        // A failed write leaves at worst a partial line behind
+        if let Err(err) = lock(&self.writer).write_all(line.as_bytes()) {
+            warn!(%err, "Failed to write to the access log");
+        }
+    }
+}
+
+/// Records every request of `router` in the access log, when one is configured.
+pub fn log_access(router: Router, state: &Arc<AppState>) -> Router {
+    if state.access_log.is_none() {
+        return router;
+    }
+    router.layer(from_fn_with_state(state.clone(), record_access))
+}
+
+async fn record_access(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(access_log) = state
+        .access_log
+        .as_ref()
+        .filter(|access_log| access_log.logs(request.uri().path()))
+    else {
+        return next.run(request).await;
+    };
+
+    let started_at = Instant::now();
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(client)| *client);
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let version = request.version();
+    let headers = request.headers().clone();
+
+    let response = next.run(request).await;
+
+    let bytes = response.body().size_hint().exact().or_else(|| {
+        response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok()?.parse().ok())
+    });
+    let at = OffsetDateTime::now_utc();
+    let record = AccessRecord {
+        at_ms: (at.unix_timestamp_nanos() / 1_000_000) as u64,
+        client,
+        method: method.as_str(),
+        uri: &uri.to_string(),
+        protocol: format!("{version:?}"),
+        status: response.status().as_u16(),
+        bytes,
+        duration_ms: started_at.elapsed().as_millis() as u64,
+        request_id: request_id(&headers),
+        referer: header(&headers, REFERER),
+        user_agent: header(&headers, USER_AGENT),
+    };
+    access_log.record(at, &record);
+    response
+}
+
+fn header(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Line of `record` in the Common Log Format, e.g.
+/// `10.0.0.1 - - [16/Oct/2026:13:55:36 +0000] "POST /wait-for-second-party/1 HTTP/1.1" 200 37`.
+fn common_line(at: OffsetDateTime, record: &AccessRecord) -> String {
+    let client = record
+        .client
+        .map_or_else(|| "-".to_owned(), |client| client.ip().to_string());
+    let bytes = record
+        .bytes
+        .map_or_else(|| "-".to_owned(), |bytes| bytes.to_string());
+    format!(
+        "{client} - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{} {} {}\" {} {bytes}",
+        at.day(),
+        MONTHS[usize::from(u8::from(at.month())) - 1],
+        at.year(),
+        at.hour(),
+        at.minute(),
+        at.second(),
+        record.method,
+        record.uri,
+        record.protocol,
+        record.status,
+    )
+}
+
+/// Quoted field of a line, `-` when missing.
+fn escape(value: Option<&str>) -> String {
+    value.map_or_else(
+        || "-".to_owned(),
+        |value| value.replace('\\', "\\\\").replace('"', "\\\""),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_lines_like_web_servers() {
+        let record = AccessRecord {
+            at_ms: 1_792_158_936_000,
+            client: Some(([10, 0, 0, 1], 52814).into()),
+            method: "POST",
+            uri: "/wait-for-second-party/1",
+            protocol: "HTTP/1.1".to_owned(),
+            status: 200,
+            bytes: Some(37),
+            duration_ms: 1234,
+            request_id: None,
+            referer: None,
+            user_agent: Some(r#"curl/8.5.0 "quoted""#),
+        };
+        let at = OffsetDateTime::from_unix_timestamp(1_792_158_936).unwrap();
+        assert_eq!(
+            common_line(at, &record),
+            r#"10.0.0.1 - - [16/Oct/2026:13:55:36 +0000] "POST /wait-for-second-party/1 HTTP/1.1" 200 37"#
+        );
+        assert_eq!(escape(record.referer), "-");
+        assert_eq!(escape(record.user_agent), r#"curl/8.5.0 \"quoted\""#);
+    }
+}
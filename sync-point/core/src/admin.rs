@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use tokio::time::Instant;
+use utoipa::ToSchema;
+
+use crate::{lock::lock, metrics::Waiting, AppState, UniqueId};
+
+/// Kind of wait a party is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitKind {
+    Rendezvous,
+    Barrier,
+    Release,
+}
+
+struct ActiveWaiter {
+    kind: WaitKind,
+    unique_id: UniqueId,
+    arrived_at: SystemTime,
+    deadline: Instant,
+    client: Option<SocketAddr>,
+}
+
+/// Party currently waiting on this instance, as listed by the admin API.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WaiterInfo {
+    pub kind: WaitKind,
+    pub unique_id: UniqueId,
+    /// When the party started waiting, in milliseconds since the Unix epoch.
+    pub arrived_at_ms: u64,
+    /// How long until the party times out.
+    pub remaining_ms: u64,
+    /// Address of the client, unknown when the server doesn't see it.
+    #[schema(value_type = Option<String>)]
+    pub client: Option<SocketAddr>,
+}
+
+/// `ActiveWaiters` tracks every party waiting on this instance, whatever the party store.
+#[derive(Default)]
+pub struct ActiveWaiters {
+    next_id: AtomicU64,
+    waiters: Mutex<HashMap<u64, ActiveWaiter>>,
+}
+
+impl ActiveWaiters {
+    /// Registers a party as waiting until the returned guard is dropped, even if its request is
+    /// aborted.
+    pub fn register(
+        &self,
+        kind: WaitKind,
+        unique_id: &str,
+        deadline: Instant,
+        client: Option<SocketAddr>,
+    ) -> Registration<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let waiter = ActiveWaiter {
+            kind,
+            unique_id: unique_id.to_owned(),
+            arrived_at: SystemTime::now(),
+            deadline,
+            client,
+        };
+        lock(&self.waiters).insert(id, waiter);
+
+        Registration {
+            waiters: self,
+            id,
+            _waiting: Waiting::start(),
+        }
+    }
+
+    /// Lists the waiting parties, the longest waiting first.
+    pub fn list(&self) -> Vec<WaiterInfo> {
+        let now = Instant::now();
+        let mut waiters: Vec<_> = lock(&self.waiters)
+            .values()
+            .map(|waiter| WaiterInfo {
+                kind: waiter.kind,
+                unique_id: waiter.unique_id.clone(),
+                arrived_at_ms: waiter
+                    .arrived_at
+                    .duration_since(UNIX_EPOCH)
+                    .map(|since_epoch| since_epoch.as_millis() as u64)
+                    .unwrap_or_default(),
+                remaining_ms: waiter.deadline.saturating_duration_since(now).as_millis() as u64,
+                client: waiter.client,
+            })
+            .collect();
+        waiters.sort_by_key(|waiter| waiter.arrived_at_ms);
+        waiters
+    }
+}
+
+/// Keeps a party listed as waiting until dropped.
+pub struct Registration<'a> {
+    waiters: &'a ActiveWaiters,
+    id: u64,
+    _waiting: Waiting,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        lock(&self.waiters.waiters).remove(&self.id);
+    }
+}
+
+/// Lists the parties waiting on this instance, for on-call engineers to see why a pipeline is
+/// stuck.
+#[utoipa::path(
+    get,
+    path = "/admin/waiters",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Parties waiting on this instance", body = [WaiterInfo]),
+        (status = 404, description = "No admin key is configured"),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn list_waiters(State(state): State<Arc<AppState>>) -> Json<Vec<WaiterInfo>> {
+    Json(state.active_waiters.list())
+}
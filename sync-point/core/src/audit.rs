@@ -0,0 +1,115 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, LineWriter, Write},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{lock::lock, response::Outcome, store::Peer};
+
+/// Path of the audit log standing for the standard output.
+const STDOUT: &str = "-";
+
+/// Entry of the audit log, one JSON object per line.
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    /// When the entry was recorded, in milliseconds since the Unix epoch.
+    at_ms: u64,
+    event: AuditEvent,
+    unique_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
+    /// The party the entry is about, its counterpart being the `peer` of a match.
+    party: &'a Peer,
+    #[serde(flatten)]
+    outcome: Option<&'a Outcome>,
+}
+
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum AuditEvent {
+    /// A party started waiting.
+    Started,
+    /// The wait of a party ended, with the outcome alongside.
+    Ended,
+}
+
+/// `AuditLog` appends every start and end of a rendezvous wait to a JSON lines file kept apart
+/// from the logs, for compliance retention.
+pub struct AuditLog(Mutex<Box<dyn Write + Send>>);
+
+impl AuditLog {
+    /// Opens the audit log at `path` for appending, creating it if needed, or the standard output
+    /// for `-`.
+    pub fn open(path: &str) -> io::Result<Self> {
+        if path == STDOUT {
+            return Ok(AuditLog::new(io::stdout()));
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog::new(file))
+    }
+
+    fn new(writer: impl Write + Send + 'static) -> Self {
+        // Flushed line by line, so that entries are never lost in a buffer nor split
+        AuditLog(Mutex::new(Box::new(LineWriter::new(writer))))
+    }
+
+    /// Records that `party` started waiting on `unique_id`.
+    pub fn started(&self, unique_id: &str, request_id: Option<&str>, party: &Peer) {
+        self.record(AuditEvent::Started, unique_id, request_id, party, None);
+    }
+
+    /// Records the `outcome` of the wait of `party` on `unique_id`.
+    pub fn ended(
+        &self,
+        unique_id: &str,
+        request_id: Option<&str>,
+        party: &Peer,
+        outcome: &Outcome,
+    ) {
+        self.record(
+            AuditEvent::Ended,
+            unique_id,
+            request_id,
+            party,
+            Some(outcome),
+        );
+    }
+
+    fn record(
+        &self,
+        event: AuditEvent,
+        unique_id: &str,
+        request_id: Option<&str>,
+        party: &Peer,
+        outcome: Option<&Outcome>,
+    ) {
+        let record = AuditRecord {
+            at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_millis() as u64)
+                .unwrap_or_default(),
+            event,
+            unique_id,
+            request_id,
+            party,
+            outcome,
+        };
+        let mut record = serde_json::to_value(&record).expect("audit records always serialize");
+        // Match tokens authenticate the parties, so they're kept out of the retained log
+        if let Some(record) = record.as_object_mut() {
+            record.remove("token");
+        }
+        let mut line = record.to_string().into_bytes();
+        line.push(b'\n');
+
+        // A failed write leaves at worst a partial line behind
+        if let Err(err) = lock(&self.0).write_all(&line) {
+            warn!(%err, unique_id, "Failed to write to the audit log");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::Response,
+};
+use subtle::{Choice, ConstantTimeEq};
+use tracing::warn;
+
+use crate::{
+    quotas::enforce_quotas,
+    response::{Outcome, ResponseFormat, UNAUTHORIZED_MESSAGE},
+    AppState,
+};
+
+static ADMIN_DISABLED_MESSAGE: &str = "The admin API is disabled\n";
+
+/// Rejects requests without one of the configured API keys, sent as a bearer token or in the
+/// `X-API-Key` header.
+///
+/// Every request is accepted when no key is configured, otherwise the waits are held to the quotas
+/// of their key.
+pub async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    format: ResponseFormat,
+    request: Request,
+    next: Next,
+) -> Response {
+    let api_keys = &state.settings().api_keys;
+    if api_keys.is_empty() {
+        return next.run(request).await;
+    }
+
+    match api_key(request.headers()) {
+        Some(token) if is_known(api_keys, token) => {
+            let token = token.to_owned();
+            enforce_quotas(&state, &token, format, request, next).await
+        }
+        _ => unauthorized(&request, format),
+    }
+}
+
+/// Rejects requests without one of the configured admin API keys, sent like the other API keys.
+///
+/// The admin routes are disabled when no admin key is configured.
+pub async fn require_admin_key(
+    State(state): State<Arc<AppState>>,
+    format: ResponseFormat,
+    request: Request,
+    next: Next,
+) -> Response {
+    let admin_api_keys = &state.settings().admin_api_keys;
+    if admin_api_keys.is_empty() {
+        return format.reply(
+            StatusCode::NOT_FOUND,
+            Outcome::error(ADMIN_DISABLED_MESSAGE),
+        );
+    }
+
+    match api_key(request.headers()) {
+        Some(token) if is_known(admin_api_keys, token) => next.run(request).await,
+        _ => unauthorized(&request, format),
+    }
+}
+
+fn unauthorized(request: &Request, format: ResponseFormat) -> Response {
+    warn!(uri = %request.uri(), "Rejected unauthenticated request");
+    let mut response = format.reply(
+        StatusCode::UNAUTHORIZED,
+        Outcome::error(UNAUTHORIZED_MESSAGE),
+    );
+    response
+        .headers_mut()
+        .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    response
+}
+
+fn api_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(authorization) = headers.get(AUTHORIZATION) {
+        return authorization.to_str().ok()?.strip_prefix("Bearer ");
+    }
+
+    headers.get("x-api-key")?.to_str().ok()
+}
+
+/// Compares `token` with every key in constant time, so that timings don't leak the keys.
+fn is_known(api_keys: &[String], token: &str) -> bool {
+    api_keys
+        .iter()
+        .fold(Choice::from(0), |known, key| {
+            known | key.as_bytes().ct_eq(token.as_bytes())
+        })
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_bearer_token_or_api_key_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(api_key(&headers), None);
+
+        headers.insert("x-api-key", HeaderValue::from_static("key"));
+        assert_eq!(api_key(&headers), Some("key"));
+
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer token"));
+        assert_eq!(api_key(&headers), Some("token"));
+
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_static("Basic dXNlcjpwYXNz"),
+        );
+        assert_eq!(api_key(&headers), None);
+    }
+
+    #[test]
+    fn matches_any_configured_key() {
+        let api_keys = ["first".to_owned(), "second".to_owned()];
+
+        assert!(is_known(&api_keys, "first"));
+        assert!(is_known(&api_keys, "second"));
+        assert!(!is_known(&api_keys, "secon"));
+        assert!(!is_known(&api_keys, ""));
+    }
+}
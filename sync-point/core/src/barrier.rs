@@ -0,0 +1,333 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::StatusCode,
+    response::Response,
+};
+use serde::Deserialize;
+use tokio::{
+    sync::{oneshot, watch},
+    time::{timeout_at, Instant},
+};
+use tracing::{field::Empty, info, info_span, warn, Instrument};
+
+use crate::{
+    admin::WaitKind,
+    id::ValidId,
+    parties::WaitQuery,
+    response::{Outcome, ResponseFormat, INVALID_PARTIES_MESSAGE, MISMATCHED_PARTIES_MESSAGE},
+    AppState, UniqueId,
+};
+
+/// `Barrier` tracks the parties that arrived for a given `UniqueId`.
+struct Barrier {
+    expected: usize,
+    /// Parties waiting for the barrier to be released, in their order of arrival.
+    parties: Vec<BarrierParty>,
+    /// Deadline registered by the first party, shared by every party of the barrier.
+    deadline: Instant,
+    released: watch::Sender<bool>,
+}
+
+impl Barrier {
+    fn new(expected: usize, deadline: Instant) -> Self {
+        Barrier {
+            expected,
+            parties: Vec::new(),
+            deadline,
+            released: watch::channel(false).0,
+        }
+    }
+}
+
+/// Party waiting at a barrier, which holds the receiving end of `present` while it waits.
+struct BarrierParty {
+    /// Identifies the party at its barrier, so that it only ever withdraws itself.
+    ticket: u64,
+    present: oneshot::Sender<()>,
+}
+
+impl BarrierParty {
+    /// A closed channel means the party went away (e.g. disconnected) without cleaning up.
+    fn is_gone(&self) -> bool {
+        self.present.is_closed()
+    }
+}
+
+/// `WaitingBarriers` holds the barriers that are still waiting for parties.
+#[derive(Default)]
+pub struct WaitingBarriers {
+    barriers: HashMap<UniqueId, Barrier>,
+    next_ticket: u64,
+}
+
+/// Party registered at a barrier until it's released, counted as present as long as this is held.
+struct Waiting {
+    released: watch::Receiver<bool>,
+    deadline: Instant,
+    ticket: u64,
+    _present: oneshot::Receiver<()>,
+}
+
+/// Outcome of a party arriving at a barrier.
+enum Arrival {
+    /// The barrier still misses parties, wait until it's released or its deadline passes.
+    Wait(Waiting),
+    /// The party was the last one expected, everyone is released.
+    Released,
+    /// The barrier is already waiting for a different number of parties.
+    Mismatched,
+}
+
+impl WaitingBarriers {
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.barriers.is_empty()
+    }
+
+    /// Number of parties currently waiting at any barrier.
+    pub fn waiting(&self) -> usize {
+        self.barriers
+            .values()
+            .map(|barrier| barrier.parties.len())
+            .sum()
+    }
+
+    /// Registers a party at the barrier of `unique_id`, which times out at `deadline` if the party
+    /// is the first one. Parties that went away don't count towards the parties expected.
+    fn arrive(&mut self, unique_id: &str, expected: usize, deadline: Instant) -> Arrival {
+        let barrier = self
+            .barriers
+            .entry(unique_id.to_owned())
+            .or_insert_with(|| Barrier::new(expected, deadline));
+        barrier.parties.retain(|party| !party.is_gone());
+        // A barrier whose parties all went away starts over with this one
+        if barrier.parties.is_empty() {
+            *barrier = Barrier::new(expected, deadline);
+        }
+
+        if barrier.expected != expected {
+            return Arrival::Mismatched;
+        }
+
+        if barrier.parties.len() + 1 < expected {
+            let ticket = self.next_ticket;
+            self.next_ticket += 1;
+            let (present, _present) = oneshot::channel();
+            barrier.parties.push(BarrierParty { ticket, present });
+            return Arrival::Wait(Waiting {
+                released: barrier.released.subscribe(),
+                deadline: barrier.deadline,
+                ticket,
+                _present,
+            });
+        }
+
+        if let Some(barrier) = self.barriers.remove(unique_id) {
+            barrier.released.send_replace(true);
+        }
+        Arrival::Released
+    }
+
+    /// Removes the parties that went away without withdrawing, and the barriers past the deadline
+    /// of all their parties by more than `grace`.
+    pub fn evict_stale(&mut self, grace: Duration) -> usize {
+        let now = Instant::now();
+        let mut evicted = 0;
+        self.barriers.retain(|_, barrier| {
+            let before = barrier.parties.len();
+            if barrier.deadline + grace <= now {
+                barrier.parties.clear();
+            } else {
+                barrier.parties.retain(|party| !party.is_gone());
+            }
+            evicted += before - barrier.parties.len();
+            !barrier.parties.is_empty()
+        });
+        evicted
+    }
+
+    /// Withdraws the party holding `ticket` that gave up waiting at the barrier of `unique_id`,
+    /// removing the barrier if it was the last one.
+    ///
+    /// The ticket keeps a party from withdrawing another one from a barrier created under the same
+    /// id after its own was evicted.
+    fn leave(&mut self, unique_id: &str, ticket: u64) {
+        if let Some(barrier) = self.barriers.get_mut(unique_id) {
+            barrier.parties.retain(|party| party.ticket != ticket);
+            if barrier.parties.is_empty() {
+                self.barriers.remove(unique_id);
+            }
+        }
+    }
+}
+
+/// Parameters of the barrier route besides the unique id.
+#[derive(Deserialize)]
+pub struct BarrierPath {
+    parties: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/wait-for-parties/{unique-id}/{parties}",
+    tag = "waits",
+    params(
+        ("unique-id" = String, Path, description = "Id shared by the parties"),
+        ("parties" = usize, Path, description = "Number of parties expected, at least 2"),
+        WaitQuery,
+    ),
+    responses(
+        (status = 200, description = "Every expected party arrived", body = Outcome),
+        (status = 400, description = "Less than 2 parties", body = Outcome),
+        (status = 408, description = "Some parties didn't arrive in time", body = Outcome),
+        (status = 409, description = "Parties wait on the id for a different number of parties", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn sync_barrier(
+    ValidId(unique_id): ValidId,
+    Path(BarrierPath { parties: expected }): Path<BarrierPath>,
+    Query(query): Query<WaitQuery>,
+    State(state): State<Arc<AppState>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    format: ResponseFormat,
+) -> Response {
+    if expected < 2 {
+        return format.reply(
+            StatusCode::BAD_REQUEST,
+            Outcome::error(INVALID_PARTIES_MESSAGE),
+        );
+    }
+
+    let wait_timeout = match state.wait_timeout(query.timeout_ms) {
+        Ok(wait_timeout) => wait_timeout,
+        Err(message) => return format.reply(StatusCode::BAD_REQUEST, Outcome::error(message)),
+    };
+
+    let client = client.map(|ConnectInfo(client)| client);
+    let span = info_span!(
+        "wait",
+        unique_id,
+        parties = expected,
+        outcome = Empty,
+        waited_ms = Empty
+    );
+    let (status, outcome) = wait_at_barrier(&state, &unique_id, expected, wait_timeout, client)
+        .instrument(span.clone())
+        .await;
+    state.record_outcome(&span, &unique_id, expected, &outcome);
+    format.reply(status, outcome)
+}
+
+/// Waits on the barrier of `unique_id` until `expected` parties arrived, it times out or the server
+/// shuts down.
+///
+/// The barrier times out `wait_timeout` after the first party arrived, whatever the timeouts of the
+/// parties after it.
+async fn wait_at_barrier(
+    state: &AppState,
+    unique_id: &str,
+    expected: usize,
+    wait_timeout: Duration,
+    client: Option<SocketAddr>,
+) -> (StatusCode, Outcome) {
+    let arrived_at = Instant::now();
+    if state.is_shutting_down() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Outcome::shutting_down(arrived_at.elapsed()),
+        );
+    }
+
+    let arrival =
+        state
+            .barriers
+            .write()
+            .await
+            .arrive(unique_id, expected, arrived_at + wait_timeout);
+
+    let Waiting {
+        mut released,
+        deadline,
+        ticket,
+        _present,
+    } = match arrival {
+        Arrival::Released => {
+            info!("Last party arrived, releasing barrier");
+            return (
+                StatusCode::OK,
+                Outcome::released(expected, arrived_at.elapsed()),
+            );
+        }
+        Arrival::Mismatched => {
+            warn!("Mismatched number of parties");
+            return (
+                StatusCode::CONFLICT,
+                Outcome::error(MISMATCHED_PARTIES_MESSAGE),
+            );
+        }
+        Arrival::Wait(waiting) => waiting,
+    };
+
+    let Some(_permit) = state.waiter_permit() else {
+        let mut barriers = state.barriers.write().await;
+        // The last party may have arrived right after us
+        if *released.borrow() {
+            return (
+                StatusCode::OK,
+                Outcome::released(expected, arrived_at.elapsed()),
+            );
+        }
+        barriers.leave(unique_id, ticket);
+        return state.overloaded();
+    };
+
+    info!("Waiting for other parties");
+    let _active = state
+        .active_waiters
+        .register(WaitKind::Barrier, unique_id, deadline, client);
+    let released_in_time = tokio::select! {
+        result = timeout_at(
+            deadline,
+            released.wait_for(|released| *released),
+        ) => matches!(result, Ok(Ok(_))),
+        _ = state.shutting_down() => false,
+    };
+
+    if released_in_time {
+        info!("Successfully synchronized parties");
+        return (
+            StatusCode::OK,
+            Outcome::released(expected, arrived_at.elapsed()),
+        );
+    }
+
+    let mut barriers = state.barriers.write().await;
+    // The last party may have arrived right as we timed out
+    if *released.borrow() {
+        info!("Successfully synchronized parties");
+        return (
+            StatusCode::OK,
+            Outcome::released(expected, arrived_at.elapsed()),
+        );
+    }
+
+    // The barrier wasn't released so it's still ours, we withdraw from it
+    barriers.leave(unique_id, ticket);
+
+    if state.is_shutting_down() {
+        info!("Released waiting party on shutdown");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Outcome::shutting_down(arrived_at.elapsed()),
+        );
+    }
+
+    warn!("Timeout waiting for other parties");
+    (
+        StatusCode::REQUEST_TIMEOUT,
+        Outcome::timeout(arrived_at.elapsed(), state.retry_after()),
+    )
+}
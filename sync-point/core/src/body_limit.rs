@@ -0,0 +1,56 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Request, State},
+    http::{header::CONTENT_LENGTH, StatusCode},
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    Router,
+};
+use http_body_util::Limited;
+use tracing::warn;
+
+use crate::{
+    response::{Outcome, ResponseFormat, BODY_TOO_LARGE_MESSAGE},
+    AppState,
+};
+
+/// Limits the size of the request bodies of `router` to the maximum of the current settings, so
+/// that a party can't push megabytes through a rendezvous.
+pub fn limit_bodies(router: Router<Arc<AppState>>, state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    router
+        .route_layer(from_fn_with_state(state.clone(), reject_large_bodies))
+        // The limit is set on the bodies themselves, the one of the extractors being fixed
+        .route_layer(DefaultBodyLimit::disable())
+}
+
+/// Rejects the requests declaring a body larger than the limit with `413 Payload Too Large`,
+/// before it's received.
+async fn reject_large_bodies(
+    State(state): State<Arc<AppState>>,
+    format: ResponseFormat,
+    request: Request,
+    next: Next,
+) -> Response {
+    let length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+
+    let max_body_bytes = state.settings().max_body_bytes;
+    match length {
+        Some(length) if length > max_body_bytes as u64 => {
+            warn!(length, "Request body is too large");
+            format.reply(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Outcome::error(BODY_TOO_LARGE_MESSAGE),
+            )
+        }
+        _ => {
+            // Bodies without a declared length are cut off when the routes read them
+            let request = request.map(|body| Body::new(Limited::new(body, max_body_bytes)));
+            next.run(request).await
+        }
+    }
+}
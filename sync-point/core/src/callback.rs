@@ -0,0 +1,89 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use reqwest::{redirect, Client, Url};
+use serde::Serialize;
+use tracing::{info, warn, Instrument};
+
+use crate::{
+    chaos::Chaos,
+    response::{Outcome, INVALID_CALLBACK_MESSAGE},
+    AppState, UniqueId,
+};
+
+/// How long the receiver of a callback has to answer.
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Client posting the outcomes to the callbacks, which doesn't follow redirects so that an allowed
+/// host can't send the outcomes on to any other.
+pub fn client() -> Client {
+    Client::builder()
+        .timeout(CALLBACK_TIMEOUT)
+        .redirect(redirect::Policy::none())
+        .build()
+        // Only fails if the TLS backend can't be initialized
+        .expect("building the callback client shouldn't fail")
+}
+
+/// Parses the callback of a wait, which must be an HTTP(S) URL on one of the configured hosts so
+/// that the server can't be used to reach arbitrary ones.
+pub fn parse(state: &AppState, callback: &str) -> Result<Url, &'static str> {
+    let allowed = |url: &Url| {
+        matches!(url.scheme(), "http" | "https")
+            && url.host_str().is_some_and(|host| {
+                state
+                    .settings()
+                    .callback_hosts
+                    .iter()
+                    .any(|allowed| allowed == host)
+            })
+    };
+
+    match Url::parse(callback) {
+        Ok(url) if allowed(&url) => Ok(url),
+        _ => {
+            warn!(callback, "Invalid callback");
+            Err(INVALID_CALLBACK_MESSAGE)
+        }
+    }
+}
+
+/// Outcome of a wait as posted to its callback, along with the id it waited on.
+#[derive(Serialize)]
+struct CallbackBody<'a> {
+    unique_id: &'a str,
+    #[serde(flatten)]
+    outcome: &'a Outcome,
+}
+
+/// Runs `wait` on `unique_id` in the background, then posts its outcome to `callback`.
+pub fn spawn_posting(
+    state: Arc<AppState>,
+    unique_id: UniqueId,
+    wait: impl Future<Output = Outcome> + Send + 'static,
+    callback: Url,
+) {
+    let wait = async move {
+        let outcome = wait.await;
+        if state.chaos.as_ref().is_some_and(Chaos::drops) {
+            info!("Dropping callback in chaos mode");
+            return;
+        }
+
+        let body = CallbackBody {
+            unique_id: &unique_id,
+            outcome: &outcome,
+        };
+        let posted = state
+            .callback_client
+            .post(callback)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        match posted {
+            Ok(_) => info!("Posted outcome to callback"),
+            Err(err) => warn!(%err, "Failed to post outcome to callback"),
+        }
+    };
+    tokio::spawn(wait.in_current_span());
+}
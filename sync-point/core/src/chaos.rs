@@ -0,0 +1,162 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{rejection::JsonRejection, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::stream;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    lock::lock,
+    response::{Outcome, ResponseFormat, CHAOS_ERROR_MESSAGE, INVALID_FAULTS_MESSAGE},
+    AppState,
+};
+
+/// Faults injected into the wait routes in chaos mode, so that the retries of client libraries
+/// can be tested against the real server.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Faults {
+    /// Latency added to each request, picked at random up to this many milliseconds.
+    pub max_latency_ms: u64,
+    /// Share of the requests failing with `500 Internal Server Error` before they're handled.
+    pub error_rate: f64,
+    /// Share of the outcomes dropped: the wait goes through, but its response is cut off and its
+    /// callback isn't posted.
+    pub drop_rate: f64,
+}
+
+impl Faults {
+    /// Checks that the rates are between 0 and 1.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let is_rate = |rate: f64| (0.0..=1.0).contains(&rate);
+        if is_rate(self.error_rate) && is_rate(self.drop_rate) {
+            Ok(())
+        } else {
+            Err(INVALID_FAULTS_MESSAGE)
+        }
+    }
+}
+
+/// `Chaos` holds the faults injected in chaos mode, which can be changed while the server runs.
+pub struct Chaos(Mutex<Faults>);
+
+impl Chaos {
+    pub fn new(faults: Faults) -> Self {
+        Chaos(Mutex::new(faults))
+    }
+
+    /// Whether to drop the next outcome.
+    pub fn drops(&self) -> bool {
+        let drop_rate = lock(&self.0).drop_rate;
+        rand::thread_rng().gen_bool(drop_rate)
+    }
+}
+
+/// Delays, fails or cuts off the responses of the wait routes at random in chaos mode.
+pub async fn inject_faults(
+    State(state): State<Arc<AppState>>,
+    format: ResponseFormat,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(chaos) = &state.chaos else {
+        return next.run(request).await;
+    };
+
+    let (latency, fails) = {
+        let faults = lock(&chaos.0);
+        let mut rng = rand::thread_rng();
+        (
+            Duration::from_millis(rng.gen_range(0..=faults.max_latency_ms)),
+            rng.gen_bool(faults.error_rate),
+        )
+    };
+    tokio::time::sleep(latency).await;
+    if fails {
+        info!("Failing request in chaos mode");
+        return format.reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Outcome::error(CHAOS_ERROR_MESSAGE),
+        );
+    }
+
+    let response = next.run(request).await;
+    if !chaos.drops() {
+        return response;
+    }
+
+    // The body fails once the headers are sent, which makes the server abort the connection
+    info!("Dropping outcome in chaos mode");
+    let (parts, _) = response.into_parts();
+    let cut_off =
+        stream::once(async { Err::<Bytes, _>(io::Error::other("outcome dropped in chaos mode")) });
+    Response::from_parts(parts, Body::from_stream(cut_off))
+}
+
+/// Reports the faults injected in chaos mode.
+#[utoipa::path(
+    get,
+    path = "/admin/chaos",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Faults injected into the wait routes", body = Faults),
+        (status = 404, description = "Chaos mode or the admin routes are disabled"),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn chaos_faults(State(state): State<Arc<AppState>>) -> Response {
+    match &state.chaos {
+        Some(chaos) => Json(lock(&chaos.0).clone()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Replaces the faults injected in chaos mode, applying to the next requests.
+#[utoipa::path(
+    put,
+    path = "/admin/chaos",
+    tag = "admin",
+    request_body = Faults,
+    responses(
+        (status = 200, description = "Faults now injected into the wait routes", body = Faults),
+        (status = 400, description = "Invalid faults", body = Outcome),
+        (status = 404, description = "Chaos mode or the admin routes are disabled"),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn set_chaos_faults(
+    State(state): State<Arc<AppState>>,
+    format: ResponseFormat,
+    faults: Result<Json<Faults>, JsonRejection>,
+) -> Response {
+    let Some(chaos) = &state.chaos else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let faults = match faults {
+        Ok(Json(faults)) if faults.validate().is_ok() => faults,
+        _ => {
+            return format.reply(
+                StatusCode::BAD_REQUEST,
+                Outcome::error(INVALID_FAULTS_MESSAGE),
+            )
+        }
+    };
+
+    warn!(?faults, "Changed the faults injected in chaos mode");
+    *lock(&chaos.0) = faults.clone();
+    Json(faults).into_response()
+}
@@ -0,0 +1,298 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::{self, Body},
+    extract::{RawPathParams, Request, State},
+    http::{
+        header::{CONNECTION, CONTENT_LENGTH, HOST, TRANSFER_ENCODING},
+        HeaderName, HeaderValue, StatusCode,
+    },
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    Router,
+};
+use futures_util::stream;
+use reqwest::{redirect, Client, Url};
+use tracing::{debug, warn};
+
+use crate::{
+    id::UNIQUE_ID_PARAM,
+    response::{Outcome, ResponseFormat, BODY_TOO_LARGE_MESSAGE, OWNER_UNREACHABLE_MESSAGE},
+    AppState,
+};
+
+/// Header marking the requests forwarded by another node, which are served wherever they land so
+/// that nodes disagreeing on the owner of an id can't bounce a request forever.
+const FORWARDED_BY: HeaderName = HeaderName::from_static("x-sync-point-forwarded-by");
+
+/// Points of each node on the hash ring, smoothing out the share of ids each one owns.
+const VIRTUAL_NODES: usize = 128;
+
+/// How long a node has to accept a forwarded connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `Cluster` assigns every id to one of the nodes of the cluster by consistent hashing, so that
+/// parties reaching different nodes still meet on the owner of their id, and adding or removing a
+/// node only moves the ids it owns.
+pub struct Cluster {
+    /// Base URL of this node.
+    own: String,
+    /// Base URLs of the nodes, this one included.
+    nodes: Vec<String>,
+    /// Hashes of the points on the ring along with the index of their node, sorted.
+    ring: Vec<(u64, usize)>,
+    client: Client,
+}
+
+impl Cluster {
+    /// Creates the cluster of `nodes` as seen from `own`, which must be one of them.
+    ///
+    /// Returns `None` without nodes, which disables cluster mode.
+    pub fn new(nodes: &[String], own: Option<&str>) -> Result<Option<Self>, String> {
+        if nodes.is_empty() {
+            return Ok(None);
+        }
+
+        let mut nodes = nodes
+            .iter()
+            .map(|node| base_url(node))
+            .collect::<Result<Vec<_>, _>>()?;
+        nodes.sort_unstable();
+        nodes.dedup();
+
+        let own = base_url(own.ok_or("the URL of this node is required in cluster mode")?)?;
+        if !nodes.contains(&own) {
+            return Err(format!("{own} isn't one of the nodes of the cluster"));
+        }
+
+        let mut ring: Vec<_> = nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(index, node)| {
+                (0..VIRTUAL_NODES).map(move |point| (hash(&format!("{node}#{point}")), index))
+            })
+            .collect();
+        ring.sort_unstable();
+
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .redirect(redirect::Policy::none())
+            .build()
+            // Only fails if the TLS backend can't be initialized
+            .expect("building the cluster client shouldn't fail");
+
+        Ok(Some(Cluster {
+            own,
+            nodes,
+            ring,
+            client,
+        }))
+    }
+
+    /// Base URL of the node owning `unique_id`, `None` when it's this one.
+    pub fn owner(&self, unique_id: &str) -> Option<&str> {
+        let node = self.node_of(unique_id);
+        (node != self.own).then_some(node)
+    }
+
+    fn node_of(&self, unique_id: &str) -> &str {
+        let hash = hash(unique_id);
+        // The ring wraps around, ids past the last point belonging to the first one
+        let point = self.ring.partition_point(|&(point, _)| point < hash) % self.ring.len();
+        &self.nodes[self.ring[point].1]
+    }
+}
+
+/// Base URL of a node without its trailing slash, so that nodes are spelled the same everywhere.
+fn base_url(node: &str) -> Result<String, String> {
+    match Url::parse(node) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {
+            Ok(node.trim_end_matches('/').to_owned())
+        }
+        _ => Err(format!("{node} isn't the HTTP(S) URL of a node")),
+    }
+}
+
+/// 64-bit FNV-1a followed by the finalizer of MurmurHash3, spreading similar strings (like the
+/// points of a node) over the ring. Unlike the hasher of the standard library, it's the same on
+/// every node whatever its build.
+fn hash(key: &str) -> u64 {
+    let mut hash = key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Forwards the requests of `router` on ids owned by another node of the cluster to it.
+pub fn forward_to_owners(
+    router: Router<Arc<AppState>>,
+    state: &Arc<AppState>,
+) -> Router<Arc<AppState>> {
+    router.route_layer(from_fn_with_state(state.clone(), forward_to_owner))
+}
+
+/// Relays the request to the node owning its id, streaming back its response, unless this node
+/// owns it or the request was already forwarded.
+///
+/// Requests with an invalid id are served here, to be rejected like any other.
+async fn forward_to_owner(
+    State(state): State<Arc<AppState>>,
+    params: Option<RawPathParams>,
+    format: ResponseFormat,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(cluster) = &state.cluster else {
+        return next.run(request).await;
+    };
+    if request.headers().contains_key(FORWARDED_BY) {
+        return next.run(request).await;
+    }
+
+    let unique_id = params.and_then(|params| {
+        params
+            .iter()
+            .find_map(|(name, value)| (name == UNIQUE_ID_PARAM).then_some(value))
+            .and_then(|unique_id| state.parse_unique_id(unique_id).ok())
+    });
+    let Some(owner) = unique_id
+        .as_deref()
+        .and_then(|unique_id| cluster.owner(unique_id))
+    else {
+        return next.run(request).await;
+    };
+
+    let (parts, request_body) = request.into_parts();
+    let Ok(request_body) = body::to_bytes(request_body, state.settings().max_body_bytes).await
+    else {
+        warn!("Request body is too large");
+        return format.reply(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Outcome::error(BODY_TOO_LARGE_MESSAGE),
+        );
+    };
+
+    let path = parts.uri.path_and_query().map_or("/", |path| path.as_str());
+    let mut headers = parts.headers;
+    for hop_by_hop in [HOST, CONNECTION, CONTENT_LENGTH, TRANSFER_ENCODING] {
+        headers.remove(hop_by_hop);
+    }
+    let own = HeaderValue::from_str(&cluster.own).expect("node URLs are valid header values");
+    headers.insert(FORWARDED_BY, own);
+
+    debug!(owner, path, "Forwarding the request to the owner of its id");
+    let forwarded = cluster
+        .client
+        .request(parts.method, format!("{owner}{path}"))
+        .headers(headers)
+        .body(request_body)
+        .send()
+        .await;
+    let forwarded = match forwarded {
+        Ok(forwarded) => forwarded,
+        Err(err) => {
+            warn!(owner, %err, "Failed to forward the request to the owner of its id");
+            return format.reply(
+                StatusCode::BAD_GATEWAY,
+                Outcome::error(OWNER_UNREACHABLE_MESSAGE),
+            );
+        }
+    };
+
+    let status = forwarded.status();
+    let mut headers = forwarded.headers().clone();
+    for hop_by_hop in [CONNECTION, TRANSFER_ENCODING] {
+        headers.remove(hop_by_hop);
+    }
+    // Streamed rather than buffered, so that keepalives and server-sent events flow through
+    let body = Body::from_stream(stream::try_unfold(forwarded, |mut forwarded| async move {
+        Ok::<_, reqwest::Error>(forwarded.chunk().await?.map(|chunk| (chunk, forwarded)))
+    }));
+
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(nodes: &[&str]) -> Vec<String> {
+        nodes.iter().map(|&node| node.to_owned()).collect()
+    }
+
+    #[test]
+    fn nodes_agree_on_the_owners() {
+        let a = Cluster::new(
+            &nodes(&["http://a", "http://b", "http://c"]),
+            Some("http://a"),
+        )
+        .unwrap()
+        .unwrap();
+        let c = Cluster::new(
+            &nodes(&["http://c/", "http://a", "http://b"]),
+            Some("http://c"),
+        )
+        .unwrap()
+        .unwrap();
+
+        for unique_id in (0..1000).map(|id| id.to_string()) {
+            assert_eq!(a.node_of(&unique_id), c.node_of(&unique_id));
+            assert_eq!(
+                a.owner(&unique_id).is_none(),
+                a.node_of(&unique_id) == "http://a"
+            );
+        }
+    }
+
+    #[test]
+    fn ids_spread_over_the_nodes() {
+        let cluster = Cluster::new(
+            &nodes(&["http://a", "http://b", "http://c"]),
+            Some("http://a"),
+        )
+        .unwrap()
+        .unwrap();
+
+        for node in ["http://a", "http://b", "http://c"] {
+            let owned = (0..10_000)
+                .filter(|id| cluster.node_of(&id.to_string()) == node)
+                .count();
+            assert!((2_000..4_700).contains(&owned), "{node} owns {owned} ids");
+        }
+    }
+
+    #[test]
+    fn removing_a_node_only_moves_its_ids() {
+        let three = Cluster::new(
+            &nodes(&["http://a", "http://b", "http://c"]),
+            Some("http://a"),
+        )
+        .unwrap()
+        .unwrap();
+        let two = Cluster::new(&nodes(&["http://a", "http://b"]), Some("http://a"))
+            .unwrap()
+            .unwrap();
+
+        for unique_id in (0..1000).map(|id| id.to_string()) {
+            let owner = three.node_of(&unique_id);
+            if owner != "http://c" {
+                assert_eq!(two.node_of(&unique_id), owner);
+            }
+        }
+    }
+
+    #[test]
+    fn cluster_is_validated() {
+        assert!(Cluster::new(&[], None).unwrap().is_none());
+        assert!(Cluster::new(&nodes(&["http://a"]), None).is_err());
+        assert!(Cluster::new(&nodes(&["http://a"]), Some("http://b")).is_err());
+        assert!(Cluster::new(&nodes(&["a:8080"]), Some("a:8080")).is_err());
+    }
+}
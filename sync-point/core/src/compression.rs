@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use axum::{
+    http::{Extensions, HeaderMap, StatusCode, Version},
+    Router,
+};
+use tower_http::compression::{
+    predicate::{DefaultPredicate, Predicate},
+    CompressionLayer,
+};
+
+use crate::AppState;
+
+/// Compresses the responses of `router` with gzip or brotli when the client accepts it, unless
+/// disabled in the current settings. Only meant for the routes listing entries, which can grow
+/// large in big deployments, the wait responses being too small to benefit.
+pub fn compress(router: Router<Arc<AppState>>, state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    let state = state.clone();
+    let enabled = move |_: StatusCode, _: Version, _: &HeaderMap, _: &Extensions| {
+        state.settings().compress_responses
+    };
+    router.layer(CompressionLayer::new().compress_when(DefaultPredicate::new().and(enabled)))
+}
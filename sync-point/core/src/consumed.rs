@@ -0,0 +1,36 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use tokio::time::Instant;
+
+use crate::{lock::lock, UniqueId};
+
+/// `ConsumedIds` remembers the ids whose pair matched until their grace window ends, so that a
+/// late third party is rejected in strict mode instead of starting a new rendezvous.
+#[derive(Default)]
+pub struct ConsumedIds(Mutex<HashMap<UniqueId, Instant>>);
+
+impl ConsumedIds {
+    /// Marks `unique_id` as consumed until `until`.
+    pub fn consume(&self, unique_id: &str, until: Instant) {
+        lock(&self.0).insert(unique_id.to_owned(), until);
+    }
+
+    /// Whether `unique_id` was consumed and its grace window isn't over yet.
+    pub fn is_consumed(&self, unique_id: &str) -> bool {
+        let mut consumed = lock(&self.0);
+        match consumed.get(unique_id) {
+            Some(until) if *until > Instant::now() => true,
+            Some(_) => {
+                consumed.remove(unique_id);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets the ids whose grace window is over.
+    pub fn evict_expired(&self) {
+        let now = Instant::now();
+        lock(&self.0).retain(|_, until| *until > now);
+    }
+}
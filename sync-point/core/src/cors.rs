@@ -0,0 +1,65 @@
+use std::str::FromStr;
+
+use axum::{
+    http::{header::RETRY_AFTER, HeaderName, HeaderValue, Method},
+    Router,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::warn;
+
+use crate::{
+    receipts::RECEIPT_HEADER,
+    response::{MATCH_TOKEN_HEADER, RELAY_URL_HEADER},
+    settings::Settings,
+    wait_any::MATCHED_ID_HEADER,
+};
+
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Answers the CORS preflight requests and decorates the responses for the configured origins, so
+/// that browsers can call the API from other origins. Routes are left untouched if no origin is
+/// allowed.
+pub fn allow_cors(router: Router, settings: &Settings) -> Router {
+    if settings.cors_allowed_origins.is_empty() {
+        return router;
+    }
+
+    let origins = if settings
+        .cors_allowed_origins
+        .iter()
+        .any(|origin| origin == "*")
+    {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(parse_all::<HeaderValue>(&settings.cors_allowed_origins))
+    };
+
+    router.layer(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(parse_all::<Method>(&settings.cors_allowed_methods))
+            .allow_headers(parse_all::<HeaderName>(&settings.cors_allowed_headers))
+            .expose_headers([
+                RETRY_AFTER,
+                REQUEST_ID_HEADER,
+                MATCH_TOKEN_HEADER,
+                RELAY_URL_HEADER,
+                HeaderName::from_static(RECEIPT_HEADER),
+                MATCHED_ID_HEADER,
+            ]),
+    )
+}
+
+/// Parses the configured values, skipping the invalid ones.
+fn parse_all<T: FromStr>(values: &[String]) -> Vec<T> {
+    values
+        .iter()
+        .filter_map(|value| {
+            let parsed = value.parse().ok();
+            if parsed.is_none() {
+                warn!(value, "Ignoring invalid CORS setting");
+            }
+            parsed
+        })
+        .collect()
+}
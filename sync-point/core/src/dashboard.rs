@@ -0,0 +1,13 @@
+use axum::response::Html;
+
+/// Page of the dashboard, which reads everything it shows from the admin routes.
+static DASHBOARD_PAGE: &str = include_str!("../assets/dashboard.html");
+
+/// Serves a dashboard showing the waiting parties, the statistics and the waits ending live, for
+/// operators to look at the instance without extra tooling.
+///
+/// The page itself holds no data: it asks for an admin API key, with which it calls
+/// `/admin/waiters`, `/stats` and `/admin/events`.
+pub async fn dashboard() -> Html<&'static str> {
+    Html(DASHBOARD_PAGE)
+}
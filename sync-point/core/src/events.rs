@@ -0,0 +1,106 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::State,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+};
+use futures_util::stream;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use utoipa::ToSchema;
+
+use crate::{response::Outcome, AppState, UniqueId};
+
+/// Events buffered for each subscriber, those lagging behind missing the oldest ones.
+const EVENTS_BUFFER: usize = 1024;
+
+/// End of a wait, as streamed to the admin dashboards.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WaitEvent {
+    pub unique_id: UniqueId,
+    /// Parties expected by the wait, 2 for a rendezvous.
+    pub parties: usize,
+    /// Status of the outcome of the wait, e.g. `matched`, `timeout` or `cancelled`.
+    pub status: &'static str,
+    /// How long the party waited, if it got to wait.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waited_ms: Option<u64>,
+    /// When the wait ended, in milliseconds since the Unix epoch.
+    pub ended_at_ms: u64,
+}
+
+/// `WaitEvents` broadcasts the end of every wait to the subscribers of the admin event stream.
+pub struct WaitEvents(broadcast::Sender<WaitEvent>);
+
+impl Default for WaitEvents {
+    fn default() -> Self {
+        WaitEvents(broadcast::channel(EVENTS_BUFFER).0)
+    }
+}
+
+impl WaitEvents {
+    /// Tells the subscribers about the `outcome` of a wait on `unique_id` between `parties`
+    /// parties.
+    pub fn publish(&self, unique_id: &str, parties: usize, outcome: &Outcome) {
+        if self.0.receiver_count() == 0 {
+            return;
+        }
+
+        let ended_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis() as u64)
+            .unwrap_or_default();
+        // Fails only when the last subscriber just left
+        let _ = self.0.send(WaitEvent {
+            unique_id: unique_id.to_owned(),
+            parties,
+            status: outcome.status(),
+            waited_ms: outcome.waited_ms(),
+            ended_at_ms,
+        });
+    }
+}
+
+/// Streams the end of every wait on the instance as server-sent events, until the server shuts
+/// down.
+///
+/// Each event is named after the outcome of the wait (e.g. `matched` or `timeout`) and carries
+/// the JSON wait event as data. Subscribers too slow to keep up get a `lagged` event with the
+/// number of events they missed.
+#[utoipa::path(
+    get,
+    path = "/admin/events",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Stream of wait events, named after their outcome", body = WaitEvent, content_type = "text/event-stream"),
+        (status = 404, description = "The admin routes are disabled"),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn stream_events(State(state): State<Arc<AppState>>) -> Response {
+    let subscriber = state.events.0.subscribe();
+    let events = stream::unfold((state, subscriber), |(state, mut subscriber)| async move {
+        let event = tokio::select! {
+            received = subscriber.recv() => match received {
+                Ok(wait_event) => Event::default().event(wait_event.status).json_data(&wait_event),
+                Err(RecvError::Lagged(missed)) => {
+                    Ok(Event::default().event("lagged").data(missed.to_string()))
+                }
+                Err(RecvError::Closed) => return None,
+            },
+            // Open streams would otherwise hold the shutdown back
+            _ = state.shutting_down() => return None,
+        };
+        Some((event, (state, subscriber)))
+    });
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
@@ -0,0 +1,383 @@
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc, time::Duration};
+
+use axum::{extract::ConnectInfo, Router};
+use futures_util::{stream, Stream};
+use tokio::time::Instant;
+use tonic::{server::NamedService, Request, Response, Status};
+use tracing::Instrument;
+
+use crate::{
+    keepalive::{keepalives, next_keepalive},
+    parties::rendezvous,
+    receipts::{self, RECEIPT_HEADER},
+    release::{open_gate, wait_for_gate},
+    response::{
+        self, Outcome, ALREADY_MATCHED_MESSAGE, INVALID_PARTY_LABEL_MESSAGE, NOT_WAITING_MESSAGE,
+    },
+    store::Peer,
+    trace::REQUEST_ID_HEADER,
+    AppState,
+};
+
+use self::proto::{
+    sync_point_server::{SyncPoint, SyncPointServer},
+    ReleaseRequest, ReleaseResponse, WaitRequest, WaitUpdate,
+};
+
+mod proto {
+    tonic::include_proto!("sync_point");
+}
+
+type UpdateStream = Pin<Box<dyn Stream<Item = Result<WaitUpdate, Status>> + Send>>;
+
+/// Routes the gRPC interface, served on the same port as the HTTP API.
+pub fn routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new().route_service(
+        &format!("/{}/*rpc", SyncPointServer::<GrpcSyncPoint>::NAME),
+        SyncPointServer::new(GrpcSyncPoint { state }),
+    )
+}
+
+/// gRPC interface to the waits, for services that can't long poll over HTTP.
+pub struct GrpcSyncPoint {
+    state: Arc<AppState>,
+}
+
+#[tonic::async_trait]
+impl SyncPoint for GrpcSyncPoint {
+    type WaitForSecondPartyStream = UpdateStream;
+    type WaitForReleaseStream = UpdateStream;
+
+    async fn wait_for_second_party(
+        &self,
+        request: Request<WaitRequest>,
+    ) -> Result<Response<UpdateStream>, Status> {
+        let request_id = request
+            .metadata()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|request_id| request_id.to_str().ok())
+            .map(str::to_owned);
+        let (unique_id, wait_timeout, peer) =
+            self.wait_params(request).map_err(invalid_argument)?;
+        let receipt = receipts::issue(&self.state.settings());
+        let state = self.state.clone();
+        let wait = {
+            let receipt = receipt.clone();
+            async move {
+                rendezvous(
+                    &state,
+                    &unique_id,
+                    wait_timeout,
+                    None,
+                    peer,
+                    request_id.as_deref(),
+                    receipt.as_deref(),
+                )
+                .await
+                .1
+            }
+        };
+
+        let mut response = Response::new(self.updates(wait));
+        // Sent with the first update, so that the party can recover the outcome
+        if let Some(Ok(receipt)) = receipt.map(|receipt| receipt.parse()) {
+            response.metadata_mut().insert(RECEIPT_HEADER, receipt);
+        }
+        Ok(response)
+    }
+
+    async fn wait_for_release(
+        &self,
+        request: Request<WaitRequest>,
+    ) -> Result<Response<UpdateStream>, Status> {
+        let (unique_id, wait_timeout, peer) =
+            self.wait_params(request).map_err(invalid_argument)?;
+        let state = self.state.clone();
+        let wait = async move {
+            wait_for_gate(&state, &unique_id, wait_timeout, peer.client)
+                .await
+                .1
+        };
+        Ok(Response::new(self.updates(wait)))
+    }
+
+    async fn release(
+        &self,
+        request: Request<ReleaseRequest>,
+    ) -> Result<Response<ReleaseResponse>, Status> {
+        let unique_id = self
+            .state
+            .parse_unique_id(&request.into_inner().unique_id)
+            .map_err(invalid_argument)?;
+
+        match open_gate(&self.state, &unique_id).await {
+            Some(parties) => Ok(Response::new(ReleaseResponse {
+                parties: parties as u64,
+            })),
+            None => Err(Status::not_found(NOT_WAITING_MESSAGE.trim_end())),
+        }
+    }
+}
+
+impl GrpcSyncPoint {
+    /// Validates a wait request like the HTTP routes do.
+    fn wait_params(
+        &self,
+        request: Request<WaitRequest>,
+    ) -> Result<(String, Duration, Peer), &'static str> {
+        let client = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(client)| *client);
+        let WaitRequest {
+            unique_id,
+            timeout_ms,
+            label,
+        } = request.into_inner();
+
+        let unique_id = self.state.parse_unique_id(&unique_id)?;
+        let wait_timeout = self.state.wait_timeout(timeout_ms)?;
+        if let Some(label) = &label {
+            if label.is_empty() || label.len() > self.state.settings().max_id_length {
+                return Err(INVALID_PARTY_LABEL_MESSAGE);
+            }
+        }
+
+        Ok((unique_id, wait_timeout, Peer::new(client, label.as_deref())))
+    }
+
+    /// Streams a `WAITING` update every keepalive interval until `wait` resolves, then its
+    /// outcome.
+    fn updates(&self, wait: impl Future<Output = Outcome> + Send + 'static) -> UpdateStream {
+        let arrived_at = Instant::now();
+        let keepalive_interval = self.state.settings().keepalive_interval;
+        let keepalives = keepalives(arrived_at, keepalive_interval);
+        // Dropping the stream when the client goes away also drops the wait, like a closed request
+        let wait = Box::pin(wait.in_current_span());
+
+        Box::pin(stream::unfold(
+            Some((wait, keepalives)),
+            move |pending| async move {
+                let (mut wait, mut keepalives) = pending?;
+
+                tokio::select! {
+                    outcome = &mut wait => Some((update(outcome), None)),
+                    () = next_keepalive(&mut keepalives) => Some((
+                        update(Outcome::waiting(arrived_at.elapsed())),
+                        Some((wait, keepalives)),
+                    )),
+                }
+            },
+        ))
+    }
+}
+
+fn invalid_argument(message: &str) -> Status {
+    Status::invalid_argument(message.trim_end())
+}
+
+/// Converts an outcome into its update, the store being unavailable failing the call instead.
+// The item type of the streams is imposed by tonic
+#[allow(clippy::result_large_err)]
+fn update(outcome: Outcome) -> Result<WaitUpdate, Status> {
+    use proto::{Role, Status as UpdateStatus};
+
+    let mut update = WaitUpdate::default();
+    match outcome {
+        Outcome::Matched {
+            role,
+            waited_ms,
+            peer,
+            token,
+            relay_url,
+        } => {
+            update.set_status(UpdateStatus::Matched);
+            update.set_role(match role {
+                response::Role::First => Role::First,
+                response::Role::Second => Role::Second,
+            });
+            update.waited_ms = waited_ms;
+            update.peer = Some(proto_peer(peer));
+            update.token = token;
+            update.relay_url = relay_url;
+        }
+        Outcome::Released { parties, waited_ms } => {
+            update.set_status(UpdateStatus::Released);
+            update.parties = parties as u64;
+            update.waited_ms = waited_ms;
+        }
+        Outcome::Waiting { waited_ms } => {
+            update.set_status(UpdateStatus::Waiting);
+            update.waited_ms = waited_ms;
+        }
+        Outcome::Timeout {
+            waited_ms,
+            retry_after_secs,
+        } => {
+            update.set_status(UpdateStatus::Timeout);
+            update.waited_ms = waited_ms;
+            update.retry_after_secs = retry_after_secs;
+        }
+        Outcome::Cancelled { waited_ms } => {
+            update.set_status(UpdateStatus::Cancelled);
+            update.waited_ms = waited_ms;
+        }
+        Outcome::ShuttingDown { waited_ms } => {
+            update.set_status(UpdateStatus::ShuttingDown);
+            update.waited_ms = waited_ms;
+        }
+        Outcome::Superseded { waited_ms } => {
+            update.set_status(UpdateStatus::Superseded);
+            update.waited_ms = waited_ms;
+        }
+        Outcome::Missed { peer, left_at_ms } => {
+            update.set_status(UpdateStatus::Missed);
+            update.peer = Some(proto_peer(peer));
+            update.left_at_ms = left_at_ms;
+        }
+        Outcome::Overloaded { retry_after_secs } => {
+            update.set_status(UpdateStatus::Overloaded);
+            update.retry_after_secs = retry_after_secs;
+        }
+        Outcome::Error { message } if message == ALREADY_MATCHED_MESSAGE => {
+            return Err(Status::already_exists(message.trim_end()))
+        }
+        Outcome::Error { message } => return Err(Status::unavailable(message.trim_end())),
+    }
+    Ok(update)
+}
+
+fn proto_peer(peer: Peer) -> proto::Peer {
+    proto::Peer {
+        client: peer.client.map(|client| client.to_string()),
+        arrived_at_ms: peer.arrived_at_ms,
+        label: peer.label,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use tonic::Code;
+
+    use super::{
+        proto::{Role, Status as UpdateStatus},
+        *,
+    };
+    use crate::{parties::LocalParties, settings::Settings};
+
+    fn make_service(settings: Settings) -> GrpcSyncPoint {
+        let state = AppState::new(settings, Box::new(LocalParties::default()));
+        GrpcSyncPoint {
+            state: Arc::new(state),
+        }
+    }
+
+    fn wait_request(unique_id: &str) -> Request<WaitRequest> {
+        Request::new(WaitRequest {
+            unique_id: unique_id.to_owned(),
+            timeout_ms: None,
+            label: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn waiting_party_gets_heartbeats_until_matched() {
+        let mut settings = Settings::new(Duration::from_millis(500));
+        settings.keepalive_interval = Duration::from_millis(50);
+        let service = make_service(settings);
+
+        let mut party1_updates = service
+            .wait_for_second_party(wait_request("1"))
+            .await
+            .unwrap()
+            .into_inner();
+        let update = party1_updates.next().await.unwrap().unwrap();
+        assert_eq!(update.status(), UpdateStatus::Waiting);
+
+        let mut request = wait_request("1");
+        request.get_mut().label = Some("party-2".to_owned());
+        let mut party2_updates = service
+            .wait_for_second_party(request)
+            .await
+            .unwrap()
+            .into_inner();
+        let update = party2_updates.next().await.unwrap().unwrap();
+        assert_eq!(update.status(), UpdateStatus::Matched);
+        assert_eq!(update.role(), Role::Second);
+        assert!(party2_updates.next().await.is_none());
+
+        let update = party1_updates.next().await.unwrap().unwrap();
+        assert_eq!(update.status(), UpdateStatus::Matched);
+        assert_eq!(update.role(), Role::First);
+        assert!(update.waited_ms >= 50);
+        assert_eq!(update.peer.unwrap().label.as_deref(), Some("party-2"));
+        assert!(party1_updates.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn zero_keepalive_interval_sends_only_the_outcome() {
+        let mut settings = Settings::new(Duration::from_millis(50));
+        settings.keepalive_interval = Duration::ZERO;
+        let service = make_service(settings);
+
+        let mut updates = service
+            .wait_for_second_party(wait_request("1"))
+            .await
+            .unwrap()
+            .into_inner();
+        let update = updates.next().await.unwrap().unwrap();
+        assert_eq!(update.status(), UpdateStatus::Timeout);
+        assert!(updates.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn release_wakes_waiting_party() {
+        let service = make_service(Settings::new(Duration::from_millis(500)));
+
+        let mut party_updates = service
+            .wait_for_release(wait_request("1"))
+            .await
+            .unwrap()
+            .into_inner();
+        let update = tokio::spawn(async move { party_updates.next().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let released = service
+            .release(Request::new(ReleaseRequest {
+                unique_id: "1".to_owned(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(released.into_inner().parties, 1);
+
+        let update = update.await.unwrap().unwrap().unwrap();
+        assert_eq!(update.status(), UpdateStatus::Released);
+        assert_eq!(update.parties, 1);
+
+        let err = service
+            .release(Request::new(ReleaseRequest {
+                unique_id: "1".to_owned(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_rejected() {
+        let service = make_service(Settings::new(Duration::from_millis(100)));
+
+        let err = service
+            .wait_for_second_party(wait_request(&"1".repeat(129)))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), Code::InvalidArgument);
+
+        let mut request = wait_request("1");
+        request.get_mut().timeout_ms = Some(0);
+        let err = service.wait_for_release(request).await.err().unwrap();
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::{response::SHUTTING_DOWN_MESSAGE, AppState};
+
+static HEALTHY_MESSAGE: &str = "OK\n";
+static READY_MESSAGE: &str = "Ready\n";
+static TOO_MANY_WAITERS_MESSAGE: &str = "Too many waiting parties\n";
+
+/// Liveness probe, answering as long as the server is able to handle requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    responses((status = 200, description = "The server is alive", body = String)),
+)]
+pub async fn healthz() -> &'static str {
+    HEALTHY_MESSAGE
+}
+
+/// Readiness probe, failing while shutting down or when too many parties are waiting.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "The server is ready for more parties", body = String),
+        (status = 503, description = "Shutting down or too many parties are waiting", body = String),
+    ),
+)]
+pub async fn readyz(State(state): State<Arc<AppState>>) -> Response {
+    if state.is_shutting_down() {
+        return (StatusCode::SERVICE_UNAVAILABLE, SHUTTING_DOWN_MESSAGE).into_response();
+    }
+
+    let waiters = state.parties.waiting().await
+        + state.barriers.read().await.waiting()
+        + state.gates.read().await.waiting();
+    if waiters > state.settings().max_waiters {
+        warn!(waiters, "Too many waiting parties to be ready");
+        return (StatusCode::SERVICE_UNAVAILABLE, TOO_MANY_WAITERS_MESSAGE).into_response();
+    }
+
+    READY_MESSAGE.into_response()
+}
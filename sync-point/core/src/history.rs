@@ -0,0 +1,280 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    any::{install_default_drivers, AnyPoolOptions},
+    AnyPool, Row,
+};
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    id::ValidId,
+    response::{Outcome, ResponseFormat},
+    AppState, UniqueId,
+};
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS rendezvous_history (
+    unique_id TEXT NOT NULL,
+    parties BIGINT NOT NULL,
+    outcome TEXT NOT NULL,
+    arrived_at_ms BIGINT NOT NULL,
+    finished_at_ms BIGINT NOT NULL
+)";
+const CREATE_INDEX: &str = "CREATE INDEX IF NOT EXISTS rendezvous_history_unique_id
+    ON rendezvous_history (unique_id)";
+
+const DEFAULT_LIMIT: u32 = 100;
+/// Most entries returned at once, so a query can't load the whole history of an id.
+const MAX_HISTORY_LIMIT: u32 = 1000;
+
+static HISTORY_DISABLED_MESSAGE: &str = "The rendezvous history is not recorded\n";
+static HISTORY_UNAVAILABLE_MESSAGE: &str = "The history store is unavailable, try again later\n";
+
+/// Final outcome of a party waiting on some `UniqueId`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct HistoryEntry {
+    pub unique_id: UniqueId,
+    /// Number of parties expected on the id, 2 for a rendezvous.
+    pub parties: i64,
+    /// Status of the outcome, as found in the JSON responses.
+    pub outcome: String,
+    /// When the party arrived, in milliseconds since the Unix epoch.
+    pub arrived_at_ms: i64,
+    /// When the party was released, in milliseconds since the Unix epoch.
+    pub finished_at_ms: i64,
+}
+
+impl HistoryEntry {
+    /// Builds the entry of a finished wait, or `None` if the outcome isn't the end of a wait.
+    fn new(unique_id: &str, parties: usize, outcome: &Outcome) -> Option<Self> {
+        let waited_ms = match *outcome {
+            Outcome::Matched { waited_ms, .. }
+            | Outcome::Released { waited_ms, .. }
+            | Outcome::Timeout { waited_ms, .. }
+            | Outcome::Cancelled { waited_ms }
+            | Outcome::ShuttingDown { waited_ms } => waited_ms as i64,
+            // A superseded wait goes on in its retry, which records the outcome, and a party that
+            // missed the other one never waited
+            Outcome::Waiting { .. }
+            | Outcome::Superseded { .. }
+            | Outcome::Missed { .. }
+            | Outcome::Overloaded { .. }
+            | Outcome::Error { .. } => return None,
+        };
+        let finished_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis() as i64)
+            .unwrap_or_default();
+
+        Some(HistoryEntry {
+            unique_id: unique_id.to_owned(),
+            parties: parties as i64,
+            outcome: outcome.status().to_owned(),
+            arrived_at_ms: finished_at_ms - waited_ms,
+            finished_at_ms,
+        })
+    }
+}
+
+/// `History` persists the outcome of every wait in a SQLite or Postgres database.
+#[derive(Clone)]
+pub struct History {
+    pool: AnyPool,
+}
+
+impl History {
+    /// Connects to the database at `url` (e.g. `sqlite://history.db?mode=rwc` or
+    /// `postgres://localhost/sync_point`), creating the history table if needed.
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        install_default_drivers();
+        let pool = AnyPoolOptions::new().connect(url).await?;
+
+        sqlx::query(CREATE_TABLE).execute(&pool).await?;
+        sqlx::query(CREATE_INDEX).execute(&pool).await?;
+
+        Ok(History { pool })
+    }
+
+    /// Records the outcome in the background, so that responses aren't delayed by the database.
+    pub fn record(&self, unique_id: &str, parties: usize, outcome: &Outcome) {
+        let Some(entry) = HistoryEntry::new(unique_id, parties, outcome) else {
+            return;
+        };
+
+        let history = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = history.insert(&entry).await {
+                warn!(%err, unique_id = entry.unique_id, "Failed to record history");
+            }
+        });
+    }
+
+    async fn insert(&self, entry: &HistoryEntry) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO rendezvous_history
+                (unique_id, parties, outcome, arrived_at_ms, finished_at_ms)
+            VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&entry.unique_id)
+        .bind(entry.parties)
+        .bind(&entry.outcome)
+        .bind(entry.arrived_at_ms)
+        .bind(entry.finished_at_ms)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the latest entries recorded for `unique_id`, most recent first.
+    pub async fn entries(
+        &self,
+        unique_id: &str,
+        limit: i64,
+    ) -> Result<Vec<HistoryEntry>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT unique_id, parties, outcome, arrived_at_ms, finished_at_ms
+            FROM rendezvous_history
+            WHERE unique_id = $1
+            ORDER BY finished_at_ms DESC
+            LIMIT $2",
+        )
+        .bind(unique_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(HistoryEntry {
+                    unique_id: row.try_get("unique_id")?,
+                    parties: row.try_get("parties")?,
+                    outcome: row.try_get("outcome")?,
+                    arrived_at_ms: row.try_get("arrived_at_ms")?,
+                    finished_at_ms: row.try_get("finished_at_ms")?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// Maximum number of entries to return, at most 1000 [default: 100]
+    limit: Option<u32>,
+}
+
+impl HistoryQuery {
+    /// Returns the number of entries to return, within `1..=MAX_HISTORY_LIMIT`.
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_LIMIT)
+            .clamp(1, MAX_HISTORY_LIMIT)
+            .into()
+    }
+}
+
+/// Lists the recorded outcomes of the waits on the id, most recent first.
+#[utoipa::path(
+    get,
+    path = "/history/{unique-id}",
+    tag = "waits",
+    params(("unique-id" = String, Path, description = "Id of the rendezvous"), HistoryQuery),
+    responses(
+        (status = 200, description = "Recorded outcomes, most recent first", body = [HistoryEntry]),
+        (status = 404, description = "The history isn't recorded", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn history(
+    ValidId(unique_id): ValidId,
+    Query(query): Query<HistoryQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    let format = ResponseFormat::Json;
+    let Some(history) = &state.history else {
+        return format.reply(
+            StatusCode::NOT_FOUND,
+            Outcome::error(HISTORY_DISABLED_MESSAGE),
+        );
+    };
+
+    match history.entries(&unique_id, query.limit()).await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(err) => {
+            warn!(%err, "History store is unavailable");
+            format.reply(
+                StatusCode::SERVICE_UNAVAILABLE,
+                Outcome::error(HISTORY_UNAVAILABLE_MESSAGE),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{response::Role, store::Peer};
+
+    #[tokio::test]
+    async fn records_and_lists_outcomes() {
+        let path =
+            std::env::temp_dir().join(format!("sync-point-history-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let history = History::connect(&format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+
+        let outcomes = [
+            Outcome::timeout(Duration::from_millis(100), Duration::from_secs(1)),
+            Outcome::matched(
+                Role::First,
+                Duration::from_millis(50),
+                Peer::new(None, None),
+                "token".to_owned(),
+                None,
+            ),
+            Outcome::error("ignored"),
+        ];
+        for outcome in &outcomes {
+            if let Some(entry) = HistoryEntry::new("1", 2, outcome) {
+                history.insert(&entry).await.unwrap();
+            }
+        }
+        let other = HistoryEntry::new("2", 3, &Outcome::released(3, Duration::ZERO)).unwrap();
+        history.insert(&other).await.unwrap();
+
+        let entries = history.entries("1", 10).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.unique_id == "1"));
+        assert!(entries
+            .iter()
+            .any(|entry| entry.outcome == "timeout"
+                && entry.finished_at_ms - entry.arrived_at_ms == 100));
+        assert_eq!(history.entries("1", 1).await.unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn clamps_the_limit() {
+        let limit = |limit| HistoryQuery { limit }.limit();
+        assert_eq!(limit(None), 100);
+        assert_eq!(limit(Some(0)), 1);
+        assert_eq!(limit(Some(10)), 10);
+        assert_eq!(limit(Some(u32::MAX)), 1000);
+    }
+}
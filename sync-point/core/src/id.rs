@@ -0,0 +1,173 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, RawPathParams},
+    http::{request::Parts, StatusCode},
+    response::Response,
+};
+use tracing::warn;
+
+use crate::{
+    response::{Outcome, ResponseFormat, ID_TOO_LONG_MESSAGE, INVALID_ID_MESSAGE},
+    AppState, UniqueId,
+};
+
+/// Name of the path parameter holding the unique id in the routes.
+pub const UNIQUE_ID_PARAM: &str = "unique-id";
+
+/// Unique id taken from the path of the request, validated and in its canonical form.
+///
+/// Requests with a malformed id are rejected with a `400 Bad Request` in the format the client
+/// accepts.
+pub struct ValidId(pub UniqueId);
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ValidId {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let format = ResponseFormat::from_headers(&parts.headers);
+        let reject = |message| format.reply(StatusCode::BAD_REQUEST, Outcome::error(message));
+
+        // Ids that aren't valid UTF-8 once decoded are rejected like any other invalid character
+        let params = RawPathParams::from_request_parts(parts, state)
+            .await
+            .map_err(|_| reject(INVALID_ID_MESSAGE))?;
+        let unique_id = params
+            .iter()
+            .find_map(|(name, value)| (name == UNIQUE_ID_PARAM).then_some(value))
+            .expect("routes extracting an id have a unique-id parameter");
+
+        state
+            .parse_unique_id(unique_id)
+            .map(ValidId)
+            .map_err(reject)
+    }
+}
+
+/// Validates a unique id against `max_length` and the allowed characters, returning its
+/// canonical form.
+///
+/// Ids are made of ASCII letters, digits, `-`, `_`, `.` and `:`. Numeric ids lose their leading
+/// zeros and UUIDs, including the simple ones made of digits only, are lowercased and hyphenated,
+/// so that parties spelling the same id differently still meet.
+pub fn parse(unique_id: &str, max_length: usize) -> Result<UniqueId, &'static str> {
+    if unique_id.len() > max_length {
+        warn!(unique_id, "Unique id is too long");
+        return Err(ID_TOO_LONG_MESSAGE);
+    }
+
+    if unique_id.is_empty() || !unique_id.bytes().all(is_id_byte) {
+        warn!(unique_id, "Unique id is invalid");
+        return Err(INVALID_ID_MESSAGE);
+    }
+
+    // A simple UUID may be all digits, and must still meet its hyphenated form
+    if let Some(uuid) = canonical_uuid(unique_id) {
+        return Ok(uuid);
+    }
+
+    if unique_id.bytes().all(|byte| byte.is_ascii_digit()) {
+        let trimmed = unique_id.trim_start_matches('0');
+        return Ok(if trimmed.is_empty() { "0" } else { trimmed }.to_owned());
+    }
+
+    Ok(unique_id.to_owned())
+}
+
+fn is_id_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b':')
+}
+
+/// Lowercase hyphenated form of a UUID, accepting the hyphenated and simple forms in any case.
+fn canonical_uuid(unique_id: &str) -> Option<UniqueId> {
+    const GROUPS: [usize; 5] = [8, 4, 4, 4, 12];
+
+    let digits = match unique_id.len() {
+        32 => unique_id.to_owned(),
+        36 => {
+            let groups: Vec<_> = unique_id.split('-').collect();
+            let grouped = groups.len() == GROUPS.len()
+                && groups
+                    .iter()
+                    .zip(GROUPS)
+                    .all(|(group, len)| group.len() == len);
+            if !grouped {
+                return None;
+            }
+            groups.concat()
+        }
+        _ => return None,
+    };
+    if !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let digits = digits.to_ascii_lowercase();
+    let mut uuid = String::with_capacity(36);
+    let mut start = 0;
+    for len in GROUPS {
+        if start != 0 {
+            uuid.push('-');
+        }
+        uuid.push_str(&digits[start..start + len]);
+        start += len;
+    }
+    Some(uuid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_validated() {
+        assert_eq!(
+            parse("session-1.a_b:c", 128).as_deref(),
+            Ok("session-1.a_b:c")
+        );
+        assert_eq!(parse("123456789", 8), Err(ID_TOO_LONG_MESSAGE));
+        assert_eq!(parse("", 8), Err(INVALID_ID_MESSAGE));
+        assert_eq!(parse("a b", 8), Err(INVALID_ID_MESSAGE));
+        assert_eq!(parse("a/b", 8), Err(INVALID_ID_MESSAGE));
+        assert_eq!(parse("café", 8), Err(INVALID_ID_MESSAGE));
+    }
+
+    #[test]
+    fn numeric_ids_are_canonical() {
+        assert_eq!(parse("42", 128).as_deref(), Ok("42"));
+        assert_eq!(parse("0042", 128).as_deref(), Ok("42"));
+        assert_eq!(parse("000", 128).as_deref(), Ok("0"));
+    }
+
+    #[test]
+    fn uuids_are_canonical() {
+        let uuid = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        assert_eq!(parse(uuid, 128).as_deref(), Ok(uuid));
+        assert_eq!(
+            parse("67E55044-10B1-426F-9247-BB680E5FE0C8", 128).as_deref(),
+            Ok(uuid)
+        );
+        assert_eq!(
+            parse("67e5504410b1426f9247bb680e5fe0c8", 128).as_deref(),
+            Ok(uuid)
+        );
+        assert_eq!(
+            parse("00000000000000000000000000000001", 128).as_deref(),
+            parse("00000000-0000-0000-0000-000000000001", 128).as_deref(),
+        );
+        // Not UUIDs, kept as they are
+        assert_eq!(
+            parse("67e5504-410b1-426f-9247-bb680e5fe0c8", 128).as_deref(),
+            Ok("67e5504-410b1-426f-9247-bb680e5fe0c8")
+        );
+        assert_eq!(
+            parse("g7e5504410b1426f9247bb680e5fe0c8", 128).as_deref(),
+            Ok("g7e5504410b1426f9247bb680e5fe0c8")
+        );
+    }
+}
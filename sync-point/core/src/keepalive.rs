@@ -0,0 +1,91 @@
+use std::{
+    convert::Infallible,
+    future::{self, Future},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header::CONTENT_TYPE, HeaderName, HeaderValue},
+    response::Response,
+};
+use futures_util::stream;
+use tokio::time::{interval_at, Instant, Interval};
+use tracing::Instrument;
+
+use crate::response::ResponseFormat;
+
+/// Sent every keepalive interval, clients ignoring the whitespace before the outcome.
+const HEARTBEAT: &[u8] = b"\n";
+
+/// Asks nginx not to buffer the response, which would hold the heartbeats back.
+const X_ACCEL_BUFFERING: HeaderName = HeaderName::from_static("x-accel-buffering");
+
+/// Answers `200 OK` right away, then sends a heartbeat every `keepalive_interval` until `wait`
+/// resolves to the body of its outcome, which ends the response.
+///
+/// Proxies closing idle connections never see this one silent, at the cost of the status code,
+/// which can't reflect the outcome anymore.
+pub fn reply_with_heartbeats(
+    wait: impl Future<Output = Bytes> + Send + 'static,
+    keepalive_interval: Duration,
+    format: ResponseFormat,
+) -> Response {
+    let keepalives = keepalives(Instant::now(), keepalive_interval);
+    // Dropping the body when the client goes away also drops the wait, like a closed request
+    let wait = Box::pin(wait.in_current_span());
+
+    let chunks = stream::unfold(Some((wait, keepalives)), move |pending| async move {
+        let (mut wait, mut keepalives) = pending?;
+
+        tokio::select! {
+            body = &mut wait => Some((Ok::<_, Infallible>(body), None)),
+            () = next_keepalive(&mut keepalives) => Some((
+                Ok(Bytes::from_static(HEARTBEAT)),
+                Some((wait, keepalives)),
+            )),
+        }
+    });
+
+    let mut response = Response::new(Body::from_stream(chunks));
+    let headers = response.headers_mut();
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    headers.insert(X_ACCEL_BUFFERING, HeaderValue::from_static("no"));
+    response
+}
+
+/// Ticks every `keepalive_interval` after `start`, or never when the interval is zero, which
+/// `interval_at` would panic on.
+pub fn keepalives(start: Instant, keepalive_interval: Duration) -> Option<Interval> {
+    (!keepalive_interval.is_zero())
+        .then(|| interval_at(start + keepalive_interval, keepalive_interval))
+}
+
+/// Resolves on the next tick of `keepalives`, if any.
+pub async fn next_keepalive(keepalives: &mut Option<Interval>) {
+    match keepalives {
+        Some(keepalives) => {
+            keepalives.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn zero_interval_sends_no_heartbeats() {
+        let wait = async { Bytes::from_static(b"matched") };
+        let response = reply_with_heartbeats(wait, Duration::ZERO, ResponseFormat::Text);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"matched");
+    }
+}
@@ -0,0 +1,2988 @@
+//! Rendezvous routes of sync-point, served by its binary or mounted inside another axum
+//! application.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use axum::{routing::get, Router};
+//! use sync_point_core::{make_router, sweep_stale_entries, AppState, LocalParties, Settings};
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let state = AppState::new(
+//!     Settings::new(Duration::from_secs(10)),
+//!     Box::new(LocalParties::default()),
+//! );
+//! let (rendezvous, state) = make_router(state);
+//! tokio::spawn(sweep_stale_entries(state));
+//! let app = Router::new()
+//!     .route("/", get(|| async { "Hello" }))
+//!     .nest("/sync", rendezvous);
+//!
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
+//! axum::serve(listener, app).await
+//! # }
+//! ```
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    http::StatusCode,
+    middleware::from_fn_with_state,
+    routing::{get, post},
+    Router,
+};
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio::sync::{watch, RwLock};
+use tracing::{error, info, warn, Span};
+
+#[cfg(feature = "history")]
+pub use crate::history::History;
+#[cfg(feature = "redis")]
+pub use crate::store::redis::RedisParties;
+use crate::{
+    access_log::log_access,
+    admin::{list_waiters, ActiveWaiters},
+    auth::{require_admin_key, require_api_key},
+    barrier::{sync_barrier, WaitingBarriers},
+    body_limit::limit_bodies,
+    chaos::{chaos_faults, inject_faults, set_chaos_faults, Chaos},
+    cluster::forward_to_owners,
+    compression::compress,
+    consumed::ConsumedIds,
+    cors::allow_cors,
+    dashboard::dashboard,
+    events::{stream_events, WaitEvents},
+    health::{healthz, readyz},
+    metrics::render_metrics,
+    missed::MissedParties,
+    namespaces::{namespace_of, sync_namespaced, NamespaceWaiters},
+    parties::{cancel_party, party_status, sync_parties},
+    permits::{WaiterPermit, WaiterPermits},
+    quotas::KeyQuotas,
+    rate_limit::{limit_rate, ClientRateLimiter},
+    receipts::{receipt, Receipts},
+    release::{release, wait_for_release, WaitingGates},
+    response::{Outcome, INVALID_TIMEOUT_MESSAGE},
+    rounds::sync_round,
+    sse::sse_wait,
+    stats::{render_stats, Stats},
+    trace::trace_requests,
+    wait_any::wait_any,
+    ws::ws_wait,
+};
+pub use crate::{
+    access_log::{AccessLog, AccessLogFormat},
+    audit::AuditLog,
+    chaos::Faults,
+    cluster::Cluster,
+    parties::LocalParties,
+    response::MessageTemplates,
+    settings::Settings,
+    store::{Arrival, PartyStore, Peer, Waiter, Wake},
+    sweeper::sweep_stale_entries,
+};
+
+mod access_log;
+mod admin;
+mod audit;
+mod auth;
+mod barrier;
+mod body_limit;
+mod callback;
+mod chaos;
+mod cluster;
+mod compression;
+mod consumed;
+mod cors;
+mod dashboard;
+mod events;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod health;
+#[cfg(feature = "history")]
+mod history;
+mod id;
+mod keepalive;
+mod lock;
+mod metrics;
+mod missed;
+mod namespaces;
+mod openapi;
+mod parties;
+mod permits;
+mod quotas;
+mod rate_limit;
+mod receipts;
+mod relay;
+mod release;
+mod response;
+mod rounds;
+pub mod settings;
+mod sse;
+mod stats;
+mod store;
+mod sweeper;
+mod trace;
+mod wait_any;
+mod ws;
+
+type UniqueId = String;
+
+/// Bounds of the delay clients are told to wait before retrying, growing with the load.
+const MIN_RETRY_AFTER: Duration = Duration::from_secs(1);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(10);
+
+/// State shared by the request handlers.
+pub struct AppState {
+    /// Settings of the next requests, which can be reloaded while the server runs.
+    settings: watch::Sender<Arc<Settings>>,
+    parties: Box<dyn PartyStore>,
+    /// Rate limiter of the current settings, rebuilt when they change its limits.
+    rate_limiter: watch::Sender<Option<Arc<ClientRateLimiter>>>,
+    barriers: RwLock<WaitingBarriers>,
+    gates: RwLock<WaitingGates>,
+    active_waiters: ActiveWaiters,
+    namespace_waiters: NamespaceWaiters,
+    key_quotas: KeyQuotas,
+    consumed: ConsumedIds,
+    missed: MissedParties,
+    receipts: Receipts,
+    stats: Stats,
+    events: WaitEvents,
+    /// Faults injected into the wait routes, in chaos mode.
+    chaos: Option<Chaos>,
+    /// One permit per party waiting, up to `max_concurrent_waiters`.
+    waiter_permits: WaiterPermits,
+    metrics: PrometheusHandle,
+    callback_client: reqwest::Client,
+    audit: Option<AuditLog>,
+    access_log: Option<AccessLog>,
+    /// Nodes of the cluster, in cluster mode.
+    cluster: Option<Cluster>,
+    shutdown: watch::Sender<bool>,
+    #[cfg(feature = "history")]
+    history: Option<History>,
+}
+
+impl AppState {
+    pub fn new(settings: Settings, parties: Box<dyn PartyStore>) -> Self {
+        AppState {
+            rate_limiter: watch::channel(rate_limiter(&settings)).0,
+            waiter_permits: Default::default(),
+            chaos: settings.chaos.clone().map(Chaos::new),
+            settings: watch::channel(Arc::new(settings)).0,
+            parties,
+            barriers: Default::default(),
+            gates: Default::default(),
+            active_waiters: Default::default(),
+            namespace_waiters: Default::default(),
+            key_quotas: Default::default(),
+            consumed: Default::default(),
+            missed: Default::default(),
+            receipts: Default::default(),
+            stats: Default::default(),
+            events: Default::default(),
+            metrics: metrics::install(),
+            callback_client: callback::client(),
+            audit: None,
+            access_log: None,
+            cluster: None,
+            shutdown: watch::channel(false).0,
+            #[cfg(feature = "history")]
+            history: None,
+        }
+    }
+
+    pub fn with_audit(self, audit: Option<AuditLog>) -> Self {
+        AppState { audit, ..self }
+    }
+
+    pub fn with_access_log(self, access_log: Option<AccessLog>) -> Self {
+        AppState { access_log, ..self }
+    }
+
+    pub fn with_cluster(self, cluster: Option<Cluster>) -> Self {
+        AppState { cluster, ..self }
+    }
+
+    #[cfg(feature = "history")]
+    pub fn with_history(self, history: Option<History>) -> Self {
+        AppState { history, ..self }
+    }
+
+    /// Settings of the requests handled from now on.
+    pub fn settings(&self) -> Arc<Settings> {
+        self.settings.borrow().clone()
+    }
+
+    /// Replaces the settings of the next requests, leaving the waits going on as they are.
+    ///
+    /// The CORS of the routes and the faults of chaos mode are set when the state is created, so
+    /// changes to them are rejected, their current settings staying. The budgets of the clients
+    /// start over when the rate limits change. Invalid settings are rejected, the current ones
+    /// staying.
+    pub fn reload_settings(&self, mut settings: Settings) {
+        if let Err(err) = settings.validate() {
+            error!(
+                err = err.trim_end(),
+                "Invalid settings, keeping the current ones"
+            );
+            return;
+        }
+        let previous = self.settings();
+        let cors = |settings: &Settings| {
+            (
+                settings.cors_allowed_origins.clone(),
+                settings.cors_allowed_methods.clone(),
+                settings.cors_allowed_headers.clone(),
+            )
+        };
+        if cors(&settings) != cors(&previous) {
+            warn!("CORS settings only apply on restart, keeping the current ones");
+            (
+                settings.cors_allowed_origins,
+                settings.cors_allowed_methods,
+                settings.cors_allowed_headers,
+            ) = cors(&previous);
+        }
+        if settings.chaos != previous.chaos {
+            warn!("Chaos mode is only set on restart or through its admin route, keeping it as is");
+            settings.chaos.clone_from(&previous.chaos);
+        }
+        if (settings.rate_limit_per_second, settings.rate_limit_burst)
+            != (previous.rate_limit_per_second, previous.rate_limit_burst)
+        {
+            self.rate_limiter.send_replace(rate_limiter(&settings));
+        }
+        self.settings.send_replace(Arc::new(settings));
+        info!("Reloaded the settings");
+    }
+
+    /// Rate limiter of the clients, if rate limiting is enabled.
+    fn rate_limiter(&self) -> Option<Arc<ClientRateLimiter>> {
+        self.rate_limiter.borrow().clone()
+    }
+
+    /// Counts a party as waiting until the returned permit is dropped, unless
+    /// `max_concurrent_waiters` parties already are.
+    fn waiter_permit(&self) -> Option<WaiterPermit<'_>> {
+        self.waiter_permits
+            .try_acquire(self.settings().max_concurrent_waiters)
+    }
+
+    /// Records the final outcome of a wait on `unique_id` between `parties` parties, on the `span`
+    /// of the wait too.
+    fn record_outcome(&self, span: &Span, unique_id: &str, parties: usize, outcome: &Outcome) {
+        span.record("outcome", outcome.status());
+        span.record("waited_ms", outcome.waited_ms());
+        info!(
+            unique_id,
+            parties,
+            outcome = outcome.status(),
+            waited_ms = outcome.waited_ms(),
+            "Wait ended"
+        );
+        metrics::record(outcome, namespace_of(unique_id));
+        self.stats.record(unique_id, outcome);
+        self.events.publish(unique_id, parties, outcome);
+
+        #[cfg(feature = "history")]
+        if let Some(history) = &self.history {
+            history.record(unique_id, parties, outcome);
+        }
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Marks the service as shutting down, waking up every waiting party.
+    pub fn begin_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Resolves once the service started shutting down.
+    async fn shutting_down(&self) {
+        // The sender lives as long as the state, so waiting can't fail
+        let _ = self
+            .shutdown
+            .subscribe()
+            .wait_for(|shutting_down| *shutting_down)
+            .await;
+    }
+
+    /// Validates a unique id received from a client, returning its canonical form.
+    fn parse_unique_id(&self, unique_id: &str) -> Result<UniqueId, &'static str> {
+        id::parse(unique_id, self.settings().max_id_length)
+    }
+
+    /// Response to a party that can't wait because too many already are.
+    fn overloaded(&self) -> (StatusCode, Outcome) {
+        warn!("Too many waiting parties");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Outcome::overloaded(self.retry_after()),
+        )
+    }
+
+    /// Delay clients are told to wait before retrying, growing with the share of the waiting
+    /// capacity in use so that retries spread out as the server fills up.
+    fn retry_after(&self) -> Duration {
+        let capacity = self.settings().max_concurrent_waiters.max(1);
+        // Parties waiting from before a reload may outnumber the capacity
+        let waiting = self.waiter_permits.in_use().min(capacity);
+        let load = waiting as f64 / capacity as f64;
+
+        MIN_RETRY_AFTER + (MAX_RETRY_AFTER - MIN_RETRY_AFTER).mul_f64(load)
+    }
+
+    /// Resolves the timeout requested in milliseconds, falling back to the configured one.
+    fn wait_timeout(&self, timeout_ms: Option<u64>) -> Result<Duration, &'static str> {
+        let Some(timeout_ms) = timeout_ms else {
+            return Ok(self.settings().wait_timeout);
+        };
+
+        let wait_timeout = Duration::from_millis(timeout_ms);
+        if wait_timeout.is_zero() || wait_timeout > self.settings().max_wait_timeout {
+            warn!(timeout_ms, "Requested timeout is out of bounds");
+            return Err(INVALID_TIMEOUT_MESSAGE);
+        }
+
+        Ok(wait_timeout)
+    }
+}
+
+/// Rate limiter enforcing the rate limits of `settings`, if they're enabled.
+fn rate_limiter(settings: &Settings) -> Option<Arc<ClientRateLimiter>> {
+    ClientRateLimiter::new(settings.rate_limit_per_second, settings.rate_limit_burst).map(Arc::new)
+}
+
+/// Builds the router serving the rendezvous API with `state`, returned along with the shared state
+/// for the tasks running beside the server.
+///
+/// Handlers read the address of clients from their `ConnectInfo`, when the router is served with
+/// it.
+pub fn make_router(state: AppState) -> (Router, Arc<AppState>) {
+    let state = Arc::new(state);
+
+    let listings = Router::new().route(
+        "/wait-for-second-party/:unique-id/status",
+        get(party_status),
+    );
+    #[cfg(feature = "history")]
+    let listings = listings.route("/history/:unique-id", get(history::history));
+
+    // Served by the node owning their id in cluster mode
+    let owned = Router::new()
+        .route(
+            "/wait-for-second-party/:unique-id",
+            post(sync_parties).delete(cancel_party),
+        )
+        .route(
+            "/wait-for-second-party/:unique-id/round/:round",
+            post(sync_round),
+        )
+        .route(
+            "/ns/:namespace/wait-for-second-party/:unique-id",
+            post(sync_namespaced),
+        )
+        .route("/wait-for-parties/:unique-id/:parties", post(sync_barrier))
+        .route("/wait-for-release/:unique-id", post(wait_for_release))
+        .route("/release/:unique-id", post(release))
+        .route("/sse/wait/:unique-id", get(sse_wait))
+        .merge(compress(listings, &state));
+    // WebSockets can't be relayed, so they're served by the node they reach, like the waits on
+    // several ids, and receipts are kept by the node that served their wait
+    let waits = forward_to_owners(owned, &state)
+        .route("/ws/wait/:unique-id", get(ws_wait))
+        .route("/wait-any", post(wait_any))
+        .route("/receipt/:receipt", get(receipt));
+    #[cfg(feature = "grpc")]
+    let waits = waits.merge(grpc::routes(state.clone()));
+
+    let router = Router::new()
+        .merge(
+            limit_bodies(waits, &state)
+                .route_layer(from_fn_with_state(state.clone(), inject_faults))
+                .route_layer(from_fn_with_state(state.clone(), require_api_key))
+                .route_layer(from_fn_with_state(state.clone(), limit_rate)),
+        )
+        .merge(
+            compress(
+                Router::new()
+                    .route("/admin/waiters", get(list_waiters))
+                    .route("/stats", get(render_stats))
+                    .route("/admin/chaos", get(chaos_faults).put(set_chaos_faults)),
+                &state,
+            )
+            // Event streams are never compressed, so that each event is sent right away
+            .route("/admin/events", get(stream_events))
+            .route_layer(from_fn_with_state(state.clone(), require_admin_key)),
+        )
+        .merge(compress(Router::new().route("/", get(dashboard)), &state))
+        .route("/metrics", get(render_metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .merge(compress(openapi::routes(), &state))
+        .with_state(state.clone());
+
+    let router = log_access(allow_cors(router, &state.settings()), &state);
+    (trace_requests(router), state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, fmt::Display, net::SocketAddr, time::Duration};
+
+    use axum::{
+        body::{Body, Bytes},
+        extract::ConnectInfo,
+        http::{Request, StatusCode},
+        response::{Redirect, Response},
+        routing::{future::RouteFuture, RouterIntoService},
+        Json,
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use http_body_util::BodyExt;
+    use tokio::time::{sleep, timeout, Instant};
+    use tokio_tungstenite::{
+        connect_async, tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream,
+    };
+    use tower::{Service, ServiceExt};
+
+    use super::*;
+    use crate::{
+        receipts::RECEIPT_HEADER,
+        response::{
+            MessageTemplates, ALREADY_MATCHED_MESSAGE, BODY_TOO_LARGE_MESSAGE, CANCELLED_MESSAGE,
+            ID_TOO_LONG_MESSAGE, INBOUND_MESSAGE, INVALID_CALLBACK_MESSAGE, INVALID_ID_MESSAGE,
+            INVALID_NAMESPACE_MESSAGE, INVALID_PARTIES_MESSAGE, MATCH_TOKEN_HEADER,
+            MISMATCHED_PARTIES_MESSAGE, NOT_WAITING_MESSAGE, OUTBOUND_MESSAGE, OVERLOADED_MESSAGE,
+            OWNER_UNREACHABLE_MESSAGE, QUOTA_EXCEEDED_MESSAGE, RATE_LIMITED_MESSAGE,
+            RELAY_URL_HEADER, RELEASED_MESSAGE, SHUTTING_DOWN_MESSAGE, SUPERSEDED_MESSAGE,
+            TIMEOUT_MESSAGE, UNAUTHORIZED_MESSAGE, UNKNOWN_RECEIPT_MESSAGE,
+        },
+        store::{StoreError, WaitStatus},
+    };
+
+    #[tokio::test]
+    async fn two_parties_with_same_id_succeed() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+
+        let party1_request = make_test_request(1);
+        let party1_response = run_request(&mut app, party1_request).await;
+
+        let party2_request = make_test_request(1);
+        let party2_response = run_request(&mut app, party2_request).await;
+
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+        let (party1_response, party2_response) =
+            (party1_response.unwrap(), party2_response.unwrap());
+
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            INBOUND_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party2_response).await[..],
+            OUTBOUND_MESSAGE.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn single_party_time_out() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+
+        let party1_request = make_test_request(1);
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+
+        sleep(Duration::from_millis(150)).await;
+
+        assert_eq!(party1_response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            TIMEOUT_MESSAGE.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn multiple_parties_with_multiple_ids_succeed() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+
+        let party1_request = make_test_request(1);
+        let party1_response = run_request(&mut app, party1_request).await;
+
+        let party2_request = make_test_request(1);
+        let party2_response = run_request(&mut app, party2_request).await;
+
+        let party3_request = make_test_request(2);
+        let party3_response = run_request(&mut app, party3_request).await;
+
+        let party4_request = make_test_request(2);
+        let party4_response = run_request(&mut app, party4_request).await;
+
+        let (party1_response, party2_response, party3_response, party4_response) = tokio::join!(
+            party1_response,
+            party2_response,
+            party3_response,
+            party4_response
+        );
+        let (party1_response, party2_response, party3_response, party4_response) = (
+            party1_response.unwrap(),
+            party2_response.unwrap(),
+            party3_response.unwrap(),
+            party4_response.unwrap(),
+        );
+
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            INBOUND_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party2_response).await[..],
+            OUTBOUND_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party3_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party3_response).await[..],
+            INBOUND_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party4_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party4_response).await[..],
+            OUTBOUND_MESSAGE.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn multiple_parties_with_multiple_ids_some_succeed_some_timeout() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+
+        let party1_request = make_test_request(1);
+        let party1_response = run_request(&mut app, party1_request).await;
+
+        let party2_request = make_test_request(1);
+        let party2_response = run_request(&mut app, party2_request).await;
+
+        let party3_request = make_test_request(2);
+        let party3_response = run_request(&mut app, party3_request).await;
+
+        let party4_request = make_test_request(2);
+        let party4_response = run_request(&mut app, party4_request).await;
+
+        let party5_request = make_test_request(2);
+        let party5_response = run_request(&mut app, party5_request).await;
+
+        let party6_request = make_test_request(3);
+        let party6_response = run_request(&mut app, party6_request).await;
+
+        let (
+            party1_response,
+            party2_response,
+            party3_response,
+            party4_response,
+            party5_response,
+            party6_response,
+        ) = tokio::join!(
+            party1_response,
+            party2_response,
+            party3_response,
+            party4_response,
+            party5_response,
+            party6_response,
+        );
+        let (
+            party1_response,
+            party2_response,
+            party3_response,
+            party4_response,
+            party5_response,
+            party6_response,
+        ) = (
+            party1_response.unwrap(),
+            party2_response.unwrap(),
+            party3_response.unwrap(),
+            party4_response.unwrap(),
+            party5_response.unwrap(),
+            party6_response.unwrap(),
+        );
+
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            INBOUND_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party2_response).await[..],
+            OUTBOUND_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party3_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party3_response).await[..],
+            INBOUND_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party4_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party4_response).await[..],
+            OUTBOUND_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party5_response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(
+            &extract_response_body(party5_response).await[..],
+            TIMEOUT_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party6_response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(
+            &extract_response_body(party6_response).await[..],
+            TIMEOUT_MESSAGE.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn two_parties_with_same_uuid_succeed() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+        let unique_id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+        let party1_request = make_test_request(unique_id);
+        let party1_response = run_request(&mut app, party1_request).await;
+
+        let party2_request = make_test_request(unique_id);
+        let party2_response = run_request(&mut app, party2_request).await;
+
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+        let (party1_response, party2_response) =
+            (party1_response.unwrap(), party2_response.unwrap());
+
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            INBOUND_MESSAGE.as_bytes()
+        );
+
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party2_response).await[..],
+            OUTBOUND_MESSAGE.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn two_parties_negotiating_json_get_structured_responses() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+
+        let party1_request = make_json_request(1);
+        let party1_response = run_request(&mut app, party1_request).await;
+
+        let party2_request = make_json_request(1);
+        let party2_response = run_request(&mut app, party2_request).await;
+
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+        let (party1_response, party2_response) =
+            (party1_response.unwrap(), party2_response.unwrap());
+
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        let party1_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(party1_response).await).unwrap();
+        assert_eq!(party1_body["status"], "matched");
+        assert_eq!(party1_body["role"], "first");
+        assert!(party1_body["waited_ms"].is_u64());
+
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        let party2_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(party2_response).await).unwrap();
+        assert_eq!(party2_body["status"], "matched");
+        assert_eq!(party2_body["role"], "second");
+    }
+
+    #[tokio::test]
+    async fn matched_parties_share_a_token() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+
+        let party1_response = run_request(&mut app, make_json_request(1)).await;
+        let party2_response = run_request(&mut app, make_json_request(1)).await;
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+
+        let party1_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(party1_response.unwrap()).await).unwrap();
+        let party2_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(party2_response.unwrap()).await).unwrap();
+        let token = party1_body["token"].as_str().unwrap();
+        assert_eq!(token.len(), 32);
+        assert_eq!(party2_body["token"], token);
+
+        // Plain text parties get theirs in a header, and every match has its own
+        let party1_response = run_request(&mut app, make_test_request(1)).await;
+        let party2_response = run_request(&mut app, make_test_request(1)).await;
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+        let (party1_response, party2_response) =
+            (party1_response.unwrap(), party2_response.unwrap());
+
+        let party1_token = &party1_response.headers()[MATCH_TOKEN_HEADER];
+        assert_eq!(party1_token, &party2_response.headers()[MATCH_TOKEN_HEADER]);
+        assert_ne!(party1_token, token);
+    }
+
+    #[tokio::test]
+    async fn matched_parties_are_handed_the_same_relay_room() {
+        let mut settings = Settings::new(Duration::from_millis(200));
+        settings.relay_url = Some("ws://relay.example:8081".to_owned());
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let party1_response = run_request(&mut app, make_json_request(1)).await;
+        let party2_response = run_request(&mut app, make_test_request(1)).await;
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+        let party2_response = party2_response.unwrap();
+
+        let party1_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(party1_response.unwrap()).await).unwrap();
+        let relay_url = party1_body["relay_url"].as_str().unwrap();
+        assert!(relay_url.starts_with("ws://relay.example:8081/relay/"));
+        assert_eq!(party2_response.headers()[RELAY_URL_HEADER], relay_url);
+    }
+
+    #[tokio::test]
+    async fn matched_parties_see_their_peer() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let labelled = |label: &str, client: [u8; 4]| {
+            let mut request = make_json_request(1);
+            request
+                .headers_mut()
+                .insert("x-party-label", label.parse().unwrap());
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((client, 1234))));
+            request
+        };
+
+        let mut party1_request = labelled("worker-1", [10, 0, 0, 1]);
+        party1_request
+            .headers_mut()
+            .insert("x-party-public-key", "8520f0098930a754".parse().unwrap());
+        let party1_response = tokio::spawn(run_request(&mut app, party1_request).await);
+        sleep(Duration::from_millis(50)).await;
+        let party2_response = run_request(&mut app, labelled("worker-2", [10, 0, 0, 2]))
+            .await
+            .await
+            .unwrap();
+
+        let party2_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(party2_response).await).unwrap();
+        assert_eq!(party2_body["peer"]["label"], "worker-1");
+        assert_eq!(party2_body["peer"]["client"], "10.0.0.1:1234");
+        assert_eq!(party2_body["peer"]["public_key"], "8520f0098930a754");
+
+        let party1_response = party1_response.await.unwrap().unwrap();
+        let party1_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(party1_response).await).unwrap();
+        assert_eq!(party1_body["peer"]["label"], "worker-2");
+        assert_eq!(party1_body["peer"]["client"], "10.0.0.2:1234");
+        assert!(party1_body["peer"].get("public_key").is_none());
+        assert!(
+            party1_body["peer"]["arrived_at_ms"].as_u64()
+                > party2_body["peer"]["arrived_at_ms"].as_u64()
+        );
+
+        let response = run_request(&mut app, labelled("", [10, 0, 0, 1]))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let mut request = labelled("worker-1", [10, 0, 0, 1]);
+        request
+            .headers_mut()
+            .insert("x-party-public-key", "a".repeat(513).parse().unwrap());
+        let response = run_request(&mut app, request).await.await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn waits_are_forwarded_to_the_owner_of_their_id() {
+        let (owner, _owner_state) = make_app(Settings::new(Duration::from_millis(500)));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let owner_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, owner).await });
+
+        // Nothing listens on port 1, so waits owned by that node can't be forwarded
+        let unreachable_url = "http://127.0.0.1:1".to_owned();
+        let own_url = "http://127.0.0.1:2".to_owned();
+        let nodes = [owner_url.clone(), unreachable_url.clone(), own_url.clone()];
+        let cluster = Cluster::new(&nodes, Some(&own_url)).unwrap().unwrap();
+        let owned_by = |node: &str| {
+            (0..)
+                .map(|id: u32| id.to_string())
+                .find(|unique_id| cluster.owner(unique_id).unwrap_or(&own_url) == node)
+                .unwrap()
+        };
+        let (forwarded_id, unreachable_id, own_id) = (
+            owned_by(&owner_url),
+            owned_by(&unreachable_url),
+            owned_by(&own_url),
+        );
+
+        let state = AppState::new(
+            Settings::new(Duration::from_millis(500)),
+            Box::new(LocalParties::default()),
+        )
+        .with_cluster(Some(cluster));
+        let (app, _state) = make_router(state);
+        let mut app = app.into_service();
+
+        // A party reaching this node meets a party reaching the owner directly
+        let party1_response =
+            tokio::spawn(run_request(&mut app, make_test_request(&forwarded_id)).await);
+        sleep(Duration::from_millis(50)).await;
+        let party2_response = reqwest::Client::new()
+            .post(format!("{owner_url}/wait-for-second-party/{forwarded_id}"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(party2_response.text().await.unwrap(), OUTBOUND_MESSAGE);
+        let party1_response = party1_response.await.unwrap().unwrap();
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            INBOUND_MESSAGE.as_bytes()
+        );
+
+        let response = run_request(&mut app, make_test_request(&unreachable_id))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            &extract_response_body(response).await[..],
+            OWNER_UNREACHABLE_MESSAGE.as_bytes()
+        );
+
+        // Ids owned by this node are served locally
+        let party1_response = tokio::spawn(run_request(&mut app, make_test_request(&own_id)).await);
+        sleep(Duration::from_millis(50)).await;
+        let party2_response = run_request(&mut app, make_test_request(&own_id))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(
+            party1_response.await.unwrap().unwrap().status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn audit_log_records_the_start_and_end_of_waits() {
+        let path =
+            std::env::temp_dir().join(format!("sync-point-audit-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let audit = AuditLog::open(path.to_str().unwrap()).unwrap();
+        let state = AppState::new(
+            Settings::new(Duration::from_millis(200)),
+            Box::new(LocalParties::default()),
+        )
+        .with_audit(Some(audit));
+        let (app, _state) = make_router(state);
+        let mut app = app.into_service();
+
+        let audited = |unique_id: u32, request_id: &str, label: &str| {
+            Request::builder()
+                .uri(format!("/wait-for-second-party/{unique_id}"))
+                .method("POST")
+                .header("x-request-id", request_id)
+                .header("x-party-label", label)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let party1_response =
+            tokio::spawn(run_request(&mut app, audited(1, "request-1", "alice")).await);
+        sleep(Duration::from_millis(50)).await;
+        let party2_response = run_request(&mut app, audited(1, "request-2", "bob"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(
+            party1_response.await.unwrap().unwrap().status(),
+            StatusCode::OK
+        );
+
+        let response = run_request(&mut app, audited(2, "request-3", "carol"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 6);
+        let record = |event: &str, request_id: &str| {
+            records
+                .iter()
+                .find(|record| record["event"] == event && record["request_id"] == request_id)
+                .unwrap()
+        };
+
+        let started = record("started", "request-1");
+        assert_eq!(started["unique_id"], "1");
+        assert_eq!(started["party"]["label"], "alice");
+        assert!(started.get("status").is_none());
+
+        let matched = record("ended", "request-1");
+        assert_eq!(matched["status"], "matched");
+        assert_eq!(matched["role"], "first");
+        assert_eq!(matched["party"]["label"], "alice");
+        assert_eq!(matched["peer"]["label"], "bob");
+        assert_eq!(record("ended", "request-2")["peer"]["label"], "alice");
+
+        record("started", "request-3");
+        assert_eq!(record("ended", "request-3")["status"], "timeout");
+    }
+
+    #[tokio::test]
+    async fn access_log_records_requests_outside_the_excluded_paths() {
+        let path =
+            std::env::temp_dir().join(format!("sync-point-access-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let access_log = AccessLog::open(
+            path.to_str().unwrap(),
+            AccessLogFormat::Json,
+            vec!["/healthz".to_owned()],
+        )
+        .unwrap();
+        let state = AppState::new(
+            Settings::new(Duration::from_millis(100)),
+            Box::new(LocalParties::default()),
+        )
+        .with_access_log(Some(access_log));
+        let (app, _state) = make_router(state);
+        let mut app = app.into_service();
+
+        let mut request = make_test_request(1);
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 1234))));
+        request
+            .headers_mut()
+            .insert("user-agent", "curl/8.5.0".parse().unwrap());
+        let response = run_request(&mut app, request).await.await.unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        let health_response = run_request(&mut app, make_get_request("/healthz"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(health_response.status(), StatusCode::OK);
+
+        let records: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["client"], "10.0.0.1:1234");
+        assert_eq!(records[0]["method"], "POST");
+        assert_eq!(records[0]["uri"], "/wait-for-second-party/1");
+        assert_eq!(records[0]["protocol"], "HTTP/1.1");
+        assert_eq!(records[0]["status"], 408);
+        assert_eq!(records[0]["user_agent"], "curl/8.5.0");
+        assert!(records[0]["bytes"].as_u64().unwrap() > 0);
+        assert!(records[0]["duration_ms"].as_u64().unwrap() >= 100);
+        assert!(records[0]["request_id"].is_string());
+    }
+
+    #[tokio::test]
+    async fn callback_receives_outcome_of_background_wait() {
+        let (callbacks, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let receiver = Router::new()
+            .route(
+                "/hook",
+                post(move |Json(body): Json<serde_json::Value>| async move {
+                    callbacks.send(body).unwrap();
+                }),
+            )
+            .route("/moved", post(|| async { Redirect::temporary("/hook") }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let receiver_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let mut settings = Settings::new(Duration::from_millis(500));
+        settings.callback_hosts = vec!["127.0.0.1".to_owned()];
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let with_callback = |callback: &str| {
+            Request::builder()
+                .uri(format!("/wait-for-second-party/1?callback={callback}"))
+                .method("POST")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let party1_request = with_callback(&format!("http://{receiver_addr}/hook"));
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+        assert_eq!(party1_response.status(), StatusCode::ACCEPTED);
+        sleep(Duration::from_millis(50)).await;
+
+        let party2_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+
+        let callback = received.recv().await.unwrap();
+        assert_eq!(callback["unique_id"], "1");
+        assert_eq!(callback["status"], "matched");
+        assert_eq!(callback["role"], "first");
+
+        // Redirects could lead the outcome anywhere, so they aren't followed
+        let party1_request = with_callback(&format!("http://{receiver_addr}/moved"));
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+        assert_eq!(party1_response.status(), StatusCode::ACCEPTED);
+        sleep(Duration::from_millis(50)).await;
+        let party2_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert!(timeout(Duration::from_millis(200), received.recv())
+            .await
+            .is_err());
+
+        let response = run_request(&mut app, with_callback("http://example.com/hook"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            &extract_response_body(response).await[..],
+            INVALID_CALLBACK_MESSAGE.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn strict_mode_rejects_parties_arriving_after_match() {
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.strict_grace = Duration::from_millis(200);
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let party1_response = run_request(&mut app, make_test_request(1)).await;
+        let party2_response = run_request(&mut app, make_test_request(1)).await;
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+        assert_eq!(party1_response.unwrap().status(), StatusCode::OK);
+        assert_eq!(party2_response.unwrap().status(), StatusCode::OK);
+
+        let party3_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party3_response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            &extract_response_body(party3_response).await[..],
+            ALREADY_MATCHED_MESSAGE.as_bytes()
+        );
+
+        // Once the grace window is over, the id starts a new rendezvous
+        sleep(Duration::from_millis(200)).await;
+        let party4_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party4_response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn status_reports_waiting_party_without_consuming_it() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let idle_request = make_status_request(1);
+        let idle_response = run_request(&mut app, idle_request).await.await.unwrap();
+        assert_eq!(idle_response.status(), StatusCode::OK);
+        let idle_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(idle_response).await).unwrap();
+        assert_eq!(idle_body, serde_json::json!({"waiting": false}));
+
+        let party1_request = make_test_request(1);
+        let party1_response = tokio::spawn(run_request(&mut app, party1_request).await);
+
+        sleep(Duration::from_millis(50)).await;
+
+        let status_request = make_status_request(1);
+        let status_response = run_request(&mut app, status_request).await.await.unwrap();
+        let status_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(status_response).await).unwrap();
+        assert_eq!(status_body["waiting"], true);
+        assert!(status_body["arrived_at_ms"].is_u64());
+        assert!(status_body["remaining_ms"].as_u64().unwrap() <= 450);
+
+        let party2_request = make_test_request(1);
+        let party2_response = run_request(&mut app, party2_request).await.await.unwrap();
+        let party1_response = party1_response.await.unwrap().unwrap();
+
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        assert_eq!(party2_response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn probe_reports_waiting_party_without_registering() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let probe_request = make_post_request("/wait-for-second-party/1?mode=probe");
+        let probe_response = run_request(&mut app, probe_request).await.await.unwrap();
+        assert_eq!(probe_response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(state.parties.waiting().await, 0);
+
+        let party1_request = make_test_request(1);
+        let party1_response = tokio::spawn(run_request(&mut app, party1_request).await);
+
+        sleep(Duration::from_millis(50)).await;
+
+        let probe_request = make_post_request("/wait-for-second-party/1?mode=probe");
+        let probe_response = run_request(&mut app, probe_request).await.await.unwrap();
+        assert_eq!(probe_response.status(), StatusCode::OK);
+        let probe_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(probe_response).await).unwrap();
+        assert_eq!(probe_body["waiting"], true);
+        assert_eq!(state.parties.waiting().await, 1);
+
+        let party2_request = make_test_request(1);
+        let party2_response = run_request(&mut app, party2_request).await.await.unwrap();
+        let party1_response = party1_response.await.unwrap().unwrap();
+
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        assert_eq!(party2_response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn wait_any_matches_on_the_first_id_another_party_arrives_on() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let any_request = make_wait_any_request(r#"["1", "2", "3"]"#);
+        let any_response = tokio::spawn(run_request(&mut app, any_request).await);
+
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(state.parties.waiting().await, 3);
+
+        let party_request = make_json_request(2);
+        let party_response = run_request(&mut app, party_request).await.await.unwrap();
+        assert_eq!(party_response.status(), StatusCode::OK);
+
+        let any_response = any_response.await.unwrap().unwrap();
+        assert_eq!(any_response.status(), StatusCode::OK);
+        assert_eq!(any_response.headers()["x-matched-id"], "2");
+        let any_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(any_response).await).unwrap();
+        assert_eq!(any_body["unique_id"], "2");
+        assert_eq!(any_body["status"], "matched");
+        assert_eq!(any_body["role"], "first");
+
+        // The party withdrew from the other ids
+        assert_eq!(state.parties.waiting().await, 0);
+
+        // A party already waiting on one of the ids is matched right away
+        let party_request = make_test_request(3);
+        let party_response = tokio::spawn(run_request(&mut app, party_request).await);
+        sleep(Duration::from_millis(50)).await;
+        let any_request = make_wait_any_request(r#"["1", "3"]"#);
+        let any_response = run_request(&mut app, any_request).await.await.unwrap();
+        assert_eq!(any_response.status(), StatusCode::OK);
+        assert_eq!(any_response.headers()["x-matched-id"], "3");
+        let party_response = party_response.await.unwrap().unwrap();
+        assert_eq!(party_response.status(), StatusCode::OK);
+        assert_eq!(state.parties.waiting().await, 0);
+
+        for ids in ["[]", r#"["1", "not valid"]"#, "{}"] {
+            let invalid_request = make_wait_any_request(ids);
+            let invalid_response = run_request(&mut app, invalid_request).await.await.unwrap();
+            assert_eq!(invalid_response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let timeout_request = make_wait_any_request(r#"["4", "5"]"#);
+        let timeout_response = run_request(&mut app, timeout_request).await.await.unwrap();
+        assert_eq!(timeout_response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(!timeout_response.headers().contains_key("x-matched-id"));
+        assert_eq!(state.parties.waiting().await, 0);
+    }
+
+    /// Local store where another party arrives on `matched_id` right after the first party to wait
+    /// on it, as if it arrived while that party was still arriving on its other ids.
+    struct MatchedWhileArriving {
+        parties: LocalParties,
+        matched_id: &'static str,
+        matched: std::sync::atomic::AtomicBool,
+    }
+
+    #[axum::async_trait]
+    impl PartyStore for MatchedWhileArriving {
+        async fn arrive(
+            &self,
+            unique_id: &str,
+            deadline: Instant,
+            idempotency_key: Option<&str>,
+            peer: &Peer,
+        ) -> Result<Arrival, StoreError> {
+            let arrival = self
+                .parties
+                .arrive(unique_id, deadline, idempotency_key, peer)
+                .await?;
+            if matches!(arrival, Arrival::Wait(_))
+                && unique_id == self.matched_id
+                && !self.matched.swap(true, std::sync::atomic::Ordering::SeqCst)
+            {
+                let other = Peer::new(None, Some("other"));
+                self.parties
+                    .arrive(unique_id, deadline, None, &other)
+                    .await?;
+            }
+            Ok(arrival)
+        }
+
+        async fn cancel(&self, unique_id: &str) -> Result<bool, StoreError> {
+            self.parties.cancel(unique_id).await
+        }
+
+        async fn status(&self, unique_id: &str) -> Result<WaitStatus, StoreError> {
+            self.parties.status(unique_id).await
+        }
+
+        async fn waiting(&self) -> usize {
+            self.parties.waiting().await
+        }
+
+        async fn evict_stale(&self, grace: Duration) -> usize {
+            self.parties.evict_stale(grace).await
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_any_stops_arriving_once_matched_on_an_earlier_id() {
+        let parties = MatchedWhileArriving {
+            parties: LocalParties::default(),
+            matched_id: "1",
+            matched: Default::default(),
+        };
+        let state = AppState::new(Settings::new(Duration::from_millis(200)), Box::new(parties));
+        let (app, state) = make_router(state);
+        let mut app = app.into_service();
+
+        let party_response = tokio::spawn(run_request(&mut app, make_test_request(2)).await);
+        sleep(Duration::from_millis(50)).await;
+
+        let any_request = make_wait_any_request(r#"["1", "2"]"#);
+        let any_response = run_request(&mut app, any_request).await.await.unwrap();
+        assert_eq!(any_response.status(), StatusCode::OK);
+        assert_eq!(any_response.headers()["x-matched-id"], "1");
+        let any_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(any_response).await).unwrap();
+        assert_eq!(any_body["role"], "first");
+        assert_eq!(any_body["peer"]["label"], "other");
+
+        // The party waiting on the further id isn't matched with a party already gone
+        let party_response = party_response.await.unwrap().unwrap();
+        assert_eq!(party_response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(state.parties.waiting().await, 0);
+    }
+
+    #[tokio::test]
+    async fn cancel_wakes_waiting_party() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let party1_request = make_test_request(1);
+        let party1_response = tokio::spawn(run_request(&mut app, party1_request).await);
+
+        sleep(Duration::from_millis(50)).await;
+
+        let cancel_request = make_cancel_request(1);
+        let cancel_response = run_request(&mut app, cancel_request).await.await.unwrap();
+        assert_eq!(cancel_response.status(), StatusCode::NO_CONTENT);
+
+        let party1_response = party1_response.await.unwrap().unwrap();
+        assert_eq!(party1_response.status(), StatusCode::GONE);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            CANCELLED_MESSAGE.as_bytes()
+        );
+        assert!(!state.parties.status("1").await.unwrap().waiting);
+    }
+
+    #[tokio::test]
+    async fn retry_with_same_idempotency_key_takes_over_wait() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let with_key = |idempotency_key: &str| {
+            let mut request = make_test_request(1);
+            request
+                .headers_mut()
+                .insert("idempotency-key", idempotency_key.parse().unwrap());
+            request
+        };
+
+        let attempt1_response = tokio::spawn(run_request(&mut app, with_key("party-1")).await);
+        sleep(Duration::from_millis(50)).await;
+        let attempt2_response = tokio::spawn(run_request(&mut app, with_key("party-1")).await);
+
+        let attempt1_response = attempt1_response.await.unwrap().unwrap();
+        assert_eq!(attempt1_response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            &extract_response_body(attempt1_response).await[..],
+            SUPERSEDED_MESSAGE.as_bytes()
+        );
+
+        let party2_response = run_request(&mut app, with_key("party-2"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party2_response).await[..],
+            OUTBOUND_MESSAGE.as_bytes()
+        );
+        let attempt2_response = attempt2_response.await.unwrap().unwrap();
+        assert_eq!(attempt2_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(attempt2_response).await[..],
+            INBOUND_MESSAGE.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_after_a_match_gets_the_match_again() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let with_key = |idempotency_key: &str| {
+            let mut request = make_test_request(1);
+            request
+                .headers_mut()
+                .insert("idempotency-key", idempotency_key.parse().unwrap());
+            request
+        };
+
+        let attempt1_response = tokio::spawn(run_request(&mut app, with_key("party-1")).await);
+        sleep(Duration::from_millis(50)).await;
+        let party2_response = run_request(&mut app, with_key("party-2"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        // As if the response had been lost along with the connection
+        drop(attempt1_response.await.unwrap().unwrap());
+
+        let attempt2_response = timeout(
+            Duration::from_millis(100),
+            run_request(&mut app, with_key("party-1")).await,
+        )
+        .await
+        .expect("the match should be replayed right away")
+        .unwrap();
+        assert_eq!(attempt2_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(attempt2_response).await[..],
+            INBOUND_MESSAGE.as_bytes()
+        );
+        assert!(!state.parties.status("1").await.unwrap().waiting);
+    }
+
+    #[tokio::test]
+    async fn session_rounds_are_separate_rendezvous() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let party1_response = tokio::spawn(run_request(&mut app, make_round_request(1, 1)).await);
+        sleep(Duration::from_millis(50)).await;
+        // Another round of the same session doesn't match the waiting party
+        let party2_response = tokio::spawn(run_request(&mut app, make_round_request(1, 2)).await);
+        sleep(Duration::from_millis(50)).await;
+
+        // The session moved past the first round, so its waiting party is cancelled
+        let party1_response = party1_response.await.unwrap().unwrap();
+        assert_eq!(party1_response.status(), StatusCode::GONE);
+        assert!(!state.parties.status("1/round/1").await.unwrap().waiting);
+
+        let party3_response = run_request(&mut app, make_round_request(1, 2))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party3_response.status(), StatusCode::OK);
+        let party2_response = party2_response.await.unwrap().unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(state.parties.waiting().await, 0);
+    }
+
+    #[tokio::test]
+    async fn namespaces_are_isolated_and_limited() {
+        let mut settings = Settings::new(Duration::from_millis(300));
+        settings.max_waiters_per_namespace = 2;
+        let (app, state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let party1_request = make_post_request("/ns/team-a/wait-for-second-party/1");
+        let party1_response = tokio::spawn(run_request(&mut app, party1_request).await);
+        // The same id in another namespace doesn't match the waiting party
+        let party2_request = make_post_request("/ns/team-b/wait-for-second-party/1");
+        let party2_response = tokio::spawn(run_request(&mut app, party2_request).await);
+        sleep(Duration::from_millis(50)).await;
+
+        let party3_request = make_post_request("/ns/team-a/wait-for-second-party/1");
+        let party3_response = run_request(&mut app, party3_request).await.await.unwrap();
+        assert_eq!(party3_response.status(), StatusCode::OK);
+        let party1_response = party1_response.await.unwrap().unwrap();
+        assert_eq!(party1_response.status(), StatusCode::OK);
+
+        let party4_request = make_post_request("/ns/team-b/wait-for-second-party/2");
+        let party4_response = tokio::spawn(run_request(&mut app, party4_request).await);
+        sleep(Duration::from_millis(50)).await;
+        let party5_request = make_post_request("/ns/team-b/wait-for-second-party/3");
+        let party5_response = run_request(&mut app, party5_request).await.await.unwrap();
+        assert_eq!(party5_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            &extract_response_body(party5_response).await[..],
+            OVERLOADED_MESSAGE.as_bytes()
+        );
+
+        for party_response in [party2_response, party4_response] {
+            let party_response = party_response.await.unwrap().unwrap();
+            assert_eq!(party_response.status(), StatusCode::REQUEST_TIMEOUT);
+        }
+        assert_eq!(state.parties.waiting().await, 0);
+
+        let invalid_request = make_post_request("/ns/team.a/wait-for-second-party/1");
+        let invalid_response = run_request(&mut app, invalid_request).await.await.unwrap();
+        assert_eq!(invalid_response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            &extract_response_body(invalid_response).await[..],
+            INVALID_NAMESPACE_MESSAGE.as_bytes()
+        );
+
+        let metrics_response = run_request(&mut app, make_get_request("/metrics"))
+            .await
+            .await
+            .unwrap();
+        let metrics = extract_response_body(metrics_response).await;
+        assert!(String::from_utf8_lossy(&metrics)
+            .contains(r#"sync_point_timeouts_total{namespace="team-b"}"#));
+    }
+
+    #[tokio::test]
+    async fn cancel_without_waiting_party_is_not_found() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+
+        let cancel_request = make_cancel_request(1);
+        let cancel_response = run_request(&mut app, cancel_request).await.await.unwrap();
+
+        assert_eq!(cancel_response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            &extract_response_body(cancel_response).await[..],
+            NOT_WAITING_MESSAGE.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn ws_parties_match_and_keep_connection_for_next_wait() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(500)));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let (mut party1, _) = connect_async(format!("ws://{addr}/ws/wait/1"))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(50)).await;
+        let (mut party2, _) = connect_async(format!("ws://{addr}/ws/wait/1"))
+            .await
+            .unwrap();
+
+        let party1_frame = next_json_frame(&mut party1).await;
+        assert_eq!(party1_frame["status"], "matched");
+        assert_eq!(party1_frame["role"], "first");
+        let party2_frame = next_json_frame(&mut party2).await;
+        assert_eq!(party2_frame["status"], "matched");
+        assert_eq!(party2_frame["role"], "second");
+
+        party2.send(WsMessage::Text("2".into())).await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        party1.send(WsMessage::Text("2".into())).await.unwrap();
+
+        let party2_frame = next_json_frame(&mut party2).await;
+        assert_eq!(party2_frame["status"], "matched");
+        assert_eq!(party2_frame["role"], "first");
+        let party1_frame = next_json_frame(&mut party1).await;
+        assert_eq!(party1_frame["status"], "matched");
+        assert_eq!(party1_frame["role"], "second");
+    }
+
+    #[tokio::test]
+    async fn sse_party_receives_keepalives_until_timeout() {
+        let mut settings = Settings::new(Duration::from_millis(200));
+        settings.keepalive_interval = Duration::from_millis(50);
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let party1_request = make_sse_request(1);
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+        assert_eq!(party1_response.status(), StatusCode::OK);
+
+        let body = extract_response_body(party1_response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("event: waiting\n"));
+        assert!(body.contains("event: timeout\n"));
+    }
+
+    #[tokio::test]
+    async fn sse_party_matches_http_party() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        // The wait starts once the event stream is polled
+        let party1_request = make_sse_request(1);
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        let party1_body = tokio::spawn(extract_response_body(party1_response));
+
+        sleep(Duration::from_millis(50)).await;
+
+        let party2_request = make_test_request(1);
+        let party2_response = run_request(&mut app, party2_request).await.await.unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(
+            &extract_response_body(party2_response).await[..],
+            OUTBOUND_MESSAGE.as_bytes()
+        );
+
+        let body = party1_body.await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("event: matched\n"));
+        assert!(body.contains(r#""role":"first""#));
+    }
+
+    #[tokio::test]
+    async fn message_templates_replace_the_default_messages() {
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.messages = MessageTemplates {
+            inbound: Some("{id}: the other party arrived after {waited_ms}ms\n".to_owned()),
+            outbound: None,
+            timeout: Some("{id}: nobody came\n".to_owned()),
+        };
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let party1_response = tokio::spawn(run_request(&mut app, make_test_request("0042")).await);
+        sleep(Duration::from_millis(50)).await;
+        let party2_response = run_request(&mut app, make_test_request(42))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(
+            &extract_response_body(party2_response).await[..],
+            OUTBOUND_MESSAGE.as_bytes()
+        );
+        let body = extract_response_body(party1_response.await.unwrap().unwrap()).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.starts_with("42: the other party arrived after "));
+        assert!(body.ends_with("ms\n"));
+
+        let response = run_request(&mut app, make_test_request(7))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(response.headers().contains_key("retry-after"));
+        assert_eq!(
+            &extract_response_body(response).await[..],
+            b"7: nobody came\n"
+        );
+
+        // JSON bodies are left as they are
+        let response = run_request(&mut app, make_json_request(7))
+            .await
+            .await
+            .unwrap();
+        let outcome: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(response).await).unwrap();
+        assert_eq!(outcome["status"], "timeout");
+    }
+
+    #[tokio::test]
+    async fn keepalive_party_receives_heartbeats_until_the_outcome() {
+        let mut settings = Settings::new(Duration::from_millis(300));
+        settings.keepalive_interval = Duration::from_millis(50);
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let keepalive_request = |unique_id: u32| {
+            Request::builder()
+                .uri(format!("/wait-for-second-party/{unique_id}?keepalive=true"))
+                .method("POST")
+                .header("accept", "application/json")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // The status is sent right away, the wait starting once the body is polled
+        let party1_response = run_request(&mut app, keepalive_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party1_response.status(), StatusCode::OK);
+        let party1_body = tokio::spawn(extract_response_body(party1_response));
+
+        sleep(Duration::from_millis(120)).await;
+
+        let party2_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+
+        let body = party1_body.await.unwrap();
+        assert!(body.starts_with(b"\n\n"));
+        let outcome: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(outcome["status"], "matched");
+        assert_eq!(outcome["role"], "first");
+
+        let response = run_request(&mut app, keepalive_request(2))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let outcome: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(response).await).unwrap();
+        assert_eq!(outcome["status"], "timeout");
+    }
+
+    #[tokio::test]
+    async fn outcomes_are_recovered_with_the_receipts_of_the_waits() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        // The receipt is sent right away along with the heartbeats
+        let party1_response = run_request(
+            &mut app,
+            make_post_request("/wait-for-second-party/1?keepalive=true"),
+        )
+        .await
+        .await
+        .unwrap();
+        let party1_receipt = party1_response.headers()[RECEIPT_HEADER]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let party1_body = tokio::spawn(extract_response_body(party1_response));
+        sleep(Duration::from_millis(50)).await;
+
+        let receipt_request = |receipt: &str| make_get_request(&format!("/receipt/{receipt}"));
+        let response = run_request(&mut app, receipt_request(&party1_receipt))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            &extract_response_body(response).await[..],
+            UNKNOWN_RECEIPT_MESSAGE.as_bytes()
+        );
+
+        let party2_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        let party2_receipt = party2_response.headers()[RECEIPT_HEADER].to_str().unwrap();
+        assert_ne!(party2_receipt, party1_receipt);
+        party1_body.await.unwrap();
+
+        for (receipt, role) in [(&party1_receipt[..], "first"), (party2_receipt, "second")] {
+            let response = run_request(&mut app, receipt_request(receipt))
+                .await
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let kept: serde_json::Value =
+                serde_json::from_slice(&extract_response_body(response).await).unwrap();
+            assert_eq!(kept["unique_id"], "1");
+            assert_eq!(kept["outcome"]["status"], "matched");
+            assert_eq!(kept["outcome"]["role"], role);
+        }
+    }
+
+    #[tokio::test]
+    async fn receipts_are_disabled_without_retention() {
+        let mut settings = Settings::new(Duration::from_millis(50));
+        settings.receipt_retention = Duration::ZERO;
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(!response.headers().contains_key(RECEIPT_HEADER));
+    }
+
+    #[tokio::test]
+    async fn openapi_document_and_swagger_ui_are_served() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+
+        let response = run_request(&mut app, make_get_request("/openapi.json"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let document: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(response).await).unwrap();
+        let paths = document["paths"].as_object().unwrap();
+        assert!(paths.contains_key("/wait-for-second-party/{unique-id}"));
+        assert!(paths.contains_key("/ns/{namespace}/wait-for-second-party/{unique-id}"));
+        assert!(paths.contains_key("/stats"));
+        assert!(document["components"]["schemas"]
+            .as_object()
+            .unwrap()
+            .contains_key("Outcome"));
+
+        let response = run_request(&mut app, make_get_request("/docs/"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn listing_responses_are_compressed_unless_disabled() {
+        let with_gzip = |mut request: Request<Body>| {
+            request
+                .headers_mut()
+                .insert("accept-encoding", "gzip".parse().unwrap());
+            request
+        };
+
+        let (app, state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+
+        let response = run_request(&mut app, with_gzip(make_get_request("/openapi.json")))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+
+        let response = run_request(&mut app, with_gzip(make_json_request(1)))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(!response.headers().contains_key("content-encoding"));
+
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.compress_responses = false;
+        state.reload_settings(settings);
+        let response = run_request(&mut app, with_gzip(make_get_request("/openapi.json")))
+            .await
+            .await
+            .unwrap();
+        assert!(!response.headers().contains_key("content-encoding"));
+    }
+
+    #[tokio::test]
+    async fn metrics_expose_matches_and_wait_durations() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+
+        let party1_response = run_request(&mut app, make_test_request("metrics")).await;
+        let party2_response = run_request(&mut app, make_test_request("metrics")).await;
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+        assert_eq!(party1_response.unwrap().status(), StatusCode::OK);
+        assert_eq!(party2_response.unwrap().status(), StatusCode::OK);
+
+        let metrics_request = make_get_request("/metrics");
+        let metrics_response = run_request(&mut app, metrics_request).await.await.unwrap();
+        assert_eq!(metrics_response.status(), StatusCode::OK);
+
+        // The recorder is shared by all tests, so we only check the metrics are exposed
+        let body = extract_response_body(metrics_response).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("sync_point_matches_total"));
+        assert!(body.contains("sync_point_waiting_parties"));
+        assert!(body.contains(r#"sync_point_wait_duration_seconds_bucket{outcome="matched""#));
+    }
+
+    #[tokio::test]
+    async fn readiness_fails_when_too_many_parties_wait() {
+        let mut settings = Settings::new(Duration::from_millis(200));
+        settings.max_waiters = 1;
+        let (app, state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let health_response = run_request(&mut app, make_get_request("/healthz"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(health_response.status(), StatusCode::OK);
+
+        let ready_response = run_request(&mut app, make_get_request("/readyz"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(ready_response.status(), StatusCode::OK);
+
+        let party1_response = tokio::spawn(run_request(&mut app, make_test_request(1)).await);
+        let party2_response = tokio::spawn(run_request(&mut app, make_test_request(2)).await);
+        sleep(Duration::from_millis(50)).await;
+
+        let ready_response = run_request(&mut app, make_get_request("/readyz"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(ready_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        party1_response.await.unwrap().unwrap();
+        party2_response.await.unwrap().unwrap();
+
+        state.begin_shutdown();
+        let ready_response = run_request(&mut app, make_get_request("/readyz"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(ready_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn shutdown_releases_waiting_parties_and_rejects_new_ones() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let party1_response = tokio::spawn(run_request(&mut app, make_test_request(1)).await);
+        let party2_response = tokio::spawn(run_request(&mut app, make_barrier_request(2, 3)).await);
+        sleep(Duration::from_millis(50)).await;
+
+        state.begin_shutdown();
+
+        for party_response in [party1_response, party2_response] {
+            let party_response = party_response.await.unwrap().unwrap();
+            assert_eq!(party_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(
+                &extract_response_body(party_response).await[..],
+                SHUTTING_DOWN_MESSAGE.as_bytes()
+            );
+        }
+        assert!(!state.parties.status("1").await.unwrap().waiting);
+        assert!(state.barriers.read().await.is_empty());
+
+        let party3_response = run_request(&mut app, make_test_request(3))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party3_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn sweeper_evicts_parties_of_aborted_requests() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+
+        let party1_response = tokio::spawn(run_request(&mut app, make_test_request(1)).await);
+        let party2_response = tokio::spawn(run_request(&mut app, make_barrier_request(2, 3)).await);
+        sleep(Duration::from_millis(50)).await;
+        party1_response.abort();
+        party2_response.abort();
+        sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(state.parties.waiting().await, 1);
+        assert_eq!(state.barriers.read().await.waiting(), 1);
+
+        assert_eq!(sweeper::evict_stale(&state, Duration::ZERO).await, 2);
+        assert_eq!(state.parties.waiting().await, 0);
+        assert!(state.barriers.read().await.is_empty());
+
+        let metrics_response = run_request(&mut app, make_get_request("/metrics"))
+            .await
+            .await
+            .unwrap();
+        let metrics = extract_response_body(metrics_response).await;
+        assert!(String::from_utf8_lossy(&metrics).contains("sync_point_evictions_total"));
+    }
+
+    #[tokio::test]
+    async fn wait_routes_require_configured_api_key() {
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.api_keys = vec!["secret".to_owned()];
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let party1_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party1_response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            UNAUTHORIZED_MESSAGE.as_bytes()
+        );
+
+        let mut party1_request = make_test_request(1);
+        party1_request
+            .headers_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+        assert_eq!(party1_response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let health_response = run_request(&mut app, make_get_request("/healthz"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(health_response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn api_keys_are_held_to_their_quotas() {
+        let mut settings = Settings::new(Duration::from_secs(1));
+        settings.api_keys = vec!["tenant-a".to_owned(), "tenant-b".to_owned()];
+        settings.max_waiters_per_key = 1;
+        settings.max_waits_per_key_per_hour = 2;
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+        let with_key = |mut request: Request<Body>, key: &str| {
+            let bearer = format!("Bearer {key}");
+            request
+                .headers_mut()
+                .insert("authorization", bearer.parse().unwrap());
+            request
+        };
+
+        let party1 =
+            tokio::spawn(run_request(&mut app, with_key(make_test_request(1), "tenant-a")).await);
+        sleep(Duration::from_millis(50)).await;
+
+        // Only one wait of the key can go on at once, lookups aside
+        let rejected = run_request(&mut app, with_key(make_test_request(2), "tenant-a"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(rejected.headers().contains_key("retry-after"));
+        assert_eq!(
+            &extract_response_body(rejected).await[..],
+            QUOTA_EXCEEDED_MESSAGE.as_bytes()
+        );
+        let status = run_request(&mut app, with_key(make_status_request(1), "tenant-a"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(status.status(), StatusCode::OK);
+        let probe = make_post_request("/wait-for-second-party/1?mode=probe");
+        let probe = run_request(&mut app, with_key(probe, "tenant-a"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(probe.status(), StatusCode::OK);
+
+        // Other keys have their own quotas
+        let party2 = run_request(&mut app, with_key(make_test_request(1), "tenant-b"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2.status(), StatusCode::OK);
+        assert_eq!(party1.await.unwrap().unwrap().status(), StatusCode::OK);
+
+        let request = make_post_request("/wait-for-second-party/3?timeout_ms=50");
+        let response = run_request(&mut app, with_key(request, "tenant-a"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        // The third wait of the hour waits until the first one leaves the window
+        let rejected = run_request(&mut app, with_key(make_test_request(4), "tenant-a"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after: u64 = rejected.headers()["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((3590..=3600).contains(&retry_after));
+    }
+
+    #[tokio::test]
+    async fn streamed_waits_count_against_quotas_until_they_end() {
+        let mut settings = Settings::new(Duration::from_secs(1));
+        settings.api_keys = vec!["tenant-a".to_owned()];
+        settings.max_waiters_per_key = 1;
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+        let with_key = |mut request: Request<Body>| {
+            request
+                .headers_mut()
+                .insert("authorization", "Bearer tenant-a".parse().unwrap());
+            request
+        };
+
+        // The event stream is answered right away, while its wait goes on
+        let streamed = run_request(&mut app, with_key(make_sse_request(1)))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(streamed.status(), StatusCode::OK);
+
+        let rejected = run_request(&mut app, with_key(make_test_request(2)))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Closing the stream ends its wait
+        drop(streamed);
+        let request = make_post_request("/wait-for-second-party/3?timeout_ms=50");
+        let response = run_request(&mut app, with_key(request))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn reloaded_settings_apply_to_the_next_requests() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let party1_response = tokio::spawn(run_request(&mut app, make_test_request(1)).await);
+        sleep(Duration::from_millis(50)).await;
+
+        let mut settings = Settings::new(Duration::from_millis(500));
+        settings.api_keys = vec!["secret".to_owned()];
+        state.reload_settings(settings);
+
+        let response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Settings the waits would fail on are rejected
+        let mut settings = Settings::new(Duration::from_millis(500));
+        settings.keepalive_interval = Duration::ZERO;
+        state.reload_settings(settings);
+        assert_eq!(state.settings().api_keys, ["secret"]);
+
+        // CORS only applies on restart, so changes to it are left out
+        let mut settings = Settings::new(Duration::from_millis(500));
+        settings.api_keys = vec!["secret".to_owned()];
+        settings.cors_allowed_origins = vec!["https://app.example.com".to_owned()];
+        settings.max_body_bytes = 1024;
+        state.reload_settings(settings);
+        assert!(state.settings().cors_allowed_origins.is_empty());
+        assert_eq!(state.settings().max_body_bytes, 1024);
+
+        // The party waiting from before the reload is still matched
+        let mut party2_request = make_test_request(1);
+        party2_request
+            .headers_mut()
+            .insert("authorization", "Bearer secret".parse().unwrap());
+        let party2_response = run_request(&mut app, party2_request).await.await.unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert_eq!(
+            party1_response.await.unwrap().unwrap().status(),
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn cors_preflight_is_answered_for_allowed_origins() {
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.api_keys = vec!["secret".to_owned()];
+        settings.cors_allowed_origins = vec!["https://app.example.com".to_owned()];
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let preflight = |origin: &str| {
+            Request::builder()
+                .uri("/wait-for-second-party/1")
+                .method("OPTIONS")
+                .header("origin", origin)
+                .header("access-control-request-method", "POST")
+                .header("access-control-request-headers", "authorization")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = run_request(&mut app, preflight("https://app.example.com"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://app.example.com"
+        );
+        assert!(response.headers()["access-control-allow-methods"]
+            .to_str()
+            .unwrap()
+            .contains("POST"));
+
+        let response = run_request(&mut app, preflight("https://evil.example.com"))
+            .await
+            .await
+            .unwrap();
+        assert!(!response
+            .headers()
+            .contains_key("access-control-allow-origin"));
+    }
+
+    #[tokio::test]
+    async fn clients_exceeding_rate_limit_are_rejected() {
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.rate_limit_per_second = 1;
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let from_client = |client: [u8; 4]| {
+            let mut request = make_status_request(1);
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((client, 1234))));
+            request
+        };
+
+        let status_response = run_request(&mut app, from_client([10, 0, 0, 1]))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(status_response.status(), StatusCode::OK);
+
+        let status_response = run_request(&mut app, from_client([10, 0, 0, 1]))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(status_response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(status_response.headers()["retry-after"], "1");
+        assert_eq!(
+            &extract_response_body(status_response).await[..],
+            RATE_LIMITED_MESSAGE.as_bytes()
+        );
+
+        let status_response = run_request(&mut app, from_client([10, 0, 0, 2]))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(status_response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn parties_over_concurrent_waiters_limit_are_rejected() {
+        let mut settings = Settings::new(Duration::from_millis(200));
+        settings.max_concurrent_waiters = 1;
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let party1_response = tokio::spawn(run_request(&mut app, make_test_request(1)).await);
+        sleep(Duration::from_millis(50)).await;
+
+        for request in [make_test_request(2), make_barrier_request(3, 3)] {
+            let party_response = run_request(&mut app, request).await.await.unwrap();
+            assert_eq!(party_response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            // Every waiting slot is taken, so clients are told to back off for the longest
+            assert_eq!(party_response.headers()["retry-after"], "10");
+            assert_eq!(
+                &extract_response_body(party_response).await[..],
+                OVERLOADED_MESSAGE.as_bytes()
+            );
+        }
+
+        // The waiting party can still be matched
+        let party2_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        let party1_response = party1_response.await.unwrap().unwrap();
+        assert_eq!(party1_response.status(), StatusCode::OK);
+
+        // The permit is released once the party stopped waiting
+        let party3_response = run_request(&mut app, make_json_request(2))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party3_response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(party3_response.headers()["retry-after"], "10");
+        let party3_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(party3_response).await).unwrap();
+        assert_eq!(party3_body["retry_after_secs"], 10);
+    }
+
+    #[tokio::test]
+    async fn retry_after_grows_with_waiting_parties() {
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.max_concurrent_waiters = 4;
+        let state = AppState::new(settings, Box::new(LocalParties::default()));
+        assert_eq!(state.retry_after(), MIN_RETRY_AFTER);
+
+        let _permits = [state.waiter_permit(), state.waiter_permit()];
+        assert_eq!(state.retry_after(), Duration::from_millis(5500));
+
+        // The load is measured against the capacity of the current settings
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.max_concurrent_waiters = 2;
+        state.reload_settings(settings);
+        assert_eq!(state.retry_after(), MAX_RETRY_AFTER);
+        assert!(state.waiter_permit().is_none());
+    }
+
+    #[tokio::test]
+    async fn reloaded_rate_limits_apply_to_the_next_requests() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+        let from_client = || {
+            let mut request = make_status_request(1);
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 1234))));
+            request
+        };
+
+        for _ in 0..2 {
+            let response = run_request(&mut app, from_client()).await.await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.rate_limit_per_second = 1;
+        state.reload_settings(settings);
+        let response = run_request(&mut app, from_client()).await.await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = run_request(&mut app, from_client()).await.await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn requested_timeout_overrides_configured_one_within_maximum() {
+        let mut settings = Settings::new(Duration::from_secs(10));
+        settings.max_wait_timeout = Duration::from_secs(60);
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let party1_request = make_test_request("1?timeout_ms=50");
+        let party1_response = tokio::time::timeout(
+            Duration::from_secs(1),
+            run_request(&mut app, party1_request).await,
+        )
+        .await
+        .expect("requested timeout should be shorter than the configured one")
+        .unwrap();
+        assert_eq!(party1_response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        for timeout_ms in [0, 60_001] {
+            let party1_request = make_test_request(format!("1?timeout_ms={timeout_ms}"));
+            let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+            assert_eq!(party1_response.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(
+                &extract_response_body(party1_response).await[..],
+                INVALID_TIMEOUT_MESSAGE.as_bytes()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn request_ids_are_generated_or_propagated() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+
+        let health_response = run_request(&mut app, make_get_request("/healthz"))
+            .await
+            .await
+            .unwrap();
+        let request_id = health_response.headers()["x-request-id"].to_str().unwrap();
+        assert!(!request_id.is_empty());
+
+        let mut health_request = make_get_request("/healthz");
+        health_request
+            .headers_mut()
+            .insert("x-request-id", "party-1".parse().unwrap());
+        let health_response = run_request(&mut app, health_request).await.await.unwrap();
+        assert_eq!(health_response.headers()["x-request-id"], "party-1");
+    }
+
+    #[tokio::test]
+    async fn party_arriving_after_a_timeout_learns_it_missed_the_other() {
+        let mut settings = Settings::new(Duration::from_millis(50));
+        settings.missed_grace = Duration::from_secs(10);
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let labelled = |label: &str| {
+            let mut request = make_json_request(1);
+            request
+                .headers_mut()
+                .insert("x-party-label", label.parse().unwrap());
+            request
+        };
+
+        let party1_response = run_request(&mut app, labelled("worker-1"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party1_response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let party2_response = run_request(&mut app, labelled("worker-2"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party2_response.status(), StatusCode::GONE);
+        let party2_body: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(party2_response).await).unwrap();
+        assert_eq!(party2_body["status"], "missed");
+        assert_eq!(party2_body["peer"]["label"], "worker-1");
+        assert!(party2_body["left_at_ms"].as_u64() > party2_body["peer"]["arrived_at_ms"].as_u64());
+
+        // Only the next party is told, the following one waits again, and so does its retry
+        for _ in 0..2 {
+            let mut party3_request = make_test_request(1);
+            party3_request
+                .headers_mut()
+                .insert("idempotency-key", "party-3".parse().unwrap());
+            let party3_response = run_request(&mut app, party3_request).await.await.unwrap();
+            assert_eq!(party3_response.status(), StatusCode::REQUEST_TIMEOUT);
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_ids_are_rejected() {
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.max_id_length = 8;
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let party1_request = make_test_request("123456789");
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+
+        assert_eq!(party1_response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            ID_TOO_LONG_MESSAGE.as_bytes()
+        );
+
+        for unique_id in ["a%20b", "a%2Fb", "%FF"] {
+            let party1_request = make_json_request(unique_id);
+            let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+
+            assert_eq!(party1_response.status(), StatusCode::BAD_REQUEST);
+            let body = extract_response_body(party1_response).await;
+            assert_eq!(
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                serde_json::to_value(Outcome::error(INVALID_ID_MESSAGE)).unwrap()
+            );
+        }
+
+        let barrier_request = make_barrier_request("a%20b", 3);
+        let barrier_response = run_request(&mut app, barrier_request).await.await.unwrap();
+        assert_eq!(barrier_response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn large_bodies_are_rejected() {
+        let mut settings = Settings::new(Duration::from_millis(50));
+        settings.max_body_bytes = 16;
+        let (app, state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let make_body_request = |body: &'static str| {
+            Request::builder()
+                .uri("/wait-for-second-party/1")
+                .method("POST")
+                .header("accept", "application/json")
+                .header("content-length", body.len())
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let party1_request = make_body_request("a body way over the limit");
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+        assert_eq!(party1_response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = extract_response_body(party1_response).await;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::to_value(Outcome::error(BODY_TOO_LARGE_MESSAGE)).unwrap()
+        );
+
+        let party1_request = make_body_request("small body");
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+        assert_eq!(party1_response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        // Bodies without a declared length are cut off at the limit of the current settings
+        let mut settings = Settings::new(Duration::from_millis(50));
+        settings.max_body_bytes = 8;
+        state.reload_settings(settings);
+        let party1_request = make_body_request("small body");
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+        assert_eq!(party1_response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let chunks = [Ok::<_, Infallible>(Bytes::from(r#"["1", "2", "3"]"#))];
+        let request = Request::builder()
+            .uri("/wait-any")
+            .method("POST")
+            .header("content-type", "application/json")
+            .body(Body::from_stream(futures_util::stream::iter(chunks)))
+            .unwrap();
+        // The list of ids is valid, but cut off
+        let response = run_request(&mut app, request).await.await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn parties_spelling_the_same_id_differently_match() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+
+        for (party1_id, party2_id) in [
+            ("42", "0042"),
+            (
+                "67e55044-10b1-426f-9247-bb680e5fe0c8",
+                "67E5504410B1426F9247BB680E5FE0C8",
+            ),
+        ] {
+            let party1_response = run_request(&mut app, make_test_request(party1_id)).await;
+            let party2_response = run_request(&mut app, make_test_request(party2_id)).await;
+
+            let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+            assert_eq!(party1_response.unwrap().status(), StatusCode::OK);
+            assert_eq!(party2_response.unwrap().status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn barrier_releases_all_parties_when_last_arrives() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+
+        let party1_request = make_barrier_request(1, 3);
+        let party1_response = run_request(&mut app, party1_request).await;
+
+        let party2_request = make_barrier_request(1, 3);
+        let party2_response = run_request(&mut app, party2_request).await;
+
+        let party3_request = make_barrier_request(1, 3);
+        let party3_response = run_request(&mut app, party3_request).await;
+
+        let (party1_response, party2_response, party3_response) =
+            tokio::join!(party1_response, party2_response, party3_response);
+
+        for response in [party1_response, party2_response, party3_response] {
+            let response = response.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                &extract_response_body(response).await[..],
+                RELEASED_MESSAGE.as_bytes()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn gate_does_not_count_parties_of_aborted_requests() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let party1_request = make_post_request("/wait-for-release/1?timeout_ms=150");
+        let party1_response = tokio::spawn(run_request(&mut app, party1_request).await);
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(state.gates.read().await.waiting(), 1);
+        party1_response.abort();
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(state.gates.read().await.waiting(), 0);
+
+        // The gate starts over with the deadline of the next party
+        let party2_request = make_post_request("/wait-for-release/1?timeout_ms=300");
+        let party2_response = tokio::spawn(run_request(&mut app, party2_request).await);
+        sleep(Duration::from_millis(150)).await;
+        assert!(!party2_response.is_finished());
+        assert_eq!(state.gates.read().await.waiting(), 1);
+
+        let release_response = run_request(&mut app, make_post_request("/release/1"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(release_response.status(), StatusCode::OK);
+        let party2_response = party2_response.await.unwrap().unwrap();
+        assert_eq!(party2_response.status(), StatusCode::OK);
+        assert!(state.gates.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn release_wakes_every_waiting_party() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let mut party_responses = Vec::new();
+        for _ in 0..3 {
+            let party_request = make_post_request("/wait-for-release/1");
+            party_responses.push(tokio::spawn(run_request(&mut app, party_request).await));
+        }
+        sleep(Duration::from_millis(50)).await;
+
+        let release_response = run_request(&mut app, make_post_request("/release/1"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(release_response.status(), StatusCode::OK);
+
+        for party_response in party_responses {
+            let party_response = party_response.await.unwrap().unwrap();
+            assert_eq!(party_response.status(), StatusCode::OK);
+            assert_eq!(
+                &extract_response_body(party_response).await[..],
+                RELEASED_MESSAGE.as_bytes()
+            );
+        }
+        assert!(state.gates.read().await.is_empty());
+
+        let release_response = run_request(&mut app, make_post_request("/release/1"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(release_response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn serves_the_dashboard_page() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(200)));
+        let mut app = app.into_service();
+
+        let response = run_request(&mut app, make_get_request("/"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        let page = extract_response_body(response).await;
+        assert!(String::from_utf8_lossy(&page).contains("admin/waiters"));
+    }
+
+    #[tokio::test]
+    async fn admin_event_stream_reports_the_end_of_waits() {
+        let mut settings = Settings::new(Duration::from_millis(500));
+        settings.admin_api_keys = vec!["admin-secret".to_owned()];
+        let (app, state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let events_response = run_request(&mut app, make_get_request("/admin/events"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(events_response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut events_request = make_get_request("/admin/events");
+        events_request
+            .headers_mut()
+            .insert("authorization", "Bearer admin-secret".parse().unwrap());
+        let events_response = run_request(&mut app, events_request).await.await.unwrap();
+        assert_eq!(events_response.status(), StatusCode::OK);
+        let mut events = events_response.into_body();
+
+        let party1_request = make_post_request("/wait-for-second-party/1?timeout_ms=50");
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+        assert_eq!(party1_response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let event = timeout(Duration::from_secs(1), events.frame())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+            .into_data()
+            .unwrap();
+        let event = std::str::from_utf8(&event).unwrap();
+        assert!(event.starts_with("event: timeout\n"));
+        assert!(event.contains(r#""unique_id":"1","parties":2,"status":"timeout""#));
+
+        // The stream ends on shutdown, so that it doesn't hold the server back
+        state.begin_shutdown();
+        let end = timeout(Duration::from_secs(1), events.frame())
+            .await
+            .unwrap();
+        assert!(end.is_none());
+    }
+
+    #[tokio::test]
+    async fn admin_route_lists_waiting_parties() {
+        let mut settings = Settings::new(Duration::from_millis(500));
+        settings.admin_api_keys = vec!["admin-secret".to_owned()];
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let mut party1_request = make_test_request(1);
+        party1_request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 1234))));
+        let party1_response = tokio::spawn(run_request(&mut app, party1_request).await);
+        sleep(Duration::from_millis(50)).await;
+
+        let admin_response = run_request(&mut app, make_get_request("/admin/waiters"))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(admin_response.status(), StatusCode::UNAUTHORIZED);
+
+        let mut admin_request = make_get_request("/admin/waiters");
+        admin_request
+            .headers_mut()
+            .insert("authorization", "Bearer admin-secret".parse().unwrap());
+        let admin_response = run_request(&mut app, admin_request).await.await.unwrap();
+        assert_eq!(admin_response.status(), StatusCode::OK);
+        let waiters: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(admin_response).await).unwrap();
+        assert_eq!(waiters.as_array().unwrap().len(), 1);
+        assert_eq!(waiters[0]["kind"], "rendezvous");
+        assert_eq!(waiters[0]["unique_id"], "1");
+        assert_eq!(waiters[0]["client"], "10.0.0.1:1234");
+        assert!(waiters[0]["remaining_ms"].as_u64().unwrap() <= 500);
+
+        let party1_response = party1_response.await.unwrap().unwrap();
+        assert_eq!(party1_response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let mut stats_request = make_get_request("/stats");
+        stats_request
+            .headers_mut()
+            .insert("authorization", "Bearer admin-secret".parse().unwrap());
+        let stats_response = run_request(&mut app, stats_request).await.await.unwrap();
+        assert_eq!(stats_response.status(), StatusCode::OK);
+        let stats: serde_json::Value =
+            serde_json::from_slice(&extract_response_body(stats_response).await).unwrap();
+        assert_eq!(stats["waits"], 1);
+        assert_eq!(stats["timeout_rate"], 1.0);
+        assert_eq!(stats["busiest_ids"][0]["unique_id"], "1");
+
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(100)));
+        let admin_response =
+            run_request(&mut app.into_service(), make_get_request("/admin/waiters"))
+                .await
+                .await
+                .unwrap();
+        assert_eq!(admin_response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn chaos_mode_injects_faults_changed_through_the_admin_api() {
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.admin_api_keys = vec!["admin-secret".to_owned()];
+        settings.chaos = Some(Faults {
+            error_rate: 1.0,
+            ..Default::default()
+        });
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+
+        let failed_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(failed_response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let set_faults = |faults: &str| {
+            Request::builder()
+                .uri("/admin/chaos")
+                .method("PUT")
+                .header("authorization", "Bearer admin-secret")
+                .header("content-type", "application/json")
+                .body(Body::from(faults.to_owned()))
+                .unwrap()
+        };
+        let invalid_response = run_request(&mut app, set_faults(r#"{"error_rate": 2}"#))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(invalid_response.status(), StatusCode::BAD_REQUEST);
+        let faults_response = run_request(&mut app, set_faults(r#"{"drop_rate": 1}"#))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(faults_response.status(), StatusCode::OK);
+
+        // The wait goes through, but its response is cut off
+        let dropped_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(dropped_response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(dropped_response.into_body().collect().await.is_err());
+
+        // Without chaos mode, nothing is injected and the faults can't be changed
+        let mut settings = Settings::new(Duration::from_millis(100));
+        settings.admin_api_keys = vec!["admin-secret".to_owned()];
+        let (app, _state) = make_app(settings);
+        let mut app = app.into_service();
+        let faults_response = run_request(&mut app, set_faults(r#"{"error_rate": 1}"#))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(faults_response.status(), StatusCode::NOT_FOUND);
+        let timeout_response = run_request(&mut app, make_test_request(1))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(timeout_response.status(), StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn barrier_times_out_with_missing_parties() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+
+        let party1_request = make_barrier_request(1, 3);
+        let party1_response = run_request(&mut app, party1_request).await;
+
+        let party2_request = make_barrier_request(1, 3);
+        let party2_response = run_request(&mut app, party2_request).await;
+
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+
+        for response in [party1_response, party2_response] {
+            let response = response.unwrap();
+            assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+            assert_eq!(
+                &extract_response_body(response).await[..],
+                TIMEOUT_MESSAGE.as_bytes()
+            );
+        }
+
+        assert!(state.barriers.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn barrier_does_not_count_parties_of_aborted_requests() {
+        let (app, state) = make_app(Settings::new(Duration::from_millis(500)));
+        let mut app = app.into_service();
+
+        let party1_response = tokio::spawn(run_request(&mut app, make_barrier_request(1, 3)).await);
+        let party2_response = tokio::spawn(run_request(&mut app, make_barrier_request(1, 3)).await);
+        sleep(Duration::from_millis(50)).await;
+        party2_response.abort();
+        sleep(Duration::from_millis(50)).await;
+
+        let party3_response = tokio::spawn(run_request(&mut app, make_barrier_request(1, 3)).await);
+        sleep(Duration::from_millis(50)).await;
+        assert!(!party1_response.is_finished());
+        assert!(!party3_response.is_finished());
+        assert_eq!(state.barriers.read().await.waiting(), 2);
+
+        let party4_response = run_request(&mut app, make_barrier_request(1, 3))
+            .await
+            .await
+            .unwrap();
+        assert_eq!(party4_response.status(), StatusCode::OK);
+        for party_response in [party1_response, party3_response] {
+            let party_response = party_response.await.unwrap().unwrap();
+            assert_eq!(party_response.status(), StatusCode::OK);
+        }
+        assert!(state.barriers.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn barrier_times_out_when_its_first_party_does() {
+        let (app, _state) = make_app(Settings::new(Duration::from_secs(10)));
+        let mut app = app.into_service();
+
+        let timed_barrier_request = |timeout_ms: u64| {
+            Request::builder()
+                .uri(format!("/wait-for-parties/1/3?timeout_ms={timeout_ms}"))
+                .method("POST")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let started_at = tokio::time::Instant::now();
+        let party1_response = run_request(&mut app, timed_barrier_request(200)).await;
+        let party2_response = run_request(&mut app, timed_barrier_request(5000)).await;
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+
+        // The second party waits no longer than the budget registered by the first one
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+        for response in [party1_response, party2_response] {
+            assert_eq!(response.unwrap().status(), StatusCode::REQUEST_TIMEOUT);
+        }
+    }
+
+    #[tokio::test]
+    async fn barrier_rejects_mismatched_parties() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+
+        let party1_request = make_barrier_request(1, 3);
+        let party1_response = run_request(&mut app, party1_request).await;
+
+        let party2_request = make_barrier_request(1, 4);
+        let party2_response = run_request(&mut app, party2_request).await;
+
+        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
+        let (party1_response, party2_response) =
+            (party1_response.unwrap(), party2_response.unwrap());
+
+        assert_eq!(party1_response.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(party2_response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            &extract_response_body(party2_response).await[..],
+            MISMATCHED_PARTIES_MESSAGE.as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn barrier_rejects_less_than_two_parties() {
+        let (app, _state) = make_app(Settings::new(Duration::from_millis(100)));
+        let mut app = app.into_service();
+
+        let party1_request = make_barrier_request(1, 1);
+        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
+
+        assert_eq!(party1_response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            &extract_response_body(party1_response).await[..],
+            INVALID_PARTIES_MESSAGE.as_bytes()
+        );
+    }
+
+    fn make_app(settings: Settings) -> (Router, Arc<AppState>) {
+        make_router(AppState::new(settings, Box::new(LocalParties::default())))
+    }
+
+    fn make_barrier_request(unique_id: impl Display, parties: usize) -> Request<Body> {
+        Request::builder()
+            .uri(format!("/wait-for-parties/{}/{}", unique_id, parties))
+            .method("POST")
+            .body(Body::empty())
+            .expect("creating fake request with empty body shouldn't fail")
+    }
+
+    fn make_json_request(unique_id: impl Display) -> Request<Body> {
+        Request::builder()
+            .uri(format!("/wait-for-second-party/{}", unique_id))
+            .method("POST")
+            .header("accept", "application/json")
+            .body(Body::empty())
+            .expect("creating fake request with empty body shouldn't fail")
+    }
+
+    fn make_round_request(unique_id: impl Display, round: u64) -> Request<Body> {
+        Request::builder()
+            .uri(format!(
+                "/wait-for-second-party/{}/round/{}",
+                unique_id, round
+            ))
+            .method("POST")
+            .body(Body::empty())
+            .expect("creating fake request with empty body shouldn't fail")
+    }
+
+    fn make_post_request(uri: &str) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .method("POST")
+            .body(Body::empty())
+            .expect("creating fake request with empty body shouldn't fail")
+    }
+
+    fn make_wait_any_request(ids: &str) -> Request<Body> {
+        Request::builder()
+            .uri("/wait-any")
+            .method("POST")
+            .header("accept", "application/json")
+            .header("content-type", "application/json")
+            .body(Body::from(ids.to_owned()))
+            .expect("creating fake request with a JSON body shouldn't fail")
+    }
+
+    fn make_status_request(unique_id: impl Display) -> Request<Body> {
+        Request::builder()
+            .uri(format!("/wait-for-second-party/{}/status", unique_id))
+            .method("GET")
+            .body(Body::empty())
+            .expect("creating fake request with empty body shouldn't fail")
+    }
+
+    fn make_cancel_request(unique_id: impl Display) -> Request<Body> {
+        Request::builder()
+            .uri(format!("/wait-for-second-party/{}", unique_id))
+            .method("DELETE")
+            .body(Body::empty())
+            .expect("creating fake request with empty body shouldn't fail")
+    }
+
+    async fn next_json_frame(
+        socket: &mut WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>,
+    ) -> serde_json::Value {
+        match socket.next().await {
+            Some(Ok(WsMessage::Text(frame))) => serde_json::from_str(&frame).unwrap(),
+            frame => panic!("expected a text frame, got {frame:?}"),
+        }
+    }
+
+    fn make_get_request(uri: &str) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .method("GET")
+            .body(Body::empty())
+            .expect("creating fake request with empty body shouldn't fail")
+    }
+
+    fn make_sse_request(unique_id: impl Display) -> Request<Body> {
+        Request::builder()
+            .uri(format!("/sse/wait/{}", unique_id))
+            .method("GET")
+            .body(Body::empty())
+            .expect("creating fake request with empty body shouldn't fail")
+    }
+
+    fn make_test_request(unique_id: impl Display) -> Request<Body> {
+        Request::builder()
+            .uri(format!("/wait-for-second-party/{}", unique_id))
+            .method("POST")
+            .body(Body::empty())
+            .expect("creating fake request with empty body shouldn't fail")
+    }
+
+    async fn extract_response_body(response: Response) -> Bytes {
+        response.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    async fn run_request(
+        app: &mut RouterIntoService<Body>,
+        request: Request<Body>,
+    ) -> RouteFuture<Infallible> {
+        ServiceExt::<Request<Body>>::ready(app)
+            .await
+            .unwrap()
+            .call(request)
+    }
+}
@@ -0,0 +1,11 @@
+use std::sync::{Mutex, MutexGuard};
+
+/// Locks `mutex`, even if a holder of the lock panicked.
+///
+/// The state kept behind a mutex is only ever changed in single steps, so it is left consistent
+/// even if a holder panicked.
+pub fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,144 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use axum::extract::State;
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::{lock::lock, response::Outcome, AppState};
+
+const MATCHES: &str = "sync_point_matches_total";
+const TIMEOUTS: &str = "sync_point_timeouts_total";
+const CANCELLATIONS: &str = "sync_point_cancellations_total";
+const EVICTIONS: &str = "sync_point_evictions_total";
+const WAITING_PARTIES: &str = "sync_point_waiting_parties";
+const WAIT_DURATION: &str = "sync_point_wait_duration_seconds";
+
+/// Most namespaces labelled in the metrics, the ones after them being labelled `other`: clients
+/// pick the namespaces, and each label adds series that are never removed.
+const MAX_NAMESPACE_LABELS: usize = 100;
+const OTHER_NAMESPACE: &str = "other";
+
+/// Buckets of the wait duration histogram, in seconds.
+const WAIT_DURATION_BUCKETS: &[f64] = &[0.01, 0.05, 0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Installs the global Prometheus recorder, returning the handle rendering its metrics.
+///
+/// The recorder is process-wide, so later calls return a handle to the same recorder.
+pub fn install() -> PrometheusHandle {
+    static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+    HANDLE
+        .get_or_init(|| {
+            PrometheusBuilder::new()
+                .set_buckets_for_metric(
+                    Matcher::Full(WAIT_DURATION.to_owned()),
+                    WAIT_DURATION_BUCKETS,
+                )
+                .expect("buckets should not be empty")
+                .install_recorder()
+                .expect("no other metrics recorder should be installed")
+        })
+        .clone()
+}
+
+/// Renders the metrics in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "Metrics in the Prometheus text format", body = String)),
+)]
+pub async fn render_metrics(State(state): State<Arc<AppState>>) -> String {
+    state.metrics.render()
+}
+
+/// Records the final outcome of a wait, labelled by its namespace if it has one.
+pub fn record(outcome: &Outcome, namespace: Option<&str>) {
+    let (counter_name, waited_ms) = match *outcome {
+        Outcome::Matched { waited_ms, .. } | Outcome::Released { waited_ms, .. } => {
+            (MATCHES, waited_ms)
+        }
+        Outcome::Timeout { waited_ms, .. } => (TIMEOUTS, waited_ms),
+        Outcome::Cancelled { waited_ms } => (CANCELLATIONS, waited_ms),
+        Outcome::Waiting { .. }
+        | Outcome::ShuttingDown { .. }
+        | Outcome::Superseded { .. }
+        | Outcome::Missed { .. }
+        | Outcome::Overloaded { .. }
+        | Outcome::Error { .. } => return,
+    };
+
+    let waited_secs = waited_ms as f64 / 1000.0;
+    match namespace {
+        Some(namespace) => {
+            let namespace = namespace_label(namespace);
+            counter!(counter_name, "namespace" => namespace.clone()).increment(1);
+            histogram!(WAIT_DURATION, "outcome" => outcome.status(), "namespace" => namespace)
+                .record(waited_secs);
+        }
+        None => {
+            counter!(counter_name).increment(1);
+            histogram!(WAIT_DURATION, "outcome" => outcome.status()).record(waited_secs);
+        }
+    }
+}
+
+/// Label of `namespace`, which is `other` once `MAX_NAMESPACE_LABELS` other namespaces were
+/// labelled.
+fn namespace_label(namespace: &str) -> String {
+    static LABELLED: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+    let mut labelled = lock(LABELLED.get_or_init(Mutex::default));
+    label_within(&mut labelled, namespace, MAX_NAMESPACE_LABELS)
+}
+
+/// Label of `namespace` among the `labelled` ones, which are kept to `max` at most.
+fn label_within(labelled: &mut HashSet<String>, namespace: &str, max: usize) -> String {
+    if !labelled.contains(namespace) {
+        if labelled.len() >= max {
+            return OTHER_NAMESPACE.to_owned();
+        }
+        labelled.insert(namespace.to_owned());
+    }
+    namespace.to_owned()
+}
+
+/// Records the stale parties evicted by the sweeper.
+pub fn record_evictions(evicted: usize) {
+    counter!(EVICTIONS).increment(evicted as u64);
+}
+
+/// Counts a party as waiting until dropped, even if its request is aborted.
+pub struct Waiting(());
+
+impl Waiting {
+    pub fn start() -> Self {
+        gauge!(WAITING_PARTIES).increment(1);
+        Waiting(())
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        gauge!(WAITING_PARTIES).decrement(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_past_the_limit_are_labelled_other() {
+        let mut labelled = HashSet::new();
+
+        assert_eq!(label_within(&mut labelled, "team-a", 2), "team-a");
+        assert_eq!(label_within(&mut labelled, "team-b", 2), "team-b");
+        assert_eq!(label_within(&mut labelled, "team-c", 2), "other");
+        assert_eq!(label_within(&mut labelled, "team-a", 2), "team-a");
+        assert_eq!(labelled.len(), 2);
+    }
+}
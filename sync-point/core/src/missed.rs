@@ -0,0 +1,69 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::time::Instant;
+
+use crate::{lock::lock, store::Peer, UniqueId};
+
+/// Party that timed out waiting on an id.
+struct MissedParty {
+    peer: Peer,
+    idempotency_key: Option<String>,
+    /// When the party gave up, in milliseconds since the Unix epoch.
+    left_at_ms: u64,
+    /// End of the grace window.
+    until: Instant,
+}
+
+/// `MissedParties` remembers the parties that timed out until their grace window ends, so that
+/// the next party arriving on their id learns it just missed them instead of starting a new wait.
+#[derive(Default)]
+pub struct MissedParties(Mutex<HashMap<UniqueId, MissedParty>>);
+
+impl MissedParties {
+    /// Remembers that the party described by `peer` timed out on `unique_id`, until `until`.
+    pub fn leave(
+        &self,
+        unique_id: &str,
+        peer: Peer,
+        idempotency_key: Option<&str>,
+        until: Instant,
+    ) {
+        let left_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis() as u64)
+            .unwrap_or_default();
+        let party = MissedParty {
+            peer,
+            idempotency_key: idempotency_key.map(str::to_owned),
+            left_at_ms,
+            until,
+        };
+        lock(&self.0).insert(unique_id.to_owned(), party);
+    }
+
+    /// Takes the party that timed out on `unique_id` within its grace window, along with when it
+    /// left.
+    ///
+    /// A party that timed out with the same `idempotency_key` is an earlier attempt of the arriving
+    /// one, which is forgotten instead.
+    pub fn take(&self, unique_id: &str, idempotency_key: Option<&str>) -> Option<(Peer, u64)> {
+        let party = lock(&self.0).remove(unique_id)?;
+        let retried =
+            idempotency_key.is_some() && party.idempotency_key.as_deref() == idempotency_key;
+        if retried || party.until <= Instant::now() {
+            return None;
+        }
+
+        Some((party.peer, party.left_at_ms))
+    }
+
+    /// Forgets the parties whose grace window is over.
+    pub fn evict_expired(&self) {
+        let now = Instant::now();
+        lock(&self.0).retain(|_, party| party.until > now);
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Extension,
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    id::ValidId,
+    lock::lock,
+    parties::{wait_for_second_party, WaitQuery},
+    quotas::QuotaHold,
+    response::{Outcome, ResponseFormat, INVALID_NAMESPACE_MESSAGE},
+    AppState, UniqueId,
+};
+
+/// Maximum length of a namespace name.
+const MAX_NAMESPACE_LENGTH: usize = 64;
+
+/// Prefix of the ids of the namespaced rendezvous.
+const NAMESPACE_PREFIX: &str = "ns/";
+
+/// Waits for another party on `unique_id` within `namespace`, isolated from the same id in other
+/// namespaces.
+#[utoipa::path(
+    post,
+    path = "/ns/{namespace}/wait-for-second-party/{unique-id}",
+    tag = "waits",
+    params(
+        ("namespace" = String, Path, description = "Letters, digits, dashes or underscores"),
+        ("unique-id" = String, Path, description = "Id shared by the two parties"),
+        WaitQuery,
+    ),
+    responses(
+        (status = 200, description = "Matched with another party", body = Outcome),
+        (status = 400, description = "Invalid namespace or id", body = Outcome),
+        (status = 408, description = "No other party arrived in time", body = Outcome),
+        (status = 503, description = "Too many parties wait in the namespace", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn sync_namespaced(
+    ValidId(unique_id): ValidId,
+    Path(NamespacePath { namespace }): Path<NamespacePath>,
+    Query(query): Query<WaitQuery>,
+    State(state): State<Arc<AppState>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    quota: Option<Extension<QuotaHold>>,
+    headers: HeaderMap,
+    format: ResponseFormat,
+) -> Response {
+    if !is_valid_namespace(&namespace) {
+        warn!(namespace, "Invalid namespace");
+        return format.reply(
+            StatusCode::BAD_REQUEST,
+            Outcome::error(INVALID_NAMESPACE_MESSAGE),
+        );
+    }
+
+    let Some(_slot) = state
+        .namespace_waiters
+        .enter(&namespace, state.settings().max_waiters_per_namespace)
+    else {
+        warn!(namespace, "Too many waiting parties in the namespace");
+        return format.reply(
+            StatusCode::SERVICE_UNAVAILABLE,
+            Outcome::overloaded(state.retry_after()),
+        );
+    };
+
+    let client = client.map(|ConnectInfo(client)| client);
+    let quota = quota.map(|Extension(quota)| quota);
+    let unique_id = namespaced_id(&namespace, &unique_id);
+    wait_for_second_party(&state, &unique_id, query, client, quota, &headers, format).await
+}
+
+/// Parameters of the namespaced route besides the unique id.
+#[derive(Deserialize)]
+pub struct NamespacePath {
+    namespace: String,
+}
+
+/// Namespaces are made of ASCII letters, digits, dashes and underscores, so that they can't
+/// contain the `/` separating them from the ids.
+fn is_valid_namespace(namespace: &str) -> bool {
+    (1..=MAX_NAMESPACE_LENGTH).contains(&namespace.len())
+        && namespace
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+}
+
+/// Id of the rendezvous of `unique_id` within `namespace`, mirroring its route.
+fn namespaced_id(namespace: &str, unique_id: &str) -> UniqueId {
+    format!("{NAMESPACE_PREFIX}{namespace}/{unique_id}")
+}
+
+/// Namespace of a rendezvous id, if it was namespaced.
+pub fn namespace_of(unique_id: &str) -> Option<&str> {
+    let (namespace, _) = unique_id.strip_prefix(NAMESPACE_PREFIX)?.split_once('/')?;
+    Some(namespace)
+}
+
+/// `NamespaceWaiters` counts the parties waiting in each namespace, to enforce its limit.
+#[derive(Default)]
+pub struct NamespaceWaiters(Mutex<HashMap<String, usize>>);
+
+impl NamespaceWaiters {
+    /// Counts a party as waiting in `namespace` until the returned slot is dropped, unless
+    /// `limit` parties already are. A `limit` of zero never turns parties away.
+    pub fn enter(&self, namespace: &str, limit: usize) -> Option<NamespaceSlot<'_>> {
+        let mut waiting = lock(&self.0);
+        let count = waiting.entry(namespace.to_owned()).or_default();
+        if limit != 0 && *count >= limit {
+            return None;
+        }
+
+        *count += 1;
+        Some(NamespaceSlot {
+            waiters: self,
+            namespace: namespace.to_owned(),
+        })
+    }
+}
+
+/// Keeps a party counted as waiting in its namespace until dropped.
+pub struct NamespaceSlot<'a> {
+    waiters: &'a NamespaceWaiters,
+    namespace: String,
+}
+
+impl Drop for NamespaceSlot<'_> {
+    fn drop(&mut self) {
+        let mut waiting = lock(&self.waiters.0);
+        if let Some(count) = waiting.get_mut(&self.namespace) {
+            *count -= 1;
+            if *count == 0 {
+                waiting.remove(&self.namespace);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_namespaces() {
+        assert!(is_valid_namespace("team-a_1"));
+        assert!(!is_valid_namespace(""));
+        assert!(!is_valid_namespace("team/a"));
+        assert!(!is_valid_namespace(&"a".repeat(MAX_NAMESPACE_LENGTH + 1)));
+    }
+
+    #[test]
+    fn namespaced_ids_keep_their_namespace() {
+        assert_eq!(namespace_of(&namespaced_id("team-a", "1")), Some("team-a"));
+        assert_eq!(
+            namespace_of(&namespaced_id("team-a", "1/round/2")),
+            Some("team-a")
+        );
+        assert_eq!(namespace_of("1"), None);
+    }
+}
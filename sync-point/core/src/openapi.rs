@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use axum::Router;
+use utoipa::{
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::AppState;
+
+/// OpenAPI document of the HTTP API, from which client teams can generate their SDKs.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Sync point",
+        description = "Rendezvous of parties waiting on a shared id. Wait routes answer in plain \
+            text unless the client accepts `application/json`."
+    ),
+    paths(
+        crate::parties::sync_parties,
+        crate::parties::cancel_party,
+        crate::parties::party_status,
+        crate::rounds::sync_round,
+        crate::namespaces::sync_namespaced,
+        crate::barrier::sync_barrier,
+        crate::release::wait_for_release,
+        crate::release::release,
+        crate::ws::ws_wait,
+        crate::sse::sse_wait,
+        crate::wait_any::wait_any,
+        crate::receipts::receipt,
+        crate::admin::list_waiters,
+        crate::events::stream_events,
+        crate::chaos::chaos_faults,
+        crate::chaos::set_chaos_faults,
+        crate::stats::render_stats,
+        crate::metrics::render_metrics,
+        crate::health::healthz,
+        crate::health::readyz,
+    ),
+    modifiers(&SecuritySchemes)
+)]
+struct ApiDoc;
+
+/// Routes of the rendezvous history, only served with the `history` feature.
+#[cfg(feature = "history")]
+#[derive(OpenApi)]
+#[openapi(paths(crate::history::history))]
+struct HistoryApiDoc;
+
+/// Declares the bearer tokens expected by the wait and admin routes.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        for name in ["api_key", "admin_key"] {
+            components.add_security_scheme(
+                name,
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+        }
+    }
+}
+
+/// The OpenAPI document, covering the routes of the enabled features.
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    #[cfg_attr(not(feature = "history"), allow(unused_mut))]
+    let mut openapi = ApiDoc::openapi();
+    #[cfg(feature = "history")]
+    openapi.merge(HistoryApiDoc::openapi());
+    openapi
+}
+
+/// Serves the OpenAPI document at `/openapi.json` and Swagger UI at `/docs`.
+pub fn routes() -> Router<Arc<AppState>> {
+    SwaggerUi::new("/docs")
+        .url("/openapi.json", openapi())
+        .into()
+}
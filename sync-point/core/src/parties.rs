@@ -0,0 +1,1005 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    hash::{BuildHasher, RandomState},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde::Deserialize;
+use tokio::{
+    sync::{oneshot, RwLock},
+    time::{timeout, Instant},
+};
+use tracing::{field::Empty, info, info_span, warn, Instrument};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    admin::WaitKind,
+    callback,
+    id::ValidId,
+    keepalive::reply_with_heartbeats,
+    quotas::QuotaHold,
+    receipts::{self, with_receipt},
+    relay,
+    response::{
+        Outcome, ResponseFormat, Role, ALREADY_MATCHED_MESSAGE, INVALID_IDEMPOTENCY_KEY_MESSAGE,
+        INVALID_PARTY_LABEL_MESSAGE, INVALID_PUBLIC_KEY_MESSAGE, NOT_WAITING_MESSAGE,
+        STORE_UNAVAILABLE_MESSAGE,
+    },
+    store::{match_token, Arrival, PartyStore, Peer, StoreError, WaitStatus, Waiter, Wake},
+    trace::request_id,
+    AppState, UniqueId,
+};
+
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const PARTY_LABEL_HEADER: &str = "x-party-label";
+const PARTY_PUBLIC_KEY_HEADER: &str = "x-party-public-key";
+
+/// Maximum length of the public keys parties send along, enough for the keys of most schemes.
+const MAX_PUBLIC_KEY_LENGTH: usize = 512;
+
+/// Number of independently locked shards of the in-memory parties.
+const SHARDS: usize = 64;
+
+/// `WaitingParty` is a party blocked until another one arrives with the same `UniqueId`.
+struct WaitingParty {
+    /// Identifies the party in its queue, so that it only ever withdraws itself.
+    ticket: u64,
+    wake: oneshot::Sender<Wake>,
+    idempotency_key: Option<String>,
+    peer: Peer,
+    deadline: Instant,
+}
+
+impl WaitingParty {
+    /// A closed channel means the party went away (e.g. disconnected) without cleaning up.
+    fn is_gone(&self) -> bool {
+        self.wake.is_closed()
+    }
+}
+
+/// `WaitingParties` holds the parties waiting on each `UniqueId`, queued in their order of arrival
+/// so that arrivals pair strictly in order: the first with the second, the third with the fourth,
+/// and so on.
+#[derive(Default)]
+struct WaitingParties {
+    queues: HashMap<UniqueId, VecDeque<WaitingParty>>,
+    next_ticket: u64,
+}
+
+impl WaitingParties {
+    /// Removes the first party queued on `unique_id` and wakes it up with `reason`, unless it
+    /// waits with `idempotency_key`, in which case it's superseded. Parties that went away are
+    /// skipped.
+    ///
+    /// Returns how the party was woken up along with its peer, or `None` if no party was waiting.
+    fn wake(
+        &mut self,
+        unique_id: &str,
+        reason: Wake,
+        idempotency_key: Option<&str>,
+    ) -> Option<(Wake, Peer)> {
+        let queue = self.queues.get_mut(unique_id)?;
+        let mut woken = None;
+        while let Some(party) = queue.pop_front() {
+            let reason = match idempotency_key {
+                Some(_) if party.idempotency_key.as_deref() == idempotency_key => Wake::Superseded,
+                _ => reason.clone(),
+            };
+
+            // Sending only fails if the party went away without cleaning up
+            if party.wake.send(reason.clone()).is_ok() {
+                woken = Some((reason, party.peer));
+                break;
+            }
+        }
+
+        if queue.is_empty() {
+            self.queues.remove(unique_id);
+        }
+        woken
+    }
+
+    /// Queues a party on `unique_id`, returning its ticket and the channel it's woken up on.
+    fn insert(
+        &mut self,
+        unique_id: UniqueId,
+        deadline: Instant,
+        idempotency_key: Option<&str>,
+        peer: &Peer,
+    ) -> (u64, oneshot::Receiver<Wake>) {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+
+        let (wake, woken) = oneshot::channel();
+        let waiting_party = WaitingParty {
+            ticket,
+            wake,
+            idempotency_key: idempotency_key.map(str::to_owned),
+            peer: peer.clone(),
+            deadline,
+        };
+        self.queues
+            .entry(unique_id)
+            .or_default()
+            .push_back(waiting_party);
+        (ticket, woken)
+    }
+
+    /// Removes the party holding `ticket` from the queue of `unique_id`.
+    fn remove(&mut self, unique_id: &str, ticket: u64) {
+        if let Some(queue) = self.queues.get_mut(unique_id) {
+            queue.retain(|party| party.ticket != ticket);
+            if queue.is_empty() {
+                self.queues.remove(unique_id);
+            }
+        }
+    }
+
+    /// Number of parties in the queues.
+    fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    /// Removes the parties that went away without cleaning up, or are past their deadline by more
+    /// than `grace`.
+    fn evict_stale(&mut self, grace: Duration) -> usize {
+        let now = Instant::now();
+        let before = self.len();
+        self.queues.retain(|_, queue| {
+            queue.retain(|party| !party.is_gone() && party.deadline + grace > now);
+            !queue.is_empty()
+        });
+        before - self.len()
+    }
+
+    /// State of the rendezvous, as seen by the next party to arrive.
+    fn status(&self, unique_id: &str) -> WaitStatus {
+        let next = self
+            .queues
+            .get(unique_id)
+            .and_then(|queue| queue.iter().find(|party| !party.is_gone()));
+
+        match next {
+            Some(party) => WaitStatus {
+                waiting: true,
+                arrived_at_ms: Some(party.peer.arrived_at_ms),
+                remaining_ms: Some(
+                    party
+                        .deadline
+                        .saturating_duration_since(Instant::now())
+                        .as_millis() as u64,
+                ),
+            },
+            None => WaitStatus::default(),
+        }
+    }
+}
+
+/// `LocalParties` keeps the waiting parties in memory, so only parties reaching the same
+/// instance can match.
+///
+/// The parties are spread over shards by id, each behind its own lock, so that requests on
+/// different ids rarely wait for each other.
+pub struct LocalParties {
+    shards: Box<[Arc<RwLock<WaitingParties>>]>,
+    hasher: RandomState,
+}
+
+impl Default for LocalParties {
+    fn default() -> Self {
+        LocalParties::with_shards(SHARDS)
+    }
+}
+
+impl LocalParties {
+    pub fn with_shards(shards: usize) -> Self {
+        LocalParties {
+            shards: (0..shards.max(1)).map(|_| Default::default()).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Shard holding the party waiting on `unique_id`.
+    fn shard(&self, unique_id: &str) -> &Arc<RwLock<WaitingParties>> {
+        let hash = self.hasher.hash_one(unique_id);
+        &self.shards[hash as usize % self.shards.len()]
+    }
+}
+
+#[async_trait]
+impl PartyStore for LocalParties {
+    async fn arrive(
+        &self,
+        unique_id: &str,
+        deadline: Instant,
+        idempotency_key: Option<&str>,
+        peer: &Peer,
+    ) -> Result<Arrival, StoreError> {
+        let shard = self.shard(unique_id);
+        let mut waiting_parties = shard.write().await;
+
+        let token = match_token();
+        let reason = Wake::Matched(peer.clone(), token.clone());
+        if let Some((Wake::Matched(..), waiting)) =
+            waiting_parties.wake(unique_id, reason, idempotency_key)
+        {
+            return Ok(Arrival::Matched(waiting, token));
+        }
+
+        // There is no other party waiting for this id, so we queue up for the next one
+        let (ticket, woken) =
+            waiting_parties.insert(unique_id.to_owned(), deadline, idempotency_key, peer);
+        Ok(Arrival::Wait(Box::new(LocalWaiter {
+            parties: shard.clone(),
+            unique_id: unique_id.to_owned(),
+            ticket,
+            woken,
+        })))
+    }
+
+    async fn cancel(&self, unique_id: &str) -> Result<bool, StoreError> {
+        Ok(self
+            .shard(unique_id)
+            .write()
+            .await
+            .wake(unique_id, Wake::Cancelled, None)
+            .is_some())
+    }
+
+    async fn status(&self, unique_id: &str) -> Result<WaitStatus, StoreError> {
+        Ok(self.shard(unique_id).read().await.status(unique_id))
+    }
+
+    async fn waiting(&self) -> usize {
+        let mut waiting = 0;
+        for shard in self.shards.iter() {
+            waiting += shard.read().await.len();
+        }
+        waiting
+    }
+
+    async fn evict_stale(&self, grace: Duration) -> usize {
+        let mut evicted = 0;
+        for shard in self.shards.iter() {
+            evicted += shard.write().await.evict_stale(grace);
+        }
+        evicted
+    }
+}
+
+struct LocalWaiter {
+    parties: Arc<RwLock<WaitingParties>>,
+    unique_id: UniqueId,
+    ticket: u64,
+    woken: oneshot::Receiver<Wake>,
+}
+
+#[async_trait]
+impl Waiter for LocalWaiter {
+    async fn woken(&mut self) -> Option<Wake> {
+        (&mut self.woken).await.ok()
+    }
+
+    async fn withdraw(mut self: Box<Self>) -> Option<Wake> {
+        let mut waiting_parties = self.parties.write().await;
+        // We may have been woken up right as we gave up
+        let wake = self.woken.try_recv().ok();
+        if wake.is_none() {
+            // We are still queued, so we clean up
+            waiting_parties.remove(&self.unique_id, self.ticket);
+        }
+        wake
+    }
+}
+
+/// Query parameters of the wait routes.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WaitQuery {
+    /// Timeout of this wait in milliseconds, instead of the configured one. Barriers and release
+    /// gates time out with their first party.
+    pub timeout_ms: Option<u64>,
+    /// Whether to wait or only report whether a party is waiting.
+    #[serde(default)]
+    #[param(inline)]
+    pub mode: WaitMode,
+    /// URL to post the outcome to, answering right away instead of waiting.
+    pub callback: Option<String>,
+    /// Whether to answer `200 OK` right away and send a newline every keepalive interval until
+    /// the outcome, for proxies closing idle connections.
+    #[serde(default)]
+    pub keepalive: bool,
+}
+
+/// How a rendezvous request waits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitMode {
+    /// Waits until another party arrives.
+    #[default]
+    Wait,
+    /// Only reports whether a party is waiting, without waiting nor matching it.
+    Probe,
+}
+
+#[utoipa::path(
+    post,
+    path = "/wait-for-second-party/{unique-id}",
+    tag = "waits",
+    params(
+        ("unique-id" = String, Path, description = "Id shared by the two parties"),
+        WaitQuery,
+        ("Idempotency-Key" = Option<String>, Header, description = "Same for every attempt of a wait"),
+        ("X-Party-Label" = Option<String>, Header, description = "Label shown to the matched party"),
+        ("X-Party-Public-Key" = Option<String>, Header, description = "Public key handed to the matched party, for it to encrypt payloads to this one"),
+    ),
+    responses(
+        (status = 200, description = "Matched with another party, a party is waiting when probing, or any outcome after the heartbeats with `keepalive`", body = Outcome),
+        (status = 202, description = "Waiting in the background, the outcome is posted to the callback", body = Outcome),
+        (status = 204, description = "No party is waiting, when probing"),
+        (status = 400, description = "Invalid id, header, timeout or callback", body = Outcome),
+        (status = 408, description = "No other party arrived in time", body = Outcome),
+        (status = 409, description = "Taken over by a retry, or the id already matched in strict mode", body = Outcome),
+        (status = 410, description = "The wait was cancelled, or the other party timed out shortly before", body = Outcome),
+        (status = 503, description = "Shutting down, overloaded or the party store is unavailable", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn sync_parties(
+    ValidId(unique_id): ValidId,
+    Query(query): Query<WaitQuery>,
+    State(state): State<Arc<AppState>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    quota: Option<Extension<QuotaHold>>,
+    headers: HeaderMap,
+    format: ResponseFormat,
+) -> Response {
+    let client = client.map(|ConnectInfo(client)| client);
+    let quota = quota.map(|Extension(quota)| quota);
+    wait_for_second_party(&state, &unique_id, query, client, quota, &headers, format).await
+}
+
+/// Answers a wait request on `unique_id`, which was already checked.
+///
+/// `quota` is kept until the wait ends, even when it goes on after the response.
+pub async fn wait_for_second_party(
+    state: &Arc<AppState>,
+    unique_id: &str,
+    query: WaitQuery,
+    client: Option<SocketAddr>,
+    quota: Option<QuotaHold>,
+    headers: &HeaderMap,
+    format: ResponseFormat,
+) -> Response {
+    if query.mode == WaitMode::Probe {
+        return probe(state, unique_id, format).await;
+    }
+
+    // The idempotency key identifies the attempts of a same wait
+    let idempotency_key = match id_header(
+        state,
+        headers,
+        IDEMPOTENCY_KEY_HEADER,
+        INVALID_IDEMPOTENCY_KEY_MESSAGE,
+    ) {
+        Ok(idempotency_key) => idempotency_key,
+        Err(message) => return format.reply(StatusCode::BAD_REQUEST, Outcome::error(message)),
+    };
+
+    let label = match id_header(
+        state,
+        headers,
+        PARTY_LABEL_HEADER,
+        INVALID_PARTY_LABEL_MESSAGE,
+    ) {
+        Ok(label) => label,
+        Err(message) => return format.reply(StatusCode::BAD_REQUEST, Outcome::error(message)),
+    };
+
+    let public_key = match public_key(headers) {
+        Ok(public_key) => public_key,
+        Err(message) => return format.reply(StatusCode::BAD_REQUEST, Outcome::error(message)),
+    };
+
+    let wait_timeout = match state.wait_timeout(query.timeout_ms) {
+        Ok(wait_timeout) => wait_timeout,
+        Err(message) => return format.reply(StatusCode::BAD_REQUEST, Outcome::error(message)),
+    };
+
+    let peer = Peer {
+        public_key: public_key.map(str::to_owned),
+        ..Peer::new(client, label)
+    };
+    let receipt = receipts::issue(&state.settings());
+    if let Some(callback) = query.callback {
+        let callback = match callback::parse(state, &callback) {
+            Ok(callback) => callback,
+            Err(message) => return format.reply(StatusCode::BAD_REQUEST, Outcome::error(message)),
+        };
+        let wait = owned_rendezvous(
+            state,
+            unique_id,
+            wait_timeout,
+            idempotency_key,
+            peer,
+            request_id(headers),
+            receipt.clone(),
+        );
+        let wait = async move {
+            let _quota = quota;
+            wait.await
+        };
+        callback::spawn_posting(state.clone(), unique_id.to_owned(), wait, callback);
+        let response = format.reply(StatusCode::ACCEPTED, Outcome::waiting(Duration::ZERO));
+        return with_receipt(response, receipt.as_deref());
+    }
+
+    if query.keepalive {
+        let keepalive_interval = state.settings().keepalive_interval;
+        let wait = owned_rendezvous(
+            state,
+            unique_id,
+            wait_timeout,
+            idempotency_key,
+            peer,
+            request_id(headers),
+            receipt.clone(),
+        );
+        let (state, unique_id) = (state.clone(), unique_id.to_owned());
+        let wait = async move {
+            let _quota = quota;
+            format.body(&wait.await, &unique_id, &state.settings().messages)
+        };
+        let response = reply_with_heartbeats(wait, keepalive_interval, format);
+        // Sent right away with the heartbeats, so that the party can recover the outcome
+        return with_receipt(response, receipt.as_deref());
+    }
+
+    let (status, outcome) = rendezvous(
+        state,
+        unique_id,
+        wait_timeout,
+        idempotency_key,
+        peer,
+        request_id(headers),
+        receipt.as_deref(),
+    )
+    .await;
+    let response = format.reply_templated(status, outcome, unique_id, &state.settings().messages);
+    with_receipt(response, receipt.as_deref())
+}
+
+/// [`rendezvous`] owning its arguments, to run in the background.
+fn owned_rendezvous(
+    state: &Arc<AppState>,
+    unique_id: &str,
+    wait_timeout: Duration,
+    idempotency_key: Option<&str>,
+    peer: Peer,
+    request_id: Option<&str>,
+    receipt: Option<String>,
+) -> impl Future<Output = Outcome> + Send + 'static {
+    let (state, unique_id) = (state.clone(), unique_id.to_owned());
+    let idempotency_key = idempotency_key.map(str::to_owned);
+    let request_id = request_id.map(str::to_owned);
+    async move {
+        let (_, outcome) = rendezvous(
+            &state,
+            &unique_id,
+            wait_timeout,
+            idempotency_key.as_deref(),
+            peer,
+            request_id.as_deref(),
+            receipt.as_deref(),
+        )
+        .await;
+        outcome
+    }
+}
+
+/// Reads an optional header which, like the unique ids, must be printable, non-empty and no
+/// longer than `max_id_length`, failing with `message` otherwise.
+fn id_header<'a>(
+    state: &AppState,
+    headers: &'a HeaderMap,
+    name: &str,
+    message: &'static str,
+) -> Result<Option<&'a str>, &'static str> {
+    let Some(value) = headers.get(name) else {
+        return Ok(None);
+    };
+
+    match value.to_str() {
+        Ok(value) if !value.is_empty() && value.len() <= state.settings().max_id_length => {
+            Ok(Some(value))
+        }
+        _ => {
+            warn!(header = name, "Invalid header");
+            Err(message)
+        }
+    }
+}
+
+/// Reads the public key a party sent along, opaque to the server as long as it's printable.
+fn public_key(headers: &HeaderMap) -> Result<Option<&str>, &'static str> {
+    let Some(value) = headers.get(PARTY_PUBLIC_KEY_HEADER) else {
+        return Ok(None);
+    };
+
+    match value.to_str() {
+        Ok(public_key)
+            if (1..=MAX_PUBLIC_KEY_LENGTH).contains(&public_key.len())
+                && public_key.bytes().all(|byte| byte.is_ascii_graphic()) =>
+        {
+            Ok(Some(public_key))
+        }
+        _ => {
+            warn!(header = PARTY_PUBLIC_KEY_HEADER, "Invalid header");
+            Err(INVALID_PUBLIC_KEY_MESSAGE)
+        }
+    }
+}
+
+/// Waits on `unique_id` until another party arrives, the wait is cancelled, times out after
+/// `wait_timeout` or the server shuts down.
+///
+/// Returns immediately if a party was already waiting on `unique_id`. A party waiting with the
+/// same `idempotency_key` is taken over instead of matched. Matched parties are told about each
+/// other's `peer`. In strict mode, parties arriving on an id that matched less than
+/// `strict_grace` ago are rejected. Parties arriving less than `missed_grace` after a party timed
+/// out on the id are told they missed it instead of waiting.
+///
+/// The start and end of the wait are recorded in the audit log along with `request_id`, and the
+/// outcome is kept under the `receipt` handed to the party, if any. A match is also parked under
+/// `idempotency_key`, so that a retry whose previous attempt matched gets the match right away,
+/// should the response of the previous attempt have been lost along with its connection.
+pub async fn rendezvous(
+    state: &AppState,
+    unique_id: &str,
+    wait_timeout: Duration,
+    idempotency_key: Option<&str>,
+    peer: Peer,
+    request_id: Option<&str>,
+    receipt: Option<&str>,
+) -> (StatusCode, Outcome) {
+    let retention = state.settings().receipt_retention;
+    if let Some(outcome) =
+        idempotency_key.and_then(|key| state.receipts.parked_match(unique_id, key))
+    {
+        info!(unique_id, "Replaying the match of a previous attempt");
+        if let Some(receipt) = receipt {
+            let until = Instant::now() + retention;
+            state.receipts.record(receipt, unique_id, &outcome, until);
+        }
+        return (StatusCode::OK, outcome);
+    }
+
+    let audited = state.audit.as_ref().map(|audit| {
+        audit.started(unique_id, request_id, &peer);
+        (audit, peer.clone())
+    });
+
+    let span = info_span!(
+        "wait",
+        unique_id,
+        parties = 2,
+        outcome = Empty,
+        waited_ms = Empty
+    );
+    let (status, outcome) = wait_for_party(state, unique_id, wait_timeout, idempotency_key, peer)
+        .instrument(span.clone())
+        .await;
+    state.record_outcome(&span, unique_id, 2, &outcome);
+    let until = Instant::now() + retention;
+    if let Some(receipt) = receipt {
+        state.receipts.record(receipt, unique_id, &outcome, until);
+    }
+    if let (Some(key), Outcome::Matched { .. }) = (idempotency_key, &outcome) {
+        if !retention.is_zero() {
+            state.receipts.park_match(unique_id, key, &outcome, until);
+        }
+    }
+    if let Some((audit, party)) = audited {
+        audit.ended(unique_id, request_id, &party, &outcome);
+    }
+    (status, outcome)
+}
+
+async fn wait_for_party(
+    state: &AppState,
+    unique_id: &str,
+    wait_timeout: Duration,
+    idempotency_key: Option<&str>,
+    peer: Peer,
+) -> (StatusCode, Outcome) {
+    let arrived_at = Instant::now();
+    if state.is_shutting_down() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Outcome::shutting_down(arrived_at.elapsed()),
+        );
+    }
+
+    if state.consumed.is_consumed(unique_id) {
+        warn!("Parties on this id already matched");
+        return (
+            StatusCode::CONFLICT,
+            Outcome::error(ALREADY_MATCHED_MESSAGE),
+        );
+    }
+
+    if let Some((missed, left_at_ms)) = state.missed.take(unique_id, idempotency_key) {
+        info!(left_at_ms, "Missed the other party");
+        return (StatusCode::GONE, Outcome::missed(missed, left_at_ms));
+    }
+
+    let deadline = arrived_at + wait_timeout;
+    let mut waiter = match state
+        .parties
+        .arrive(unique_id, deadline, idempotency_key, &peer)
+        .await
+    {
+        Ok(Arrival::Matched(waiting, token)) => {
+            info!("Found matching party");
+            if !state.settings().strict_grace.is_zero() {
+                state
+                    .consumed
+                    .consume(unique_id, Instant::now() + state.settings().strict_grace);
+            }
+
+            return (
+                StatusCode::OK,
+                matched(state, Role::Second, arrived_at.elapsed(), waiting, token),
+            );
+        }
+        Ok(Arrival::Wait(waiter)) => waiter,
+        Err(err) => return store_unavailable(err),
+    };
+
+    let Some(_permit) = state.waiter_permit() else {
+        // Another party may have matched us right as we arrived
+        return match waiter.withdraw().await {
+            Some(Wake::Matched(arriving, token)) => (
+                StatusCode::OK,
+                matched(state, Role::First, arrived_at.elapsed(), arriving, token),
+            ),
+            _ => state.overloaded(),
+        };
+    };
+
+    info!("Waiting for another party");
+    let _active =
+        state
+            .active_waiters
+            .register(WaitKind::Rendezvous, unique_id, deadline, peer.client);
+
+    // We will wait patiently up to the timeout for someone else to connect,
+    // unless the server shuts down first
+    let woken_up = tokio::select! {
+        woken_up = timeout(wait_timeout, waiter.woken()) => woken_up.ok(),
+        _ = state.shutting_down() => None,
+    };
+
+    let wake = match woken_up {
+        Some(wake) => wake,
+        None => waiter.withdraw().await,
+    };
+
+    match wake {
+        Some(Wake::Matched(arriving, token)) => {
+            info!("Successfully synchronized parties");
+            (
+                StatusCode::OK,
+                matched(state, Role::First, arrived_at.elapsed(), arriving, token),
+            )
+        }
+        Some(Wake::Cancelled) => {
+            info!("Wait was cancelled");
+            (StatusCode::GONE, Outcome::cancelled(arrived_at.elapsed()))
+        }
+        Some(Wake::Superseded) => {
+            info!("Wait was taken over by a retry");
+            (
+                StatusCode::CONFLICT,
+                Outcome::superseded(arrived_at.elapsed()),
+            )
+        }
+        None if state.is_shutting_down() => {
+            info!("Released waiting party on shutdown");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Outcome::shutting_down(arrived_at.elapsed()),
+            )
+        }
+        None => {
+            warn!("Timeout waiting for other party");
+            if !state.settings().missed_grace.is_zero() {
+                state.missed.leave(
+                    unique_id,
+                    peer,
+                    idempotency_key,
+                    Instant::now() + state.settings().missed_grace,
+                );
+            }
+            (
+                StatusCode::REQUEST_TIMEOUT,
+                Outcome::timeout(arrived_at.elapsed(), state.retry_after()),
+            )
+        }
+    }
+}
+
+/// Wakes up the party waiting on the id with a cancelled response.
+#[utoipa::path(
+    delete,
+    path = "/wait-for-second-party/{unique-id}",
+    tag = "waits",
+    params(("unique-id" = String, Path, description = "Id of the waiting party")),
+    responses(
+        (status = 204, description = "The waiting party was cancelled"),
+        (status = 404, description = "No party is waiting on the id", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn cancel_party(
+    ValidId(unique_id): ValidId,
+    State(state): State<Arc<AppState>>,
+    format: ResponseFormat,
+) -> Response {
+    match state.parties.cancel(&unique_id).await {
+        Ok(true) => {
+            info!(unique_id, "Cancelled waiting party");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => format.reply(StatusCode::NOT_FOUND, Outcome::error(NOT_WAITING_MESSAGE)),
+        Err(err) => {
+            let (status, outcome) = store_unavailable(err);
+            format.reply(status, outcome)
+        }
+    }
+}
+
+/// Reports whether a party is waiting on the id without consuming the rendezvous.
+#[utoipa::path(
+    get,
+    path = "/wait-for-second-party/{unique-id}/status",
+    tag = "waits",
+    params(("unique-id" = String, Path, description = "Id of the rendezvous")),
+    responses((status = 200, description = "State of the rendezvous", body = WaitStatus)),
+    security((), ("api_key" = [])),
+)]
+pub async fn party_status(
+    ValidId(unique_id): ValidId,
+    State(state): State<Arc<AppState>>,
+    format: ResponseFormat,
+) -> Response {
+    match state.parties.status(&unique_id).await {
+        Ok(status) => Json(status).into_response(),
+        Err(err) => {
+            let (status, outcome) = store_unavailable(err);
+            format.reply(status, outcome)
+        }
+    }
+}
+
+/// Answers `200 OK` with the status of the party waiting on the id, or `204 No Content` if none
+/// is, so that clients can poll cheaply before committing to a wait.
+async fn probe(state: &AppState, unique_id: &str, format: ResponseFormat) -> Response {
+    match state.parties.status(unique_id).await {
+        Ok(status) if status.waiting => Json(status).into_response(),
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(err) => {
+            let (status, outcome) = store_unavailable(err);
+            format.reply(status, outcome)
+        }
+    }
+}
+
+/// Outcome of a match, handing both parties the same room of the relay when one is configured.
+pub fn matched(
+    state: &AppState,
+    role: Role,
+    waited: Duration,
+    peer: Peer,
+    token: String,
+) -> Outcome {
+    let relay_url = state
+        .settings()
+        .relay_url
+        .as_deref()
+        .map(|relay_url| relay::room_url(relay_url, &token));
+    Outcome::matched(role, waited, peer, token, relay_url)
+}
+
+pub fn store_unavailable(err: StoreError) -> (StatusCode, Outcome) {
+    warn!(%err, "Party store is unavailable");
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Outcome::error(STORE_UNAVAILABLE_MESSAGE),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant as StdInstant;
+
+    use super::*;
+
+    /// Matches `pairs` pairs of parties on distinct ids from each of `tasks` concurrent tasks,
+    /// returning the rendezvous per second.
+    async fn rendezvous_throughput(parties: LocalParties, tasks: usize, pairs: usize) -> f64 {
+        let parties = Arc::new(parties);
+        let started_at = StdInstant::now();
+
+        let handles: Vec<_> = (0..tasks)
+            .map(|task| {
+                let parties = parties.clone();
+                tokio::spawn(async move {
+                    let deadline = Instant::now() + Duration::from_secs(60);
+                    let peer = Peer::new(None, None);
+                    for pair in 0..pairs {
+                        let unique_id = format!("{task}-{pair}");
+                        let Ok(Arrival::Wait(mut waiter)) =
+                            parties.arrive(&unique_id, deadline, None, &peer).await
+                        else {
+                            panic!("the first party should wait");
+                        };
+                        let arrival = parties.arrive(&unique_id, deadline, None, &peer).await;
+                        assert!(matches!(arrival, Ok(Arrival::Matched(..))));
+                        assert!(matches!(waiter.woken().await, Some(Wake::Matched(..))));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        let elapsed = started_at.elapsed();
+        assert_eq!(parties.waiting().await, 0);
+        (tasks * pairs) as f64 / elapsed.as_secs_f64()
+    }
+
+    #[tokio::test]
+    async fn parties_on_different_shards_match_independently() {
+        let parties = LocalParties::with_shards(4);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let peer = Peer::new(None, None);
+
+        let mut waiters = Vec::new();
+        for unique_id in 0..16 {
+            match parties
+                .arrive(&unique_id.to_string(), deadline, None, &peer)
+                .await
+            {
+                Ok(Arrival::Wait(waiter)) => waiters.push(waiter),
+                _ => panic!("the first party should wait"),
+            }
+        }
+        assert_eq!(parties.waiting().await, 16);
+        assert!(parties.status("3").await.unwrap().waiting);
+
+        assert!(parties.cancel("3").await.unwrap());
+        assert_eq!(parties.waiting().await, 15);
+        for unique_id in (0..16).filter(|unique_id| *unique_id != 3) {
+            let arrival = parties
+                .arrive(&unique_id.to_string(), deadline, None, &peer)
+                .await;
+            assert!(matches!(arrival, Ok(Arrival::Matched(..))));
+        }
+        assert_eq!(parties.waiting().await, 0);
+    }
+
+    #[tokio::test]
+    async fn arrivals_pair_in_order() {
+        let parties = LocalParties::default();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let arrive = |label: &'static str| {
+            let parties = &parties;
+            async move {
+                let peer = Peer::new(None, Some(label));
+                parties.arrive("1", deadline, None, &peer).await
+            }
+        };
+
+        let Ok(Arrival::Wait(mut waiter1)) = arrive("1").await else {
+            panic!("the first party should wait");
+        };
+        let Ok(Arrival::Matched(peer, _)) = arrive("2").await else {
+            panic!("the second party should match the first");
+        };
+        assert_eq!(peer.label.as_deref(), Some("1"));
+        assert!(
+            matches!(waiter1.woken().await, Some(Wake::Matched(peer, _)) if peer.label.as_deref() == Some("2"))
+        );
+
+        // A party that went away is skipped by the next arrival
+        let Ok(Arrival::Wait(waiter3)) = arrive("3").await else {
+            panic!("the third party should wait");
+        };
+        drop(waiter3);
+        let Ok(Arrival::Wait(mut waiter4)) = arrive("4").await else {
+            panic!("the fourth party should wait for the next one");
+        };
+        let Ok(Arrival::Matched(peer, _)) = arrive("5").await else {
+            panic!("the fifth party should match the fourth");
+        };
+        assert_eq!(peer.label.as_deref(), Some("4"));
+        assert!(
+            matches!(waiter4.woken().await, Some(Wake::Matched(peer, _)) if peer.label.as_deref() == Some("5"))
+        );
+        assert_eq!(parties.waiting().await, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn three_simultaneous_arrivals_leave_one_waiting() {
+        let parties = Arc::new(LocalParties::default());
+        let deadline = Instant::now() + Duration::from_secs(1);
+
+        let arrivals: Vec<_> = (0..3)
+            .map(|party| {
+                let parties = parties.clone();
+                tokio::spawn(async move {
+                    let peer = Peer::new(None, Some(&party.to_string()));
+                    let arrival = parties.arrive("1", deadline, None, &peer).await;
+                    (party.to_string(), arrival.ok().unwrap())
+                })
+            })
+            .collect();
+
+        let mut matched = Vec::new();
+        let mut waiters = Vec::new();
+        for arrival in arrivals {
+            match arrival.await.unwrap() {
+                (label, Arrival::Matched(peer, _)) => matched.push((label, peer.label.unwrap())),
+                (label, Arrival::Wait(waiter)) => waiters.push((label, waiter)),
+            }
+        }
+        assert_eq!(matched.len(), 1);
+        assert_eq!(waiters.len(), 2);
+
+        // The second arrival paired with the first, the third one waits for the fourth
+        let (second, first) = &matched[0];
+        let mut paired = None;
+        for (label, mut waiter) in waiters {
+            if &label == first {
+                let Some(Wake::Matched(peer, _)) = waiter.woken().await else {
+                    panic!("the first party should be matched");
+                };
+                paired = peer.label;
+            } else {
+                assert!(parties.status("1").await.unwrap().waiting);
+                assert!(matches!(
+                    parties.arrive("1", deadline, None, &Peer::new(None, None)).await,
+                    Ok(Arrival::Matched(peer, _)) if peer.label == Some(label)
+                ));
+            }
+        }
+        assert_eq!(paired.as_ref(), Some(second));
+        assert_eq!(parties.waiting().await, 0);
+    }
+
+    /// Compares the throughput of a single lock with the sharded one, run with
+    /// `cargo test --release -- --ignored --nocapture rendezvous_throughput`.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark"]
+    async fn bench_rendezvous_throughput() {
+        let tasks = 64;
+        let pairs = 10_000;
+
+        let single = rendezvous_throughput(LocalParties::with_shards(1), tasks, pairs).await;
+        let sharded = rendezvous_throughput(LocalParties::default(), tasks, pairs).await;
+        println!("single lock: {single:.0} rendezvous/s");
+        println!("{SHARDS} shards: {sharded:.0} rendezvous/s");
+    }
+}
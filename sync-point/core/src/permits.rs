@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// `WaiterPermits` counts the parties waiting at once, against a capacity read from the settings
+/// of each arrival so that it can be reloaded while parties wait.
+#[derive(Default)]
+pub struct WaiterPermits(AtomicUsize);
+
+impl WaiterPermits {
+    /// Counts a party as waiting until the returned permit is dropped, unless `capacity` parties
+    /// already are.
+    pub fn try_acquire(&self, capacity: usize) -> Option<WaiterPermit<'_>> {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |waiting| {
+                (waiting < capacity).then_some(waiting + 1)
+            })
+            .ok()?;
+        Some(WaiterPermit { permits: self })
+    }
+
+    /// Number of parties waiting.
+    pub fn in_use(&self) -> usize {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Keeps a party counted as waiting until dropped.
+pub struct WaiterPermit<'a> {
+    permits: &'a WaiterPermits,
+}
+
+impl Drop for WaiterPermit<'_> {
+    fn drop(&mut self) {
+        self.permits.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capacity_applies_to_the_next_arrivals() {
+        let permits = WaiterPermits::default();
+
+        let first = permits.try_acquire(2).unwrap();
+        let _second = permits.try_acquire(2).unwrap();
+        assert!(permits.try_acquire(2).is_none());
+        assert_eq!(permits.in_use(), 2);
+
+        // Lowered below the parties waiting, which keep waiting
+        assert!(permits.try_acquire(1).is_none());
+        drop(first);
+        assert!(permits.try_acquire(1).is_none());
+        assert!(permits.try_acquire(3).is_some());
+        assert_eq!(permits.in_use(), 1);
+    }
+}
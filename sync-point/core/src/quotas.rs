@@ -0,0 +1,199 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Query, Request},
+    http::{header::RETRY_AFTER, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use tokio::time::Instant;
+use tracing::warn;
+
+use crate::{
+    lock::lock,
+    parties::{WaitMode, WaitQuery},
+    response::{Outcome, ResponseFormat, QUOTA_EXCEEDED_MESSAGE},
+    AppState,
+};
+
+/// Window over which the waits of each key are counted.
+const WINDOW: Duration = Duration::from_secs(3600);
+
+/// Use of its quotas by an API key.
+#[derive(Default)]
+struct KeyUsage {
+    /// Waits going on.
+    waiting: usize,
+    /// Starts of the waits of the last hour, oldest first, only kept when they're limited.
+    started: VecDeque<Instant>,
+}
+
+/// `KeyQuotas` tracks the waits of each API key, to keep a tenant of a shared deployment from
+/// monopolizing it.
+#[derive(Default)]
+pub struct KeyQuotas(Arc<Mutex<HashMap<String, KeyUsage>>>);
+
+impl KeyQuotas {
+    /// Counts a wait of `key` as going on until the returned slot is dropped, unless `key` already
+    /// has `max_waiting` waits going on or started `max_per_hour` waits in the hour before `now`.
+    /// Limits of zero never turn waits away.
+    ///
+    /// Returns how long to wait before retrying when the wait is turned away.
+    fn enter(
+        &self,
+        key: &str,
+        max_waiting: usize,
+        max_per_hour: usize,
+        now: Instant,
+    ) -> Result<QuotaSlot, Duration> {
+        let mut quotas = lock(&self.0);
+        let usage = quotas.entry(key.to_owned()).or_default();
+        while let Some(&started) = usage.started.front() {
+            if now.duration_since(started) < WINDOW {
+                break;
+            }
+            usage.started.pop_front();
+        }
+
+        if max_waiting != 0 && usage.waiting >= max_waiting {
+            return Err(Duration::ZERO);
+        }
+        if max_per_hour != 0 {
+            if usage.started.len() >= max_per_hour {
+                // Until the oldest wait leaves the window
+                return Err(WINDOW - now.duration_since(usage.started[0]));
+            }
+            usage.started.push_back(now);
+        }
+
+        usage.waiting += 1;
+        Ok(QuotaSlot {
+            quotas: self.0.clone(),
+            key: key.to_owned(),
+        })
+    }
+}
+
+/// Keeps a wait counted against the quotas of its key until dropped.
+struct QuotaSlot {
+    quotas: Arc<Mutex<HashMap<String, KeyUsage>>>,
+    key: String,
+}
+
+impl Drop for QuotaSlot {
+    fn drop(&mut self) {
+        let mut quotas = lock(&self.quotas);
+        if let Some(usage) = quotas.get_mut(&self.key) {
+            usage.waiting -= 1;
+            if usage.waiting == 0 && usage.started.is_empty() {
+                quotas.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// Counts a wait against the quotas of its key for as long as a clone of it is kept.
+///
+/// [`enforce_quotas`] hands it to the wait as a request extension, which keeps it until the
+/// response is built. Waits going on after their response, such as the ones posting their outcome
+/// to a callback or streaming it, take it along until they end.
+#[derive(Clone)]
+pub struct QuotaHold {
+    _slot: Arc<QuotaSlot>,
+}
+
+/// Runs `request`, sent with `key`, if it's within the quotas of the key, rejecting it with
+/// `429 Too Many Requests` otherwise.
+///
+/// Only the waits count against the quotas, not the lookups, probes, cancellations and releases.
+pub async fn enforce_quotas(
+    state: &AppState,
+    key: &str,
+    format: ResponseFormat,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !is_wait(&request) {
+        return next.run(request).await;
+    }
+
+    let settings = &state.settings();
+    match state.key_quotas.enter(
+        key,
+        settings.max_waiters_per_key,
+        settings.max_waits_per_key_per_hour,
+        Instant::now(),
+    ) {
+        Ok(slot) => {
+            request.extensions_mut().insert(QuotaHold {
+                _slot: Arc::new(slot),
+            });
+            next.run(request).await
+        }
+        Err(retry_after) => {
+            warn!("API key exceeded its quota");
+            // Waits going on end within the retry delay the load calls for
+            let retry_after = retry_after.max(state.retry_after());
+            let mut response = format.reply(
+                StatusCode::TOO_MANY_REQUESTS,
+                Outcome::error(QUOTA_EXCEEDED_MESSAGE),
+            );
+            // Rounded up so that retrying right after the delay succeeds
+            let retry_after_secs =
+                retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after_secs));
+            response
+        }
+    }
+}
+
+/// Whether `request` waits for other parties, over HTTP, Server-Sent Events, WebSockets or gRPC.
+///
+/// Probes only report whether a party is waiting, so they don't count as waits.
+fn is_wait(request: &Request) -> bool {
+    let path = request.uri().path();
+    let is_probe = || {
+        Query::<WaitQuery>::try_from_uri(request.uri())
+            .is_ok_and(|Query(query)| query.mode == WaitMode::Probe)
+    };
+    match *request.method() {
+        Method::POST => {
+            !path.starts_with("/release/") && !path.ends_with("/Release") && !is_probe()
+        }
+        Method::GET => path.starts_with("/sse/wait/") || path.starts_with("/ws/wait/"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_waits_going_on_and_per_hour() {
+        let quotas = KeyQuotas::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let slot = quotas.enter("key-a", 1, 2, at(0)).unwrap();
+        assert_eq!(
+            quotas.enter("key-a", 1, 2, at(0)).err(),
+            Some(Duration::ZERO)
+        );
+        assert!(quotas.enter("key-b", 1, 2, at(0)).is_ok());
+        drop(slot);
+
+        drop(quotas.enter("key-a", 1, 2, at(600)).unwrap());
+        assert_eq!(
+            quotas.enter("key-a", 1, 2, at(600)).err(),
+            Some(Duration::from_secs(3000))
+        );
+        assert!(quotas.enter("key-a", 1, 2, at(3600)).is_ok());
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use governor::{
+    clock::{Clock, DefaultClock},
+    DefaultKeyedRateLimiter, Quota, RateLimiter,
+};
+use tracing::warn;
+
+use crate::{
+    response::{Outcome, ResponseFormat, RATE_LIMITED_MESSAGE},
+    AppState,
+};
+
+/// Number of tracked clients above which the ones that are back to a full burst get forgotten.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// `ClientRateLimiter` limits the requests of every client IP independently.
+pub struct ClientRateLimiter(DefaultKeyedRateLimiter<IpAddr>);
+
+impl ClientRateLimiter {
+    /// Allows `per_second` requests per second and client, with bursts of up to `burst` requests.
+    ///
+    /// Returns `None` if `per_second` is zero, which disables rate limiting.
+    pub fn new(per_second: u32, burst: u32) -> Option<Self> {
+        let per_second = NonZeroU32::new(per_second)?;
+        let burst = NonZeroU32::new(burst).unwrap_or(per_second);
+
+        Some(ClientRateLimiter(RateLimiter::keyed(
+            Quota::per_second(per_second).allow_burst(burst),
+        )))
+    }
+
+    /// Consumes a request from the budget of `client`, returning how many seconds to wait before
+    /// retrying if it's exhausted.
+    fn check(&self, client: IpAddr) -> Result<(), u64> {
+        let checked = self.0.check_key(&client).map_err(|not_until| {
+            let wait = not_until.wait_time_from(DefaultClock::default().now());
+            // Rounded up so that retrying right after the delay succeeds
+            wait.as_secs() + u64::from(wait.subsec_nanos() > 0)
+        });
+
+        if self.0.len() > MAX_TRACKED_CLIENTS {
+            self.0.retain_recent();
+        }
+
+        checked
+    }
+}
+
+/// Rejects the requests of clients that exceeded their rate limit with `429 Too Many Requests`.
+///
+/// Requests are let through when the client address is unknown, i.e. when the app isn't served
+/// with connection info.
+pub async fn limit_rate(
+    State(state): State<Arc<AppState>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    format: ResponseFormat,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (Some(rate_limiter), Some(ConnectInfo(client))) = (state.rate_limiter(), client) else {
+        return next.run(request).await;
+    };
+
+    match rate_limiter.check(client.ip()) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after_secs) => {
+            warn!(%client, "Rate limited client");
+            let mut response = format.reply(
+                StatusCode::TOO_MANY_REQUESTS,
+                Outcome::error(RATE_LIMITED_MESSAGE),
+            );
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after_secs));
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_clients_independently() {
+        let rate_limiter = ClientRateLimiter::new(1, 2).unwrap();
+        let (client1, client2) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+
+        assert!(rate_limiter.check(client1).is_ok());
+        assert!(rate_limiter.check(client1).is_ok());
+        assert_eq!(rate_limiter.check(client1), Err(1));
+        assert!(rate_limiter.check(client2).is_ok());
+    }
+
+    #[test]
+    fn zero_rate_disables_limiting() {
+        assert!(ClientRateLimiter::new(0, 10).is_none());
+    }
+}
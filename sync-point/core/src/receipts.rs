@@ -0,0 +1,169 @@
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use tokio::time::Instant;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    lock::lock,
+    response::{Outcome, ResponseFormat, UNKNOWN_RECEIPT_MESSAGE},
+    settings::Settings,
+    AppState, UniqueId,
+};
+
+/// Header carrying the receipt of a wait.
+pub const RECEIPT_HEADER: &str = "x-receipt-id";
+
+/// Final outcome of a wait, kept for its party to recover it with the receipt of the wait.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Receipt {
+    /// Id the party waited on.
+    pub unique_id: UniqueId,
+    /// When the wait ended, in milliseconds since the Unix epoch.
+    pub ended_at_ms: u64,
+    pub outcome: Outcome,
+    /// End of the retention window.
+    #[serde(skip)]
+    until: Instant,
+}
+
+/// `Receipts` keeps the outcomes of the waits by receipt until their retention window ends, so
+/// that a party losing its connection right as its wait ended can still learn how it did.
+///
+/// The matches of the waits sent with an idempotency key are parked under the key as well, for
+/// the party to get its match again by retrying the wait.
+#[derive(Default)]
+pub struct Receipts(Mutex<Kept>);
+
+#[derive(Default)]
+struct Kept {
+    by_receipt: HashMap<String, Receipt>,
+    by_idempotency_key: HashMap<(UniqueId, String), Receipt>,
+}
+
+impl Receipts {
+    /// Keeps `outcome` of the wait on `unique_id` under `receipt` until `until`.
+    pub fn record(&self, receipt: &str, unique_id: &str, outcome: &Outcome, until: Instant) {
+        let kept = Receipt::new(unique_id, outcome, until);
+        lock(&self.0).by_receipt.insert(receipt.to_owned(), kept);
+    }
+
+    /// The outcome kept under `receipt`, unless its retention window is over.
+    pub fn get(&self, receipt: &str) -> Option<Receipt> {
+        unexpired(&mut lock(&self.0).by_receipt, receipt)
+    }
+
+    /// Parks the match of the wait on `unique_id` sent with `idempotency_key` until `until`.
+    pub fn park_match(
+        &self,
+        unique_id: &str,
+        idempotency_key: &str,
+        outcome: &Outcome,
+        until: Instant,
+    ) {
+        let parked = Receipt::new(unique_id, outcome, until);
+        lock(&self.0)
+            .by_idempotency_key
+            .insert((unique_id.to_owned(), idempotency_key.to_owned()), parked);
+    }
+
+    /// The match parked for the wait on `unique_id` sent with `idempotency_key`, unless its
+    /// retention window is over.
+    pub fn parked_match(&self, unique_id: &str, idempotency_key: &str) -> Option<Outcome> {
+        let key = (unique_id.to_owned(), idempotency_key.to_owned());
+        unexpired(&mut lock(&self.0).by_idempotency_key, &key).map(|parked| parked.outcome)
+    }
+
+    /// Forgets the outcomes whose retention window is over.
+    pub fn evict_expired(&self) {
+        let now = Instant::now();
+        let mut kept = lock(&self.0);
+        kept.by_receipt.retain(|_, kept| kept.until > now);
+        kept.by_idempotency_key
+            .retain(|_, parked| parked.until > now);
+    }
+}
+
+impl Receipt {
+    fn new(unique_id: &str, outcome: &Outcome, until: Instant) -> Self {
+        let ended_at_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis() as u64)
+            .unwrap_or_default();
+        Receipt {
+            unique_id: unique_id.to_owned(),
+            ended_at_ms,
+            outcome: outcome.clone(),
+            until,
+        }
+    }
+}
+
+/// The outcome kept in `kept` under `key`, forgetting it if its retention window is over.
+fn unexpired<K, Q>(kept: &mut HashMap<K, Receipt>, key: &Q) -> Option<Receipt>
+where
+    K: Borrow<Q> + Hash + Eq,
+    Q: Hash + Eq + ?Sized,
+{
+    match kept.get(key) {
+        Some(receipt) if receipt.until > Instant::now() => Some(receipt.clone()),
+        Some(_) => {
+            kept.remove(key);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Issues the receipt of a new wait, unless receipts are disabled.
+pub fn issue(settings: &Settings) -> Option<String> {
+    (!settings.receipt_retention.is_zero()).then(|| Uuid::new_v4().simple().to_string())
+}
+
+/// Adds the `receipt` of its wait to `response`.
+pub fn with_receipt(mut response: Response, receipt: Option<&str>) -> Response {
+    // Receipts are hexadecimal, so always valid header values
+    if let Some(Ok(receipt)) = receipt.map(HeaderValue::from_str) {
+        response.headers_mut().insert(RECEIPT_HEADER, receipt);
+    }
+    response
+}
+
+/// Reports the outcome of the wait that was handed the receipt.
+#[utoipa::path(
+    get,
+    path = "/receipt/{receipt}",
+    tag = "waits",
+    params(("receipt" = String, Path, description = "Receipt of the wait, from its `X-Receipt-Id` header")),
+    responses(
+        (status = 200, description = "Outcome of the wait", body = Receipt),
+        (status = 404, description = "The wait is still going on, or its receipt is unknown or expired", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn receipt(
+    Path(receipt): Path<String>,
+    State(state): State<Arc<AppState>>,
+    format: ResponseFormat,
+) -> Response {
+    match state.receipts.get(&receipt) {
+        Some(kept) => Json(kept).into_response(),
+        None => format.reply(
+            StatusCode::NOT_FOUND,
+            Outcome::error(UNKNOWN_RECEIPT_MESSAGE),
+        ),
+    }
+}
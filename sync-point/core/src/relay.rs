@@ -0,0 +1,35 @@
+use sha2::{Digest, Sha256};
+
+/// Path of the rooms on the relay, followed by the id of the room.
+const ROOMS_PATH: &str = "/relay/";
+
+/// URL of the room of the relay at `relay_url` allocated to the match of `token`, where both
+/// parties can open a bidirectional channel once matched.
+///
+/// The room is named after a hash of the token, so that both parties get the same one without
+/// the server keeping track of it, while knowing the room isn't enough to join it: the parties
+/// connect with the token in the query, and the relay only accepts the token hashing to the room.
+pub fn room_url(relay_url: &str, token: &str) -> String {
+    let room = Sha256::digest(token.as_bytes());
+    format!("{}{ROOMS_PATH}{room:x}", relay_url.trim_end_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_get_their_own_room() {
+        let room = room_url("ws://localhost:8081/", "8f14e45fceea167a5a36dedd4bea2543");
+        assert_eq!(
+            room,
+            room_url("ws://localhost:8081", "8f14e45fceea167a5a36dedd4bea2543")
+        );
+        assert!(room.starts_with("ws://localhost:8081/relay/"));
+        assert!(!room.contains("8f14e45fceea167a5a36dedd4bea2543"));
+        assert_ne!(
+            room,
+            room_url("ws://localhost:8081", "c9f0f895fb98ab9159f51fd0297e236d")
+        );
+    }
+}
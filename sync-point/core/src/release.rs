@@ -0,0 +1,313 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::StatusCode,
+    response::Response,
+};
+use tokio::{
+    sync::{oneshot, watch},
+    time::{timeout_at, Instant},
+};
+use tracing::{field::Empty, info, info_span, warn, Instrument};
+
+use crate::{
+    admin::WaitKind,
+    id::ValidId,
+    parties::WaitQuery,
+    response::{Outcome, ResponseFormat, NOT_WAITING_MESSAGE},
+    AppState, UniqueId,
+};
+
+/// `Gate` holds the parties waiting for a coordinator to release a given `UniqueId`.
+struct Gate {
+    /// Parties waiting for the gate to be released, in their order of arrival.
+    parties: Vec<GateParty>,
+    /// Deadline registered by the first party, shared by every party at the gate.
+    deadline: Instant,
+    /// Number of parties released, once the gate is released.
+    released: watch::Sender<Option<usize>>,
+}
+
+impl Gate {
+    fn new(deadline: Instant) -> Self {
+        Gate {
+            parties: Vec::new(),
+            deadline,
+            released: watch::channel(None).0,
+        }
+    }
+}
+
+/// Party waiting at a gate, which holds the receiving end of `present` while it waits.
+struct GateParty {
+    /// Identifies the party at its gate, so that it only ever withdraws itself.
+    ticket: u64,
+    present: oneshot::Sender<()>,
+}
+
+impl GateParty {
+    /// A closed channel means the party went away (e.g. disconnected) without cleaning up.
+    fn is_gone(&self) -> bool {
+        self.present.is_closed()
+    }
+}
+
+/// `WaitingGates` holds the gates that haven't been released yet.
+#[derive(Default)]
+pub struct WaitingGates {
+    gates: HashMap<UniqueId, Gate>,
+    next_ticket: u64,
+}
+
+/// Party registered at a gate until it's released, counted as present as long as this is held.
+struct Waiting {
+    released: watch::Receiver<Option<usize>>,
+    deadline: Instant,
+    ticket: u64,
+    _present: oneshot::Receiver<()>,
+}
+
+impl WaitingGates {
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.gates.is_empty()
+    }
+
+    /// Number of parties currently waiting at any gate.
+    pub fn waiting(&self) -> usize {
+        self.gates
+            .values()
+            .flat_map(|gate| &gate.parties)
+            .filter(|party| !party.is_gone())
+            .count()
+    }
+
+    /// Registers a party at the gate of `unique_id`, which times out at `deadline` if the party is
+    /// the first one. A gate whose parties all went away starts over with this one.
+    fn arrive(&mut self, unique_id: &str, deadline: Instant) -> Waiting {
+        let gate = self
+            .gates
+            .entry(unique_id.to_owned())
+            .or_insert_with(|| Gate::new(deadline));
+        gate.parties.retain(|party| !party.is_gone());
+        if gate.parties.is_empty() {
+            *gate = Gate::new(deadline);
+        }
+
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        let (present, _present) = oneshot::channel();
+        gate.parties.push(GateParty { ticket, present });
+        Waiting {
+            released: gate.released.subscribe(),
+            deadline: gate.deadline,
+            ticket,
+            _present,
+        }
+    }
+
+    /// Releases every party waiting at the gate, returning how many there were, none if they all
+    /// went away.
+    fn release(&mut self, unique_id: &str) -> Option<usize> {
+        let gate = self.gates.remove(unique_id)?;
+        let parties = gate.parties.iter().filter(|party| !party.is_gone()).count();
+        if parties == 0 {
+            return None;
+        }
+        gate.released.send_replace(Some(parties));
+        Some(parties)
+    }
+
+    /// Removes the parties that went away without withdrawing, and the gates past the deadline of
+    /// all their parties by more than `grace`.
+    pub fn evict_stale(&mut self, grace: Duration) -> usize {
+        let now = Instant::now();
+        let mut evicted = 0;
+        self.gates.retain(|_, gate| {
+            let before = gate.parties.len();
+            if gate.deadline + grace <= now {
+                gate.parties.clear();
+            } else {
+                gate.parties.retain(|party| !party.is_gone());
+            }
+            evicted += before - gate.parties.len();
+            !gate.parties.is_empty()
+        });
+        evicted
+    }
+
+    /// Withdraws the party holding `ticket` that gave up waiting at the gate of `unique_id`,
+    /// removing the gate if it was the last one.
+    ///
+    /// The ticket keeps a party from withdrawing another one from a gate created under the same id
+    /// after its own was evicted.
+    fn leave(&mut self, unique_id: &str, ticket: u64) {
+        if let Some(gate) = self.gates.get_mut(unique_id) {
+            gate.parties.retain(|party| party.ticket != ticket);
+            if gate.parties.is_empty() {
+                self.gates.remove(unique_id);
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/wait-for-release/{unique-id}",
+    tag = "waits",
+    params(("unique-id" = String, Path, description = "Id of the gate"), WaitQuery),
+    responses(
+        (status = 200, description = "Released by a coordinator", body = Outcome),
+        (status = 408, description = "Not released in time", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn wait_for_release(
+    ValidId(unique_id): ValidId,
+    Query(query): Query<WaitQuery>,
+    State(state): State<Arc<AppState>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    format: ResponseFormat,
+) -> Response {
+    let wait_timeout = match state.wait_timeout(query.timeout_ms) {
+        Ok(wait_timeout) => wait_timeout,
+        Err(message) => return format.reply(StatusCode::BAD_REQUEST, Outcome::error(message)),
+    };
+
+    let client = client.map(|ConnectInfo(client)| client);
+    let (status, outcome) = wait_for_gate(&state, &unique_id, wait_timeout, client).await;
+    format.reply(status, outcome)
+}
+
+/// Waits at the gate of `unique_id` until a coordinator releases it, recording the outcome.
+pub async fn wait_for_gate(
+    state: &AppState,
+    unique_id: &str,
+    wait_timeout: Duration,
+    client: Option<SocketAddr>,
+) -> (StatusCode, Outcome) {
+    let span = info_span!("release", unique_id, outcome = Empty, waited_ms = Empty);
+    let (status, outcome) = wait_at_gate(state, unique_id, wait_timeout, client)
+        .instrument(span.clone())
+        .await;
+    let released = match outcome {
+        Outcome::Released { parties, .. } => parties,
+        _ => 1,
+    };
+    state.record_outcome(&span, unique_id, released, &outcome);
+    (status, outcome)
+}
+
+/// Waits at the gate of `unique_id` until a coordinator releases it, it times out or the server
+/// shuts down.
+///
+/// The gate times out `wait_timeout` after the first party arrived, whatever the timeouts of the
+/// parties after it.
+async fn wait_at_gate(
+    state: &AppState,
+    unique_id: &str,
+    wait_timeout: Duration,
+    client: Option<SocketAddr>,
+) -> (StatusCode, Outcome) {
+    let arrived_at = Instant::now();
+    if state.is_shutting_down() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Outcome::shutting_down(arrived_at.elapsed()),
+        );
+    }
+
+    let Waiting {
+        mut released,
+        deadline,
+        ticket,
+        _present,
+    } = state
+        .gates
+        .write()
+        .await
+        .arrive(unique_id, arrived_at + wait_timeout);
+
+    let Some(_permit) = state.waiter_permit() else {
+        let mut gates = state.gates.write().await;
+        // The gate may have been released right after we arrived
+        if let Some(parties) = *released.borrow() {
+            return (
+                StatusCode::OK,
+                Outcome::released(parties, arrived_at.elapsed()),
+            );
+        }
+        gates.leave(unique_id, ticket);
+        return state.overloaded();
+    };
+
+    info!("Waiting for release");
+    let _active = state
+        .active_waiters
+        .register(WaitKind::Release, unique_id, deadline, client);
+    tokio::select! {
+        _ = timeout_at(
+            deadline,
+            released.wait_for(|released| released.is_some()),
+        ) => {}
+        _ = state.shutting_down() => {}
+    };
+
+    let mut gates = state.gates.write().await;
+    // The gate may have been released right as we timed out
+    if let Some(parties) = *released.borrow() {
+        info!("Released by the coordinator");
+        return (
+            StatusCode::OK,
+            Outcome::released(parties, arrived_at.elapsed()),
+        );
+    }
+
+    // The gate wasn't released so it's still ours, we withdraw from it
+    gates.leave(unique_id, ticket);
+
+    if state.is_shutting_down() {
+        info!("Released waiting party on shutdown");
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Outcome::shutting_down(arrived_at.elapsed()),
+        );
+    }
+
+    warn!("Timeout waiting for release");
+    (
+        StatusCode::REQUEST_TIMEOUT,
+        Outcome::timeout(arrived_at.elapsed(), state.retry_after()),
+    )
+}
+
+/// Releases every party waiting on the id at once, like a starting gun.
+#[utoipa::path(
+    post,
+    path = "/release/{unique-id}",
+    tag = "waits",
+    params(("unique-id" = String, Path, description = "Id of the gate")),
+    responses(
+        (status = 200, description = "Released the waiting parties", body = Outcome),
+        (status = 404, description = "No party is waiting at the gate", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn release(
+    ValidId(unique_id): ValidId,
+    State(state): State<Arc<AppState>>,
+    format: ResponseFormat,
+) -> Response {
+    match open_gate(&state, &unique_id).await {
+        Some(parties) => format.reply(StatusCode::OK, Outcome::released(parties, Duration::ZERO)),
+        None => format.reply(StatusCode::NOT_FOUND, Outcome::error(NOT_WAITING_MESSAGE)),
+    }
+}
+
+/// Releases the parties waiting at the gate of `unique_id`, returning how many there were.
+pub async fn open_gate(state: &AppState, unique_id: &str) -> Option<usize> {
+    let parties = state.gates.write().await.release(unique_id)?;
+    info!(unique_id, parties, "Released waiting parties");
+    Some(parties)
+}
@@ -0,0 +1,506 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::FromRequestParts,
+    http::{
+        header::{ACCEPT, RETRY_AFTER},
+        request::Parts,
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::store::Peer;
+
+pub static INBOUND_MESSAGE: &str = "Hooray! Another party is connected!\n";
+pub static OUTBOUND_MESSAGE: &str = "Yippee! We connected to another party!\n";
+pub static RELEASED_MESSAGE: &str = "Hooray! All parties are connected!\n";
+pub static WAITING_MESSAGE: &str = "Still waiting for another party...\n";
+pub static TIMEOUT_MESSAGE: &str = "Oh no... we timed out waiting for another party\n";
+pub static CANCELLED_MESSAGE: &str = "Oh no... our wait was cancelled\n";
+pub static SHUTTING_DOWN_MESSAGE: &str = "Oh no... the server is shutting down\n";
+pub static STORE_UNAVAILABLE_MESSAGE: &str = "The party store is unavailable, try again later\n";
+pub static UNAUTHORIZED_MESSAGE: &str = "A valid API key is required\n";
+pub static RATE_LIMITED_MESSAGE: &str = "Too many requests, slow down\n";
+pub static QUOTA_EXCEEDED_MESSAGE: &str = "The API key exceeded its quota of waits\n";
+pub static INVALID_TIMEOUT_MESSAGE: &str =
+    "The requested timeout must be positive and within the configured maximum\n";
+pub static OVERLOADED_MESSAGE: &str = "Oh no... too many parties are waiting, try again later\n";
+pub static MISSED_MESSAGE: &str = "Oh no... the other party left shortly before we arrived\n";
+pub static SUPERSEDED_MESSAGE: &str = "Oh no... a retry of this request took over our wait\n";
+pub static INVALID_IDEMPOTENCY_KEY_MESSAGE: &str =
+    "The idempotency key must be printable, non-empty and no longer than a unique id\n";
+pub static INVALID_PARTY_LABEL_MESSAGE: &str =
+    "The party label must be printable, non-empty and no longer than a unique id\n";
+pub static INVALID_PUBLIC_KEY_MESSAGE: &str =
+    "The party public key must be printable, non-empty and at most 512 characters long\n";
+pub static INVALID_CALLBACK_MESSAGE: &str =
+    "The callback must be an HTTP(S) URL on one of the allowed hosts\n";
+pub static ALREADY_MATCHED_MESSAGE: &str =
+    "Oh no... the parties on this id already matched, use a new one\n";
+pub static OWNER_UNREACHABLE_MESSAGE: &str =
+    "The instance owning this id is unreachable, try again later\n";
+pub static NOT_WAITING_MESSAGE: &str = "No party is waiting on this id\n";
+pub static INVALID_NAMESPACE_MESSAGE: &str =
+    "The namespace must be 1 to 64 letters, digits, dashes or underscores\n";
+pub static BODY_TOO_LARGE_MESSAGE: &str = "The request body is too large\n";
+pub static ID_TOO_LONG_MESSAGE: &str = "The unique id is too long\n";
+pub static INVALID_ID_MESSAGE: &str =
+    "The unique id may only contain letters, digits, dashes, underscores, dots or colons\n";
+pub static UNKNOWN_RECEIPT_MESSAGE: &str =
+    "No outcome is known for this receipt, the wait may still be going on\n";
+pub static INVALID_ANY_IDS_MESSAGE: &str = "Waiting on any id needs a JSON list of 1 to 32 ids\n";
+pub static CHAOS_ERROR_MESSAGE: &str = "Chaos mode failed this request on purpose\n";
+pub static INVALID_FAULTS_MESSAGE: &str =
+    "The faults must be a JSON object with error and drop rates between 0 and 1\n";
+pub static INVALID_KEEPALIVE_MESSAGE: &str = "The keepalive interval must be above zero\n";
+pub static INVALID_PARTIES_MESSAGE: &str = "A barrier needs at least 2 parties\n";
+pub static MISMATCHED_PARTIES_MESSAGE: &str =
+    "Parties are already waiting on this id for a different number of parties\n";
+
+/// Header carrying the token of a match, see [`Outcome::Matched`].
+pub const MATCH_TOKEN_HEADER: HeaderName = HeaderName::from_static("x-match-token");
+/// Header carrying the relay room of a match, see [`Outcome::Matched`].
+pub const RELAY_URL_HEADER: HeaderName = HeaderName::from_static("x-relay-url");
+
+/// Which side of the rendezvous a party was on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// The party arrived first and waited for the other one.
+    First,
+    /// The party arrived second and found the other one waiting.
+    Second,
+}
+
+/// Machine-readable outcome of a request, serialized as the JSON response body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    Matched {
+        role: Role,
+        waited_ms: u64,
+        /// The party this one matched with.
+        peer: Peer,
+        /// Random token given to both parties, to prove they come from the same rendezvous.
+        token: String,
+        /// Room of the relay where both parties can open a channel, when a relay is configured.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        relay_url: Option<String>,
+    },
+    Released {
+        parties: usize,
+        waited_ms: u64,
+    },
+    Waiting {
+        waited_ms: u64,
+    },
+    Timeout {
+        waited_ms: u64,
+        /// Suggested delay before waiting again, growing with the load of the server.
+        retry_after_secs: u64,
+    },
+    Cancelled {
+        waited_ms: u64,
+    },
+    ShuttingDown {
+        waited_ms: u64,
+    },
+    Superseded {
+        waited_ms: u64,
+    },
+    /// The other party timed out shortly before this one arrived.
+    Missed {
+        /// The party that timed out.
+        peer: Peer,
+        /// When it gave up, in milliseconds since the Unix epoch.
+        left_at_ms: u64,
+    },
+    Overloaded {
+        retry_after_secs: u64,
+    },
+    Error {
+        #[serde(serialize_with = "serialize_trimmed")]
+        message: &'static str,
+    },
+}
+
+impl Outcome {
+    pub fn matched(
+        role: Role,
+        waited: Duration,
+        peer: Peer,
+        token: String,
+        relay_url: Option<String>,
+    ) -> Self {
+        Outcome::Matched {
+            role,
+            waited_ms: waited.as_millis() as u64,
+            peer,
+            token,
+            relay_url,
+        }
+    }
+
+    pub fn released(parties: usize, waited: Duration) -> Self {
+        Outcome::Released {
+            parties,
+            waited_ms: waited.as_millis() as u64,
+        }
+    }
+
+    pub fn waiting(waited: Duration) -> Self {
+        Outcome::Waiting {
+            waited_ms: waited.as_millis() as u64,
+        }
+    }
+
+    pub fn timeout(waited: Duration, retry_after: Duration) -> Self {
+        Outcome::Timeout {
+            waited_ms: waited.as_millis() as u64,
+            retry_after_secs: retry_after.as_secs(),
+        }
+    }
+
+    pub fn cancelled(waited: Duration) -> Self {
+        Outcome::Cancelled {
+            waited_ms: waited.as_millis() as u64,
+        }
+    }
+
+    pub fn shutting_down(waited: Duration) -> Self {
+        Outcome::ShuttingDown {
+            waited_ms: waited.as_millis() as u64,
+        }
+    }
+
+    pub fn superseded(waited: Duration) -> Self {
+        Outcome::Superseded {
+            waited_ms: waited.as_millis() as u64,
+        }
+    }
+
+    pub fn missed(peer: Peer, left_at_ms: u64) -> Self {
+        Outcome::Missed { peer, left_at_ms }
+    }
+
+    pub fn overloaded(retry_after: Duration) -> Self {
+        Outcome::Overloaded {
+            retry_after_secs: retry_after.as_secs(),
+        }
+    }
+
+    pub fn error(message: &'static str) -> Self {
+        Outcome::Error { message }
+    }
+
+    /// How long the party waited, unknown if it never got to wait.
+    pub fn waited_ms(&self) -> Option<u64> {
+        match *self {
+            Outcome::Matched { waited_ms, .. }
+            | Outcome::Released { waited_ms, .. }
+            | Outcome::Timeout { waited_ms, .. }
+            | Outcome::Cancelled { waited_ms }
+            | Outcome::ShuttingDown { waited_ms }
+            | Outcome::Superseded { waited_ms } => Some(waited_ms),
+            Outcome::Waiting { .. }
+            | Outcome::Missed { .. }
+            | Outcome::Overloaded { .. }
+            | Outcome::Error { .. } => None,
+        }
+    }
+
+    /// Name of the outcome, as found in the `status` field of its JSON form.
+    pub fn status(&self) -> &'static str {
+        match self {
+            Outcome::Matched { .. } => "matched",
+            Outcome::Released { .. } => "released",
+            Outcome::Waiting { .. } => "waiting",
+            Outcome::Timeout { .. } => "timeout",
+            Outcome::Cancelled { .. } => "cancelled",
+            Outcome::ShuttingDown { .. } => "shutting_down",
+            Outcome::Superseded { .. } => "superseded",
+            Outcome::Missed { .. } => "missed",
+            Outcome::Overloaded { .. } => "overloaded",
+            Outcome::Error { .. } => "error",
+        }
+    }
+
+    /// Human-readable message used for plain text responses.
+    fn message(&self) -> &'static str {
+        match self {
+            Outcome::Matched {
+                role: Role::First, ..
+            } => INBOUND_MESSAGE,
+            Outcome::Matched {
+                role: Role::Second, ..
+            } => OUTBOUND_MESSAGE,
+            Outcome::Released { .. } => RELEASED_MESSAGE,
+            Outcome::Waiting { .. } => WAITING_MESSAGE,
+            Outcome::Timeout { .. } => TIMEOUT_MESSAGE,
+            Outcome::Cancelled { .. } => CANCELLED_MESSAGE,
+            Outcome::ShuttingDown { .. } => SHUTTING_DOWN_MESSAGE,
+            Outcome::Superseded { .. } => SUPERSEDED_MESSAGE,
+            Outcome::Missed { .. } => MISSED_MESSAGE,
+            Outcome::Overloaded { .. } => OVERLOADED_MESSAGE,
+            Outcome::Error { message } => message,
+        }
+    }
+}
+
+/// Plain text messages replacing the default ones of some outcomes, e.g. to localize them.
+///
+/// `{id}` is replaced by the unique id and `{waited_ms}` by how long the party waited.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageTemplates {
+    /// Message of the party that arrived first, once matched.
+    pub inbound: Option<String>,
+    /// Message of the party that arrived second.
+    pub outbound: Option<String>,
+    pub timeout: Option<String>,
+}
+
+impl MessageTemplates {
+    /// Message of `outcome` on `unique_id`, if a template replaces its default one.
+    fn render(&self, unique_id: &str, outcome: &Outcome) -> Option<String> {
+        let template = match outcome {
+            Outcome::Matched {
+                role: Role::First, ..
+            } => self.inbound.as_ref(),
+            Outcome::Matched {
+                role: Role::Second, ..
+            } => self.outbound.as_ref(),
+            Outcome::Timeout { .. } => self.timeout.as_ref(),
+            _ => None,
+        }?;
+
+        let waited_ms = outcome.waited_ms().unwrap_or_default();
+        Some(
+            template
+                .replace("{id}", unique_id)
+                .replace("{waited_ms}", &waited_ms.to_string()),
+        )
+    }
+}
+
+/// Format of the response body, negotiated from the `Accept` header.
+///
+/// Plain text is used unless the client prefers `application/json`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl ResponseFormat {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let Some(accept) = headers.get(ACCEPT).and_then(|accept| accept.to_str().ok()) else {
+            return ResponseFormat::Text;
+        };
+
+        let mut best = (ResponseFormat::Text, 0.0);
+        for media_range in accept.split(',') {
+            let mut params = media_range.split(';');
+            let format = match params.next().unwrap_or_default().trim() {
+                "application/json" => ResponseFormat::Json,
+                "text/plain" | "text/*" | "*/*" => ResponseFormat::Text,
+                _ => continue,
+            };
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if quality > best.1 {
+                best = (format, quality);
+            }
+        }
+
+        best.0
+    }
+
+    pub fn reply(self, status: StatusCode, outcome: Outcome) -> Response {
+        let headers = outcome_headers(&outcome);
+        match self {
+            ResponseFormat::Text => (status, headers, outcome.message()).into_response(),
+            ResponseFormat::Json => (status, headers, Json(outcome)).into_response(),
+        }
+    }
+
+    /// Replies like [`reply`](Self::reply), with the plain text message taken from `templates`
+    /// when one replaces the default message of `outcome`.
+    pub fn reply_templated(
+        self,
+        status: StatusCode,
+        outcome: Outcome,
+        unique_id: &str,
+        templates: &MessageTemplates,
+    ) -> Response {
+        match (self, templates.render(unique_id, &outcome)) {
+            (ResponseFormat::Text, Some(message)) => {
+                (status, outcome_headers(&outcome), message).into_response()
+            }
+            _ => self.reply(status, outcome),
+        }
+    }
+
+    /// Content type of the bodies in this format.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ResponseFormat::Text => "text/plain; charset=utf-8",
+            ResponseFormat::Json => "application/json",
+        }
+    }
+
+    /// Body of the response with `outcome` on `unique_id` in this format, for responses built by
+    /// hand.
+    pub fn body(self, outcome: &Outcome, unique_id: &str, templates: &MessageTemplates) -> Bytes {
+        match self {
+            ResponseFormat::Text => match templates.render(unique_id, outcome) {
+                Some(message) => message.into(),
+                None => Bytes::from_static(outcome.message().as_bytes()),
+            },
+            ResponseFormat::Json => serde_json::to_vec(outcome)
+                .expect("outcomes always serialize")
+                .into(),
+        }
+    }
+}
+
+/// Headers repeating parts of `outcome` for plain text clients: the suggested delay before
+/// retrying, and the token and relay room of a match.
+fn outcome_headers(outcome: &Outcome) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    match outcome {
+        Outcome::Timeout {
+            retry_after_secs, ..
+        }
+        | Outcome::Overloaded { retry_after_secs } => {
+            headers.insert(RETRY_AFTER, HeaderValue::from(*retry_after_secs));
+        }
+        Outcome::Matched {
+            token, relay_url, ..
+        } => {
+            // Tokens are hexadecimal, so always valid header values
+            if let Ok(token) = HeaderValue::from_str(token) {
+                headers.insert(MATCH_TOKEN_HEADER, token);
+            }
+            if let Some(Ok(relay_url)) = relay_url.as_deref().map(HeaderValue::from_str) {
+                headers.insert(RELAY_URL_HEADER, relay_url);
+            }
+        }
+        _ => {}
+    }
+    headers
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ResponseFormat {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(ResponseFormat::from_headers(&parts.headers))
+    }
+}
+
+/// Serializes messages without the trailing newline meant for terminals.
+fn serialize_trimmed<S: serde::Serializer>(
+    message: &str,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(message.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_for(accept: &'static str) -> ResponseFormat {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(accept));
+        ResponseFormat::from_headers(&headers)
+    }
+
+    #[test]
+    fn negotiates_response_format() {
+        assert_eq!(
+            ResponseFormat::from_headers(&HeaderMap::new()),
+            ResponseFormat::Text
+        );
+        assert_eq!(format_for("*/*"), ResponseFormat::Text);
+        assert_eq!(format_for("application/json"), ResponseFormat::Json);
+        assert_eq!(format_for("application/json, */*"), ResponseFormat::Json);
+        assert_eq!(
+            format_for("text/plain;q=0.5, application/json;q=0.9"),
+            ResponseFormat::Json
+        );
+        assert_eq!(
+            format_for("text/plain, application/json;q=0.9"),
+            ResponseFormat::Text
+        );
+    }
+
+    #[test]
+    fn serializes_outcomes() {
+        let peer = Peer {
+            client: Some(([10, 0, 0, 1], 1234).into()),
+            arrived_at_ms: 1731000000000,
+            label: None,
+            public_key: None,
+        };
+        assert_eq!(
+            serde_json::to_value(Outcome::matched(
+                Role::First,
+                Duration::from_millis(42),
+                peer,
+                "8f14e45fceea167a5a36dedd4bea2543".to_owned(),
+                None,
+            ))
+            .unwrap(),
+            serde_json::json!({
+                "status": "matched",
+                "role": "first",
+                "waited_ms": 42,
+                "peer": {"client": "10.0.0.1:1234", "arrived_at_ms": 1731000000000u64},
+                "token": "8f14e45fceea167a5a36dedd4bea2543"
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Outcome::error(ID_TOO_LONG_MESSAGE)).unwrap(),
+            serde_json::json!({"status": "error", "message": "The unique id is too long"})
+        );
+    }
+
+    #[test]
+    fn status_matches_serialized_tag() {
+        for outcome in [
+            Outcome::matched(
+                Role::Second,
+                Duration::ZERO,
+                Peer::new(None, None),
+                "token".to_owned(),
+                None,
+            ),
+            Outcome::released(3, Duration::ZERO),
+            Outcome::waiting(Duration::ZERO),
+            Outcome::timeout(Duration::ZERO, Duration::ZERO),
+            Outcome::cancelled(Duration::ZERO),
+            Outcome::shutting_down(Duration::ZERO),
+            Outcome::superseded(Duration::ZERO),
+            Outcome::overloaded(Duration::ZERO),
+            Outcome::error(ID_TOO_LONG_MESSAGE),
+        ] {
+            assert_eq!(
+                serde_json::to_value(&outcome).unwrap()["status"],
+                outcome.status()
+            );
+        }
+    }
+}
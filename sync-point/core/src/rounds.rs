@@ -0,0 +1,80 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::HeaderMap,
+    response::Response,
+    Extension,
+};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{
+    id::ValidId,
+    parties::{sync_parties, WaitQuery},
+    quotas::QuotaHold,
+    response::{Outcome, ResponseFormat},
+    AppState, UniqueId,
+};
+
+/// Waits for the other party of the session `unique_id` at the protocol round `round`.
+///
+/// Each round is a rendezvous of its own. A party still waiting at the previous round is
+/// cancelled, since the session already moved past it.
+#[utoipa::path(
+    post,
+    path = "/wait-for-second-party/{unique-id}/round/{round}",
+    tag = "waits",
+    params(
+        ("unique-id" = String, Path, description = "Id of the session"),
+        ("round" = u64, Path, description = "Protocol round of the session"),
+        WaitQuery,
+    ),
+    responses(
+        (status = 200, description = "Matched with the other party at this round", body = Outcome),
+        (status = 408, description = "The other party didn't reach this round in time", body = Outcome),
+        (status = 410, description = "The session moved past this round", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn sync_round(
+    ValidId(unique_id): ValidId,
+    Path(RoundPath { round }): Path<RoundPath>,
+    query: Query<WaitQuery>,
+    State(state): State<Arc<AppState>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    quota: Option<Extension<QuotaHold>>,
+    headers: HeaderMap,
+    format: ResponseFormat,
+) -> Response {
+    if let Some(previous) = round.checked_sub(1) {
+        match state.parties.cancel(&round_id(&unique_id, previous)).await {
+            Ok(true) => info!(unique_id, round = previous, "Cancelled stale round"),
+            Ok(false) => {}
+            Err(err) => warn!(%err, unique_id, "Failed to clean up previous round"),
+        }
+    }
+
+    sync_parties(
+        ValidId(round_id(&unique_id, round)),
+        query,
+        State(state),
+        client,
+        quota,
+        headers,
+        format,
+    )
+    .await
+}
+
+/// Parameters of the round route besides the unique id.
+#[derive(Deserialize)]
+pub struct RoundPath {
+    round: u64,
+}
+
+/// Id of the rendezvous of a session round, mirroring its route.
+fn round_id(unique_id: &str, round: u64) -> UniqueId {
+    format!("{unique_id}/round/{round}")
+}
@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use crate::{
+    chaos::Faults,
+    response::{MessageTemplates, INVALID_KEEPALIVE_MESSAGE},
+};
+
+pub const DEFAULT_MAX_ID_LENGTH: usize = 128;
+pub const DEFAULT_MAX_WAITERS: usize = 10_000;
+pub const DEFAULT_MAX_CONCURRENT_WAITERS: usize = 100_000;
+pub const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024;
+pub const DEFAULT_CORS_METHODS: &[&str] = &["GET", "POST", "DELETE"];
+pub const DEFAULT_CORS_HEADERS: &[&str] = &[
+    "accept",
+    "authorization",
+    "idempotency-key",
+    "x-party-label",
+    "x-party-public-key",
+    "x-request-id",
+];
+
+/// Settings driving the behavior of the request handlers.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub wait_timeout: Duration,
+    /// Maximum timeout a request can ask for, instead of the default `wait_timeout`.
+    pub max_wait_timeout: Duration,
+    pub max_id_length: usize,
+    pub keepalive_interval: Duration,
+    pub max_waiters: usize,
+    /// Number of parties allowed to wait at once.
+    pub max_concurrent_waiters: usize,
+    /// Number of parties allowed to wait at once in each namespace, unlimited when zero.
+    pub max_waiters_per_namespace: usize,
+    /// Keys accepted as bearer tokens, authentication is disabled when empty.
+    pub api_keys: Vec<String>,
+    /// Number of waits each API key can have going on at once, unlimited when zero.
+    pub max_waiters_per_key: usize,
+    /// Number of waits each API key can start in an hour, unlimited when zero.
+    pub max_waits_per_key_per_hour: usize,
+    /// Keys accepted by the admin routes, which are disabled when empty.
+    pub admin_api_keys: Vec<String>,
+    /// Hosts allowed to receive callbacks, which are disabled when empty.
+    pub callback_hosts: Vec<String>,
+    /// Origins allowed to call the API from a browser, CORS is disabled when empty.
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_methods: Vec<String>,
+    pub cors_allowed_headers: Vec<String>,
+    /// How long a matched id rejects further parties, strict mode is disabled when zero.
+    pub strict_grace: Duration,
+    /// Whether the listing routes compress their responses.
+    pub compress_responses: bool,
+    /// Maximum size of request bodies, larger ones being rejected.
+    pub max_body_bytes: usize,
+    /// How long the next party on an id is told it missed a party that timed out, disabled when
+    /// zero.
+    pub missed_grace: Duration,
+    /// Requests per second allowed from each client IP, rate limiting is disabled when zero.
+    pub rate_limit_per_second: u32,
+    /// Requests a client IP can burst, zero standing for `rate_limit_per_second`.
+    pub rate_limit_burst: u32,
+    /// Templates replacing the default plain text messages.
+    pub messages: MessageTemplates,
+    /// WebSocket URL of the relay whose rooms are handed to matched parties, if any.
+    pub relay_url: Option<String>,
+    /// How long the outcomes of the waits are kept for their receipts, receipts are disabled
+    /// when zero.
+    pub receipt_retention: Duration,
+    /// Faults injected into the wait routes, chaos mode is disabled when none.
+    pub chaos: Option<Faults>,
+}
+
+impl Settings {
+    /// Creates settings with the given wait timeout and default limits.
+    pub fn new(wait_timeout: Duration) -> Self {
+        Settings {
+            wait_timeout,
+            max_wait_timeout: Duration::from_secs(300),
+            max_id_length: DEFAULT_MAX_ID_LENGTH,
+            keepalive_interval: Duration::from_secs(5),
+            max_waiters: DEFAULT_MAX_WAITERS,
+            max_concurrent_waiters: DEFAULT_MAX_CONCURRENT_WAITERS,
+            max_waiters_per_namespace: 0,
+            api_keys: Vec::new(),
+            max_waiters_per_key: 0,
+            max_waits_per_key_per_hour: 0,
+            admin_api_keys: Vec::new(),
+            callback_hosts: Vec::new(),
+            cors_allowed_origins: Vec::new(),
+            cors_allowed_methods: to_strings(DEFAULT_CORS_METHODS),
+            cors_allowed_headers: to_strings(DEFAULT_CORS_HEADERS),
+            strict_grace: Duration::ZERO,
+            compress_responses: true,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            missed_grace: Duration::ZERO,
+            rate_limit_per_second: 0,
+            rate_limit_burst: 0,
+            messages: MessageTemplates::default(),
+            relay_url: None,
+            receipt_retention: Duration::from_secs(300),
+            chaos: None,
+        }
+    }
+
+    /// Checks the settings the waits would fail on: a zero keepalive interval, which can't tick,
+    /// and fault rates outside 0 to 1.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.keepalive_interval.is_zero() {
+            return Err(INVALID_KEEPALIVE_MESSAGE);
+        }
+        self.chaos.as_ref().map_or(Ok(()), Faults::validate)
+    }
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|&value| value.to_owned()).collect()
+}
@@ -0,0 +1,104 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    Extension,
+};
+use futures_util::stream;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+use crate::{
+    id::ValidId,
+    keepalive::{keepalives, next_keepalive},
+    parties::{rendezvous, WaitQuery},
+    quotas::QuotaHold,
+    receipts::{self, with_receipt},
+    response::{Outcome, ResponseFormat},
+    store::Peer,
+    trace::request_id,
+    AppState,
+};
+
+/// Waits for another party over server-sent events, for browser clients without WebSockets.
+///
+/// A `waiting` event is sent every keepalive interval so that the response never stays silent,
+/// then a final event named after the outcome (e.g. `matched` or `timeout`) ends the stream.
+/// Every event carries the JSON outcome as data.
+#[utoipa::path(
+    get,
+    path = "/sse/wait/{unique-id}",
+    tag = "waits",
+    params(("unique-id" = String, Path, description = "Id shared by the two parties"), WaitQuery),
+    responses(
+        (status = 200, description = "Stream of `waiting` events, then one named after the outcome", content_type = "text/event-stream"),
+        (status = 400, description = "Invalid id or timeout", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn sse_wait(
+    ValidId(unique_id): ValidId,
+    Query(query): Query<WaitQuery>,
+    State(state): State<Arc<AppState>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    quota: Option<Extension<QuotaHold>>,
+    headers: HeaderMap,
+    format: ResponseFormat,
+) -> Response {
+    let wait_timeout = match state.wait_timeout(query.timeout_ms) {
+        Ok(wait_timeout) => wait_timeout,
+        Err(message) => return format.reply(StatusCode::BAD_REQUEST, Outcome::error(message)),
+    };
+
+    let peer = Peer::new(client.map(|ConnectInfo(client)| client), None);
+    let receipt = receipts::issue(&state.settings());
+    let header = receipt.clone();
+    let request_id = request_id(&headers).map(str::to_owned);
+    let arrived_at = Instant::now();
+    let keepalive_interval = state.settings().keepalive_interval;
+    let keepalives = keepalives(arrived_at, keepalive_interval);
+    // Dropping the stream when the client goes away also drops the wait, like a closed request
+    let wait = Box::pin(
+        async move {
+            // The wait counts against the quotas of its key until it ends, not with the headers
+            let _quota = quota;
+            // Browsers can't send custom headers with EventSource, so the parties are unlabelled
+            rendezvous(
+                &state,
+                &unique_id,
+                wait_timeout,
+                None,
+                peer,
+                request_id.as_deref(),
+                receipt.as_deref(),
+            )
+            .await
+            .1
+        }
+        .in_current_span(),
+    );
+
+    let events = stream::unfold(Some((wait, keepalives)), move |pending| async move {
+        let (mut wait, mut keepalives) = pending?;
+
+        tokio::select! {
+            outcome = &mut wait => Some((event(&outcome), None)),
+            () = next_keepalive(&mut keepalives) => Some((
+                event(&Outcome::waiting(arrived_at.elapsed())),
+                Some((wait, keepalives)),
+            )),
+        }
+    });
+
+    // Sent right away with the headers, so that the party can recover the outcome
+    with_receipt(Sse::new(events).into_response(), header.as_deref())
+}
+
+fn event(outcome: &Outcome) -> Result<Event, axum::Error> {
+    Event::default().event(outcome.status()).json_data(outcome)
+}
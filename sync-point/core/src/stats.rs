@@ -0,0 +1,202 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use tokio::time::Instant;
+use utoipa::ToSchema;
+
+use crate::{lock::lock, response::Outcome, AppState, UniqueId};
+
+/// How far back the statistics look.
+const STATS_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Waits kept in the window at most, the oldest being dropped first.
+const MAX_SAMPLES: usize = 100_000;
+/// Number of ids listed as the busiest.
+const BUSIEST_IDS: usize = 10;
+
+/// Wait that ended within the window.
+struct Sample {
+    finished_at: Instant,
+    unique_id: UniqueId,
+    matched: bool,
+    timed_out: bool,
+    waited_ms: u64,
+}
+
+/// Aggregates of the waits that ended within the window.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatsReport {
+    pub window_secs: u64,
+    /// Number of waits that ended within the window.
+    pub waits: usize,
+    /// Matched parties per minute, each party of a match counting once like in the metrics.
+    pub matches_per_minute: f64,
+    /// Share of the waits that timed out.
+    pub timeout_rate: f64,
+    /// Percentiles of the wait durations, unknown without waits.
+    pub wait_ms: Option<Percentiles>,
+    /// Ids with the most waits, the busiest first.
+    pub busiest_ids: Vec<BusyId>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Percentiles {
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct BusyId {
+    pub unique_id: UniqueId,
+    pub waits: usize,
+}
+
+/// `Stats` keeps the waits that ended recently in memory, to report aggregates independently of the
+/// Prometheus metrics.
+#[derive(Default)]
+pub struct Stats(Mutex<VecDeque<Sample>>);
+
+impl Stats {
+    /// Records the final outcome of a wait on `unique_id`, ignoring the requests that never
+    /// got to wait.
+    pub fn record(&self, unique_id: &str, outcome: &Outcome) {
+        let Some(waited_ms) = outcome.waited_ms() else {
+            return;
+        };
+
+        let mut samples = lock(&self.0);
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(Sample {
+            finished_at: Instant::now(),
+            unique_id: unique_id.to_owned(),
+            matched: matches!(outcome, Outcome::Matched { .. } | Outcome::Released { .. }),
+            timed_out: matches!(outcome, Outcome::Timeout { .. }),
+            waited_ms,
+        });
+    }
+
+    pub fn report(&self) -> StatsReport {
+        self.report_at(Instant::now())
+    }
+
+    fn report_at(&self, now: Instant) -> StatsReport {
+        let mut samples = lock(&self.0);
+        while samples
+            .front()
+            .is_some_and(|sample| sample.finished_at + STATS_WINDOW <= now)
+        {
+            samples.pop_front();
+        }
+
+        let waits = samples.len();
+        let matches = samples.iter().filter(|sample| sample.matched).count();
+        let timeouts = samples.iter().filter(|sample| sample.timed_out).count();
+
+        let mut waited: Vec<_> = samples.iter().map(|sample| sample.waited_ms).collect();
+        waited.sort_unstable();
+        let percentile = |rank: usize| waited[(waits * rank).div_ceil(100).max(1) - 1];
+        let wait_ms = (waits > 0).then(|| Percentiles {
+            p50: percentile(50),
+            p95: percentile(95),
+            p99: percentile(99),
+        });
+
+        let mut per_id = HashMap::<&str, usize>::new();
+        for sample in samples.iter() {
+            *per_id.entry(&sample.unique_id).or_default() += 1;
+        }
+        let mut busiest_ids: Vec<_> = per_id
+            .into_iter()
+            .map(|(unique_id, waits)| BusyId {
+                unique_id: unique_id.to_owned(),
+                waits,
+            })
+            .collect();
+        busiest_ids.sort_by(|a, b| b.waits.cmp(&a.waits).then(a.unique_id.cmp(&b.unique_id)));
+        busiest_ids.truncate(BUSIEST_IDS);
+
+        StatsReport {
+            window_secs: STATS_WINDOW.as_secs(),
+            waits,
+            matches_per_minute: matches as f64 / (STATS_WINDOW.as_secs_f64() / 60.0),
+            timeout_rate: if waits > 0 {
+                timeouts as f64 / waits as f64
+            } else {
+                0.0
+            },
+            wait_ms,
+            busiest_ids,
+        }
+    }
+}
+
+/// Reports aggregates of the recent waits, for dashboards not scraping the metrics.
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Aggregates of the recent waits", body = StatsReport),
+        (status = 404, description = "No admin key is configured"),
+    ),
+    security(("admin_key" = [])),
+)]
+pub async fn render_stats(State(state): State<Arc<AppState>>) -> Json<StatsReport> {
+    Json(state.stats.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{response::Role, store::Peer};
+
+    #[test]
+    fn aggregates_waits_within_window() {
+        let stats = Stats::default();
+        for waited_ms in 1..=100 {
+            let outcome = Outcome::matched(
+                Role::First,
+                Duration::from_millis(waited_ms),
+                Peer::new(None, None),
+                "token".to_owned(),
+                None,
+            );
+            stats.record(&format!("{}", waited_ms % 3), &outcome);
+        }
+        let timeout = Outcome::timeout(Duration::from_millis(1000), Duration::from_secs(1));
+        stats.record("3", &timeout);
+        stats.record("3", &Outcome::error("ignored"));
+
+        let report = stats.report();
+        assert_eq!(report.waits, 101);
+        assert_eq!(report.matches_per_minute, 20.0);
+        assert_eq!(report.timeout_rate, 1.0 / 101.0);
+        assert_eq!(
+            report.wait_ms,
+            Some(Percentiles {
+                p50: 51,
+                p95: 96,
+                p99: 100,
+            })
+        );
+        assert_eq!(
+            report.busiest_ids[0],
+            BusyId {
+                unique_id: "1".to_owned(),
+                waits: 34,
+            }
+        );
+        assert_eq!(report.busiest_ids.len(), 4);
+
+        let report = stats.report_at(Instant::now() + STATS_WINDOW);
+        assert_eq!(report.waits, 0);
+        assert_eq!(report.wait_ms, None);
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    error::Error,
+    fmt,
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[cfg(feature = "redis")]
+pub mod redis;
+
+/// What a party tells about itself to the party it matches, so that both can check they paired
+/// with the expected counterpart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Peer {
+    /// Address of the party, unknown when the server doesn't see it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "10.0.0.2:52814")]
+    pub client: Option<SocketAddr>,
+    /// When the party arrived, in milliseconds since the Unix epoch.
+    pub arrived_at_ms: u64,
+    /// Label the party sent along, e.g. the name of its service.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Public key the party sent along, for the party it matches to encrypt payloads to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+
+impl Peer {
+    /// Describes a party arriving now.
+    pub fn new(client: Option<SocketAddr>, label: Option<&str>) -> Self {
+        Peer {
+            client,
+            arrived_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_millis() as u64)
+                .unwrap_or_default(),
+            label: label.map(str::to_owned),
+            public_key: None,
+        }
+    }
+}
+
+/// Random token shared by the two parties of a match, so that they can prove to a third party
+/// (e.g. a relay they connect to next) that they come from the same rendezvous.
+pub fn match_token() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+/// Reason for waking up a waiting party.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Wake {
+    /// Another party arrived with the same `UniqueId`, along with the token of the match.
+    Matched(Peer, String),
+    /// The wait was cancelled through the API.
+    Cancelled,
+    /// A retry of the same request, with the same idempotency key, took over the wait.
+    Superseded,
+}
+
+/// Outcome of a party arriving on a `UniqueId`.
+pub enum Arrival {
+    /// Another party was waiting and has been woken up with the token of the match.
+    Matched(Peer, String),
+    /// No party was waiting, so the arriving one waits for the next.
+    Wait(Box<dyn Waiter>),
+}
+
+/// Current state of a rendezvous, as reported by the status endpoint.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct WaitStatus {
+    /// Whether a party is currently waiting on the id.
+    pub waiting: bool,
+    /// When the waiting party arrived, in milliseconds since the Unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrived_at_ms: Option<u64>,
+    /// How long until the waiting party times out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_ms: Option<u64>,
+}
+
+/// Failure of the backend holding the waiting parties.
+#[derive(Debug)]
+pub struct StoreError(Box<dyn Error + Send + Sync>);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for StoreError {}
+
+#[cfg(feature = "redis")]
+impl From<::redis::RedisError> for StoreError {
+    fn from(err: ::redis::RedisError) -> Self {
+        StoreError(Box::new(err))
+    }
+}
+
+#[cfg(feature = "redis")]
+impl From<serde_json::Error> for StoreError {
+    fn from(err: serde_json::Error) -> Self {
+        StoreError(Box::new(err))
+    }
+}
+
+/// `PartyStore` holds the parties waiting for another one, which may be shared between instances.
+#[async_trait]
+pub trait PartyStore: Send + Sync {
+    /// Wakes up the party waiting on `unique_id`, or registers a new one waiting until `deadline`.
+    /// The arriving and the waiting parties are told about each other as `peer`.
+    ///
+    /// A party waiting with the same `idempotency_key` is an earlier attempt of the arriving one,
+    /// so it's superseded by the arriving party instead of matched with it.
+    async fn arrive(
+        &self,
+        unique_id: &str,
+        deadline: Instant,
+        idempotency_key: Option<&str>,
+        peer: &Peer,
+    ) -> Result<Arrival, StoreError>;
+
+    /// Wakes up the party waiting on `unique_id` with a cancellation.
+    ///
+    /// Returns `false` if no party was waiting.
+    async fn cancel(&self, unique_id: &str) -> Result<bool, StoreError>;
+
+    async fn status(&self, unique_id: &str) -> Result<WaitStatus, StoreError>;
+
+    /// Number of parties waiting on this instance.
+    async fn waiting(&self) -> usize;
+
+    /// Removes the parties left behind past their deadline (e.g. their request was aborted before
+    /// they could withdraw), returning how many were removed.
+    async fn evict_stale(&self, grace: Duration) -> usize;
+}
+
+/// Handle of a party waiting in a `PartyStore`.
+#[async_trait]
+pub trait Waiter: Send {
+    /// Resolves once the party is woken up, or with `None` if it can no longer be.
+    async fn woken(&mut self) -> Option<Wake>;
+
+    /// Withdraws the party from the store after it gave up waiting.
+    ///
+    /// Returns how the party was woken up if it happened right as it gave up.
+    async fn withdraw(self: Box<Self>) -> Option<Wake>;
+}
@@ -0,0 +1,290 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::async_trait;
+use futures_util::{Stream, StreamExt};
+use redis::{aio::ConnectionManager, Client, Msg, Script};
+use serde::{Deserialize, Serialize};
+use tokio::time::{timeout, Instant};
+use tracing::warn;
+
+use super::{match_token, Arrival, PartyStore, Peer, StoreError, WaitStatus, Waiter, Wake};
+
+const KEY_PREFIX: &str = "sync-point:party:";
+const CHANNEL_PREFIX: &str = "sync-point:wake:";
+const TOKENS_KEY: &str = "sync-point:tokens";
+
+/// Extra lifetime of the waiting party keys, so they outlive the wait and only expire once the
+/// instance owning them is gone.
+const KEY_GRACE: Duration = Duration::from_secs(5);
+/// How long a party giving up waits for the wake-up of the party that just claimed it.
+const WAKE_GRACE: Duration = Duration::from_secs(1);
+
+/// Deletes a key only if it still holds the given value.
+const COMPARE_AND_DELETE: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// `RedisParties` shares the waiting parties between instances through Redis.
+///
+/// A waiting party is stored as a key holding a unique token, followed by its peer and idempotency
+/// key as JSON. The next party on the same id atomically takes the key and wakes the waiting one by
+/// publishing its own peer on the channel named after its token, to which the waiting party
+/// subscribed before storing the key.
+pub struct RedisParties {
+    client: Client,
+    connection: ConnectionManager,
+    waiting: Arc<AtomicUsize>,
+}
+
+impl RedisParties {
+    pub async fn connect(url: &str) -> Result<Self, StoreError> {
+        let client = Client::open(url)?;
+        let connection = client.get_connection_manager().await?;
+
+        Ok(RedisParties {
+            client,
+            connection,
+            waiting: Default::default(),
+        })
+    }
+
+    /// Wakes up the party waiting on `unique_id` with `reason`, unless it waits with
+    /// `idempotency_key`, in which case it's superseded.
+    ///
+    /// Returns how the party was woken up along with its peer, or `None` if none was listening.
+    async fn wake(
+        &self,
+        unique_id: &str,
+        reason: Wake,
+        idempotency_key: Option<&str>,
+    ) -> Result<Option<(Wake, Peer)>, StoreError> {
+        let mut connection = self.connection.clone();
+
+        loop {
+            let Some(value) = redis::cmd("GETDEL")
+                .arg(key(unique_id))
+                .query_async::<Option<String>>(&mut connection)
+                .await?
+            else {
+                return Ok(None);
+            };
+
+            let (token, party) = parse_value(&value)?;
+            let reason = match idempotency_key {
+                Some(_) if party.idempotency_key.as_deref() == idempotency_key => Wake::Superseded,
+                _ => reason.clone(),
+            };
+            let receivers: usize = redis::cmd("PUBLISH")
+                .arg(channel(token))
+                .arg(wake_payload(&reason)?)
+                .query_async(&mut connection)
+                .await?;
+
+            // Nobody listening means the party went away (e.g. its instance crashed)
+            // and a newer one may be waiting.
+            if receivers > 0 {
+                return Ok(Some((reason, party.peer)));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl PartyStore for RedisParties {
+    async fn arrive(
+        &self,
+        unique_id: &str,
+        deadline: Instant,
+        idempotency_key: Option<&str>,
+        peer: &Peer,
+    ) -> Result<Arrival, StoreError> {
+        let mut connection = self.connection.clone();
+
+        loop {
+            let shared_token = match_token();
+            let reason = Wake::Matched(peer.clone(), shared_token.clone());
+            if let Some((Wake::Matched(..), waiting)) =
+                self.wake(unique_id, reason, idempotency_key).await?
+            {
+                return Ok(Arrival::Matched(waiting, shared_token));
+            }
+
+            // There is no other party waiting for this id, so we are the one waiting
+            let token: u64 = redis::cmd("INCR")
+                .arg(TOKENS_KEY)
+                .query_async(&mut connection)
+                .await?;
+            let token = token.to_string();
+
+            // We subscribe before storing the key so that no wake-up can be missed
+            let mut pubsub = self.client.get_async_pubsub().await?;
+            pubsub.subscribe(channel(&token)).await?;
+
+            let party = StoredParty {
+                peer: peer.clone(),
+                idempotency_key: idempotency_key.map(str::to_owned),
+            };
+            let value = format!("{token}:{}", serde_json::to_string(&party)?);
+            let ttl = deadline.saturating_duration_since(Instant::now()) + KEY_GRACE;
+            let stored: Option<String> = redis::cmd("SET")
+                .arg(key(unique_id))
+                .arg(&value)
+                .arg("NX")
+                .arg("PX")
+                .arg(ttl.as_millis() as u64)
+                .query_async(&mut connection)
+                .await?;
+
+            if stored.is_some() {
+                self.waiting.fetch_add(1, Ordering::Relaxed);
+
+                return Ok(Arrival::Wait(Box::new(RedisWaiter {
+                    connection,
+                    key: key(unique_id),
+                    value,
+                    messages: Box::pin(pubsub.into_on_message()),
+                    waiting: self.waiting.clone(),
+                })));
+            }
+            // Another party started waiting in the meantime, so we try to match it instead
+        }
+    }
+
+    async fn cancel(&self, unique_id: &str) -> Result<bool, StoreError> {
+        Ok(self.wake(unique_id, Wake::Cancelled, None).await?.is_some())
+    }
+
+    async fn status(&self, unique_id: &str) -> Result<WaitStatus, StoreError> {
+        let (value, ttl_ms): (Option<String>, i64) = redis::pipe()
+            .cmd("GET")
+            .arg(key(unique_id))
+            .cmd("PTTL")
+            .arg(key(unique_id))
+            .query_async(&mut self.connection.clone())
+            .await?;
+
+        let Some(value) = value else {
+            return Ok(WaitStatus::default());
+        };
+
+        Ok(WaitStatus {
+            waiting: true,
+            arrived_at_ms: parse_value(&value)
+                .ok()
+                .map(|(_, party)| party.peer.arrived_at_ms),
+            remaining_ms: u64::try_from(ttl_ms)
+                .ok()
+                .map(|ttl_ms| ttl_ms.saturating_sub(KEY_GRACE.as_millis() as u64)),
+        })
+    }
+
+    async fn waiting(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    async fn evict_stale(&self, _grace: Duration) -> usize {
+        // Keys expire on their own
+        0
+    }
+}
+
+struct RedisWaiter {
+    connection: ConnectionManager,
+    key: String,
+    value: String,
+    messages: Pin<Box<dyn Stream<Item = Msg> + Send>>,
+    waiting: Arc<AtomicUsize>,
+}
+
+impl RedisWaiter {
+    async fn next_wake(&mut self) -> Option<Wake> {
+        let message = self.messages.next().await?;
+        let payload = message.get_payload::<String>().ok()?;
+
+        match payload.split_once(':').unwrap_or((&payload, "")) {
+            ("matched", matched) => {
+                let (token, peer) = matched.split_once(':')?;
+                let peer = serde_json::from_str(peer).ok()?;
+                Some(Wake::Matched(peer, token.to_owned()))
+            }
+            ("cancelled", _) => Some(Wake::Cancelled),
+            ("superseded", _) => Some(Wake::Superseded),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl Waiter for RedisWaiter {
+    async fn woken(&mut self) -> Option<Wake> {
+        self.next_wake().await
+    }
+
+    async fn withdraw(mut self: Box<Self>) -> Option<Wake> {
+        let deleted = Script::new(COMPARE_AND_DELETE)
+            .key(&self.key)
+            .arg(&self.value)
+            .invoke_async::<u64>(&mut self.connection)
+            .await;
+
+        match deleted {
+            Ok(1) => None,
+            // Another party took the key right as we gave up, so its wake-up is on the way
+            Ok(_) => timeout(WAKE_GRACE, self.next_wake()).await.ok().flatten(),
+            Err(err) => {
+                warn!(%err, "Failed to withdraw waiting party, it will expire");
+                None
+            }
+        }
+    }
+}
+
+impl Drop for RedisWaiter {
+    fn drop(&mut self) {
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn key(unique_id: &str) -> String {
+    format!("{KEY_PREFIX}{unique_id}")
+}
+
+fn channel(token: &str) -> String {
+    format!("{CHANNEL_PREFIX}{token}")
+}
+
+/// Waiting party, as stored after its token.
+#[derive(Serialize, Deserialize)]
+struct StoredParty {
+    #[serde(flatten)]
+    peer: Peer,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idempotency_key: Option<String>,
+}
+
+/// Splits the value of a waiting party key into its token and the party.
+fn parse_value(value: &str) -> Result<(&str, StoredParty), StoreError> {
+    let (token, party) = value.split_once(':').unwrap_or((value, ""));
+    Ok((token, serde_json::from_str(party)?))
+}
+
+/// Message waking up a party, carrying the token of the match and the peer of the matching party.
+fn wake_payload(reason: &Wake) -> Result<String, StoreError> {
+    Ok(match reason {
+        Wake::Matched(peer, token) => {
+            format!("matched:{token}:{}", serde_json::to_string(peer)?)
+        }
+        Wake::Cancelled => "cancelled".to_owned(),
+        Wake::Superseded => "superseded".to_owned(),
+    })
+}
@@ -0,0 +1,44 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::info;
+
+use crate::{metrics, AppState};
+
+/// How often the stale entries are looked for.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+/// How long past its deadline an entry is left to its party to clean up.
+const EVICTION_GRACE: Duration = Duration::from_secs(1);
+
+/// Periodically evicts the stale waiting entries until the service shuts down.
+pub async fn sweep_stale_entries(state: Arc<AppState>) {
+    let mut ticks = interval(SWEEP_INTERVAL);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {
+                evict_stale(&state, EVICTION_GRACE).await;
+            }
+            _ = state.shutting_down() => return,
+        }
+    }
+}
+
+/// Evicts the parties, barriers and gates left behind past their deadline by more than `grace`,
+/// returning how many parties were evicted, along with the ids consumed in strict mode, the
+/// parties missed past their grace window and the expired receipts.
+pub async fn evict_stale(state: &AppState, grace: Duration) -> usize {
+    state.consumed.evict_expired();
+    state.missed.evict_expired();
+    state.receipts.evict_expired();
+    let evicted = state.parties.evict_stale(grace).await
+        + state.barriers.write().await.evict_stale(grace)
+        + state.gates.write().await.evict_stale(grace);
+
+    if evicted > 0 {
+        info!(evicted, "Evicted stale waiting parties");
+        metrics::record_evictions(evicted);
+    }
+    evicted
+}
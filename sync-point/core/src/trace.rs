@@ -0,0 +1,55 @@
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request},
+    Router,
+};
+use tower::ServiceBuilder;
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::{info_span, Level, Span};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+/// W3C trace context header of the client, recorded on the request span so that exported traces
+/// join the ones of the client.
+const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Wraps every request in a span carrying its `x-request-id`, generated if the client didn't send
+/// one and returned in the response, so that the logs of both halves of a rendezvous can be told
+/// apart and correlated through their unique id.
+pub fn trace_requests(router: Router) -> Router {
+    router.layer(
+        ServiceBuilder::new()
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(request_span)
+                    .on_response(DefaultOnResponse::new().level(Level::INFO)),
+            )
+            .layer(PropagateRequestIdLayer::x_request_id()),
+    )
+}
+
+fn request_span(request: &Request<Body>) -> Span {
+    let request_id = request_id(request.headers()).unwrap_or_default();
+    let traceparent = request
+        .headers()
+        .get(TRACEPARENT_HEADER)
+        .and_then(|traceparent| traceparent.to_str().ok());
+
+    info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id,
+        traceparent,
+    )
+}
+
+/// Id of the request carrying `headers`, set by [`trace_requests`] unless the client sent one.
+pub fn request_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|request_id| request_id.to_str().ok())
+}
@@ -0,0 +1,340 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{rejection::JsonRejection, ConnectInfo, Query, State},
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures_util::{future::select_all, FutureExt};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep_until, Instant};
+use tracing::{field::Empty, info, info_span, warn, Instrument};
+use utoipa::IntoParams;
+
+use crate::{
+    admin::WaitKind,
+    parties::{matched, store_unavailable},
+    receipts::{self, with_receipt},
+    response::{Outcome, ResponseFormat, Role, ALREADY_MATCHED_MESSAGE, INVALID_ANY_IDS_MESSAGE},
+    store::{Arrival, Peer, Waiter, Wake},
+    AppState, UniqueId,
+};
+
+/// Maximum number of ids a party can wait on at once.
+const MAX_IDS: usize = 32;
+
+/// Header naming the id a party waiting on several ones matched on.
+pub const MATCHED_ID_HEADER: HeaderName = HeaderName::from_static("x-matched-id");
+
+/// Query parameters of the wait-any route.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WaitAnyQuery {
+    /// Timeout of this wait in milliseconds, instead of the configured one.
+    pub timeout_ms: Option<u64>,
+}
+
+/// Outcome of a wait on several ids, along with the id it matched on.
+#[derive(Serialize)]
+struct AnyOutcome<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unique_id: Option<&'a str>,
+    #[serde(flatten)]
+    outcome: &'a Outcome,
+}
+
+/// Waits on every id of the JSON list in the body until another party arrives on one of them,
+/// then withdraws from the others.
+///
+/// Served by the instance it reaches, even in cluster mode, so only the ids this instance owns can
+/// match.
+#[utoipa::path(
+    post,
+    path = "/wait-any",
+    tag = "waits",
+    params(WaitAnyQuery),
+    request_body(content = Vec<String>, description = "Ids to wait on, 1 to 32 of them"),
+    responses(
+        (status = 200, description = "Matched with another party on the id named in `unique_id` and the `X-Matched-Id` header", body = Outcome),
+        (status = 400, description = "Invalid list of ids, id or timeout", body = Outcome),
+        (status = 408, description = "No other party arrived in time on any id", body = Outcome),
+        (status = 409, description = "Every id already matched in strict mode", body = Outcome),
+        (status = 410, description = "The wait was cancelled on one of the ids", body = Outcome),
+        (status = 503, description = "Shutting down, overloaded or the party store is unavailable", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn wait_any(
+    Query(query): Query<WaitAnyQuery>,
+    State(state): State<Arc<AppState>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    format: ResponseFormat,
+    ids: Result<Json<Vec<String>>, JsonRejection>,
+) -> Response {
+    let reject = |message| format.reply(StatusCode::BAD_REQUEST, Outcome::error(message));
+
+    let ids = match ids {
+        Ok(Json(ids)) if (1..=MAX_IDS).contains(&ids.len()) => ids,
+        _ => {
+            warn!("Invalid list of ids");
+            return reject(INVALID_ANY_IDS_MESSAGE);
+        }
+    };
+    let mut unique_ids = Vec::with_capacity(ids.len());
+    for unique_id in ids {
+        match state.parse_unique_id(&unique_id) {
+            // Spellings of the same id are waited on once
+            Ok(unique_id) if !unique_ids.contains(&unique_id) => unique_ids.push(unique_id),
+            Ok(_) => {}
+            Err(message) => return reject(message),
+        }
+    }
+
+    let wait_timeout = match state.wait_timeout(query.timeout_ms) {
+        Ok(wait_timeout) => wait_timeout,
+        Err(message) => return reject(message),
+    };
+
+    let client = client.map(|ConnectInfo(client)| client);
+    let receipt = receipts::issue(&state.settings());
+    let span = info_span!(
+        "wait",
+        unique_ids = ?unique_ids,
+        parties = 2,
+        outcome = Empty,
+        waited_ms = Empty
+    );
+    let (status, unique_id, outcome) = wait_for_any(&state, &unique_ids, wait_timeout, client)
+        .instrument(span.clone())
+        .await;
+
+    // Waits that matched on none of their ids are recorded under the first one
+    let recorded_id = unique_id.as_deref().unwrap_or(&unique_ids[0]);
+    state.record_outcome(&span, recorded_id, 2, &outcome);
+    if let Some(receipt) = &receipt {
+        let until = Instant::now() + state.settings().receipt_retention;
+        state.receipts.record(receipt, recorded_id, &outcome, until);
+    }
+
+    let mut response = match format {
+        ResponseFormat::Text => format.reply_templated(
+            status,
+            outcome.clone(),
+            recorded_id,
+            &state.settings().messages,
+        ),
+        ResponseFormat::Json => {
+            let body = AnyOutcome {
+                unique_id: unique_id.as_deref(),
+                outcome: &outcome,
+            };
+            // Keeps the headers of the outcome, e.g. its match token
+            let mut response = format.reply(status, outcome.clone());
+            *response.body_mut() = Json(body).into_response().into_body();
+            response
+        }
+    };
+    if let Some(Ok(unique_id)) = unique_id.as_deref().map(HeaderValue::from_str) {
+        response.headers_mut().insert(MATCHED_ID_HEADER, unique_id);
+    }
+    with_receipt(response, receipt.as_deref())
+}
+
+/// Waits on each of `unique_ids` until another party arrives on one of them, the wait is
+/// cancelled on one of them, times out after `wait_timeout` or the server shuts down.
+///
+/// Returns immediately if a party was already waiting on one of the ids, trying them in order.
+/// Along with the outcome, returns the id the party matched on.
+async fn wait_for_any(
+    state: &AppState,
+    unique_ids: &[UniqueId],
+    wait_timeout: Duration,
+    client: Option<SocketAddr>,
+) -> (StatusCode, Option<UniqueId>, Outcome) {
+    let arrived_at = Instant::now();
+    if state.is_shutting_down() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            None,
+            Outcome::shutting_down(arrived_at.elapsed()),
+        );
+    }
+
+    let unique_ids: Vec<_> = unique_ids
+        .iter()
+        .filter(|unique_id| !state.consumed.is_consumed(unique_id))
+        .collect();
+    if unique_ids.is_empty() {
+        warn!("Parties on every id already matched");
+        return (
+            StatusCode::CONFLICT,
+            None,
+            Outcome::error(ALREADY_MATCHED_MESSAGE),
+        );
+    }
+
+    let peer = Peer::new(client, None);
+    let deadline = arrived_at + wait_timeout;
+    let mut waiters = Vec::with_capacity(unique_ids.len());
+    let mut woken = None;
+    for unique_id in unique_ids {
+        // Matching on a further id would leave the peer already matched on an earlier one alone
+        woken = already_woken(&mut waiters);
+        if woken.is_some() {
+            break;
+        }
+        match state.parties.arrive(unique_id, deadline, None, &peer).await {
+            Ok(Arrival::Matched(waiting, token)) => {
+                info!(unique_id, "Found matching party");
+                withdraw_all(waiters).await;
+                if !state.settings().strict_grace.is_zero() {
+                    state
+                        .consumed
+                        .consume(unique_id, Instant::now() + state.settings().strict_grace);
+                }
+                return (
+                    StatusCode::OK,
+                    Some(unique_id.clone()),
+                    matched(state, Role::Second, arrived_at.elapsed(), waiting, token),
+                );
+            }
+            Ok(Arrival::Wait(waiter)) => waiters.push((unique_id, waiter)),
+            Err(err) => {
+                withdraw_all(waiters).await;
+                let (status, outcome) = store_unavailable(err);
+                return (status, None, outcome);
+            }
+        }
+    }
+
+    let wake = match state.waiter_permit() {
+        _ if woken.is_some() => {
+            withdraw_all(waiters).await;
+            woken
+        }
+        Some(_permit) => {
+            let _active: Vec<_> = waiters
+                .iter()
+                .map(|(unique_id, _)| {
+                    state
+                        .active_waiters
+                        .register(WaitKind::Rendezvous, unique_id, deadline, client)
+                })
+                .collect();
+
+            info!("Waiting for another party on any id");
+            let wake = tokio::select! {
+                wake = wake_on_any(&mut waiters) => Some(wake),
+                _ = sleep_until(deadline) => None,
+                _ = state.shutting_down() => None,
+            };
+            match wake {
+                Some(wake) => {
+                    withdraw_all(waiters).await;
+                    Some(wake)
+                }
+                None => withdraw_all(waiters).await,
+            }
+        }
+        // Another party may have matched us right as we arrived
+        None => match withdraw_all(waiters).await {
+            Some(wake) => Some(wake),
+            None => {
+                let (status, outcome) = state.overloaded();
+                return (status, None, outcome);
+            }
+        },
+    };
+
+    match wake {
+        Some((unique_id, Wake::Matched(arriving, token))) => {
+            info!(unique_id, "Successfully synchronized parties");
+            (
+                StatusCode::OK,
+                Some(unique_id.clone()),
+                matched(state, Role::First, arrived_at.elapsed(), arriving, token),
+            )
+        }
+        Some((unique_id, Wake::Cancelled)) => {
+            info!(unique_id, "Wait was cancelled");
+            (
+                StatusCode::GONE,
+                None,
+                Outcome::cancelled(arrived_at.elapsed()),
+            )
+        }
+        Some((unique_id, Wake::Superseded)) => {
+            // Only a retry sending the same idempotency key supersedes a wait, and there is none
+            warn!(unique_id, "Wait was unexpectedly taken over");
+            (
+                StatusCode::CONFLICT,
+                None,
+                Outcome::superseded(arrived_at.elapsed()),
+            )
+        }
+        None if state.is_shutting_down() => {
+            info!("Released waiting party on shutdown");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                None,
+                Outcome::shutting_down(arrived_at.elapsed()),
+            )
+        }
+        None => {
+            warn!("Timeout waiting for other party on any id");
+            (
+                StatusCode::REQUEST_TIMEOUT,
+                None,
+                Outcome::timeout(arrived_at.elapsed(), state.retry_after()),
+            )
+        }
+    }
+}
+
+/// Resolves once one of `waiters` is woken up, removing it from them. Waiters that can no longer
+/// be woken up are removed too, and it never resolves once none is left.
+async fn wake_on_any<'a>(
+    waiters: &mut Vec<(&'a UniqueId, Box<dyn Waiter>)>,
+) -> (&'a UniqueId, Wake) {
+    while !waiters.is_empty() {
+        let (wake, index, _) =
+            select_all(waiters.iter_mut().map(|(_, waiter)| waiter.woken())).await;
+        let (unique_id, _) = waiters.swap_remove(index);
+        if let Some(wake) = wake {
+            return (unique_id, wake);
+        }
+    }
+    std::future::pending().await
+}
+
+/// Returns how one of `waiters` was woken up if one already was, removing it from them.
+fn already_woken<'a>(
+    waiters: &mut Vec<(&'a UniqueId, Box<dyn Waiter>)>,
+) -> Option<(&'a UniqueId, Wake)> {
+    let (index, wake) = waiters
+        .iter_mut()
+        .enumerate()
+        .find_map(|(index, (_, waiter))| Some((index, waiter.woken().now_or_never()??)))?;
+    let (unique_id, _) = waiters.swap_remove(index);
+    Some((unique_id, wake))
+}
+
+/// Withdraws every one of `waiters`, returning how the first one woken up right as it gave up was
+/// woken up.
+///
+/// The others woken up at the same time were matched too, but the party can only keep one of the
+/// matches, so their peers are left with a match nobody follows up on.
+async fn withdraw_all(waiters: Vec<(&UniqueId, Box<dyn Waiter>)>) -> Option<(&UniqueId, Wake)> {
+    let mut first = None;
+    for (unique_id, waiter) in waiters {
+        if let Some(wake) = waiter.withdraw().await {
+            if first.is_some() {
+                warn!(unique_id, "Dropped a match made while withdrawing");
+            } else {
+                first = Some((unique_id, wake));
+            }
+        }
+    }
+    first
+}
@@ -0,0 +1,152 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        ConnectInfo, Query, State, WebSocketUpgrade,
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Extension,
+};
+use tracing::{info, warn, Instrument, Span};
+
+use crate::{
+    id::ValidId,
+    parties::{rendezvous, WaitQuery},
+    quotas::QuotaHold,
+    response::{Outcome, ResponseFormat},
+    store::Peer,
+    trace::request_id,
+    AppState, UniqueId,
+};
+
+/// Waits for another party over a WebSocket, which survives proxies killing idle long-polls.
+///
+/// The outcome of the wait is sent as a JSON text frame, after which the connection stays open:
+/// every text frame received afterwards is the unique id of another wait. Frames received while
+/// waiting are ignored. The requested timeout applies to every wait of the connection.
+#[utoipa::path(
+    get,
+    path = "/ws/wait/{unique-id}",
+    tag = "waits",
+    params(("unique-id" = String, Path, description = "Id of the first wait"), WaitQuery),
+    responses(
+        (status = 101, description = "Upgraded to a WebSocket sending the outcomes as JSON text frames"),
+        (status = 400, description = "Invalid id or timeout", body = Outcome),
+    ),
+    security((), ("api_key" = [])),
+)]
+pub async fn ws_wait(
+    ws: WebSocketUpgrade,
+    ValidId(unique_id): ValidId,
+    Query(query): Query<WaitQuery>,
+    State(state): State<Arc<AppState>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    quota: Option<Extension<QuotaHold>>,
+    headers: HeaderMap,
+) -> Response {
+    let wait_timeout = match state.wait_timeout(query.timeout_ms) {
+        Ok(wait_timeout) => wait_timeout,
+        Err(message) => {
+            return ResponseFormat::Json.reply(StatusCode::BAD_REQUEST, Outcome::error(message))
+        }
+    };
+
+    // The socket is handled once the request is done, so it keeps the request span explicitly
+    let span = Span::current();
+    // Every wait of the connection is recorded under the id of its upgrade request
+    let request_id = request_id(&headers).map(str::to_owned);
+    ws.on_upgrade(move |socket| {
+        let client = client.map(|ConnectInfo(client)| client);
+        let wait = handle_socket(socket, state, unique_id, wait_timeout, client, request_id);
+        async move {
+            // The waits of the connection count against the quotas of its key until it closes
+            let _quota = quota;
+            wait.await
+        }
+        .instrument(span)
+    })
+}
+
+async fn handle_socket(
+    mut socket: WebSocket,
+    state: Arc<AppState>,
+    unique_id: UniqueId,
+    wait_timeout: Duration,
+    client: Option<SocketAddr>,
+    request_id: Option<String>,
+) {
+    let mut next_id = Some(unique_id);
+
+    while let Some(unique_id) = next_id.take() {
+        // Browsers can't send custom headers with WebSockets, so the parties are unlabelled
+        let peer = Peer::new(client, None);
+        let outcome = tokio::select! {
+            (_, outcome) = rendezvous(
+                &state,
+                &unique_id,
+                wait_timeout,
+                None,
+                peer,
+                request_id.as_deref(),
+                // The wait ends with the socket, so there's nothing left to recover
+                None,
+            ) => outcome,
+            _ = closed(&mut socket) => {
+                info!(unique_id, "Connection closed while waiting");
+                return;
+            }
+        };
+
+        if !send_outcome(&mut socket, &outcome).await {
+            return;
+        }
+
+        // Open connections would hold the graceful shutdown back
+        if let Outcome::ShuttingDown { .. } = outcome {
+            let _ = socket.send(Message::Close(None)).await;
+            return;
+        }
+
+        next_id = next_unique_id(&mut socket, &state).await;
+    }
+}
+
+/// Resolves once the client closed the connection, discarding any frame received meanwhile.
+async fn closed(socket: &mut WebSocket) {
+    while let Some(Ok(message)) = socket.recv().await {
+        if let Message::Close(_) = message {
+            return;
+        }
+    }
+}
+
+/// Reads frames until the client sends a valid unique id to wait on, or closes the connection.
+async fn next_unique_id(socket: &mut WebSocket, state: &AppState) -> Option<UniqueId> {
+    loop {
+        match socket.recv().await? {
+            Ok(Message::Text(unique_id)) => match state.parse_unique_id(&unique_id) {
+                Ok(unique_id) => return Some(unique_id),
+                Err(message) => {
+                    if !send_outcome(socket, &Outcome::error(message)).await {
+                        return None;
+                    }
+                }
+            },
+            Ok(Message::Close(_)) => return None,
+            Ok(_) => {}
+            Err(err) => {
+                warn!(%err, "Connection error");
+                return None;
+            }
+        }
+    }
+}
+
+/// Sends `outcome` as a JSON text frame, returning `false` if the connection is gone.
+async fn send_outcome(socket: &mut WebSocket, outcome: &Outcome) -> bool {
+    let frame = serde_json::to_string(outcome).expect("outcomes should serialize to JSON");
+
+    socket.send(Message::Text(frame)).await.is_ok()
+}
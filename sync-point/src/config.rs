@@ -0,0 +1,923 @@
+use std::{
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use tracing_subscriber::filter::LevelFilter;
+
+use sync_point_core::{
+    settings::{
+        Settings, DEFAULT_CORS_HEADERS, DEFAULT_CORS_METHODS, DEFAULT_MAX_BODY_BYTES,
+        DEFAULT_MAX_CONCURRENT_WAITERS, DEFAULT_MAX_ID_LENGTH, DEFAULT_MAX_WAITERS,
+    },
+    AccessLogFormat, Faults, MessageTemplates,
+};
+
+use crate::http::Connections;
+
+/// Format of the emitted logs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log pipelines.
+    Json,
+}
+
+/// Command line flags, each falling back to an environment variable.
+///
+/// Every setting is optional so that unset flags don't shadow the configuration file.
+#[derive(Debug, Default, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Path to a TOML configuration file.
+    #[arg(long, env = "SYNC_POINT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to bind the HTTP listener to [default: 0.0.0.0]
+    #[arg(long, env = "SYNC_POINT_ADDR")]
+    pub addr: Option<IpAddr>,
+
+    /// Port to bind the HTTP listener to [default: 8080]
+    #[arg(long, env = "SYNC_POINT_PORT")]
+    pub port: Option<u16>,
+
+    /// Addresses to listen on instead of `--addr` and `--port`, each either `host:port` or
+    /// `unix:/path/to.sock` for a Unix domain socket
+    #[arg(long, env = "SYNC_POINT_LISTEN", value_delimiter = ',')]
+    pub listen: Vec<ListenAddr>,
+
+    /// Address of a TCP listener speaking the line protocol, for clients that can't do HTTP
+    /// [default: none, disabled]
+    #[arg(long, env = "SYNC_POINT_LINE_LISTEN")]
+    pub line_listen: Option<SocketAddr>,
+
+    /// Seconds a party waits for another one before timing out [default: 10]
+    #[arg(long, env = "SYNC_POINT_TIMEOUT_SECS")]
+    pub timeout_secs: Option<u64>,
+
+    /// Maximum length in bytes of the unique ids [default: 128]
+    #[arg(long, env = "SYNC_POINT_MAX_ID_LENGTH")]
+    pub max_id_length: Option<usize>,
+
+    /// Seconds between keepalive events sent to streaming waiters [default: 5]
+    #[arg(long, env = "SYNC_POINT_KEEPALIVE_SECS")]
+    pub keepalive_secs: Option<u64>,
+
+    /// Number of waiting parties above which the service reports itself not ready [default: 10000]
+    #[arg(long, env = "SYNC_POINT_MAX_WAITERS")]
+    pub max_waiters: Option<usize>,
+
+    /// Redis URL to share waiting parties between instances, kept in memory when unset
+    #[cfg(feature = "redis")]
+    #[arg(long, env = "SYNC_POINT_REDIS_URL")]
+    pub redis_url: Option<String>,
+
+    /// Database URL (SQLite or Postgres) recording the rendezvous history, disabled when unset
+    #[cfg(feature = "history")]
+    #[arg(long, env = "SYNC_POINT_HISTORY_URL")]
+    pub history_url: Option<String>,
+
+    /// PEM certificate chain to serve HTTPS, along with `--tls-key`
+    #[cfg(feature = "tls")]
+    #[arg(long, env = "SYNC_POINT_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key to serve HTTPS, along with `--tls-cert`
+    #[cfg(feature = "tls")]
+    #[arg(long, env = "SYNC_POINT_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Requests per second allowed from each client IP on the wait routes [default: 0, unlimited]
+    #[arg(long, env = "SYNC_POINT_RATE_LIMIT_PER_SECOND")]
+    pub rate_limit_per_second: Option<u32>,
+
+    /// Requests a client IP can burst above its rate limit [default: the rate limit]
+    #[arg(long, env = "SYNC_POINT_RATE_LIMIT_BURST")]
+    pub rate_limit_burst: Option<u32>,
+
+    /// API key clients must send as a bearer token, can be repeated [default: none, open access]
+    #[arg(long = "api-key", env = "SYNC_POINT_API_KEYS", value_delimiter = ',')]
+    pub api_keys: Vec<String>,
+
+    /// API key required by the admin routes, can be repeated [default: none, admin API disabled]
+    #[arg(
+        long = "admin-api-key",
+        env = "SYNC_POINT_ADMIN_API_KEYS",
+        value_delimiter = ','
+    )]
+    pub admin_api_keys: Vec<String>,
+
+    /// Host allowed to receive the `callback` of a wait, can be repeated [default: none, callbacks
+    /// disabled]
+    #[arg(
+        long = "callback-host",
+        env = "SYNC_POINT_CALLBACK_HOSTS",
+        value_delimiter = ','
+    )]
+    pub callback_hosts: Vec<String>,
+
+    /// Base URL of an instance of the cluster, this one included, can be repeated [default: none,
+    /// cluster mode disabled]
+    #[arg(
+        long = "cluster-node",
+        env = "SYNC_POINT_CLUSTER_NODES",
+        value_delimiter = ','
+    )]
+    pub cluster_nodes: Vec<String>,
+
+    /// Base URL of this instance among the `--cluster-node`s, required in cluster mode
+    #[arg(long, env = "SYNC_POINT_CLUSTER_SELF")]
+    pub cluster_self: Option<String>,
+
+    /// Origin allowed to call the API from a browser, `*` for any, can be repeated [default: none,
+    /// CORS disabled]
+    #[arg(
+        long = "cors-allowed-origin",
+        env = "SYNC_POINT_CORS_ALLOWED_ORIGINS",
+        value_delimiter = ','
+    )]
+    pub cors_allowed_origins: Vec<String>,
+
+    /// Method allowed in cross-origin requests, can be repeated [default: GET, POST, DELETE]
+    #[arg(
+        long = "cors-allowed-method",
+        env = "SYNC_POINT_CORS_ALLOWED_METHODS",
+        value_delimiter = ','
+    )]
+    pub cors_allowed_methods: Vec<String>,
+
+    /// Header allowed in cross-origin requests, can be repeated [default: the headers of the API]
+    #[arg(
+        long = "cors-allowed-header",
+        env = "SYNC_POINT_CORS_ALLOWED_HEADERS",
+        value_delimiter = ','
+    )]
+    pub cors_allowed_headers: Vec<String>,
+
+    /// Maximum seconds a request can ask to wait with `timeout_ms` [default: 300]
+    #[arg(long, env = "SYNC_POINT_MAX_TIMEOUT_SECS")]
+    pub max_timeout_secs: Option<u64>,
+
+    /// Maximum number of parties waiting at once, others get a 503 response [default: 100000]
+    #[arg(long, env = "SYNC_POINT_MAX_CONCURRENT_WAITERS")]
+    pub max_concurrent_waiters: Option<usize>,
+
+    /// Parties allowed to wait at once in each namespace, 0 for no limit [default: 0]
+    #[arg(long, env = "SYNC_POINT_MAX_WAITERS_PER_NAMESPACE")]
+    pub max_waiters_per_namespace: Option<usize>,
+
+    /// Seconds a matched id rejects further parties with a 409 response, 0 to disable [default: 0]
+    #[arg(long, env = "SYNC_POINT_STRICT_GRACE_SECS")]
+    pub strict_grace_secs: Option<u64>,
+
+    /// Format of the emitted logs, `pretty` or `json` [default: pretty]
+    #[arg(long, env = "SYNC_POINT_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Whether to compress the larger JSON responses with gzip or brotli when clients accept it [default: true]
+    #[arg(long, env = "SYNC_POINT_COMPRESS_RESPONSES")]
+    pub compress_responses: Option<bool>,
+
+    /// Maximum size of request bodies in bytes [default: 65536]
+    #[arg(long, env = "SYNC_POINT_MAX_BODY_BYTES")]
+    pub max_body_bytes: Option<usize>,
+
+    /// Seconds the next party on an id learns it missed a party that timed out, 0 to disable [default: 0]
+    #[arg(long, env = "SYNC_POINT_MISSED_GRACE_SECS")]
+    pub missed_grace_secs: Option<u64>,
+
+    /// File to append the audit log of the rendezvous to as JSON lines, `-` for stdout [default:
+    /// none, disabled]
+    #[arg(long, env = "SYNC_POINT_AUDIT_LOG")]
+    pub audit_log: Option<String>,
+
+    /// WebSocket URL of the relay handing a room to each match [default: none, disabled]
+    #[arg(long, env = "SYNC_POINT_RELAY_URL")]
+    pub relay_url: Option<String>,
+
+    /// OTLP/HTTP endpoint of the OpenTelemetry collector to export the spans to [default: none,
+    /// disabled]
+    #[arg(long, env = "SYNC_POINT_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Whether to accept HTTP/2, over TLS or as cleartext h2c, besides HTTP/1.1 [default: true]
+    #[arg(long, env = "SYNC_POINT_HTTP2")]
+    pub http2: Option<bool>,
+
+    /// Seconds between the pings keeping idle HTTP/2 connections alive, 0 to disable [default: 0]
+    #[arg(long, env = "SYNC_POINT_HTTP2_KEEP_ALIVE_SECS")]
+    pub http2_keep_alive_secs: Option<u64>,
+
+    /// Seconds to wait for the acknowledgement of an HTTP/2 ping before closing the connection
+    /// [default: 20]
+    #[arg(long, env = "SYNC_POINT_HTTP2_KEEP_ALIVE_TIMEOUT_SECS")]
+    pub http2_keep_alive_timeout_secs: Option<u64>,
+
+    /// Maximum number of concurrent HTTP/2 streams, hence of waits, per connection [default: 200]
+    #[arg(long, env = "SYNC_POINT_HTTP2_MAX_CONCURRENT_STREAMS")]
+    pub http2_max_concurrent_streams: Option<u32>,
+
+    /// Seconds the outcomes of the waits are kept for their receipts, 0 to disable receipts
+    /// [default: 300]
+    #[arg(long, env = "SYNC_POINT_RECEIPT_RETENTION_SECS")]
+    pub receipt_retention_secs: Option<u64>,
+
+    /// Whether to inject the configured faults into the wait routes, to test the retries of
+    /// clients [default: false]
+    #[arg(long, env = "SYNC_POINT_CHAOS", num_args = 0..=1, default_missing_value = "true")]
+    pub chaos: Option<bool>,
+
+    /// Maximum latency in milliseconds added at random to each request in chaos mode [default: 0]
+    #[arg(long, env = "SYNC_POINT_CHAOS_MAX_LATENCY_MS")]
+    pub chaos_max_latency_ms: Option<u64>,
+
+    /// Share of the requests failing with a 500 in chaos mode, between 0 and 1 [default: 0]
+    #[arg(long, env = "SYNC_POINT_CHAOS_ERROR_RATE")]
+    pub chaos_error_rate: Option<f64>,
+
+    /// Share of the outcomes dropped in chaos mode, between 0 and 1 [default: 0]
+    #[arg(long, env = "SYNC_POINT_CHAOS_DROP_RATE")]
+    pub chaos_drop_rate: Option<f64>,
+
+    /// File to append a line per request to, `-` for stdout [default: none, disabled]
+    #[arg(long, env = "SYNC_POINT_ACCESS_LOG")]
+    pub access_log: Option<String>,
+
+    /// Format of the access log lines, `common`, `combined` or `json` [default: common]
+    #[arg(long, env = "SYNC_POINT_ACCESS_LOG_FORMAT")]
+    pub access_log_format: Option<AccessLogFormat>,
+
+    /// Path prefix of the requests left out of the access log, can be repeated [default: none]
+    #[arg(
+        long = "access-log-exclude-path",
+        env = "SYNC_POINT_ACCESS_LOG_EXCLUDE_PATHS",
+        value_delimiter = ','
+    )]
+    pub access_log_exclude_paths: Vec<String>,
+
+    /// Waits each API key can have going on at once, 0 for no limit [default: 0]
+    #[arg(long, env = "SYNC_POINT_MAX_WAITERS_PER_KEY")]
+    pub max_waiters_per_key: Option<usize>,
+
+    /// Waits each API key can start in an hour, 0 for no limit [default: 0]
+    #[arg(long, env = "SYNC_POINT_MAX_WAITS_PER_KEY_PER_HOUR")]
+    pub max_waits_per_key_per_hour: Option<usize>,
+
+    /// Maximum level of the emitted logs [default: info]
+    #[arg(long, env = "SYNC_POINT_LOG_LEVEL")]
+    pub log_level: Option<LevelFilter>,
+}
+
+/// Settings read from the TOML configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub addr: Option<IpAddr>,
+    pub port: Option<u16>,
+    #[serde(default, with = "one_or_many")]
+    pub listen: Option<Vec<ListenAddr>>,
+    pub line_listen: Option<SocketAddr>,
+    pub timeout_secs: Option<u64>,
+    pub max_id_length: Option<usize>,
+    pub keepalive_secs: Option<u64>,
+    pub max_waiters: Option<usize>,
+    #[cfg(feature = "redis")]
+    pub redis_url: Option<String>,
+    #[cfg(feature = "history")]
+    pub history_url: Option<String>,
+    #[cfg(feature = "tls")]
+    pub tls_cert: Option<PathBuf>,
+    #[cfg(feature = "tls")]
+    pub tls_key: Option<PathBuf>,
+    pub rate_limit_per_second: Option<u32>,
+    pub rate_limit_burst: Option<u32>,
+    pub api_keys: Option<Vec<String>>,
+    pub admin_api_keys: Option<Vec<String>>,
+    pub callback_hosts: Option<Vec<String>>,
+    pub cluster_nodes: Option<Vec<String>>,
+    pub cluster_self: Option<String>,
+    pub cors_allowed_origins: Option<Vec<String>>,
+    pub cors_allowed_methods: Option<Vec<String>>,
+    pub cors_allowed_headers: Option<Vec<String>>,
+    pub max_timeout_secs: Option<u64>,
+    pub max_concurrent_waiters: Option<usize>,
+    pub max_waiters_per_namespace: Option<usize>,
+    pub strict_grace_secs: Option<u64>,
+    pub log_format: Option<LogFormat>,
+    pub compress_responses: Option<bool>,
+    pub max_body_bytes: Option<usize>,
+    pub missed_grace_secs: Option<u64>,
+    pub audit_log: Option<String>,
+    pub messages: Option<MessageTemplates>,
+    pub relay_url: Option<String>,
+    pub otlp_endpoint: Option<String>,
+    pub http2: Option<bool>,
+    pub http2_keep_alive_secs: Option<u64>,
+    pub http2_keep_alive_timeout_secs: Option<u64>,
+    pub http2_max_concurrent_streams: Option<u32>,
+    pub receipt_retention_secs: Option<u64>,
+    pub chaos: Option<bool>,
+    pub chaos_max_latency_ms: Option<u64>,
+    pub chaos_error_rate: Option<f64>,
+    pub chaos_drop_rate: Option<f64>,
+    pub access_log: Option<String>,
+    pub access_log_format: Option<AccessLogFormat>,
+    pub access_log_exclude_paths: Option<Vec<String>>,
+    pub max_waiters_per_key: Option<usize>,
+    pub max_waits_per_key_per_hour: Option<usize>,
+    #[serde(default, with = "level_filter")]
+    pub log_level: Option<LevelFilter>,
+}
+
+impl FileConfig {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)?;
+
+        toml::from_str(&content).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// Runtime configuration of the service.
+///
+/// Settings are layered, from highest to lowest precedence: CLI flags, environment
+/// variables, configuration file and defaults.
+#[derive(Debug)]
+pub struct Config {
+    pub addr: IpAddr,
+    pub port: u16,
+    pub listen: Vec<ListenAddr>,
+    pub line_listen: Option<SocketAddr>,
+    pub timeout_secs: u64,
+    pub max_id_length: usize,
+    pub keepalive_secs: u64,
+    pub max_waiters: usize,
+    #[cfg(feature = "redis")]
+    pub redis_url: Option<String>,
+    #[cfg(feature = "history")]
+    pub history_url: Option<String>,
+    #[cfg(feature = "tls")]
+    pub tls_cert: Option<PathBuf>,
+    #[cfg(feature = "tls")]
+    pub tls_key: Option<PathBuf>,
+    pub rate_limit_per_second: u32,
+    pub rate_limit_burst: u32,
+    pub api_keys: Vec<String>,
+    pub admin_api_keys: Vec<String>,
+    pub callback_hosts: Vec<String>,
+    pub cluster_nodes: Vec<String>,
+    pub cluster_self: Option<String>,
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_methods: Vec<String>,
+    pub cors_allowed_headers: Vec<String>,
+    pub max_timeout_secs: u64,
+    pub max_concurrent_waiters: usize,
+    pub max_waiters_per_namespace: usize,
+    pub strict_grace_secs: u64,
+    pub log_format: LogFormat,
+    pub compress_responses: bool,
+    pub max_body_bytes: usize,
+    pub missed_grace_secs: u64,
+    pub audit_log: Option<String>,
+    /// Only read from the file, being a table.
+    pub messages: MessageTemplates,
+    pub relay_url: Option<String>,
+    pub otlp_endpoint: Option<String>,
+    pub http2: bool,
+    pub http2_keep_alive_secs: u64,
+    pub http2_keep_alive_timeout_secs: u64,
+    pub http2_max_concurrent_streams: u32,
+    pub receipt_retention_secs: u64,
+    pub chaos: bool,
+    pub chaos_max_latency_ms: u64,
+    pub chaos_error_rate: f64,
+    pub chaos_drop_rate: f64,
+    pub access_log: Option<String>,
+    pub access_log_format: AccessLogFormat,
+    pub access_log_exclude_paths: Vec<String>,
+    pub max_waiters_per_key: usize,
+    pub max_waits_per_key_per_hour: usize,
+    pub log_level: LevelFilter,
+}
+
+impl Config {
+    /// Loads the configuration from the process arguments, environment and configuration file.
+    pub fn load() -> io::Result<Self> {
+        let cli = Cli::parse();
+        let file = match &cli.config {
+            Some(path) => FileConfig::from_path(path)?,
+            None => FileConfig::default(),
+        };
+
+        Ok(Self::from_sources(cli, file))
+    }
+
+    pub fn from_sources(cli: Cli, file: FileConfig) -> Self {
+        Config {
+            addr: cli
+                .addr
+                .or(file.addr)
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            port: cli.port.or(file.port).unwrap_or(8080),
+            listen: if cli.listen.is_empty() {
+                file.listen.unwrap_or_default()
+            } else {
+                cli.listen
+            },
+            line_listen: cli.line_listen.or(file.line_listen),
+            timeout_secs: cli.timeout_secs.or(file.timeout_secs).unwrap_or(10),
+            max_id_length: cli
+                .max_id_length
+                .or(file.max_id_length)
+                .unwrap_or(DEFAULT_MAX_ID_LENGTH),
+            keepalive_secs: cli.keepalive_secs.or(file.keepalive_secs).unwrap_or(5),
+            max_waiters: cli
+                .max_waiters
+                .or(file.max_waiters)
+                .unwrap_or(DEFAULT_MAX_WAITERS),
+            #[cfg(feature = "redis")]
+            redis_url: cli.redis_url.or(file.redis_url),
+            #[cfg(feature = "history")]
+            history_url: cli.history_url.or(file.history_url),
+            #[cfg(feature = "tls")]
+            tls_cert: cli.tls_cert.or(file.tls_cert),
+            #[cfg(feature = "tls")]
+            tls_key: cli.tls_key.or(file.tls_key),
+            rate_limit_per_second: cli
+                .rate_limit_per_second
+                .or(file.rate_limit_per_second)
+                .unwrap_or(0),
+            rate_limit_burst: cli.rate_limit_burst.or(file.rate_limit_burst).unwrap_or(0),
+            api_keys: if cli.api_keys.is_empty() {
+                file.api_keys.unwrap_or_default()
+            } else {
+                cli.api_keys
+            },
+            admin_api_keys: if cli.admin_api_keys.is_empty() {
+                file.admin_api_keys.unwrap_or_default()
+            } else {
+                cli.admin_api_keys
+            },
+            callback_hosts: if cli.callback_hosts.is_empty() {
+                file.callback_hosts.unwrap_or_default()
+            } else {
+                cli.callback_hosts
+            },
+            cluster_nodes: if cli.cluster_nodes.is_empty() {
+                file.cluster_nodes.unwrap_or_default()
+            } else {
+                cli.cluster_nodes
+            },
+            cluster_self: cli.cluster_self.or(file.cluster_self),
+            cors_allowed_origins: if cli.cors_allowed_origins.is_empty() {
+                file.cors_allowed_origins.unwrap_or_default()
+            } else {
+                cli.cors_allowed_origins
+            },
+            cors_allowed_methods: if cli.cors_allowed_methods.is_empty() {
+                file.cors_allowed_methods
+                    .unwrap_or_else(|| to_strings(DEFAULT_CORS_METHODS))
+            } else {
+                cli.cors_allowed_methods
+            },
+            cors_allowed_headers: if cli.cors_allowed_headers.is_empty() {
+                file.cors_allowed_headers
+                    .unwrap_or_else(|| to_strings(DEFAULT_CORS_HEADERS))
+            } else {
+                cli.cors_allowed_headers
+            },
+            max_timeout_secs: cli
+                .max_timeout_secs
+                .or(file.max_timeout_secs)
+                .unwrap_or(300),
+            max_concurrent_waiters: cli
+                .max_concurrent_waiters
+                .or(file.max_concurrent_waiters)
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_WAITERS),
+            max_waiters_per_namespace: cli
+                .max_waiters_per_namespace
+                .or(file.max_waiters_per_namespace)
+                .unwrap_or(0),
+            strict_grace_secs: cli
+                .strict_grace_secs
+                .or(file.strict_grace_secs)
+                .unwrap_or(0),
+            log_format: cli
+                .log_format
+                .or(file.log_format)
+                .unwrap_or(LogFormat::Pretty),
+            compress_responses: cli
+                .compress_responses
+                .or(file.compress_responses)
+                .unwrap_or(true),
+            max_body_bytes: cli
+                .max_body_bytes
+                .or(file.max_body_bytes)
+                .unwrap_or(DEFAULT_MAX_BODY_BYTES),
+            missed_grace_secs: cli
+                .missed_grace_secs
+                .or(file.missed_grace_secs)
+                .unwrap_or(0),
+            audit_log: cli.audit_log.or(file.audit_log),
+            messages: file.messages.unwrap_or_default(),
+            relay_url: cli.relay_url.or(file.relay_url),
+            otlp_endpoint: cli.otlp_endpoint.or(file.otlp_endpoint),
+            http2: cli.http2.or(file.http2).unwrap_or(true),
+            http2_keep_alive_secs: cli
+                .http2_keep_alive_secs
+                .or(file.http2_keep_alive_secs)
+                .unwrap_or(0),
+            http2_keep_alive_timeout_secs: cli
+                .http2_keep_alive_timeout_secs
+                .or(file.http2_keep_alive_timeout_secs)
+                .unwrap_or(20),
+            http2_max_concurrent_streams: cli
+                .http2_max_concurrent_streams
+                .or(file.http2_max_concurrent_streams)
+                .unwrap_or(200),
+            receipt_retention_secs: cli
+                .receipt_retention_secs
+                .or(file.receipt_retention_secs)
+                .unwrap_or(300),
+            chaos: cli.chaos.or(file.chaos).unwrap_or(false),
+            chaos_max_latency_ms: cli
+                .chaos_max_latency_ms
+                .or(file.chaos_max_latency_ms)
+                .unwrap_or(0),
+            chaos_error_rate: cli
+                .chaos_error_rate
+                .or(file.chaos_error_rate)
+                .unwrap_or(0.0),
+            chaos_drop_rate: cli.chaos_drop_rate.or(file.chaos_drop_rate).unwrap_or(0.0),
+            access_log: cli.access_log.or(file.access_log),
+            access_log_format: cli
+                .access_log_format
+                .or(file.access_log_format)
+                .unwrap_or_default(),
+            access_log_exclude_paths: if cli.access_log_exclude_paths.is_empty() {
+                file.access_log_exclude_paths.unwrap_or_default()
+            } else {
+                cli.access_log_exclude_paths
+            },
+            max_waiters_per_key: cli
+                .max_waiters_per_key
+                .or(file.max_waiters_per_key)
+                .unwrap_or(0),
+            max_waits_per_key_per_hour: cli
+                .max_waits_per_key_per_hour
+                .or(file.max_waits_per_key_per_hour)
+                .unwrap_or(0),
+            log_level: cli
+                .log_level
+                .or(file.log_level)
+                .unwrap_or(LevelFilter::INFO),
+        }
+    }
+
+    /// Addresses to listen on, `addr` and `port` unless others are configured.
+    pub fn listen_addrs(&self) -> Vec<ListenAddr> {
+        if self.listen.is_empty() {
+            vec![ListenAddr::Tcp(SocketAddr::new(self.addr, self.port))]
+        } else {
+            self.listen.clone()
+        }
+    }
+
+    pub fn wait_timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+
+    pub fn connections(&self) -> Connections {
+        Connections {
+            http2: self.http2,
+            keep_alive_interval: (self.http2_keep_alive_secs > 0)
+                .then(|| Duration::from_secs(self.http2_keep_alive_secs)),
+            keep_alive_timeout: Duration::from_secs(self.http2_keep_alive_timeout_secs),
+            max_concurrent_streams: self.http2_max_concurrent_streams,
+        }
+    }
+
+    pub fn settings(&self) -> Settings {
+        Settings {
+            max_id_length: self.max_id_length,
+            keepalive_interval: Duration::from_secs(self.keepalive_secs),
+            max_waiters: self.max_waiters,
+            max_concurrent_waiters: self.max_concurrent_waiters,
+            max_waiters_per_namespace: self.max_waiters_per_namespace,
+            max_wait_timeout: Duration::from_secs(self.max_timeout_secs),
+            api_keys: self.api_keys.clone(),
+            max_waiters_per_key: self.max_waiters_per_key,
+            max_waits_per_key_per_hour: self.max_waits_per_key_per_hour,
+            admin_api_keys: self.admin_api_keys.clone(),
+            callback_hosts: self.callback_hosts.clone(),
+            cors_allowed_origins: self.cors_allowed_origins.clone(),
+            cors_allowed_methods: self.cors_allowed_methods.clone(),
+            cors_allowed_headers: self.cors_allowed_headers.clone(),
+            strict_grace: Duration::from_secs(self.strict_grace_secs),
+            compress_responses: self.compress_responses,
+            max_body_bytes: self.max_body_bytes,
+            missed_grace: Duration::from_secs(self.missed_grace_secs),
+            rate_limit_per_second: self.rate_limit_per_second,
+            rate_limit_burst: self.rate_limit_burst,
+            messages: self.messages.clone(),
+            relay_url: self.relay_url.clone(),
+            receipt_retention: Duration::from_secs(self.receipt_retention_secs),
+            chaos: self.chaos.then_some(Faults {
+                max_latency_ms: self.chaos_max_latency_ms,
+                error_rate: self.chaos_error_rate,
+                drop_rate: self.chaos_drop_rate,
+            }),
+            ..Settings::new(self.wait_timeout())
+        }
+    }
+}
+
+/// Address the server listens on.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// Unix domain socket, for sidecars that shouldn't open a network port.
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(listen: &str) -> Result<Self, Self::Err> {
+        match listen.strip_prefix("unix:") {
+            Some("") => Err("the socket path is empty".to_owned()),
+            Some(path) => Ok(ListenAddr::Unix(PathBuf::from(path))),
+            None => listen
+                .parse()
+                .map(ListenAddr::Tcp)
+                .map_err(|err| format!("{err}, expected `host:port` or `unix:/path`")),
+        }
+    }
+}
+
+impl TryFrom<String> for ListenAddr {
+    type Error = String;
+
+    fn try_from(listen: String) -> Result<Self, Self::Error> {
+        listen.parse()
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => addr.fmt(f),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|&value| value.to_owned()).collect()
+}
+
+// `LevelFilter` doesn't implement `Deserialize`, so we parse it from its string form.
+mod level_filter {
+    use serde::{Deserialize, Deserializer};
+    use tracing_subscriber::filter::LevelFilter;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<LevelFilter>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<String>::deserialize(deserializer)?
+            .map(|level| level.parse().map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+// `listen` is either a single address or a list of them.
+mod one_or_many {
+    use serde::{Deserialize, Deserializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+    {
+        Ok(
+            Option::<OneOrMany<T>>::deserialize(deserializer)?.map(|values| match values {
+                OneOrMany::One(value) => vec![value],
+                OneOrMany::Many(values) => values,
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listen_addr(listen: &str) -> ListenAddr {
+        listen.parse().unwrap()
+    }
+
+    #[test]
+    fn defaults() {
+        let cli = Cli::try_parse_from(["sync-point"]).unwrap();
+        let config = Config::from_sources(cli, FileConfig::default());
+
+        assert_eq!(config.listen_addrs(), [listen_addr("0.0.0.0:8080")]);
+        assert_eq!(config.wait_timeout(), Duration::from_secs(10));
+        assert_eq!(config.log_level, LevelFilter::INFO);
+        assert_eq!(config.log_format, LogFormat::Pretty);
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        let cli = Cli::try_parse_from([
+            "sync-point",
+            "--addr",
+            "127.0.0.1",
+            "--port",
+            "9000",
+            "--timeout-secs",
+            "30",
+        ])
+        .unwrap();
+        let config = Config::from_sources(cli, FileConfig::default());
+
+        assert_eq!(config.listen_addrs(), [listen_addr("127.0.0.1:9000")]);
+        assert_eq!(config.wait_timeout(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn flags_override_file() {
+        let cli = Cli::try_parse_from(["sync-point", "--port", "9000"]).unwrap();
+        let file: FileConfig = toml::from_str(
+            r#"
+            addr = "127.0.0.1"
+            port = 8000
+            timeout_secs = 5
+            max_id_length = 36
+            log_level = "debug"
+            log_format = "json"
+            "#,
+        )
+        .unwrap();
+        let config = Config::from_sources(cli, file);
+
+        assert_eq!(config.listen_addrs(), [listen_addr("127.0.0.1:9000")]);
+        assert_eq!(config.wait_timeout(), Duration::from_secs(5));
+        assert_eq!(config.max_id_length, 36);
+        assert_eq!(config.log_level, LevelFilter::DEBUG);
+        assert_eq!(config.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn api_keys_from_flags_replace_file_keys() {
+        let file: FileConfig = toml::from_str(r#"api_keys = ["from-file"]"#).unwrap();
+
+        let cli = Cli::try_parse_from(["sync-point"]).unwrap();
+        let config = Config::from_sources(cli, file);
+        assert_eq!(config.api_keys, ["from-file"]);
+
+        let cli =
+            Cli::try_parse_from(["sync-point", "--api-key", "a", "--api-key", "b,c"]).unwrap();
+        let config = Config::from_sources(cli, FileConfig::default());
+        assert_eq!(config.api_keys, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn listen_replaces_addr_and_port() {
+        let cli =
+            Cli::try_parse_from(["sync-point", "--listen", "unix:/tmp/sync-point.sock"]).unwrap();
+        let file: FileConfig = toml::from_str(r#"listen = "127.0.0.1:9000""#).unwrap();
+        let config = Config::from_sources(cli, file);
+        assert_eq!(
+            config.listen_addrs(),
+            [ListenAddr::Unix("/tmp/sync-point.sock".into())]
+        );
+
+        let cli = Cli::try_parse_from(["sync-point", "--port", "8000"]).unwrap();
+        let file: FileConfig = toml::from_str(r#"listen = "127.0.0.1:9000""#).unwrap();
+        let config = Config::from_sources(cli, file);
+        assert_eq!(config.listen_addrs(), [listen_addr("127.0.0.1:9000")]);
+
+        assert!(Cli::try_parse_from(["sync-point", "--listen", "unix:"]).is_err());
+        assert!(Cli::try_parse_from(["sync-point", "--listen", "localhost"]).is_err());
+    }
+
+    #[test]
+    fn listen_takes_several_addresses() {
+        let cli = Cli::try_parse_from([
+            "sync-point",
+            "--listen",
+            "0.0.0.0:8080,[::]:8080",
+            "--listen",
+            "unix:/tmp/sync-point.sock",
+        ])
+        .unwrap();
+        let config = Config::from_sources(cli, FileConfig::default());
+        assert_eq!(
+            config.listen_addrs(),
+            [
+                listen_addr("0.0.0.0:8080"),
+                listen_addr("[::]:8080"),
+                listen_addr("unix:/tmp/sync-point.sock"),
+            ]
+        );
+
+        let cli = Cli::try_parse_from(["sync-point"]).unwrap();
+        let file: FileConfig =
+            toml::from_str(r#"listen = ["127.0.0.1:8080", "127.0.0.1:8081"]"#).unwrap();
+        let config = Config::from_sources(cli, file);
+        assert_eq!(
+            config.listen_addrs(),
+            [listen_addr("127.0.0.1:8080"), listen_addr("127.0.0.1:8081")]
+        );
+    }
+
+    #[test]
+    fn chaos_flag_enables_the_configured_faults() {
+        let cli = Cli::try_parse_from(["sync-point", "--chaos-error-rate", "0.1"]).unwrap();
+        let config = Config::from_sources(cli, FileConfig::default());
+        assert_eq!(config.settings().chaos, None);
+
+        let cli =
+            Cli::try_parse_from(["sync-point", "--chaos", "--chaos-error-rate", "0.1"]).unwrap();
+        let config = Config::from_sources(cli, FileConfig::default());
+        assert_eq!(
+            config.settings().chaos,
+            Some(Faults {
+                error_rate: 0.1,
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn zero_keepalive_is_rejected() {
+        let cli = Cli::try_parse_from(["sync-point", "--keepalive-secs", "0"]).unwrap();
+        let config = Config::from_sources(cli, FileConfig::default());
+        assert!(config.settings().validate().is_err());
+
+        let file: FileConfig = toml::from_str("keepalive_secs = 0").unwrap();
+        let config = Config::from_sources(Cli::default(), file);
+        assert!(config.settings().validate().is_err());
+    }
+
+    #[test]
+    fn access_log_format_is_read_from_flags_or_file() {
+        let file: FileConfig = toml::from_str(r#"access_log_format = "json""#).unwrap();
+        let config = Config::from_sources(Cli::default(), file);
+        assert_eq!(config.access_log_format, AccessLogFormat::Json);
+
+        let cli = Cli::try_parse_from(["sync-point", "--access-log-format", "combined"]).unwrap();
+        let config = Config::from_sources(cli, FileConfig::default());
+        assert_eq!(config.access_log_format, AccessLogFormat::Combined);
+
+        assert!(Cli::try_parse_from(["sync-point", "--access-log-format", "apache"]).is_err());
+    }
+
+    #[test]
+    fn message_templates_are_read_from_file() {
+        let cli = Cli::try_parse_from(["sync-point"]).unwrap();
+        let file: FileConfig = toml::from_str(
+            r#"
+            [messages]
+            inbound = "Bienvenue sur {id} !"
+            "#,
+        )
+        .unwrap();
+        let config = Config::from_sources(cli, file);
+        assert_eq!(
+            config.settings().messages,
+            MessageTemplates {
+                inbound: Some("Bienvenue sur {id} !".to_owned()),
+                ..MessageTemplates::default()
+            }
+        );
+
+        assert!(toml::from_str::<FileConfig>(
+            "[messages]
+unknown = \"\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn file_rejects_unknown_settings() {
+        assert!(toml::from_str::<FileConfig>("unknown = 1").is_err());
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::{pin, Pin},
+    time::Duration,
+};
+
+use axum::{extract::ConnectInfo, Router};
+use hyper::server::conn::http1;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::watch,
+};
+use tower::ServiceExt;
+use tracing::{debug, warn};
+
+/// Tuning of the HTTP connections, the same for every listener.
+#[derive(Debug, Clone)]
+pub struct Connections {
+    /// Whether HTTP/2 is accepted besides HTTP/1.1, negotiated over TLS or with prior knowledge.
+    pub http2: bool,
+    /// Interval of the pings keeping idle HTTP/2 connections alive, if any.
+    pub keep_alive_interval: Option<Duration>,
+    /// How long a ping may go unacknowledged before the connection is closed.
+    pub keep_alive_timeout: Duration,
+    /// Maximum number of concurrent streams of an HTTP/2 connection, each holding a request.
+    pub max_concurrent_streams: u32,
+}
+
+impl Default for Connections {
+    /// Defaults of hyper, with HTTP/2 enabled.
+    fn default() -> Self {
+        Connections {
+            http2: true,
+            keep_alive_interval: None,
+            keep_alive_timeout: Duration::from_secs(20),
+            max_concurrent_streams: 200,
+        }
+    }
+}
+
+impl Connections {
+    /// Builds the connection builder with these settings, serving both HTTP/1.1 and HTTP/2.
+    pub fn builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder
+            .http2()
+            .timer(TokioTimer::new())
+            .keep_alive_interval(self.keep_alive_interval)
+            .keep_alive_timeout(self.keep_alive_timeout)
+            .max_concurrent_streams(self.max_concurrent_streams);
+        builder
+    }
+}
+
+/// Binds a TCP listener to `addr`, IPv6 addresses only accepting IPv6 so that they can be bound
+/// along with the IPv4 address of the same port.
+pub fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // Like the listeners of tokio, so that restarts don't wait for the old connections to close
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Source of the connections served by [`serve`].
+pub trait Listener: Send + 'static {
+    type Io: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+    /// Address of the clients, handed to the handlers as [`ConnectInfo`].
+    type Addr: Clone + Send + Sync + 'static;
+
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Io, Self::Addr)>> + Send;
+}
+
+impl Listener for TcpListener {
+    type Io = tokio::net::TcpStream;
+    type Addr = SocketAddr;
+
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Io, Self::Addr)>> + Send {
+        TcpListener::accept(self)
+    }
+}
+
+/// Serves `app` on the connections of `listener` until `shutdown` resolves and the open
+/// connections are closed.
+pub async fn serve<L: Listener>(
+    listener: L,
+    app: Router,
+    connections: &Connections,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let builder = connections.builder();
+
+    // Connections hold a receiver each, so that we can wait for all of them to close
+    let (shutting_down, shutdown_requested) = watch::channel(());
+    let mut shutdown = pin!(shutdown);
+    loop {
+        let (stream, client) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(%err, "Failed to accept connection");
+                    // Avoids spinning when out of file descriptors
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let service = TowerToHyperService::new(app.clone().map_request(
+            move |mut request: axum::extract::Request<_>| {
+                request.extensions_mut().insert(ConnectInfo(client.clone()));
+                request
+            },
+        ));
+        let builder = builder.clone();
+        let http2 = connections.http2;
+        let shutdown_requested = shutdown_requested.clone();
+        tokio::spawn(async move {
+            let io = TokioIo::new(stream);
+            // The automatic builder ignores `http1_only` once upgrades, hence WebSockets, are on
+            let served = if http2 {
+                let connection = builder.serve_connection_with_upgrades(io, service);
+                until_shutdown(connection, |c| c.graceful_shutdown(), shutdown_requested).await
+            } else {
+                let connection = http1::Builder::new()
+                    .serve_connection(io, service)
+                    .with_upgrades();
+                until_shutdown(connection, |c| c.graceful_shutdown(), shutdown_requested)
+                    .await
+                    .map_err(Into::into)
+            };
+            if let Err(err) = served {
+                debug!(%err, "Connection failed");
+            }
+        });
+    }
+
+    drop(listener);
+    drop(shutdown_requested);
+    shutting_down.send_replace(());
+    shutting_down.closed().await;
+}
+
+/// Drives `connection` to completion, shutting it down gracefully once shutdown is requested.
+async fn until_shutdown<C, E>(
+    connection: C,
+    graceful_shutdown: impl FnOnce(Pin<&mut C>),
+    mut shutdown_requested: watch::Receiver<()>,
+) -> Result<(), E>
+where
+    C: Future<Output = Result<(), E>>,
+{
+    let mut connection = pin!(connection);
+    tokio::select! {
+        served = connection.as_mut() => served,
+        _ = shutdown_requested.changed() => {
+            graceful_shutdown(connection.as_mut());
+            connection.await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use axum::routing::get;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::oneshot,
+    };
+
+    use super::*;
+
+    /// Sends the HTTP/2 connection preface with prior knowledge, returning what the server
+    /// answered with.
+    async fn h2c_preface(http2: bool) -> Vec<u8> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/healthz", get(|| async { "OK" }));
+        let (shutdown, shutdown_requested) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve(
+                listener,
+                app,
+                &Connections {
+                    http2,
+                    ..Default::default()
+                },
+                async {
+                    let _ = shutdown_requested.await;
+                },
+            )
+            .await
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        // Preface followed by an empty SETTINGS frame
+        stream
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+            .await
+            .unwrap();
+        let mut answer = vec![0; 9];
+        let read = stream.read(&mut answer).await.unwrap();
+        answer.truncate(read);
+
+        drop(stream);
+        shutdown.send(()).unwrap();
+        server.await.unwrap();
+        answer
+    }
+
+    #[tokio::test]
+    async fn serves_h2c_unless_disabled() {
+        // The server answers the preface with its own SETTINGS frame
+        let answer = h2c_preface(true).await;
+        assert_eq!(answer.get(3), Some(&0x04));
+
+        // Whereas an HTTP/1.1 server drops the connection
+        let answer = h2c_preface(false).await;
+        assert_eq!(answer, b"");
+    }
+
+    #[tokio::test]
+    async fn ipv4_and_ipv6_addresses_share_ports() {
+        // Skipped on hosts without IPv6
+        if bind((Ipv6Addr::LOCALHOST, 0).into()).is_err() {
+            return;
+        }
+
+        let ipv4 = bind((Ipv4Addr::UNSPECIFIED, 0).into()).unwrap();
+        let port = ipv4.local_addr().unwrap().port();
+        let ipv6 = bind((Ipv6Addr::UNSPECIFIED, port).into()).unwrap();
+        assert_eq!(ipv6.local_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn hands_the_client_address_to_the_handlers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route(
+            "/client",
+            get(|ConnectInfo(client): ConnectInfo<SocketAddr>| async move { client.to_string() }),
+        );
+        let (shutdown, shutdown_requested) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve(listener, app, &Connections::default(), async {
+                let _ = shutdown_requested.await;
+            })
+            .await
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let local = stream.local_addr().unwrap();
+        stream
+            .write_all(b"GET /client HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(&local.to_string()));
+
+        shutdown.send(()).unwrap();
+        server.await.unwrap();
+    }
+}
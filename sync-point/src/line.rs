@@ -0,0 +1,288 @@
+use std::{fmt::Write as _, future::Future, io, net::SocketAddr, pin::pin, time::Duration};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request},
+    http::{
+        header::{ACCEPT, AUTHORIZATION},
+        Method,
+    },
+    Router,
+};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
+
+/// Longest command accepted, enough for an id and an API key.
+const MAX_LINE_LENGTH: usize = 1024;
+
+/// Largest response read back from the app, far more than any outcome takes.
+const MAX_RESPONSE_LENGTH: usize = 64 * 1024;
+
+/// Outcome of a wait as answered by the app in JSON.
+#[derive(Deserialize)]
+struct Answer {
+    status: String,
+    message: Option<String>,
+}
+
+/// Serves the line protocol on `listener` until `shutdown` resolves and the open connections are
+/// closed, for clients that can't speak HTTP, like shell scripts over `/dev/tcp` or
+/// microcontrollers.
+///
+/// Every line a client sends is a command answered with a line:
+/// - `WAIT <id>` or `WAIT <id> <api key>` waits like `POST /wait-for-second-party/<id>`, answering
+///   `MATCHED` or `TIMEOUT`, or the status of any other outcome in uppercase, e.g. `CANCELLED`.
+/// - Invalid commands and failed waits are answered with `ERROR <message>`.
+///
+/// The waits go through `app`, so they're authenticated, rate limited and forwarded in a cluster
+/// like the HTTP ones. A client closing the connection while waiting gives up its wait.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    info!(
+        "Serving the line protocol on {}",
+        listener.local_addr().unwrap()
+    );
+
+    // Connections hold a receiver each, so that we can wait for all of them to close
+    let (shutting_down, shutdown_requested) = watch::channel(false);
+    let mut shutdown = pin!(shutdown);
+    loop {
+        let (stream, client) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(%err, "Failed to accept connection");
+                    // Avoids spinning when out of file descriptors
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let (app, shutdown_requested) = (app.clone(), shutdown_requested.clone());
+        tokio::spawn(async move {
+            if let Err(err) = serve_connection(stream, client, app, shutdown_requested).await {
+                debug!(%err, "Connection failed");
+            }
+        });
+    }
+
+    drop(listener);
+    drop(shutdown_requested);
+    shutting_down.send_replace(true);
+    shutting_down.closed().await;
+}
+
+/// Answers the commands of `client` until it closes the connection, or until shutdown is
+/// requested while it isn't waiting.
+async fn serve_connection(
+    stream: TcpStream,
+    client: SocketAddr,
+    app: Router,
+    mut shutdown_requested: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let mut command = (&mut reader).take(MAX_LINE_LENGTH as u64);
+        let read = tokio::select! {
+            read = command.read_line(&mut line) => read?,
+            _ = shutdown_requested.wait_for(|requested| *requested) => return Ok(()),
+        };
+        if read == 0 {
+            return Ok(());
+        }
+        if !line.ends_with('\n') && read == MAX_LINE_LENGTH {
+            writer.write_all(b"ERROR The command is too long\n").await?;
+            return Ok(());
+        }
+
+        let request = match parse_command(&line) {
+            Ok(request) => request,
+            Err(message) => {
+                writer
+                    .write_all(format!("ERROR {message}\n").as_bytes())
+                    .await?;
+                continue;
+            }
+        };
+        let Some(answer) = wait(&app, request, client, &mut reader).await else {
+            // The client went away, the wait was given up along with the request
+            return Ok(());
+        };
+        writer.write_all(answer.as_bytes()).await?;
+    }
+}
+
+/// Request of the wait asked for by `line`, or why it isn't a valid command.
+fn parse_command(line: &str) -> Result<Request, &'static str> {
+    let mut words = line.split_ascii_whitespace();
+    let (Some("WAIT"), Some(unique_id), api_key, None) =
+        (words.next(), words.next(), words.next(), words.next())
+    else {
+        return Err("Expected WAIT <id> or WAIT <id> <api key>");
+    };
+
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(format!(
+            "/wait-for-second-party/{}",
+            percent_encode(unique_id)
+        ))
+        .header(ACCEPT, "application/json");
+    if let Some(api_key) = api_key {
+        request = request.header(AUTHORIZATION, format!("Bearer {api_key}"));
+    }
+    request
+        .body(Body::empty())
+        .map_err(|_| "The API key may only contain visible characters")
+}
+
+/// Runs the wait of `request` through `app`, returning the line answering it, or `None` if the
+/// client closed the connection first.
+async fn wait(
+    app: &Router,
+    mut request: Request,
+    client: SocketAddr,
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Option<String> {
+    request.extensions_mut().insert(ConnectInfo(client));
+    let mut response = pin!(app.clone().oneshot(request));
+    let response = tokio::select! {
+        response = &mut response => response,
+        closed = is_closed(reader) => {
+            if closed {
+                return None;
+            }
+            // Commands sent ahead are left buffered until the outcome is answered
+            response.await
+        }
+    };
+    let response = response.unwrap_or_else(|infallible| match infallible {});
+
+    let status = response.status();
+    let answer = to_bytes(response.into_body(), MAX_RESPONSE_LENGTH)
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice::<Answer>(&body).ok());
+    Some(match answer {
+        Some(Answer { status, .. }) if status != "error" => {
+            format!("{}\n", status.to_ascii_uppercase())
+        }
+        Some(Answer {
+            message: Some(message),
+            ..
+        }) => format!("ERROR {}\n", message.trim_end()),
+        _ => format!("ERROR {status}\n"),
+    })
+}
+
+/// Whether the client closed the connection, resolving only once it did or sent more data.
+async fn is_closed(reader: &mut (impl AsyncBufRead + Unpin)) -> bool {
+    reader.fill_buf().await.map_or(true, <[u8]>::is_empty)
+}
+
+/// Encodes the characters of `unique_id` that can't appear as is in a path, so that every id
+/// reaches the route and gets validated there.
+fn percent_encode(unique_id: &str) -> String {
+    let mut encoded = String::with_capacity(unique_id.len());
+    for byte in unique_id.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.:".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use sync_point_core::{make_router, AppState, LocalParties, Settings};
+    use tokio::{io::BufStream, sync::oneshot};
+
+    use super::*;
+
+    async fn command(stream: &mut BufStream<TcpStream>, command: &str) -> String {
+        stream.write_all(command.as_bytes()).await.unwrap();
+        stream.flush().await.unwrap();
+        let mut answer = String::new();
+        stream.read_line(&mut answer).await.unwrap();
+        answer
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_party_line_by_line() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (app, _) = make_router(AppState::new(
+            Settings::new(Duration::from_millis(500)),
+            Box::new(LocalParties::default()),
+        ));
+        let (shutdown, shutdown_requested) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, app, async {
+            let _ = shutdown_requested.await;
+        }));
+
+        let mut first = BufStream::new(TcpStream::connect(addr).await.unwrap());
+        let mut second = BufStream::new(TcpStream::connect(addr).await.unwrap());
+        let first_answer = tokio::spawn(async move {
+            let answer = command(&mut first, "WAIT 1\n").await;
+            (first, answer)
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(command(&mut second, "WAIT 1\n").await, "MATCHED\n");
+        let (mut first, answer) = first_answer.await.unwrap();
+        assert_eq!(answer, "MATCHED\n");
+
+        assert_eq!(command(&mut first, "WAIT 2\n").await, "TIMEOUT\n");
+        assert_eq!(
+            command(&mut first, "WAIT not/valid\n").await,
+            "ERROR The unique id may only contain letters, digits, dashes, underscores, dots or colons\n"
+        );
+        assert_eq!(
+            command(&mut first, "MATCH 1\n").await,
+            "ERROR Expected WAIT <id> or WAIT <id> <api key>\n"
+        );
+
+        // Giving up a wait by closing the connection, so that the next party doesn't match it
+        second.write_all(b"WAIT 3\n").await.unwrap();
+        second.flush().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(second);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(command(&mut first, "WAIT 3\n").await, "TIMEOUT\n");
+
+        drop(first);
+        shutdown.send(()).unwrap();
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn encodes_ids_into_paths() {
+        assert_eq!(percent_encode("session-1:a_b.c"), "session-1:a_b.c");
+        assert_eq!(percent_encode("a/b?c"), "a%2Fb%3Fc");
+        assert_eq!(percent_encode("é"), "%C3%A9");
+    }
+
+    #[test]
+    fn api_keys_are_sent_as_bearer_tokens() {
+        let request = parse_command("WAIT 1 secret\r\n").unwrap();
+        assert_eq!(request.uri(), "/wait-for-second-party/1");
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer secret");
+        assert!(parse_command("WAIT\n").is_err());
+        assert!(parse_command("WAIT 1 secret extra\n").is_err());
+    }
+}
@@ -1,347 +1,320 @@
-use std::{collections::HashMap, io, sync::Arc, time::Duration};
-
-use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::post,
-    Router,
+use std::{future::Future, io, sync::Arc};
+
+use axum::Router;
+#[cfg(feature = "history")]
+use sync_point_core::History;
+use sync_point_core::{
+    make_router, sweep_stale_entries, AccessLog, AppState, AuditLog, Cluster, LocalParties,
+    PartyStore,
 };
-use tokio::{
-    sync::{Notify, RwLock},
-    time::timeout,
+use tokio::{sync::watch, task::JoinSet};
+use tracing::{error, info, warn};
+use tracing_subscriber::{
+    filter::LevelFilter, layer::SubscriberExt, reload, util::SubscriberInitExt, Layer,
 };
-use tracing::{info, warn};
-
-static INBOUND_MESSAGE: &str = "Hooray! Another party is connected!\n";
-static OUTBOUND_MESSAGE: &str = "Yippee! We connected to another party!\n";
-static TIMEOUT_MESSAGE: &str = "Oh no... we timed out waiting for another party\n";
 
-type UniqueId = u32;
+use crate::{
+    config::{Config, ListenAddr, LogFormat},
+    http::Connections,
+};
 
-/// `WaitingParties` holds the actual waiting party associated with some `UniqueId`.
-#[derive(Default)]
-struct WaitingParties(HashMap<UniqueId, Arc<Notify>>);
+mod config;
+mod http;
+mod line;
+mod otlp;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(unix)]
+mod unix;
 
-impl WaitingParties {
-    fn take(&mut self, unique_id: UniqueId) -> Option<Arc<Notify>> {
-        self.0.remove(&unique_id)
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let config = Config::load()?;
+
+    let logs = tracing_subscriber::fmt::layer().with_target(false);
+    let logs = match config.log_format {
+        LogFormat::Pretty => logs.compact().boxed(),
+        // Events are flattened so that their fields, like the outcome of a wait, are top-level
+        LogFormat::Json => logs
+            .json()
+            .flatten_event(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+    let (otlp, exporter) = config.otlp_endpoint.as_deref().map(otlp::layer).unzip();
+    // The log level can be changed on reload
+    let (log_level, log_levels) = reload::Layer::new(config.log_level);
+    tracing_subscriber::registry()
+        .with(logs)
+        .with(otlp)
+        .with(log_level)
+        .init();
+    if let Some(endpoint) = &config.otlp_endpoint {
+        info!(endpoint, "Exporting the spans over OTLP");
     }
 
-    fn insert(&mut self, unique_id: UniqueId) -> Arc<Notify> {
-        let waiting_party = Arc::new(Notify::new());
-        self.0.insert(unique_id, waiting_party.clone());
-        waiting_party
+    let cluster = Cluster::new(&config.cluster_nodes, config.cluster_self.as_deref())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    if cluster.is_some() {
+        info!(
+            nodes = config.cluster_nodes.len(),
+            "Forwarding waits to the owners of their ids"
+        );
     }
-
-    fn remove(&mut self, unique_id: UniqueId) {
-        self.0.remove(&unique_id);
+    let audit = config
+        .audit_log
+        .as_deref()
+        .map(AuditLog::open)
+        .transpose()?;
+    let access_log = config
+        .access_log
+        .as_deref()
+        .map(|path| {
+            AccessLog::open(
+                path,
+                config.access_log_format,
+                config.access_log_exclude_paths.clone(),
+            )
+        })
+        .transpose()?;
+    let settings = config.settings();
+    settings
+        .validate()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.trim_end()))?;
+    if let Some(faults) = &settings.chaos {
+        warn!(?faults, "Chaos mode is on, injecting faults into the waits");
     }
-}
-
-#[derive(Default)]
-struct AppState {
-    wait_timeout: Duration,
-    waiting_parties: RwLock<WaitingParties>,
-}
-
-impl AppState {
-    fn new(wait_timeout: Duration) -> Self {
-        AppState {
-            wait_timeout,
-            waiting_parties: Default::default(),
+    let state = AppState::new(settings, party_store(&config).await?)
+        .with_cluster(cluster)
+        .with_audit(audit)
+        .with_access_log(access_log);
+    #[cfg(feature = "history")]
+    let state = state.with_history(history_store(&config).await?);
+    let (app, state) = make_router(state);
+    tokio::spawn(sweep_stale_entries(state.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(state.clone(), move |log_level| {
+        if let Err(err) = log_levels.reload(log_level) {
+            warn!(%err, "Failed to change the log level");
         }
+    }));
+
+    let served = serve(&config, app, state).await;
+    // The spans of the last waits would be lost otherwise
+    if let Some(exporter) = exporter {
+        exporter.flush().await;
     }
+    served
 }
 
-async fn sync_parties(
-    Path(unique_id): Path<UniqueId>,
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    let mut waiting_parties = state.waiting_parties.write().await;
-
-    if let Some(party) = waiting_parties.take(unique_id) {
-        info!(unique_id, "Found matching party");
-        // Simply notify the other waiting party
-        party.notify_one();
-
-        (StatusCode::OK, OUTBOUND_MESSAGE.to_string()).into_response()
-    } else {
-        info!(unique_id, "Waiting for another party");
-        // There is no waiting party for this id, so we are the one waiting
-        let party = waiting_parties.insert(unique_id);
+/// Serves `app` on every configured address, each from a task of its own, until the server shuts
+/// down or one of the listeners fails, which shuts the other ones down too.
+async fn serve(config: &Config, app: Router, state: Arc<AppState>) -> io::Result<()> {
+    let server = Server {
+        app,
+        connections: config.connections(),
+        #[cfg(feature = "tls")]
+        tls: tls::rustls_config(config.tls_cert.as_deref(), config.tls_key.as_deref()).await?,
+    };
+    let listen_addrs = config.listen_addrs();
+    #[cfg(feature = "tls")]
+    if server.tls.is_some()
+        && listen_addrs
+            .iter()
+            .any(|listen| matches!(listen, ListenAddr::Unix(_)))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TLS is only served over TCP",
+        ));
+    }
 
-        // We drop the guard to avoid race condition
-        drop(waiting_parties);
+    let (stop, stopped) = watch::channel(false);
+    tokio::spawn({
+        let (state, stop) = (state.clone(), stop.clone());
+        async move {
+            shutdown_signal(state).await;
+            stop.send_replace(true);
+        }
+    });
 
-        // We will wait patiently up to 10 seconds for someone else to connect
-        match timeout(state.wait_timeout, party.notified()).await {
-            Ok(_) => {
-                info!(unique_id, "Successfully synchronized parties");
-                (StatusCode::OK, INBOUND_MESSAGE.to_string()).into_response()
-            }
-            Err(_) => {
-                warn!(unique_id, "Timeout waiting for other party");
-                // In case we timed out, we clean up the previously stored waiting party.
-                state.waiting_parties.write().await.remove(unique_id);
-                (StatusCode::REQUEST_TIMEOUT, TIMEOUT_MESSAGE.to_string()).into_response()
-            }
+    let shutdown = || {
+        let mut stopped = stopped.clone();
+        async move {
+            // The sender lives until every listener is done, so waiting can't fail
+            let _ = stopped.wait_for(|stopped| *stopped).await;
         }
+    };
+    let line_listener = config.line_listen.map(http::bind).transpose()?;
+    let mut listeners = JoinSet::new();
+    for listen in listen_addrs {
+        listeners.spawn(server.clone().serve(listen, shutdown()));
+    }
+    if let Some(listener) = line_listener {
+        let (app, shutdown) = (server.app.clone(), shutdown());
+        listeners.spawn(async move {
+            line::serve(listener, app, shutdown).await;
+            Ok(())
+        });
     }
-}
-
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    tracing_subscriber::fmt()
-        .with_target(false)
-        .compact()
-        .init();
-
-    let (app, _state) = make_app(Duration::from_secs(10));
-
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await?;
-    info!("Listening on {}", listener.local_addr().unwrap());
 
-    Ok(axum::serve(listener, app).await?)
+    let mut served = Ok(());
+    while let Some(listener) = listeners.join_next().await {
+        let listener = listener.unwrap_or_else(|err| Err(io::Error::other(err)));
+        if let (Err(err), Ok(())) = (listener, &served) {
+            error!(%err, "Listener failed, shutting down");
+            state.begin_shutdown();
+            stop.send_replace(true);
+            served = Err(err);
+        }
+    }
+    served
 }
 
-fn make_app(wait_duration: Duration) -> (Router, Arc<AppState>) {
-    let state = Arc::new(AppState::new(wait_duration));
-
-    (
-        Router::new()
-            .route("/wait-for-second-party/:unique-id", post(sync_parties))
-            .with_state(state.clone()),
-        state,
-    )
+/// What every listener serves, and how.
+#[derive(Clone)]
+struct Server {
+    app: Router,
+    connections: Connections,
+    #[cfg(feature = "tls")]
+    tls: Option<axum_server::tls_rustls::RustlsConfig>,
 }
 
-#[cfg(test)]
-mod tests {
-    use std::convert::Infallible;
-
-    use axum::{
-        body::{Body, Bytes},
-        http::Request,
-        response::Response,
-        routing::{future::RouteFuture, RouterIntoService},
-    };
-    use http_body_util::BodyExt;
-    use tokio::time::sleep;
-    use tower::{Service, ServiceExt};
-
-    use super::*;
-
-    #[tokio::test]
-    async fn two_parties_with_same_id_succeed() {
-        let (app, _state) = make_app(Duration::from_millis(200));
-        let mut app = app.into_service();
-
-        let party1_request = make_test_request(1);
-        let party1_response = run_request(&mut app, party1_request).await;
-
-        let party2_request = make_test_request(1);
-        let party2_response = run_request(&mut app, party2_request).await;
-
-        let (party1_response, party2_response) = tokio::join!(party1_response, party2_response);
-        let (party1_response, party2_response) =
-            (party1_response.unwrap(), party2_response.unwrap());
-
-        assert_eq!(party1_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party1_response).await[..],
-            INBOUND_MESSAGE.as_bytes()
-        );
+impl Server {
+    /// Serves the app on `listen` until `shutdown` resolves and the open connections are closed.
+    async fn serve(
+        self,
+        listen: ListenAddr,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> io::Result<()> {
+        let addr = match listen {
+            ListenAddr::Tcp(addr) => addr,
+            ListenAddr::Unix(path) => return self.serve_unix(&path, shutdown).await,
+        };
+        let listener = http::bind(addr)?;
+        info!("Listening on {}", listener.local_addr().unwrap());
+
+        #[cfg(feature = "tls")]
+        if let Some(tls) = self.tls {
+            info!("Serving HTTPS");
+            return tls::serve(listener, self.app, tls, &self.connections, shutdown).await;
+        }
 
-        assert_eq!(party2_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party2_response).await[..],
-            OUTBOUND_MESSAGE.as_bytes()
-        );
+        http::serve(listener, self.app, &self.connections, shutdown).await;
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn single_party_time_out() {
-        let (app, _state) = make_app(Duration::from_millis(100));
-        let mut app = app.into_service();
-
-        let party1_request = make_test_request(1);
-        let party1_response = run_request(&mut app, party1_request).await.await.unwrap();
-
-        sleep(Duration::from_millis(150)).await;
-
-        assert_eq!(party1_response.status(), StatusCode::REQUEST_TIMEOUT);
-        assert_eq!(
-            &extract_response_body(party1_response).await[..],
-            TIMEOUT_MESSAGE.as_bytes()
-        );
+    /// Serves the app on a Unix domain socket, over plain HTTP since the socket never leaves the
+    /// host.
+    #[cfg(unix)]
+    async fn serve_unix(
+        self,
+        path: &std::path::Path,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> io::Result<()> {
+        unix::serve(path, self.app, &self.connections, shutdown).await
     }
 
-    #[tokio::test]
-    async fn multiple_parties_with_multiple_ids_succeed() {
-        let (app, _state) = make_app(Duration::from_millis(200));
-        let mut app = app.into_service();
-
-        let party1_request = make_test_request(1);
-        let party1_response = run_request(&mut app, party1_request).await;
-
-        let party2_request = make_test_request(1);
-        let party2_response = run_request(&mut app, party2_request).await;
-
-        let party3_request = make_test_request(2);
-        let party3_response = run_request(&mut app, party3_request).await;
-
-        let party4_request = make_test_request(2);
-        let party4_response = run_request(&mut app, party4_request).await;
-
-        let (party1_response, party2_response, party3_response, party4_response) = tokio::join!(
-            party1_response,
-            party2_response,
-            party3_response,
-            party4_response
-        );
-        let (party1_response, party2_response, party3_response, party4_response) = (
-            party1_response.unwrap(),
-            party2_response.unwrap(),
-            party3_response.unwrap(),
-            party4_response.unwrap(),
-        );
-
-        assert_eq!(party1_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party1_response).await[..],
-            INBOUND_MESSAGE.as_bytes()
-        );
-
-        assert_eq!(party2_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party2_response).await[..],
-            OUTBOUND_MESSAGE.as_bytes()
-        );
-
-        assert_eq!(party3_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party3_response).await[..],
-            INBOUND_MESSAGE.as_bytes()
-        );
-
-        assert_eq!(party4_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party4_response).await[..],
-            OUTBOUND_MESSAGE.as_bytes()
-        );
+    #[cfg(not(unix))]
+    async fn serve_unix(
+        self,
+        _path: &std::path::Path,
+        _shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix domain sockets aren't supported on this platform",
+        ))
     }
+}
 
-    #[tokio::test]
-    async fn multiple_parties_with_multiple_ids_some_succeed_some_timeout() {
-        let (app, _state) = make_app(Duration::from_millis(200));
-        let mut app = app.into_service();
-
-        let party1_request = make_test_request(1);
-        let party1_response = run_request(&mut app, party1_request).await;
-
-        let party2_request = make_test_request(1);
-        let party2_response = run_request(&mut app, party2_request).await;
-
-        let party3_request = make_test_request(2);
-        let party3_response = run_request(&mut app, party3_request).await;
-
-        let party4_request = make_test_request(2);
-        let party4_response = run_request(&mut app, party4_request).await;
-
-        let party5_request = make_test_request(2);
-        let party5_response = run_request(&mut app, party5_request).await;
-
-        let party6_request = make_test_request(3);
-        let party6_response = run_request(&mut app, party6_request).await;
-
-        let (
-            party1_response,
-            party2_response,
-            party3_response,
-            party4_response,
-            party5_response,
-            party6_response,
-        ) = tokio::join!(
-            party1_response,
-            party2_response,
-            party3_response,
-            party4_response,
-            party5_response,
-            party6_response,
-        );
-        let (
-            party1_response,
-            party2_response,
-            party3_response,
-            party4_response,
-            party5_response,
-            party6_response,
-        ) = (
-            party1_response.unwrap(),
-            party2_response.unwrap(),
-            party3_response.unwrap(),
-            party4_response.unwrap(),
-            party5_response.unwrap(),
-            party6_response.unwrap(),
-        );
+/// Connects to the party store shared between instances, if one is configured.
+#[cfg(feature = "redis")]
+async fn party_store(config: &Config) -> io::Result<Box<dyn PartyStore>> {
+    let Some(url) = &config.redis_url else {
+        return Ok(Box::new(LocalParties::default()));
+    };
 
-        assert_eq!(party1_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party1_response).await[..],
-            INBOUND_MESSAGE.as_bytes()
-        );
+    let parties = sync_point_core::RedisParties::connect(url)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::ConnectionRefused, err))?;
+    info!("Sharing waiting parties through Redis");
+    Ok(Box::new(parties))
+}
 
-        assert_eq!(party2_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party2_response).await[..],
-            OUTBOUND_MESSAGE.as_bytes()
-        );
+#[cfg(not(feature = "redis"))]
+async fn party_store(_config: &Config) -> io::Result<Box<dyn PartyStore>> {
+    Ok(Box::new(LocalParties::default()))
+}
 
-        assert_eq!(party3_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party3_response).await[..],
-            INBOUND_MESSAGE.as_bytes()
-        );
+/// Connects to the rendezvous history database, if one is configured.
+#[cfg(feature = "history")]
+async fn history_store(config: &Config) -> io::Result<Option<History>> {
+    let Some(url) = &config.history_url else {
+        return Ok(None);
+    };
 
-        assert_eq!(party4_response.status(), StatusCode::OK);
-        assert_eq!(
-            &extract_response_body(party4_response).await[..],
-            OUTBOUND_MESSAGE.as_bytes()
-        );
+    let history = History::connect(url)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::ConnectionRefused, err))?;
+    info!("Recording the rendezvous history");
+    Ok(Some(history))
+}
 
-        assert_eq!(party5_response.status(), StatusCode::REQUEST_TIMEOUT);
-        assert_eq!(
-            &extract_response_body(party5_response).await[..],
-            TIMEOUT_MESSAGE.as_bytes()
-        );
+/// Reloads the settings and the log level from the configuration on `SIGHUP`, applying them to
+/// the next requests while the waits going on carry on.
+///
+/// The configuration is left as it was if the file can't be read.
+#[cfg(unix)]
+async fn reload_on_hangup(state: Arc<AppState>, set_log_level: impl Fn(LevelFilter)) {
+    let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(err) => {
+            warn!(%err, "Failed to listen for SIGHUP");
+            return;
+        }
+    };
 
-        assert_eq!(party6_response.status(), StatusCode::REQUEST_TIMEOUT);
-        assert_eq!(
-            &extract_response_body(party6_response).await[..],
-            TIMEOUT_MESSAGE.as_bytes()
-        );
+    while hangups.recv().await.is_some() {
+        match Config::load() {
+            Ok(config) => {
+                set_log_level(config.log_level);
+                state.reload_settings(config.settings());
+            }
+            Err(err) => error!(%err, "Failed to reload the configuration, keeping the current one"),
+        }
     }
+}
 
-    fn make_test_request(unique_id: UniqueId) -> Request<Body> {
-        Request::builder()
-            .uri(format!("/wait-for-second-party/{}", unique_id))
-            .method("POST")
-            .body(Body::empty())
-            .expect("creating fake request with empty body shouldn't fail")
-    }
+/// Resolves on ctrl-c or SIGTERM, after waking up every waiting party.
+async fn shutdown_signal(state: Arc<AppState>) {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            warn!(%err, "Failed to listen for ctrl-c");
+            std::future::pending::<()>().await;
+        }
+    };
 
-    async fn extract_response_body(response: Response) -> Bytes {
-        response.into_body().collect().await.unwrap().to_bytes()
-    }
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                warn!(%err, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    async fn run_request(
-        app: &mut RouterIntoService<Body>,
-        request: Request<Body>,
-    ) -> RouteFuture<Infallible> {
-        ServiceExt::<Request<Body>>::ready(app)
-            .await
-            .unwrap()
-            .call(request)
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
+
+    info!("Shutting down, releasing waiting parties");
+    state.begin_shutdown();
 }
@@ -0,0 +1,398 @@
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use serde_json::json;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::interval,
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+use uuid::Uuid;
+
+/// Name the spans are exported under.
+const SERVICE_NAME: &str = "sync-point";
+
+/// Path of the traces on the OTLP/HTTP endpoint of the collector.
+const TRACES_PATH: &str = "/v1/traces";
+
+/// Field of the request span holding the trace context sent by the client.
+const TRACEPARENT_FIELD: &str = "traceparent";
+
+/// How often the spans closed in the meantime are exported.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Spans kept until the next export, the ones closing past it being dropped.
+const MAX_QUEUED_SPANS: usize = 4096;
+
+/// Spans sent to the collector at once.
+const MAX_BATCH_SPANS: usize = 512;
+
+/// OTLP span kind of the spans of the server, handling the requests of clients.
+const SPAN_KIND_SERVER: u8 = 2;
+
+/// `OtlpLayer` exports the closed spans to an OpenTelemetry collector over OTLP/HTTP, in JSON, so
+/// that the waits can be followed next to the traces of the clients.
+///
+/// Root spans continue the trace of the client when its request carried a W3C `traceparent`
+/// header, recorded on them as the `traceparent` field.
+pub struct OtlpLayer {
+    spans: mpsc::Sender<ExportedSpan>,
+}
+
+/// Handle on the export task, flushing the pending spans on shutdown.
+pub struct Exporter {
+    flush: mpsc::Sender<oneshot::Sender<()>>,
+}
+
+impl Exporter {
+    /// Exports the spans closed since the last export, waiting for the collector to answer.
+    pub async fn flush(&self) {
+        let (flushed, done) = oneshot::channel();
+        if self.flush.send(flushed).await.is_ok() {
+            let _ = done.await;
+        }
+    }
+}
+
+/// Creates the layer exporting the spans to the collector at `endpoint`, spawning the task doing
+/// it on the current runtime.
+pub fn layer(endpoint: &str) -> (OtlpLayer, Exporter) {
+    let (spans, queued) = mpsc::channel(MAX_QUEUED_SPANS);
+    let (flush, flushes) = mpsc::channel(1);
+    let url = format!("{}{TRACES_PATH}", endpoint.trim_end_matches('/'));
+    tokio::spawn(export(url, queued, flushes));
+
+    (OtlpLayer { spans }, Exporter { flush })
+}
+
+/// Sends the queued spans to the collector every export interval and on every flush.
+async fn export(
+    url: String,
+    mut queued: mpsc::Receiver<ExportedSpan>,
+    mut flushes: mpsc::Receiver<oneshot::Sender<()>>,
+) {
+    let client = reqwest::Client::new();
+    let mut ticks = interval(EXPORT_INTERVAL);
+    loop {
+        let flushed = tokio::select! {
+            _ = ticks.tick() => None,
+            Some(flushed) = flushes.recv() => Some(flushed),
+        };
+
+        let mut spans = Vec::new();
+        while let Ok(span) = queued.try_recv() {
+            spans.push(span);
+        }
+        for batch in spans.chunks(MAX_BATCH_SPANS) {
+            // Logging the failure would feed spans back into the exporter
+            let _ = client.post(&url).json(&request(batch)).send().await;
+        }
+
+        if let Some(flushed) = flushed {
+            let _ = flushed.send(());
+        }
+    }
+}
+
+/// Body of the export request of `spans`.
+fn request(spans: &[ExportedSpan]) -> serde_json::Value {
+    let service_name = AttributeValue::String(SERVICE_NAME.to_owned());
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [attribute("service.name", &service_name)],
+            },
+            "scopeSpans": [{
+                "scope": {"name": SERVICE_NAME},
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+fn attribute(key: &str, value: &AttributeValue) -> serde_json::Value {
+    json!({"key": key, "value": value})
+}
+
+/// Span as sent to the collector.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedSpan {
+    trace_id: String,
+    span_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_span_id: Option<String>,
+    name: &'static str,
+    kind: u8,
+    start_time_unix_nano: String,
+    end_time_unix_nano: String,
+    attributes: Vec<serde_json::Value>,
+}
+
+/// Span being recorded, kept in the extensions of the span until it closes.
+struct OpenSpan {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    started_at: SystemTime,
+    fields: Fields,
+}
+
+/// Fields of a span, as OTLP attributes.
+#[derive(Default)]
+struct Fields {
+    attributes: Vec<(&'static str, AttributeValue)>,
+    traceparent: Option<String>,
+}
+
+#[derive(Serialize)]
+enum AttributeValue {
+    #[serde(rename = "stringValue")]
+    String(String),
+    /// 64-bit integers are strings in OTLP/JSON.
+    #[serde(rename = "intValue")]
+    Int(String),
+    #[serde(rename = "boolValue")]
+    Bool(bool),
+}
+
+impl Fields {
+    fn set(&mut self, field: &Field, value: AttributeValue) {
+        let name = field.name();
+        if name == TRACEPARENT_FIELD {
+            if let AttributeValue::String(traceparent) = value {
+                self.traceparent = Some(traceparent);
+            }
+            return;
+        }
+
+        match self.attributes.iter_mut().find(|(key, _)| *key == name) {
+            Some((_, previous)) => *previous = value,
+            None => self.attributes.push((name, value)),
+        }
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, AttributeValue::String(value.to_owned()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.set(field, AttributeValue::Int(value.to_string()));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.set(field, AttributeValue::Int(value.to_string()));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.set(field, AttributeValue::Bool(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.set(field, AttributeValue::String(format!("{value:?}")));
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+
+        let parent = span.parent().and_then(|parent| {
+            let extensions = parent.extensions();
+            let parent = extensions.get::<OpenSpan>()?;
+            Some((parent.trace_id, parent.span_id))
+        });
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, parent_span_id)) => (trace_id, Some(parent_span_id)),
+            None => match fields.traceparent.as_deref().and_then(parse_traceparent) {
+                Some((trace_id, parent_span_id)) => (trace_id, Some(parent_span_id)),
+                None => (random_id(), None),
+            },
+        };
+
+        span.extensions_mut().insert(OpenSpan {
+            trace_id,
+            span_id: random_id(),
+            parent_span_id,
+            started_at: SystemTime::now(),
+            fields,
+        });
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(open) = span.extensions_mut().get_mut::<OpenSpan>() {
+                values.record(&mut open.fields);
+            }
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(open) = span.extensions_mut().remove::<OpenSpan>() else {
+            return;
+        };
+
+        let exported = ExportedSpan {
+            trace_id: hex(&open.trace_id),
+            span_id: hex(&open.span_id),
+            parent_span_id: open.parent_span_id.as_ref().map(|id| hex(id)),
+            name: span.name(),
+            kind: SPAN_KIND_SERVER,
+            start_time_unix_nano: unix_nanos(open.started_at),
+            end_time_unix_nano: unix_nanos(SystemTime::now()),
+            attributes: open
+                .fields
+                .attributes
+                .iter()
+                .map(|(key, value)| attribute(key, value))
+                .collect(),
+        };
+        // Spans are dropped rather than slowing down the requests when the collector lags behind
+        let _ = self.spans.try_send(exported);
+    }
+}
+
+/// Trace id and parent span id of a W3C `traceparent` header, e.g.
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+fn parse_traceparent(traceparent: &str) -> Option<([u8; 16], [u8; 8])> {
+    let mut parts = traceparent.trim().split('-');
+    let (Some(version), Some(trace_id), Some(parent_id), Some(_flags)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+    if version == "ff" {
+        return None;
+    }
+
+    let trace_id: [u8; 16] = parse_hex(trace_id)?;
+    let parent_id: [u8; 8] = parse_hex(parent_id)?;
+    // All-zero ids are invalid
+    if trace_id == [0; 16] || parent_id == [0; 8] {
+        return None;
+    }
+    Some((trace_id, parent_id))
+}
+
+fn parse_hex<const N: usize>(digits: &str) -> Option<[u8; N]> {
+    if digits.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (byte, pair) in bytes.iter_mut().zip(digits.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0; N];
+    id.copy_from_slice(&Uuid::new_v4().as_bytes()[..N]);
+    id
+}
+
+fn unix_nanos(at: SystemTime) -> String {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{extract::State, routing::post, Json, Router};
+    use tracing::info_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[test]
+    fn traceparent_is_parsed() {
+        let (trace_id, parent_id) =
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(hex(&trace_id), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(hex(&parent_id), "00f067aa0ba902b7");
+
+        assert!(parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-01").is_none());
+        assert!(
+            parse_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none()
+        );
+        assert!(
+            parse_traceparent("00-4bf92f3577b34da6a3ce929d0e0e47zz-00f067aa0ba902b7-01").is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn spans_are_exported_in_the_trace_of_the_client() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let collector = Router::new()
+            .route(
+                TRACES_PATH,
+                post(
+                    |State(received): State<Arc<Mutex<Vec<serde_json::Value>>>>,
+                     Json(body): Json<serde_json::Value>| async move {
+                        received.lock().unwrap().push(body);
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, collector).await.unwrap() });
+
+        let (layer, exporter) = layer(&format!("http://{addr}/"));
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let request = info_span!(
+                "request",
+                traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            );
+            let _request = request.enter();
+            let wait = info_span!("wait", unique_id = "1", outcome = tracing::field::Empty);
+            wait.record("outcome", "matched");
+        });
+        exporter.flush().await;
+
+        let received = received.lock().unwrap();
+        let spans = &received[0]["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        let wait = &spans[0];
+        let request = &spans[1];
+        assert_eq!(wait["name"], "wait");
+        assert_eq!(wait["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(wait["parentSpanId"], request["spanId"]);
+        assert_eq!(
+            wait["attributes"],
+            json!([
+                {"key": "unique_id", "value": {"stringValue": "1"}},
+                {"key": "outcome", "value": {"stringValue": "matched"}},
+            ])
+        );
+        assert_eq!(request["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(request["parentSpanId"], "00f067aa0ba902b7");
+    }
+}
@@ -0,0 +1,122 @@
+use std::{future::Future, io, net::SocketAddr, path::Path, sync::Arc};
+
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use tokio::net::TcpListener;
+
+use crate::http::Connections;
+
+/// Loads the PEM certificate chain and private key to serve HTTPS, if both are configured.
+pub async fn rustls_config(
+    cert: Option<&Path>,
+    key: Option<&Path>,
+) -> io::Result<Option<RustlsConfig>> {
+    match (cert, key) {
+        (Some(cert), Some(key)) => {
+            // Installing fails if a provider was already installed, which is just as good
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            RustlsConfig::from_pem_file(cert, key).await.map(Some)
+        }
+        (None, None) => Ok(None),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "both the TLS certificate and key must be configured",
+        )),
+    }
+}
+
+/// Serves `app` over HTTPS until `shutdown` resolves and the open connections are closed.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    tls: RustlsConfig,
+    connections: &Connections,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<()> {
+    let handle = Handle::new();
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        shutdown_handle.graceful_shutdown(None);
+    });
+
+    if !connections.http2 {
+        // Clients then never negotiate HTTP/2
+        let mut config = (*tls.get_inner()).clone();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        tls.reload_from_config(Arc::new(config));
+    }
+
+    let mut server = axum_server::from_tcp_rustls(listener.into_std()?, tls).handle(handle);
+    *server.http_builder() = connections.builder();
+    server
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_https_with_configured_certificate() {
+        let certified = rcgen::generate_simple_self_signed(["localhost".to_owned()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("sync-point-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+        std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+
+        let tls = rustls_config(Some(&cert_path), Some(&key_path))
+            .await
+            .unwrap()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/healthz", get(|| async { "OK\n" }));
+        tokio::spawn(async move {
+            serve(
+                listener,
+                app,
+                tls,
+                &Connections::default(),
+                std::future::pending(),
+            )
+            .await
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(certified.cert.der().clone()).unwrap();
+        let connector = TlsConnector::from(Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ));
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(server_name, stream).await.unwrap();
+
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn requires_both_certificate_and_key() {
+        assert!(rustls_config(None, None).await.unwrap().is_none());
+        assert!(rustls_config(Some(Path::new("cert.pem")), None)
+            .await
+            .is_err());
+    }
+}
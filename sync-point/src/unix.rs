@@ -0,0 +1,106 @@
+use std::{fs, future::Future, io, os::unix::fs::FileTypeExt, path::Path};
+
+use axum::Router;
+use tokio::net::{unix::SocketAddr, UnixListener, UnixStream};
+use tracing::info;
+
+use crate::http::{self, Connections, Listener};
+
+impl Listener for UnixListener {
+    type Io = UnixStream;
+    type Addr = SocketAddr;
+
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Io, Self::Addr)>> + Send {
+        UnixListener::accept(self)
+    }
+}
+
+/// Serves `app` on the Unix domain socket at `path` until `shutdown` resolves and the open
+/// connections are closed, removing the socket afterwards.
+///
+/// The clients have no address, so they aren't rate limited.
+pub async fn serve(
+    path: &Path,
+    app: Router,
+    connections: &Connections,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> io::Result<()> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    info!("Listening on unix:{}", path.display());
+
+    http::serve(listener, app, connections, shutdown).await;
+
+    fs::remove_file(path)
+}
+
+/// Removes the socket left behind by a previous run, which would prevent binding, but nothing
+/// else that might be at `path`.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path.display()),
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::routing::get;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        sync::oneshot,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_requests_until_shutdown() {
+        let path = std::env::temp_dir().join(format!("sync-point-{}.sock", std::process::id()));
+        let app = Router::new().route("/healthz", get(|| async { "OK" }));
+        let (shutdown, shutdown_requested) = oneshot::channel::<()>();
+        let server = tokio::spawn({
+            let path = path.clone();
+            async move {
+                serve(&path, app, &Connections::default(), async {
+                    let _ = shutdown_requested.await;
+                })
+                .await
+            }
+        });
+
+        let mut stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("OK"));
+
+        shutdown.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn only_stale_sockets_are_removed() {
+        let path = std::env::temp_dir().join(format!("sync-point-{}.txt", std::process::id()));
+        fs::write(&path, "").unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        fs::remove_file(&path).unwrap();
+        assert!(remove_stale_socket(&path).is_ok());
+    }
+}
@@ -0,0 +1,57 @@
+use js_sys::{encode_uri_component, Error, Promise, Reflect};
+use wasm_bindgen::{prelude::*, JsCast, JsValue};
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use web_sys::{Request, RequestInit, Response};
+
+#[wasm_bindgen]
+extern "C" {
+    // The global `fetch`, so that it works in windows, workers and Deno alike
+    #[wasm_bindgen(js_name = fetch)]
+    fn fetch_with_request(request: &Request) -> Promise;
+}
+
+/// Waits on `uniqueId` for another party through the sync-point HTTP API at `baseUrl`.
+///
+/// Resolves with the JSON outcome of the wait, e.g. `{ status: "matched", role: "first",
+/// waited_ms: 1234 }` or `{ status: "timeout", waited_ms: 10000 }`, and rejects if the request
+/// couldn't be sent or was rejected (e.g. the id is too long). The wait times out after
+/// `timeoutMs`, or the server timeout if omitted.
+#[wasm_bindgen(js_name = waitForParty)]
+pub fn wait_for_party(base_url: &str, unique_id: &str, timeout_ms: Option<u32>) -> Promise {
+    let mut url = format!(
+        "{}/wait-for-second-party/{}",
+        base_url.trim_end_matches('/'),
+        encode_uri_component(unique_id)
+    );
+    if let Some(timeout_ms) = timeout_ms {
+        url.push_str(&format!("?timeout_ms={timeout_ms}"));
+    }
+
+    future_to_promise(async move { wait(&url).await })
+}
+
+async fn wait(url: &str) -> Result<JsValue, JsValue> {
+    let init = RequestInit::new();
+    init.set_method("POST");
+    let request = Request::new_with_str_and_init(url, &init)?;
+    request.headers().set("Accept", "application/json")?;
+
+    let response: Response = JsFuture::from(fetch_with_request(&request))
+        .await?
+        .dyn_into()?;
+    let outcome = JsFuture::from(response.json()?).await?;
+
+    // Rejected requests carry an error message instead of an outcome
+    if Reflect::get(&outcome, &"status".into())?
+        .as_string()
+        .as_deref()
+        == Some("error")
+    {
+        let message = Reflect::get(&outcome, &"message".into())?
+            .as_string()
+            .unwrap_or_else(|| format!("request failed with status {}", response.status()));
+        return Err(Error::new(&message).into());
+    }
+
+    Ok(outcome)
+}
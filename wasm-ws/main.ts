@@ -1,5 +1,15 @@
-import init, { wsPing } from "./ws-client/pkg/ws_client.js";
+import init, { WsClient, wsPing } from "./ws-client/pkg/ws_client.js";
 
 await init();
 
-console.log(await wsPing("ws://localhost:8081/ws", "hello"));
+// Without a reply within 5 seconds, the promise is rejected
+console.log(await wsPing("ws://localhost:8081/ws", "hello", 5000));
+// Bytes are sent as a binary message, and the reply comes back as a Uint8Array
+console.log(await wsPing("ws://localhost:8081/ws", new TextEncoder().encode("hello")));
+
+// A client keeps its connection open for as many messages as needed
+const client = await WsClient.connect("ws://localhost:8081/ws");
+client.send("first");
+client.send("second");
+console.log(await client.nextMessage(), await client.nextMessage());
+client.close();
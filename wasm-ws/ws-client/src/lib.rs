@@ -1,65 +1,1161 @@
-use js_sys::{Error, Promise};
-use wasm_bindgen::{prelude::*, JsValue};
-use web_sys::{ErrorEvent, MessageEvent, WebSocket};
-
-#[wasm_bindgen(js_name= wsPing)]
-pub fn ws_ping(endpoint: &str, message: &str) -> Promise {
-    js_sys::Promise::new(&mut |resolve, reject| {
-        // Connect to the endpoint
-        let ws = WebSocket::new(endpoint).unwrap();
-
-        // Create onopen callback
-        let cloned_reject = reject.clone();
-        let cloned_ws = ws.clone();
-        let cloned_message = message.to_string();
-        let onopen_callback = Closure::<dyn FnMut()>::new(move || {
-            if let Err(err) = cloned_ws.send_with_str(&cloned_message) {
-                cloned_reject
-                    .call1(&JsValue::NULL, &err)
-                    .expect("call to reject shouldn't fail");
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    pin::pin,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use dlog_proof::DLogProof;
+use futures_channel::{mpsc, oneshot};
+use futures_util::{
+    future::{self, Either},
+    lock::Mutex,
+    stream::{self, Stream},
+    StreamExt,
+};
+use k256::{elliptic_curve::rand_core::OsRng, AffinePoint, ProjectivePoint, Scalar};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native;
+#[cfg(target_arch = "wasm32")]
+mod web;
+
+#[cfg(not(target_arch = "wasm32"))]
+use native::{now, open, random, sleep, spawn_local, Connection};
+#[cfg(target_arch = "wasm32")]
+use web::{now, open, random, sleep, spawn_local, Connection};
+#[cfg(target_arch = "wasm32")]
+pub use web::{ws_ping, ws_sync_point, WsClientError};
+
+/// Failure of a ping or of a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsError {
+    /// The WebSocket couldn't be created, e.g. because the endpoint isn't a WebSocket URL.
+    InvalidEndpoint(String),
+    /// The message couldn't be sent once connected.
+    Send(String),
+    /// The connection failed, browsers giving no details on purpose.
+    Connection,
+    /// The connection was closed, by the server or with `close`.
+    Closed { code: u16, reason: String },
+    /// The message to send is neither a string nor bytes.
+    InvalidMessage,
+    /// The reply isn't of the kind of the message sent, text or binary.
+    UnsupportedMessage,
+    /// Nothing came in time, the connection being closed.
+    Timeout(Duration),
+    /// The connection dropped and the client is reconnecting, with no room left to queue the
+    /// message.
+    Reconnecting,
+    /// The sync-point wait couldn't be sent or was rejected.
+    SyncPoint(String),
+    /// The server didn't open the handshake as expected, or rejected the proof.
+    Handshake(String),
+    /// The caller gave up, the connection being closed.
+    Cancelled,
+    /// The request couldn't be serialized as JSON, or the reply parsed.
+    Json(String),
+    /// The options given aren't of the expected shape.
+    InvalidOptions(String),
+    /// A message larger than the limit, in bytes, was received, the connection being closed.
+    MessageTooLarge(usize),
+    /// The native client was used outside of a tokio `LocalSet`, which it spawns its tasks on.
+    NoLocalSet,
+}
+
+impl fmt::Display for WsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WsError::InvalidEndpoint(err) => write!(f, "invalid endpoint: {err}"),
+            WsError::Send(err) => write!(f, "failed to send the message: {err}"),
+            WsError::Connection => write!(f, "connection failed"),
+            WsError::Closed { code, reason } => write!(f, "connection closed ({code} {reason})"),
+            WsError::InvalidMessage => write!(f, "messages must be strings or bytes"),
+            WsError::UnsupportedMessage => write!(f, "received unsupported message type"),
+            WsError::Timeout(timeout) => write!(f, "no reply within {} ms", timeout.as_millis()),
+            WsError::Reconnecting => write!(f, "reconnecting, and the send queue is full"),
+            WsError::SyncPoint(err) => write!(f, "sync-point wait failed: {err}"),
+            WsError::Handshake(err) => write!(f, "handshake failed: {err}"),
+            WsError::Cancelled => write!(f, "cancelled"),
+            WsError::Json(err) => write!(f, "invalid JSON: {err}"),
+            WsError::InvalidOptions(err) => write!(f, "invalid options: {err}"),
+            WsError::MessageTooLarge(max_size) => {
+                write!(f, "received a message larger than {max_size} bytes")
+            }
+            WsError::NoLocalSet => write!(f, "the client must run on a tokio LocalSet"),
+        }
+    }
+}
+
+impl std::error::Error for WsError {}
+
+impl WsError {
+    /// Cause of the failure, to branch on.
+    pub fn code(&self) -> WsErrorCode {
+        match self {
+            WsError::InvalidEndpoint(_) | WsError::Connection | WsError::NoLocalSet => {
+                WsErrorCode::ConnectFailed
+            }
+            WsError::Send(_) | WsError::InvalidMessage | WsError::Reconnecting => {
+                WsErrorCode::SendFailed
             }
+            WsError::UnsupportedMessage => WsErrorCode::UnsupportedFrame,
+            WsError::Timeout(_) => WsErrorCode::Timeout,
+            WsError::Closed { .. } => WsErrorCode::Closed,
+            WsError::SyncPoint(_) => WsErrorCode::SyncPointFailed,
+            WsError::Handshake(_) => WsErrorCode::HandshakeFailed,
+            WsError::Cancelled => WsErrorCode::Cancelled,
+            WsError::Json(_) => WsErrorCode::InvalidJson,
+            WsError::InvalidOptions(_) => WsErrorCode::InvalidOptions,
+            WsError::MessageTooLarge(_) => WsErrorCode::MessageTooLarge,
+        }
+    }
+
+    /// Whether the failure may not happen again, e.g. once the server is back.
+    fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            WsError::Connection
+                | WsError::Closed { .. }
+                | WsError::Send(_)
+                | WsError::Timeout(_)
+                | WsError::Reconnecting
+        )
+    }
+}
+
+/// Cause of a failure, as a string in JS.
+// Only wasm-bindgen makes a string enum of it, which it does on every target
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WsErrorCode {
+    /// The endpoint is invalid or the connection failed.
+    ConnectFailed = "ConnectFailed",
+    /// The message is neither a string nor bytes, or couldn't be sent.
+    SendFailed = "SendFailed",
+    /// The reply isn't of the kind of the message sent.
+    UnsupportedFrame = "UnsupportedFrame",
+    /// No reply came in time.
+    Timeout = "Timeout",
+    /// The connection was closed.
+    Closed = "Closed",
+    /// The sync-point wait couldn't be sent or was rejected.
+    SyncPointFailed = "SyncPointFailed",
+    /// The server didn't authenticate the client.
+    HandshakeFailed = "HandshakeFailed",
+    /// The caller cancelled the ping or the connection.
+    Cancelled = "Cancelled",
+    /// The request isn't serializable as JSON, or the reply isn't JSON.
+    InvalidJson = "InvalidJson",
+    /// The options given aren't of the expected shape.
+    InvalidOptions = "InvalidOptions",
+    /// The server sent a message larger than the client accepts.
+    MessageTooLarge = "MessageTooLarge",
+}
+
+/// Message sent or received over a WebSocket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Fails with `WsError::Timeout` if `future` isn't done within `timeout`, dropping it, which
+/// closes any connection it opened.
+pub async fn within<T>(
+    timeout: Duration,
+    future: impl Future<Output = Result<T, WsError>>,
+) -> Result<T, WsError> {
+    match future::select(pin!(future), sleep(timeout)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(((), _)) => Err(WsError::Timeout(timeout)),
+    }
+}
+
+/// Fails with `WsError::Cancelled` once `cancelled` resolves, unless `future` is done first,
+/// dropping it, which closes any connection it opened.
+pub async fn cancellable<T>(
+    cancelled: impl Future<Output = ()>,
+    future: impl Future<Output = Result<T, WsError>>,
+) -> Result<T, WsError> {
+    match future::select(pin!(future), pin!(cancelled)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(((), _)) => Err(WsError::Cancelled),
+    }
+}
+
+/// Sends `message` to the WebSocket `endpoint` and returns the text message received in reply,
+/// over the connection shared by the pings to the endpoint.
+///
+/// Natively, the pings must run on a tokio `LocalSet`, which their connections spawn their tasks
+/// on, failing with `WsError::NoLocalSet` otherwise.
+pub async fn ping(endpoint: &str, message: &str) -> Result<String, WsError> {
+    match exchange(endpoint, Message::Text(message.to_owned())).await? {
+        Message::Text(reply) => Ok(reply),
+        Message::Binary(_) => Err(WsError::UnsupportedMessage),
+    }
+}
+
+/// Sends `payload` to the WebSocket `endpoint` as a binary message and returns the binary message
+/// received in reply, over the connection shared by the pings to the endpoint.
+pub async fn ping_binary(endpoint: &str, payload: &[u8]) -> Result<Vec<u8>, WsError> {
+    match exchange(endpoint, Message::Binary(payload.to_owned())).await? {
+        Message::Binary(reply) => Ok(reply),
+        Message::Text(_) => Err(WsError::UnsupportedMessage),
+    }
+}
+
+/// Sends `request` to the WebSocket `endpoint` as a JSON text message and returns the reply parsed
+/// as JSON, over the connection shared by the pings to the endpoint.
+pub async fn request_json<R: DeserializeOwned>(
+    endpoint: &str,
+    request: &impl Serialize,
+) -> Result<R, WsError> {
+    let request = serde_json::to_string(request).map_err(|err| WsError::Json(err.to_string()))?;
+    let reply = ping(endpoint, &request).await?;
+    serde_json::from_str(&reply).map_err(|err| WsError::Json(err.to_string()))
+}
+
+/// Round-trip times of the echoes sent by `measure_latency`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Latency {
+    pub min: Duration,
+    pub avg: Duration,
+    /// 95th percentile, by the nearest rank.
+    pub p95: Duration,
+}
+
+/// Text echoed to measure the round-trip times.
+const LATENCY_PROBE: &str = "latency";
+
+/// Measures the round-trip times of `samples` echoes of the WebSocket `endpoint`, sent one after
+/// the other over the connection shared by the pings to the endpoint.
+///
+/// The connection is opened before the first echo, so that its handshake isn't measured.
+pub async fn measure_latency(endpoint: &str, samples: u32) -> Result<Latency, WsError> {
+    if samples == 0 {
+        return Err(WsError::InvalidOptions(
+            "at least one sample must be taken".to_owned(),
+        ));
+    }
+    PingConnection::get(endpoint).await?;
+
+    let mut round_trips = Vec::with_capacity(samples as usize);
+    for _ in 0..samples {
+        let sent = now();
+        ping(endpoint, LATENCY_PROBE).await?;
+        round_trips.push(now().saturating_sub(sent));
+    }
+    round_trips.sort();
+    Ok(Latency {
+        min: round_trips[0],
+        avg: round_trips.iter().sum::<Duration>() / samples,
+        p95: round_trips[(round_trips.len() * 95).div_ceil(100) - 1],
+    })
+}
+
+/// How long a connection of the pings is kept open once no ping goes through it.
+pub const PING_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+thread_local! {
+    /// Connections shared by the pings, by endpoint.
+    static PING_CONNECTIONS: RefCell<HashMap<String, Rc<PingConnection>>> = RefCell::default();
+}
+
+/// Connection shared by the pings to an endpoint, which the server answers in order.
+struct PingConnection {
+    endpoint: String,
+    client: WsClient,
+    /// Pings awaiting their reply, oldest first.
+    pending: RefCell<VecDeque<oneshot::Sender<Result<Message, WsError>>>>,
+    /// Pings sent so far, telling whether the connection was used while idle.
+    sent: Cell<u64>,
+}
+
+impl PingConnection {
+    /// Connection of the pings to `endpoint`, connecting unless one is open already.
+    async fn get(endpoint: &str) -> Result<Rc<Self>, WsError> {
+        let cached = || PING_CONNECTIONS.with_borrow(|pool| pool.get(endpoint).cloned());
+        if let Some(connection) = cached() {
+            return Ok(connection);
+        }
+
+        let client = WsClient::connect(endpoint).await?;
+        // Another ping may have connected in the meantime
+        if let Some(connection) = cached() {
+            client.close();
+            return Ok(connection);
+        }
+        let connection = Rc::new(PingConnection {
+            endpoint: endpoint.to_owned(),
+            client,
+            pending: RefCell::default(),
+            sent: Cell::new(0),
         });
-        // Set onopen event handler on websocket
-        ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
-        // Forget the callback to keep it alive
-        onopen_callback.forget();
-
-        // Create onerror callback
-        let cloned_reject = reject.clone();
-        let cloned_ws = ws.clone();
-        let onerror_callback = Closure::<dyn FnMut(_)>::new(move |e: ErrorEvent| {
-            // We close the connection silently
-            let _ = cloned_ws.close();
-            cloned_reject
-                .call1(&JsValue::NULL, &e.error())
-                .expect("call to reject shouldn't fail");
+        spawn_local(connection.clone().dispatch_replies())?;
+        PING_CONNECTIONS.with_borrow_mut(|pool| {
+            pool.insert(endpoint.to_owned(), connection.clone());
         });
-        // Set onerror event handler on websocket
-        ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
-        // Forget the callback to keep it alive
-        onerror_callback.forget();
-
-        // Create onmessage callback
-        let cloned_ws = ws.clone();
-        let onmessage_callback = Closure::<dyn FnMut(_)>::new(move |e: MessageEvent| {
-            // We close the connection silently
-            let _ = cloned_ws.close();
-            if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
-                resolve
-                    .call1(&JsValue::NULL, &txt)
-                    .expect("call to resolve shouldn't fail");
-            } else {
-                reject
-                    .call1(
-                        &JsValue::NULL,
-                        &Error::new("received unsupported message type"),
-                    )
-                    .expect("call to reject shouldn't fail");
+        Ok(connection)
+    }
+
+    /// Hands every reply to the oldest ping awaiting one, until the connection fails or is
+    /// closed, failing the pings still waiting.
+    async fn dispatch_replies(self: Rc<Self>) {
+        loop {
+            let reply = self.client.next_message().await;
+            let replied = self.pending.borrow_mut().pop_front();
+            let Err(err) = reply else {
+                // Messages nobody waits for, e.g. replies to pings given up, are dropped
+                if let Some(replied) = replied {
+                    let _ = replied.send(reply);
+                }
+                continue;
+            };
+
+            self.evict();
+            for replied in replied.into_iter().chain(self.pending.take()) {
+                let _ = replied.send(Err(err.clone()));
+            }
+            return;
+        }
+    }
+
+    /// Closes the connection, so that the next pings open a new one.
+    fn evict(self: &Rc<Self>) {
+        PING_CONNECTIONS.with_borrow_mut(|pool| {
+            if pool
+                .get(&self.endpoint)
+                .is_some_and(|connection| Rc::ptr_eq(connection, self))
+            {
+                pool.remove(&self.endpoint);
             }
         });
-        // Set onmessage event handler on websocket
-        ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
-        // Forget the callback to keep it alive
-        onmessage_callback.forget();
-    })
+        self.client.close();
+    }
+
+    /// Evicts the connection after `PING_IDLE_TIMEOUT` unless another ping is sent meanwhile.
+    fn evict_when_idle(self: &Rc<Self>) -> Result<(), WsError> {
+        let (connection, sent) = (self.clone(), self.sent.get());
+        spawn_local(async move {
+            sleep(PING_IDLE_TIMEOUT).await;
+            if connection.sent.get() == sent && connection.pending.borrow().is_empty() {
+                connection.evict();
+            }
+        })
+    }
+}
+
+/// Evicts a connection when dropped before being disarmed, as a ping given up, e.g. on timeout,
+/// leaves it suspected dead.
+struct EvictOnDrop(Option<Rc<PingConnection>>);
+
+impl Drop for EvictOnDrop {
+    fn drop(&mut self) {
+        if let Some(connection) = self.0.take() {
+            connection.evict();
+        }
+    }
+}
+
+/// Sends `message` to `endpoint` over the connection of the pings and returns the reply, which
+/// must be of the same kind, text or binary.
+async fn exchange(endpoint: &str, message: Message) -> Result<Message, WsError> {
+    let connection = PingConnection::get(endpoint).await?;
+    if let Err(err) = connection.client.send(&message) {
+        connection.evict();
+        return Err(err);
+    }
+    let (replied, reply) = oneshot::channel();
+    connection.pending.borrow_mut().push_back(replied);
+    connection.sent.set(connection.sent.get() + 1);
+
+    let mut given_up = EvictOnDrop(Some(connection.clone()));
+    let reply = reply.await.unwrap_or(Err(WsError::Connection));
+    given_up.0 = None;
+    connection.evict_when_idle()?;
+
+    match (message, reply?) {
+        (Message::Text(_), reply @ Message::Text(_))
+        | (Message::Binary(_), reply @ Message::Binary(_)) => Ok(reply),
+        _ => Err(WsError::UnsupportedMessage),
+    }
+}
+
+/// Messages received over a connection, followed by why it ended.
+type Incoming = mpsc::UnboundedReceiver<Result<Message, WsError>>;
+
+/// Acknowledgement of the server once it verified the proof of a handshake.
+const AUTHENTICATED: &str = "authenticated";
+
+/// Longest delay between two attempts to reconnect, however many failed.
+const MAX_BACKOFF_DELAY: Duration = Duration::from_secs(60);
+
+/// How a client connects, and keeps connected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectOptions {
+    /// Subprotocols offered to the server, which picks one of them.
+    pub protocols: Vec<String>,
+    /// How to reconnect once the connection dropped, the client failing at once otherwise.
+    pub reconnect: Option<Reconnect>,
+    /// Messages sent to tell a dead connection from an idle one, if any.
+    pub heartbeat: Option<Heartbeat>,
+    /// Identity proven to the server before any message is exchanged, if it asks for one.
+    pub handshake: Option<Handshake>,
+    /// Messages sent while reconnecting that are queued until reconnected, sending more failing
+    /// with `WsError::Reconnecting`.
+    pub send_queue: usize,
+    /// How the first connection is retried, reconnecting afterwards being up to `reconnect`.
+    pub retry: RetryPolicy,
+    /// Largest message received, in bytes of its UTF-8 encoding for a text, the connection being
+    /// closed with `WsError::MessageTooLarge` on a larger one.
+    pub max_message_size: usize,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            protocols: Vec::new(),
+            reconnect: None,
+            heartbeat: None,
+            handshake: None,
+            send_queue: 64,
+            retry: RetryPolicy::default(),
+            max_message_size: 16 << 20,
+        }
+    }
+}
+
+/// How a client reconnects once its connection dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reconnect {
+    /// Attempts made in a row before giving up.
+    pub max_attempts: u32,
+    /// Delay before the first attempt, doubling with every attempt that failed.
+    pub base_delay: Duration,
+    /// Share of the delays added or removed at random, from 0 to 1, so that the clients dropped
+    /// together don't all reconnect at once.
+    pub jitter: f64,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Reconnect {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl Reconnect {
+    /// Delay before the attempt numbered `attempt`, from 1.
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = Backoff {
+            base_delay: self.base_delay,
+            jitter: self.jitter,
+        };
+        backoff.delay(attempt)
+    }
+}
+
+/// How a ping, or the first connection of a client, is retried once it failed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RetryPolicy {
+    /// Attempts made after the first one, as long as they fail for a reason that may not last,
+    /// e.g. a connection that failed or timed out.
+    pub retries: u32,
+    /// How long each attempt may take before it's given up, without limit if `None`.
+    pub per_attempt_timeout: Option<Duration>,
+    pub backoff: Backoff,
+}
+
+/// Delays between the attempts of a retry.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    /// Delay before the first retry, doubling with every retry that failed.
+    pub base_delay: Duration,
+    /// Share of the delays added or removed at random, from 0 to 1.
+    pub jitter: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            base_delay: Duration::from_millis(500),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before the retry numbered `attempt`, from 1.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(MAX_BACKOFF_DELAY);
+        let jitter = self.jitter.clamp(0.0, 1.0) * (2.0 * random() - 1.0);
+        delay.mul_f64(1.0 + jitter)
+    }
+}
+
+/// Runs `attempt` until it succeeds, fails for good, or `policy` runs out of retries, giving up
+/// each attempt after the timeout of `policy`.
+///
+/// Failures that would fail again, e.g. an invalid endpoint or message, are returned at once.
+pub async fn retrying<T, F>(
+    policy: &RetryPolicy,
+    mut attempt: impl FnMut() -> F,
+) -> Result<T, WsError>
+where
+    F: Future<Output = Result<T, WsError>>,
+{
+    let mut retries = 0;
+    loop {
+        let result = match policy.per_attempt_timeout {
+            Some(timeout) => within(timeout, attempt()).await,
+            None => attempt().await,
+        };
+        match result {
+            Err(err) if retries < policy.retries && err.is_transient() => {
+                retries += 1;
+                sleep(policy.backoff.delay(retries)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Keepalive of a client, since browsers neither send WebSocket pings nor tell when a connection
+/// died silently.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Message sent once the connection was idle for `interval`.
+    pub message: Message,
+    /// Reply of the server to `message`, kept from the messages handed out.
+    pub reply: Message,
+    pub interval: Duration,
+    /// How long any message is awaited after `message`, before the connection is dropped as
+    /// dead.
+    pub deadline: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            message: Message::Text("ping".to_owned()),
+            reply: Message::Text("ping".to_owned()),
+            interval: Duration::from_secs(30),
+            deadline: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Identity of a client, proven with a DLog proof of its secret key in answer to the nonce the
+/// server opens the connection with, for the session id.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Handshake {
+    /// Participant id the proof is made for.
+    pub pid: u32,
+    /// Secret key of the public key sent along with the proof.
+    pub secret_key: Scalar,
+}
+
+impl fmt::Debug for Handshake {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The secret key stays out of the logs
+        f.debug_struct("Handshake")
+            .field("pid", &self.pid)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Answer to the nonce of the server, proving the identity of a client.
+#[derive(Serialize)]
+struct Authentication<'a> {
+    pid: u32,
+    public_key: AffinePoint,
+    proof: &'a DLogProof,
+}
+
+/// What a reconnecting client is doing about a dropped connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReconnectEvent {
+    /// The attempt numbered `attempt`, from 1, will be made after `delay`.
+    Reconnecting { attempt: u32, delay: Duration },
+    /// The attempt numbered `attempt` succeeded.
+    Reconnected { attempt: u32 },
+    /// Every attempt failed, the client failing with what dropped the connection.
+    GaveUp { attempts: u32 },
+}
+
+/// Connection to a WebSocket endpoint kept open to exchange any number of messages, unlike a
+/// ping.
+///
+/// The messages received are queued until read, in their order of arrival.
+///
+/// Natively, a client must be connected on a tokio `LocalSet`, which it spawns its tasks on, and
+/// used while it runs.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct WsClient {
+    link: Rc<Link>,
+    incoming: Rc<Mutex<Incoming>>,
+}
+
+/// Current connection of a client, shared with the task keeping it connected.
+struct Link {
+    /// Taken on close, which unsets the callbacks and so ends the incoming messages, and while
+    /// reconnecting.
+    connection: RefCell<Option<Connection>>,
+    /// Why the client is done with connecting, once closed or given up.
+    ended: RefCell<Option<WsError>>,
+    /// Messages sent while reconnecting, oldest first.
+    queued: RefCell<VecDeque<Message>>,
+    send_queue: usize,
+    hooks: Hooks,
+}
+
+impl Link {
+    /// Sends the messages queued while reconnecting, leaving those that fail for the next
+    /// connection.
+    fn flush(&self) {
+        let connection = self.connection.borrow();
+        let Some(connection) = connection.as_ref() else {
+            return;
+        };
+        let mut queued = self.queued.borrow_mut();
+        while let Some(message) = queued.front() {
+            if connection.send(message).is_err() {
+                break;
+            }
+            queued.pop_front();
+        }
+    }
+}
+
+/// Callbacks told about the connections of a client opening and closing, so that a UI can show
+/// its state.
+///
+/// The callbacks are cloned out before being called, so that they can be replaced meanwhile.
+#[derive(Default)]
+struct Hooks {
+    on_open: Hook<dyn Fn()>,
+    on_close: Hook<OnClose>,
+    on_error: Hook<dyn Fn(&WsError)>,
+}
+
+/// Callback set on a client, if any.
+type Hook<F> = RefCell<Option<Rc<F>>>;
+
+/// Callback called with the code and the reason of a close.
+type OnClose = dyn Fn(u16, &str);
+
+impl Hooks {
+    /// Tells that the client reconnected.
+    fn opened(&self) {
+        let on_open = self.on_open.borrow().clone();
+        if let Some(on_open) = on_open {
+            on_open();
+        }
+    }
+
+    /// Tells that the connection dropped with `err`, which is a failure unless it was closed, the
+    /// failed connections being closed with 1006 as browsers do.
+    fn dropped(&self, err: &WsError) {
+        let (code, reason) = match err {
+            WsError::Closed { code, reason } => (*code, reason.as_str()),
+            err => {
+                let on_error = self.on_error.borrow().clone();
+                if let Some(on_error) = on_error {
+                    on_error(err);
+                }
+                (1006, "")
+            }
+        };
+        let on_close = self.on_close.borrow().clone();
+        if let Some(on_close) = on_close {
+            on_close(code, reason);
+        }
+    }
+}
+
+impl WsClient {
+    /// Connects to the WebSocket `endpoint`, returning once the connection is open.
+    ///
+    /// Natively, it fails with `WsError::NoLocalSet` outside of a tokio `LocalSet`.
+    pub async fn connect(endpoint: &str) -> Result<Self, WsError> {
+        WsClient::connect_with(endpoint, ConnectOptions::default(), |_| {}).await
+    }
+
+    /// Connects to the WebSocket `endpoint` as set by `options`, returning once the connection is
+    /// open, and calling `on_event` along the way whenever it reconnects.
+    ///
+    /// Once the connection dropped, up to `options.send_queue` messages sent while reconnecting are
+    /// queued and sent over the next connection, while `next_message` waits for it and only fails
+    /// once every attempt did. The first connection is retried as set by `options.retry`.
+    ///
+    /// Natively, it fails with `WsError::NoLocalSet` outside of a tokio `LocalSet`.
+    pub async fn connect_with(
+        endpoint: &str,
+        options: ConnectOptions,
+        on_event: impl Fn(ReconnectEvent) + 'static,
+    ) -> Result<Self, WsError> {
+        let (connection, received) =
+            retrying(&options.retry, || establish(endpoint, &options)).await?;
+        let (forwarded, incoming) = mpsc::unbounded();
+        let client = WsClient::new(connection, incoming, options.send_queue);
+        spawn_local(keep_connected(
+            endpoint.to_owned(),
+            options,
+            Rc::downgrade(&client.link),
+            received,
+            forwarded,
+            on_event,
+        ))?;
+        Ok(client)
+    }
+
+    fn new(connection: Connection, incoming: Incoming, send_queue: usize) -> Self {
+        let link = Link {
+            connection: RefCell::new(Some(connection)),
+            ended: RefCell::default(),
+            queued: RefCell::default(),
+            send_queue,
+            hooks: Hooks::default(),
+        };
+        WsClient {
+            link: Rc::new(link),
+            incoming: Rc::new(Mutex::new(incoming)),
+        }
+    }
+
+    /// Subprotocol picked by the server among the ones offered, empty if none was or while
+    /// reconnecting.
+    pub fn protocol(&self) -> String {
+        match self.link.connection.borrow().as_ref() {
+            Some(connection) => connection.protocol(),
+            None => String::new(),
+        }
+    }
+
+    /// Sends `message` as a text or a binary message, or queues it until reconnected.
+    pub fn send(&self, message: &Message) -> Result<(), WsError> {
+        if let Some(connection) = self.link.connection.borrow().as_ref() {
+            return connection.send(message);
+        }
+        if let Some(ended) = self.link.ended.borrow().clone() {
+            return Err(ended);
+        }
+
+        let mut queued = self.link.queued.borrow_mut();
+        if queued.len() >= self.link.send_queue {
+            return Err(WsError::Reconnecting);
+        }
+        queued.push_back(message.clone());
+        Ok(())
+    }
+
+    /// Returns the next message received, failing once the connection failed or was closed.
+    pub async fn next_message(&self) -> Result<Message, WsError> {
+        next_message(&self.incoming).await
+    }
+
+    /// Closes the connection, dropping the messages not read yet, and stops reconnecting.
+    pub fn close(&self) {
+        self.link.ended.replace(Some(closed_by_client()));
+        // A client closed while reconnecting has no connection left to tell about
+        if self.link.connection.borrow_mut().take().is_some() {
+            self.link.hooks.dropped(&closed_by_client());
+        }
+    }
+
+    /// Calls `on_open` whenever the client reconnected, instead of the callback set before if
+    /// any, the first connection being told by the client being returned.
+    pub fn on_open(&self, on_open: impl Fn() + 'static) {
+        self.link.hooks.on_open.replace(Some(Rc::new(on_open)));
+    }
+
+    /// Calls `on_close` with the code and the reason whenever the connection closes, whether the
+    /// client reconnects or not, instead of the callback set before if any.
+    ///
+    /// A connection that failed is told as closed with 1006, as browsers do, and one closed with
+    /// `close` with 1000.
+    pub fn on_close(&self, on_close: impl Fn(u16, &str) + 'static) {
+        self.link.hooks.on_close.replace(Some(Rc::new(on_close)));
+    }
+
+    /// Calls `on_error` with the failure whenever the connection fails, e.g. when the heartbeat
+    /// found it dead, right before `on_close`, instead of the callback set before if any.
+    pub fn on_error(&self, on_error: impl Fn(&WsError) + 'static) {
+        self.link.hooks.on_error.replace(Some(Rc::new(on_error)));
+    }
+
+    /// Stream of the messages received, ending with the error once the connection failed or was
+    /// closed.
+    ///
+    /// The messages are read from the same queue as `next_message`, so that each one goes to
+    /// whichever reads first.
+    pub fn messages(&self) -> impl Stream<Item = Result<Message, WsError>> + 'static {
+        stream::unfold(Some(self.incoming.clone()), |incoming| async move {
+            let incoming = incoming?;
+            match next_message(&incoming).await {
+                Ok(message) => Some((Ok(message), Some(incoming))),
+                Err(err) => Some((Err(err), None)),
+            }
+        })
+    }
+}
+
+/// Client making any number of calls at once over a single connection, the request of each call
+/// being tagged with an id that the server hands back along with the reply, so that the replies can
+/// come in any order.
+///
+/// Requests and replies are JSON envelopes, `{ "id": 1, "payload": ... }`, as the `/rpc` route of
+/// the server answers them.
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+pub struct RpcClient {
+    calls: Rc<Calls>,
+}
+
+/// Request or reply of a call, tagged with the id of the call.
+#[derive(Deserialize, Serialize)]
+struct Envelope<T> {
+    id: u64,
+    payload: T,
+}
+
+/// Calls of a client awaiting their reply, shared with the task dispatching the replies.
+struct Calls {
+    client: WsClient,
+    /// Id of the next call.
+    next_id: Cell<u64>,
+    pending: RefCell<HashMap<u64, oneshot::Sender<Result<serde_json::Value, WsError>>>>,
+}
+
+impl Calls {
+    /// Sends `request` tagged with a fresh id and returns the payload of the reply with that id.
+    async fn call<R: DeserializeOwned>(&self, request: &impl Serialize) -> Result<R, WsError> {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        let request = Envelope {
+            id,
+            payload: request,
+        };
+        let request =
+            serde_json::to_string(&request).map_err(|err| WsError::Json(err.to_string()))?;
+
+        let (replied, reply) = oneshot::channel();
+        self.pending.borrow_mut().insert(id, replied);
+        let _pending = PendingCall { calls: self, id };
+        self.client.send(&Message::Text(request))?;
+        let payload = reply.await.unwrap_or(Err(WsError::Connection))?;
+        serde_json::from_value(payload).map_err(|err| WsError::Json(err.to_string()))
+    }
+
+    /// Fails every call awaiting its reply with `err`.
+    fn fail(&self, err: &WsError) {
+        for (_, replied) in self.pending.take() {
+            let _ = replied.send(Err(err.clone()));
+        }
+    }
+}
+
+/// Forgets a call when dropped, once replied or given up, e.g. on timeout.
+struct PendingCall<'a> {
+    calls: &'a Calls,
+    id: u64,
+}
+
+impl Drop for PendingCall<'_> {
+    fn drop(&mut self) {
+        self.calls.pending.borrow_mut().remove(&self.id);
+    }
+}
+
+impl RpcClient {
+    /// Connects to the WebSocket `endpoint`, returning a client making calls once the connection
+    /// is open.
+    pub async fn connect(endpoint: &str) -> Result<Self, WsError> {
+        WsClient::connect(endpoint).await.and_then(RpcClient::new)
+    }
+
+    /// Makes calls over `client`, whose messages are all taken as replies, and whose callbacks on
+    /// close are replaced.
+    ///
+    /// The calls awaiting their reply when the connection drops fail, even if the client
+    /// reconnects, as the server won't reply over the next connection. Natively, it fails with
+    /// `WsError::NoLocalSet` outside of a tokio `LocalSet`.
+    pub fn new(client: WsClient) -> Result<Self, WsError> {
+        let messages = client.messages();
+        let calls = Rc::new(Calls {
+            client,
+            next_id: Cell::new(0),
+            pending: RefCell::default(),
+        });
+        let dropped = Rc::downgrade(&calls);
+        calls.client.on_close(move |code, reason| {
+            if let Some(calls) = dropped.upgrade() {
+                calls.fail(&WsError::Closed {
+                    code,
+                    reason: reason.to_owned(),
+                });
+            }
+        });
+        spawn_local(dispatch_replies(messages, Rc::downgrade(&calls)))?;
+        Ok(RpcClient { calls })
+    }
+
+    /// Sends `request` serialized as JSON and returns the payload of its reply, parsed as JSON,
+    /// while other calls may be awaiting theirs.
+    pub async fn call<R: DeserializeOwned>(&self, request: &impl Serialize) -> Result<R, WsError> {
+        self.calls.call(request).await
+    }
+
+    /// Closes the connection, failing the calls awaiting their reply.
+    pub fn close(&self) {
+        self.calls.client.close();
+    }
+}
+
+/// Hands every reply to the call with its id, until the connection of `calls` fails or is closed,
+/// failing the calls still waiting.
+async fn dispatch_replies(
+    messages: impl Stream<Item = Result<Message, WsError>>,
+    calls: Weak<Calls>,
+) {
+    let mut messages = pin!(messages);
+    while let Some(message) = messages.next().await {
+        let Some(calls) = calls.upgrade() else {
+            return;
+        };
+        let text = match message {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Binary(_)) => continue,
+            Err(err) => {
+                calls.fail(&err);
+                return;
+            }
+        };
+        // Messages that aren't replies to a call awaiting one, e.g. given up, are dropped
+        let Ok(reply) = serde_json::from_str::<Envelope<serde_json::Value>>(&text) else {
+            continue;
+        };
+        let replied = calls.pending.borrow_mut().remove(&reply.id);
+        if let Some(replied) = replied {
+            let _ = replied.send(Ok(reply.payload));
+        }
+    }
+}
+
+/// Connects to the WebSocket `endpoint` as set by `options`, going through the handshake if they
+/// have one.
+async fn establish(
+    endpoint: &str,
+    options: &ConnectOptions,
+) -> Result<(Connection, Incoming), WsError> {
+    let (connection, mut incoming) =
+        open(endpoint, &options.protocols, options.max_message_size).await?;
+    if let Some(handshake) = &options.handshake {
+        authenticate(&connection, &mut incoming, handshake).await?;
+    }
+    Ok((connection, incoming))
+}
+
+/// Answers the nonce the server opens `connection` with by a proof of `handshake`, returning once
+/// the server acknowledged it.
+async fn authenticate(
+    connection: &Connection,
+    incoming: &mut Incoming,
+    handshake: &Handshake,
+) -> Result<(), WsError> {
+    let unexpected = |what: &str| WsError::Handshake(format!("expected {what} from the server"));
+    let rejected = |err| match err {
+        WsError::Closed { code, reason } => {
+            WsError::Handshake(format!("rejected by the server ({code} {reason})"))
+        }
+        err => err,
+    };
+
+    let nonce = match incoming.next().await {
+        Some(Ok(Message::Text(nonce))) => nonce,
+        Some(Ok(Message::Binary(_))) => return Err(unexpected("a nonce")),
+        Some(Err(err)) => return Err(rejected(err)),
+        None => return Err(WsError::Connection),
+    };
+
+    let public_key = ProjectivePoint::GENERATOR * handshake.secret_key;
+    let proof = DLogProof::prove(
+        &mut OsRng,
+        &nonce,
+        handshake.pid,
+        handshake.secret_key,
+        public_key,
+    );
+    let authentication = Authentication {
+        pid: handshake.pid,
+        public_key: public_key.to_affine(),
+        proof: &proof,
+    };
+    let authentication = serde_json::to_string(&authentication)
+        .map_err(|err| WsError::Handshake(err.to_string()))?;
+    connection.send(&Message::Text(authentication))?;
+
+    match incoming.next().await {
+        Some(Ok(Message::Text(ack))) if ack == AUTHENTICATED => Ok(()),
+        Some(Ok(_)) => Err(unexpected("an acknowledgement")),
+        Some(Err(err)) => Err(rejected(err)),
+        None => Err(WsError::Connection),
+    }
+}
+
+/// Forwards the messages `received` by the connection of `link` to the client, telling its hooks
+/// when it drops and reconnecting as set by `options`, until the client is closed or dropped, or
+/// gives up.
+async fn keep_connected(
+    endpoint: String,
+    options: ConnectOptions,
+    link: Weak<Link>,
+    mut received: Incoming,
+    forwarded: mpsc::UnboundedSender<Result<Message, WsError>>,
+    on_event: impl Fn(ReconnectEvent),
+) {
+    'connected: loop {
+        let Some(dropped) =
+            forward(&mut received, &forwarded, options.heartbeat.as_ref(), &link).await
+        else {
+            return;
+        };
+        match live(&link) {
+            Some(link) => {
+                // Closes the connection if it's still open, e.g. when the heartbeat found it dead
+                link.connection.borrow_mut().take();
+                link.hooks.dropped(&dropped);
+            }
+            None => return,
+        }
+
+        // A message too large is the server misbehaving, which reconnecting doesn't fix, so the
+        // client fails with it at once, sending included
+        let reconnect = options
+            .reconnect
+            .as_ref()
+            .filter(|_| !matches!(dropped, WsError::MessageTooLarge(_)));
+        if let Some(reconnect) = reconnect {
+            for attempt in 1..=reconnect.max_attempts {
+                let delay = reconnect.delay(attempt);
+                on_event(ReconnectEvent::Reconnecting { attempt, delay });
+                sleep(delay).await;
+                if live(&link).is_none() {
+                    return;
+                }
+
+                let Ok((connection, incoming)) = establish(&endpoint, &options).await else {
+                    continue;
+                };
+                match live(&link) {
+                    Some(link) => {
+                        *link.connection.borrow_mut() = Some(connection);
+                        link.flush();
+                        link.hooks.opened();
+                    }
+                    None => return,
+                }
+                received = incoming;
+                on_event(ReconnectEvent::Reconnected { attempt });
+                continue 'connected;
+            }
+            on_event(ReconnectEvent::GaveUp {
+                attempts: reconnect.max_attempts,
+            });
+        }
+
+        if let Some(link) = link.upgrade() {
+            link.ended.replace(Some(dropped.clone()));
+            link.queued.take();
+        }
+        let _ = forwarded.unbounded_send(Err(dropped));
+        return;
+    }
+}
+
+/// Forwards the messages `received` by the connection of `link` to the client, sending the
+/// message of `heartbeat` whenever it's idle, until the connection drops, returning why, or until
+/// the client is closed or dropped.
+async fn forward(
+    received: &mut Incoming,
+    forwarded: &mpsc::UnboundedSender<Result<Message, WsError>>,
+    heartbeat: Option<&Heartbeat>,
+    link: &Weak<Link>,
+) -> Option<WsError> {
+    // Whether the heartbeat was sent and nothing received since
+    let mut awaiting = false;
+    loop {
+        let idle = heartbeat.map(|heartbeat| match awaiting {
+            true => sleep(heartbeat.deadline),
+            false => sleep(heartbeat.interval),
+        });
+        let idle = pin!(async {
+            match idle {
+                Some(idle) => idle.await,
+                None => future::pending().await,
+            }
+        });
+        let message = match future::select(received.next(), idle).await {
+            Either::Left((Some(Ok(message)), _)) => message,
+            Either::Left((Some(Err(err)), _)) => return Some(err),
+            // The callbacks are gone with the connection, closed by the client
+            Either::Left((None, _)) => return None,
+            Either::Right(((), _)) => {
+                let heartbeat = heartbeat?;
+                if awaiting {
+                    return Some(WsError::Timeout(heartbeat.deadline));
+                }
+                let link = live(link)?;
+                let connection = link.connection.borrow();
+                if let Some(connection) = connection.as_ref() {
+                    if let Err(err) = connection.send(&heartbeat.message) {
+                        return Some(err);
+                    }
+                }
+                awaiting = true;
+                continue;
+            }
+        };
+
+        awaiting = false;
+        if heartbeat.is_some_and(|heartbeat| heartbeat.reply == message) {
+            continue;
+        }
+        if forwarded.unbounded_send(Ok(message)).is_err() {
+            return None;
+        }
+    }
+}
+
+/// Link of a client that's neither dropped nor closed.
+fn live(link: &Weak<Link>) -> Option<Rc<Link>> {
+    link.upgrade().filter(|link| link.ended.borrow().is_none())
+}
+
+async fn next_message(incoming: &Mutex<Incoming>) -> Result<Message, WsError> {
+    // Concurrent reads are served one after the other
+    let mut incoming = incoming.lock().await;
+    incoming
+        .next()
+        .await
+        .unwrap_or_else(|| Err(closed_by_client()))
+}
+
+/// Failure of a client once closed, when its callbacks are gone and with them the close event.
+fn closed_by_client() -> WsError {
+    WsError::Closed {
+        code: 1000,
+        reason: "closed by the client".to_owned(),
+    }
 }
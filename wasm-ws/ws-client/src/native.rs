@@ -0,0 +1,186 @@
+//! Native side of the client, over tokio-tungstenite, so that the same code runs outside of
+//! browsers, e.g. in test harnesses.
+
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use futures_channel::mpsc;
+use futures_util::{SinkExt, StreamExt};
+use k256::elliptic_curve::rand_core::{OsRng, RngCore};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async_with_config,
+    tungstenite::{
+        self,
+        client::IntoClientRequest,
+        error::CapacityError,
+        http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue},
+        protocol::{frame::coding::CloseCode, CloseFrame, WebSocketConfig},
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{Incoming, Message, WsError};
+
+impl From<Message> for tungstenite::Message {
+    fn from(message: Message) -> Self {
+        match message {
+            Message::Text(text) => tungstenite::Message::Text(text),
+            Message::Binary(bytes) => tungstenite::Message::Binary(bytes),
+        }
+    }
+}
+
+/// Connects to the WebSocket `endpoint` offering the subprotocols `protocols`, returning the open
+/// connection with the messages it receives, up to `max_message_size` bytes each.
+///
+/// Unlike browsers, tungstenite fails the connection if the server picks none of the
+/// `protocols`.
+pub(crate) async fn open(
+    endpoint: &str,
+    protocols: &[String],
+    max_message_size: usize,
+) -> Result<(Connection, Incoming), WsError> {
+    let invalid = |err: &dyn std::fmt::Display| WsError::InvalidEndpoint(err.to_string());
+    let mut request = endpoint
+        .into_client_request()
+        .map_err(|err| invalid(&err))?;
+    if !protocols.is_empty() {
+        // tungstenite splits the protocols offered on the commas alone
+        let protocols = HeaderValue::from_str(&protocols.join(",")).map_err(|err| invalid(&err))?;
+        request
+            .headers_mut()
+            .insert(SEC_WEBSOCKET_PROTOCOL, protocols);
+    }
+
+    let config = WebSocketConfig {
+        max_message_size: Some(max_message_size),
+        max_frame_size: Some(max_message_size),
+        ..WebSocketConfig::default()
+    };
+    let connected = connect_async_with_config(request, Some(config), false).await;
+    let (ws, response) = connected.map_err(|err| match err {
+        tungstenite::Error::Url(err) => invalid(&err),
+        _ => WsError::Connection,
+    })?;
+    let protocol = response
+        .headers()
+        .get(SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|protocol| protocol.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+
+    let (outgoing, sent) = mpsc::unbounded();
+    let (received, incoming) = mpsc::unbounded();
+    tokio::spawn(serve(ws, sent, received));
+    Ok((Connection { outgoing, protocol }, incoming))
+}
+
+/// Sends the messages `sent` over `ws` and hands the ones it receives to `received`, until the
+/// connection drops, or closes it once the messages `sent` end.
+async fn serve(
+    mut ws: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    mut sent: mpsc::UnboundedReceiver<Message>,
+    received: mpsc::UnboundedSender<Result<Message, WsError>>,
+) {
+    // Why the server closed the connection, told once the close handshake is done
+    let mut closed = None;
+    loop {
+        tokio::select! {
+            message = sent.next() => {
+                let Some(message) = message else {
+                    // We close the connection silently
+                    let _ = ws.close(None).await;
+                    return;
+                };
+                if let Err(err) = ws.send(message.into()).await {
+                    let _ = received.unbounded_send(Err(WsError::Send(err.to_string())));
+                    return;
+                }
+            }
+            frame = ws.next() => {
+                let message = match frame {
+                    Some(Ok(tungstenite::Message::Text(text))) => Message::Text(text),
+                    Some(Ok(tungstenite::Message::Binary(bytes))) => Message::Binary(bytes),
+                    Some(Ok(tungstenite::Message::Close(frame))) => {
+                        // Browsers tell a close frame without a code as 1005
+                        let (code, reason) = frame.map_or((1005, String::new()), |frame| {
+                            (frame.code.into(), frame.reason.into_owned())
+                        });
+                        closed = Some(WsError::Closed { code, reason });
+                        continue;
+                    }
+                    // Pings are answered by tungstenite itself
+                    Some(Ok(_)) => continue,
+                    Some(Err(tungstenite::Error::Capacity(
+                        CapacityError::MessageTooLong { max_size, .. },
+                    ))) => {
+                        let _ = received.unbounded_send(Err(WsError::MessageTooLarge(max_size)));
+                        let _ = ws
+                            .close(Some(CloseFrame {
+                                code: CloseCode::Size,
+                                reason: "message too large".into(),
+                            }))
+                            .await;
+                        return;
+                    }
+                    Some(Err(_)) | None => {
+                        let _ = received.unbounded_send(Err(closed.unwrap_or(WsError::Connection)));
+                        return;
+                    }
+                };
+                let _ = received.unbounded_send(Ok(message));
+            }
+        }
+    }
+}
+
+/// Resolves after `duration`, through the timer of tokio.
+pub(crate) fn sleep(duration: Duration) -> impl Future<Output = ()> + Unpin {
+    Box::pin(tokio::time::sleep(duration))
+}
+
+/// Time elapsed since the first call, measured like `performance.now()` with a monotonic clock.
+pub(crate) fn now() -> Duration {
+    static ORIGIN: OnceLock<Instant> = OnceLock::new();
+    ORIGIN.get_or_init(Instant::now).elapsed()
+}
+
+/// Spawns `future` on the current `LocalSet`, the client being single-threaded as in browsers,
+/// failing with `WsError::NoLocalSet` outside of one.
+pub(crate) fn spawn_local(future: impl Future<Output = ()> + 'static) -> Result<(), WsError> {
+    // tokio tells no other way whether a `LocalSet` runs than by panicking
+    panic::catch_unwind(AssertUnwindSafe(|| tokio::task::spawn_local(future)))
+        .map(drop)
+        .map_err(|_| WsError::NoLocalSet)
+}
+
+/// Random number from 0 included to 1 excluded, like `Math.random`.
+pub(crate) fn random() -> f64 {
+    // As many random bits as a double holds
+    (OsRng.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// WebSocket served by a task of its own, which closes the connection once it's dropped.
+pub(crate) struct Connection {
+    outgoing: mpsc::UnboundedSender<Message>,
+    protocol: String,
+}
+
+impl Connection {
+    /// Sends `message` as a text or a binary message.
+    pub(crate) fn send(&self, message: &Message) -> Result<(), WsError> {
+        self.outgoing
+            .unbounded_send(message.clone())
+            .map_err(|_| WsError::Send("the connection is closed".to_owned()))
+    }
+
+    /// Subprotocol picked by the server, empty if none was.
+    pub(crate) fn protocol(&self) -> String {
+        self.protocol.clone()
+    }
+}
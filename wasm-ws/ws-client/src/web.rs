@@ -0,0 +1,1103 @@
+//! Browser side of the client, over the `WebSocket` of the JS runtime, and its JS bindings.
+
+use std::{
+    future::Future,
+    pin::{pin, Pin},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_channel::{mpsc, oneshot};
+use futures_util::{
+    future::{self, Either, LocalBoxFuture},
+    FutureExt, StreamExt,
+};
+use gloo_timers::future::TimeoutFuture;
+use js_sys::{
+    encode_uri_component, Array, ArrayBuffer, Error, Function, JsString, Math, Object, Promise,
+    Reflect, Uint8Array,
+};
+use k256::{elliptic_curve::PrimeField, FieldBytes, Scalar};
+use serde::{Deserialize, Serialize};
+use serde_wasm_bindgen::Serializer;
+use wasm_bindgen::{prelude::*, JsValue};
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use web_sys::{
+    AbortSignal, BinaryType, Blob, CloseEvent, Event, MessageEvent, Request, RequestInit, Response,
+    WebSocket,
+};
+
+use crate::{
+    cancellable, exchange, measure_latency, next_message, request_json, retrying, within, Backoff,
+    ConnectOptions, Handshake, Heartbeat, Incoming, Latency, Message, Reconnect, ReconnectEvent,
+    RetryPolicy, RpcClient, WsClient, WsError, WsErrorCode,
+};
+
+#[wasm_bindgen(typescript_custom_section)]
+const TS_TYPES: &str = r#"
+/** Message to send, a string as a text message, bytes as a binary one. */
+export type WsData = string | Uint8Array | ArrayBuffer;
+
+/** Message received, a string for a text message, a `Uint8Array` for a binary one. */
+export type WsMessage = string | Uint8Array;
+
+/** Delay before a retry, doubled after each one up to a minute, give or take a `jitter` share. */
+export interface WsBackoff {
+  baseDelayMs?: number;
+  jitter?: number;
+}
+
+/** How a ping, a request or the first connection of a client is retried once it failed. */
+export interface WsRetryPolicy {
+  retries?: number;
+  perAttemptTimeoutMs?: number;
+  backoff?: WsBackoff;
+}
+
+/** Settings of `wsPing` and `wsRequestJson`, given as a number for the `timeoutMs` alone. */
+export interface WsPingOptions extends WsRetryPolicy {
+  timeoutMs?: number;
+}
+
+/** How a client reconnects whenever its connection drops. */
+export interface WsReconnectOptions {
+  maxAttempts?: number;
+  baseDelayMs?: number;
+  jitter?: number;
+}
+
+/** Message sent whenever the connection was idle, and the reply expected in time. */
+export interface WsHeartbeatOptions {
+  message?: WsData;
+  reply?: WsData;
+  intervalMs?: number;
+  deadlineMs?: number;
+}
+
+/** Identity a client proves on every connection, its secret key being 32 big-endian bytes. */
+export interface WsHandshakeOptions {
+  pid: number;
+  secretKey: Uint8Array;
+}
+
+/** Settings of `WsClient.connectWith` and `RpcClient.connect`. */
+export interface WsConnectOptions {
+  protocols?: string[];
+  reconnect?: WsReconnectOptions;
+  heartbeat?: WsHeartbeatOptions;
+  handshake?: WsHandshakeOptions;
+  sendQueue?: number;
+  retry?: WsRetryPolicy;
+  maxMessageSize?: number;
+  signal?: AbortSignal;
+}
+
+/** Event of a client reconnecting. */
+export type WsReconnectEvent =
+  | { type: "reconnecting"; attempt: number; delayMs: number }
+  | { type: "reconnected"; attempt: number }
+  | { type: "gave_up"; attempts: number };
+
+/** Round-trip times measured by `wsMeasureLatency`, in milliseconds. */
+export interface WsLatency {
+  minMs: number;
+  avgMs: number;
+  p95Ms: number;
+}
+
+/** Outcome of a wait through sync-point, its other fields depending on the `status`. */
+export interface WsSyncPointOutcome {
+  status: string;
+  waited_ms?: number;
+  [field: string]: unknown;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    // JS values as typed in the TypeScript definitions, which the functions taking or returning
+    // them read like any other value
+
+    #[wasm_bindgen(typescript_type = "WsData")]
+    pub type JsData;
+
+    #[wasm_bindgen(typescript_type = "WsPingOptions | number")]
+    pub type JsPingOptions;
+
+    #[wasm_bindgen(typescript_type = "WsReconnectOptions")]
+    pub type JsReconnectOptions;
+
+    #[wasm_bindgen(typescript_type = "WsConnectOptions")]
+    pub type JsConnectOptions;
+
+    #[wasm_bindgen(typescript_type = "(event: WsReconnectEvent) => void")]
+    pub type OnReconnectEvent;
+
+    #[wasm_bindgen(typescript_type = "(message: WsMessage) => void")]
+    pub type OnMessage;
+
+    #[wasm_bindgen(typescript_type = "() => void")]
+    pub type OnOpen;
+
+    #[wasm_bindgen(typescript_type = "(code: number, reason: string) => void")]
+    pub type OnClose;
+
+    #[wasm_bindgen(typescript_type = "(err: WsClientError) => void")]
+    pub type OnError;
+
+    #[wasm_bindgen(typescript_type = "Promise<WsMessage>")]
+    pub type MessagePromise;
+
+    #[wasm_bindgen(typescript_type = "Promise<WsLatency>")]
+    pub type LatencyPromise;
+
+    #[wasm_bindgen(typescript_type = "Promise<WsSyncPointOutcome>")]
+    pub type OutcomePromise;
+}
+
+impl From<WsError> for JsValue {
+    fn from(err: WsError) -> Self {
+        WsClientError::from(err).into()
+    }
+}
+
+/// Failure of a ping or of a client, which the promises are rejected with and the methods throw.
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct WsClientError {
+    code: WsErrorCode,
+    close_code: Option<u16>,
+    reason: String,
+    message: String,
+}
+
+#[wasm_bindgen]
+impl WsClientError {
+    /// Cause of the failure.
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> WsErrorCode {
+        self.code
+    }
+
+    /// Code the connection was closed with, for the `Closed` failures only.
+    #[wasm_bindgen(getter = closeCode)]
+    pub fn close_code(&self) -> Option<u16> {
+        self.close_code
+    }
+
+    /// Reason the connection was closed with, or the details of any other failure, if known.
+    #[wasm_bindgen(getter)]
+    pub fn reason(&self) -> String {
+        self.reason.clone()
+    }
+
+    /// Description of the failure.
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    /// The failure as shown when thrown, with its message.
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_string_js(&self) -> String {
+        format!("WsClientError: {}", self.message)
+    }
+}
+
+impl From<WsError> for WsClientError {
+    fn from(err: WsError) -> Self {
+        let message = err.to_string();
+        let code = err.code();
+        let (close_code, reason) = match err {
+            WsError::Closed { code, reason } => (Some(code), reason),
+            WsError::InvalidEndpoint(details)
+            | WsError::Send(details)
+            | WsError::SyncPoint(details)
+            | WsError::Handshake(details)
+            | WsError::Json(details)
+            | WsError::InvalidOptions(details) => (None, details),
+            _ => (None, String::new()),
+        };
+        WsClientError {
+            code,
+            close_code,
+            reason,
+            message,
+        }
+    }
+}
+
+impl Message {
+    /// Message of a JS string, `Uint8Array` or `ArrayBuffer`.
+    fn from_js(value: &JsValue) -> Result<Self, WsError> {
+        if let Some(text) = value.as_string() {
+            Ok(Message::Text(text))
+        } else if let Some(array) = value.dyn_ref::<Uint8Array>() {
+            Ok(Message::Binary(array.to_vec()))
+        } else if let Some(buffer) = value.dyn_ref::<ArrayBuffer>() {
+            Ok(Message::Binary(Uint8Array::new(buffer).to_vec()))
+        } else {
+            Err(WsError::InvalidMessage)
+        }
+    }
+
+    /// Message carried by the data of a message event, read from it first if it's a `Blob`, as
+    /// binary messages are in runtimes ignoring the binary type of the socket, failing with
+    /// `WsError::MessageTooLarge` if it's larger than `max_size` bytes.
+    fn read(data: JsValue, max_size: usize) -> Reading {
+        // The size is checked before the data is copied into the memory of the module, a text
+        // taking at least a byte in UTF-8 by UTF-16 code unit
+        let size = if let Some(text) = data.dyn_ref::<JsString>() {
+            text.length() as usize
+        } else if let Some(buffer) = data.dyn_ref::<ArrayBuffer>() {
+            buffer.byte_length() as usize
+        } else if let Some(blob) = data.dyn_ref::<Blob>() {
+            blob.size() as usize
+        } else {
+            0
+        };
+        if size > max_size {
+            return Box::pin(future::ready(Err(WsError::MessageTooLarge(max_size))));
+        }
+
+        let within_size = move |message: Message| match &message {
+            Message::Text(text) if text.len() > max_size => Err(WsError::MessageTooLarge(max_size)),
+            _ => Ok(message),
+        };
+        match data.dyn_into::<Blob>() {
+            Ok(blob) => Box::pin(async move {
+                let buffer = JsFuture::from(blob.array_buffer())
+                    .await
+                    .map_err(|_| WsError::UnsupportedMessage)?;
+                Message::from_data(buffer)
+            }),
+            Err(data) => Box::pin(future::ready(
+                Message::from_data(data).and_then(within_size),
+            )),
+        }
+    }
+
+    /// Message carried by the data of a message event, a string or an `ArrayBuffer`.
+    fn from_data(data: JsValue) -> Result<Self, WsError> {
+        if let Some(text) = data.as_string() {
+            return Ok(Message::Text(text));
+        }
+        data.dyn_into::<ArrayBuffer>()
+            .map(|buffer| Message::Binary(Uint8Array::new(&buffer).to_vec()))
+            .map_err(|_| WsError::UnsupportedMessage)
+    }
+
+    /// The message as a JS string or `Uint8Array`.
+    fn into_js(self) -> JsValue {
+        match self {
+            Message::Text(text) => text.into(),
+            Message::Binary(bytes) => Uint8Array::from(bytes.as_slice()).into(),
+        }
+    }
+}
+
+/// Sends `message` to the WebSocket `endpoint` and resolves with the message received in reply.
+///
+/// A string is sent as a text message and answered with a string, while a `Uint8Array` or an
+/// `ArrayBuffer` is sent as a binary message and answered with a `Uint8Array`.
+///
+/// `options` is either a number of milliseconds after which the promise is rejected and the
+/// connection closed if no reply came, counting from the call, or an object with that `timeoutMs`
+/// and the `retries`, `perAttemptTimeoutMs` and `backoff`, `{ baseDelayMs, jitter }`, of the
+/// retries of a ping that failed. Aborting `signal` gives up on the ping like the timeout does,
+/// rejecting with a `Cancelled` error instead.
+///
+/// The pings to an endpoint share a connection, kept open until no ping went through it for
+/// `PING_IDLE_TIMEOUT`, the replies being matched to the pings in the order they were sent.
+#[wasm_bindgen(js_name = wsPing)]
+pub fn ws_ping(
+    endpoint: String,
+    message: JsData,
+    options: Option<JsPingOptions>,
+    signal: Option<AbortSignal>,
+) -> MessagePromise {
+    let reply = future_to_promise(async move {
+        let message = Message::from_js(&message)?;
+        let options = PingOptions::from_js(options)?;
+        let retry = options.retry.into();
+        let reply = retrying(&retry, || exchange(&endpoint, message.clone()));
+        let reply = bounded(reply, options.timeout_ms, signal).await?;
+        Ok(reply.into_js())
+    });
+    reply.unchecked_into()
+}
+
+/// Sends `value` to the WebSocket `endpoint` serialized as JSON, and resolves with the reply
+/// parsed as JSON, objects being plain JS objects.
+///
+/// The request goes over the connection of the pings, and is retried, timed out and cancelled as
+/// set by `options` and `signal` like a ping. The promise is rejected with an `InvalidJson` error if
+/// `value` can't be serialized, e.g. because it's a function, or if the reply isn't JSON.
+#[wasm_bindgen(js_name = wsRequestJson)]
+pub fn ws_request_json(
+    endpoint: String,
+    value: JsValue,
+    options: Option<JsPingOptions>,
+    signal: Option<AbortSignal>,
+) -> Promise {
+    future_to_promise(async move {
+        let invalid = |err: serde_wasm_bindgen::Error| WsError::Json(err.to_string());
+        let request: serde_json::Value = serde_wasm_bindgen::from_value(value).map_err(invalid)?;
+        let options = PingOptions::from_js(options)?;
+        let retry = options.retry.into();
+        let reply = retrying(&retry, || request_json(&endpoint, &request));
+        let reply: serde_json::Value = bounded(reply, options.timeout_ms, signal).await?;
+        Ok(reply
+            .serialize(&Serializer::json_compatible())
+            .map_err(invalid)?)
+    })
+}
+
+/// Settings of a ping or a request, as the JS object they're read from.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct PingOptions {
+    timeout_ms: Option<u32>,
+    #[serde(flatten)]
+    retry: RetryOptions,
+}
+
+impl PingOptions {
+    /// Settings read from a number of milliseconds to time out after, or from an object with the
+    /// `timeoutMs`, `retries`, `perAttemptTimeoutMs` and `backoff` of the ping.
+    fn from_js(options: Option<JsPingOptions>) -> Result<Self, WsError> {
+        let Some(options) = options.map(JsValue::from) else {
+            return Ok(PingOptions::default());
+        };
+        if let Some(timeout_ms) = options.as_f64() {
+            return Ok(PingOptions {
+                timeout_ms: Some(timeout_ms as u32),
+                ..PingOptions::default()
+            });
+        }
+        serde_wasm_bindgen::from_value(options)
+            .map_err(|err| WsError::InvalidOptions(err.to_string()))
+    }
+}
+
+/// Retry policy, as the JS object `{ retries, perAttemptTimeoutMs, backoff }` it's read from.
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct RetryOptions {
+    retries: u32,
+    per_attempt_timeout_ms: Option<u32>,
+    backoff: BackoffOptions,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct BackoffOptions {
+    base_delay_ms: u32,
+    jitter: f64,
+}
+
+impl Default for BackoffOptions {
+    fn default() -> Self {
+        let backoff = Backoff::default();
+        BackoffOptions {
+            base_delay_ms: backoff.base_delay.as_millis() as u32,
+            jitter: backoff.jitter,
+        }
+    }
+}
+
+impl From<RetryOptions> for RetryPolicy {
+    fn from(options: RetryOptions) -> Self {
+        let millis = |ms: u32| Duration::from_millis(ms.into());
+        RetryPolicy {
+            retries: options.retries,
+            per_attempt_timeout: options.per_attempt_timeout_ms.map(millis),
+            backoff: Backoff {
+                base_delay: millis(options.backoff.base_delay_ms),
+                jitter: options.backoff.jitter,
+            },
+        }
+    }
+}
+
+/// Echoes `samples` messages with the WebSocket `endpoint`, one after the other, and resolves with
+/// the `minMs`, `avgMs` and `p95Ms` of their round-trip times, measured with `performance.now()`.
+///
+/// The echoes go over the connection of the pings, opened beforehand so that its handshake isn't
+/// measured. The promise is rejected with an `InvalidOptions` error if `samples` is 0.
+#[wasm_bindgen(js_name = wsMeasureLatency)]
+pub fn ws_measure_latency(endpoint: String, samples: u32) -> LatencyPromise {
+    let latency = future_to_promise(async move {
+        let latency = measure_latency(&endpoint, samples).await?;
+        Ok(latency.into_js())
+    });
+    latency.unchecked_into()
+}
+
+impl Latency {
+    /// The round-trip times as a JS object of milliseconds, fractions included.
+    fn into_js(self) -> JsValue {
+        let latency = Object::new();
+        for (name, round_trip) in [
+            ("minMs", self.min),
+            ("avgMs", self.avg),
+            ("p95Ms", self.p95),
+        ] {
+            // Setting a property of a plain object can't fail
+            let _ = Reflect::set(
+                &latency,
+                &name.into(),
+                &(round_trip.as_secs_f64() * 1000.0).into(),
+            );
+        }
+        latency.into()
+    }
+}
+
+/// Awaits `future`, unless it's given up after `timeout_ms` or once `signal` is aborted.
+async fn bounded<T>(
+    future: impl Future<Output = Result<T, WsError>>,
+    timeout_ms: Option<u32>,
+    signal: Option<AbortSignal>,
+) -> Result<T, WsError> {
+    let future = match timeout_ms {
+        Some(timeout_ms) => {
+            let timeout = Duration::from_millis(timeout_ms.into());
+            Either::Left(within(timeout, future))
+        }
+        None => Either::Right(future),
+    };
+    match &signal {
+        Some(signal) => cancellable(aborted(signal), future).await,
+        None => future.await,
+    }
+}
+
+/// Waits on `uniqueId` for another party through the sync-point HTTP API at `baseUrl`, so that a
+/// page can rendezvous then exchange messages with a single module.
+///
+/// Resolves with the JSON outcome of the wait, e.g. `{ status: "matched", role: "first",
+/// waited_ms: 1234, ... }` or `{ status: "timeout", waited_ms: 10000, ... }`, and rejects if the
+/// request couldn't be sent or was rejected, e.g. because the id is too long. The wait times out
+/// after `timeoutMs`, or the server timeout if omitted.
+#[wasm_bindgen(js_name = wsSyncPoint)]
+pub fn ws_sync_point(base_url: &str, unique_id: &str, timeout_ms: Option<u32>) -> OutcomePromise {
+    let mut url = format!(
+        "{}/wait-for-second-party/{}",
+        base_url.trim_end_matches('/'),
+        encode_uri_component(unique_id)
+    );
+    if let Some(timeout_ms) = timeout_ms {
+        url.push_str(&format!("?timeout_ms={timeout_ms}"));
+    }
+
+    future_to_promise(async move { Ok(wait_for_party(&url).await?) }).unchecked_into()
+}
+
+/// Outcome of the wait posted to `url`.
+async fn wait_for_party(url: &str) -> Result<JsValue, WsError> {
+    let failed = |err: JsValue| WsError::SyncPoint(describe(&err));
+    let init = RequestInit::new();
+    init.set_method("POST");
+    let request = Request::new_with_str_and_init(url, &init).map_err(failed)?;
+    request
+        .headers()
+        .set("Accept", "application/json")
+        .map_err(failed)?;
+
+    let response: Response = JsFuture::from(fetch_with_request(&request))
+        .await
+        .and_then(JsCast::dyn_into)
+        .map_err(failed)?;
+    let outcome = JsFuture::from(response.json().map_err(failed)?)
+        .await
+        .map_err(failed)?;
+
+    // Rejected requests carry an error message instead of an outcome
+    let status = js_property(&outcome, "status").and_then(|status| status.as_string());
+    if status.as_deref() == Some("error") {
+        let message = js_property(&outcome, "message")
+            .and_then(|message| message.as_string())
+            .unwrap_or_else(|| format!("request failed with status {}", response.status()));
+        return Err(WsError::SyncPoint(message.trim_end().to_owned()));
+    }
+    Ok(outcome)
+}
+
+#[wasm_bindgen]
+extern "C" {
+    // The global `fetch`, so that it works in windows, workers and Deno alike
+    #[wasm_bindgen(js_name = fetch)]
+    fn fetch_with_request(request: &Request) -> Promise;
+
+    // The global `performance`, which workers and Deno have as well
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
+}
+
+impl ConnectOptions {
+    /// Options read from the `protocols`, `reconnect`, `heartbeat`, `handshake`, `sendQueue`,
+    /// `retry` and `maxMessageSize` of a JS object, those left out being the defaults.
+    fn from_js(options: &JsValue) -> Result<Self, WsError> {
+        let default = ConnectOptions::default();
+        Ok(ConnectOptions {
+            protocols: js_property(options, "protocols")
+                .map(|protocols| {
+                    Array::from(&protocols)
+                        .iter()
+                        .filter_map(|protocol| protocol.as_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            reconnect: js_property(options, "reconnect")
+                .map(|reconnect| Reconnect::from_js(&reconnect)),
+            heartbeat: js_property(options, "heartbeat")
+                .map(|heartbeat| Heartbeat::from_js(&heartbeat))
+                .transpose()?,
+            handshake: js_property(options, "handshake")
+                .map(|handshake| Handshake::from_js(&handshake))
+                .transpose()?,
+            send_queue: js_property(options, "sendQueue")
+                .and_then(|send_queue| send_queue.as_f64())
+                .map_or(default.send_queue, |send_queue| send_queue as usize),
+            max_message_size: js_property(options, "maxMessageSize")
+                .and_then(|max_size| max_size.as_f64())
+                .map_or(default.max_message_size, |max_size| max_size as usize),
+            retry: js_property(options, "retry")
+                .map(serde_wasm_bindgen::from_value::<RetryOptions>)
+                .transpose()
+                .map_err(|err| WsError::InvalidOptions(err.to_string()))?
+                .map_or(default.retry, RetryPolicy::from),
+        })
+    }
+}
+
+impl Reconnect {
+    /// Settings read from the `maxAttempts`, `baseDelayMs` and `jitter` of a JS object, those left
+    /// out being the defaults.
+    fn from_js(options: &JsValue) -> Self {
+        let default = Reconnect::default();
+        let number = |name| js_property(options, name).and_then(|value| value.as_f64());
+        Reconnect {
+            max_attempts: number("maxAttempts").map_or(default.max_attempts, |n| n as u32),
+            base_delay: number("baseDelayMs")
+                .map_or(default.base_delay, |ms| Duration::from_millis(ms as u64)),
+            jitter: number("jitter").unwrap_or(default.jitter),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// Settings read from the `message`, `reply`, `intervalMs` and `deadlineMs` of a JS object,
+    /// those left out being the defaults, except for `reply` which is then `message`.
+    fn from_js(options: &JsValue) -> Result<Self, WsError> {
+        let default = Heartbeat::default();
+        let message = js_property(options, "message")
+            .map(|message| Message::from_js(&message))
+            .transpose()?
+            .unwrap_or(default.message);
+        let reply = js_property(options, "reply")
+            .map(|reply| Message::from_js(&reply))
+            .transpose()?
+            .unwrap_or_else(|| message.clone());
+        let millis = |name| {
+            js_property(options, name)
+                .and_then(|value| value.as_f64())
+                .map(|ms| Duration::from_millis(ms as u64))
+        };
+        Ok(Heartbeat {
+            message,
+            reply,
+            interval: millis("intervalMs").unwrap_or(default.interval),
+            deadline: millis("deadlineMs").unwrap_or(default.deadline),
+        })
+    }
+}
+
+impl Handshake {
+    /// Identity read from the `pid` and the `secretKey`, 32 big-endian bytes, of a JS object.
+    fn from_js(options: &JsValue) -> Result<Self, WsError> {
+        let pid = js_property(options, "pid")
+            .and_then(|pid| pid.as_f64())
+            .ok_or_else(|| WsError::Handshake("the pid must be a number".to_owned()))?;
+        let secret_key = js_property(options, "secretKey")
+            .and_then(|key| key.dyn_into::<Uint8Array>().ok())
+            .map(|key| key.to_vec())
+            .filter(|key| key.len() == 32)
+            .and_then(|key| Scalar::from_repr(FieldBytes::clone_from_slice(&key)).into_option())
+            .ok_or_else(|| {
+                WsError::Handshake("the secret key must be a scalar of 32 bytes".to_owned())
+            })?;
+        Ok(Handshake {
+            pid: pid as u32,
+            secret_key,
+        })
+    }
+}
+
+impl ReconnectEvent {
+    /// The event as a JS object with its `type` and the fields in camel case.
+    fn into_js(self) -> JsValue {
+        let event = Object::new();
+        let set = |name: &str, value: JsValue| {
+            // Setting a property of a plain object can't fail
+            let _ = Reflect::set(&event, &name.into(), &value);
+        };
+        match self {
+            ReconnectEvent::Reconnecting { attempt, delay } => {
+                set("type", "reconnecting".into());
+                set("attempt", attempt.into());
+                set("delayMs", (delay.as_millis() as f64).into());
+            }
+            ReconnectEvent::Reconnected { attempt } => {
+                set("type", "reconnected".into());
+                set("attempt", attempt.into());
+            }
+            ReconnectEvent::GaveUp { attempts } => {
+                set("type", "gave_up".into());
+                set("attempts", attempts.into());
+            }
+        }
+        event.into()
+    }
+}
+
+#[wasm_bindgen]
+impl WsClient {
+    /// Connects to the WebSocket `endpoint`, resolving with the client once the connection is
+    /// open.
+    ///
+    /// The server picks one of the `protocols` offered if any, its choice being read from
+    /// `protocol`.
+    #[wasm_bindgen(js_name = connect)]
+    pub async fn connect_js(
+        endpoint: String,
+        protocols: Option<Vec<String>>,
+    ) -> Result<WsClient, JsValue> {
+        let options = ConnectOptions {
+            protocols: protocols.unwrap_or_default(),
+            ..ConnectOptions::default()
+        };
+        WsClient::connect_with_js(endpoint, options, None, None).await
+    }
+
+    /// Connects to the WebSocket `endpoint` like `connect`, then reconnects whenever the
+    /// connection drops, as set by the `maxAttempts`, `baseDelayMs` and `jitter` of `options`.
+    ///
+    /// `onEvent` is called with each `reconnecting`, `reconnected` or `gave_up` event, and
+    /// `nextMessage` only rejects once the client gave up.
+    #[wasm_bindgen(js_name = connectReconnecting)]
+    pub async fn connect_reconnecting_js(
+        endpoint: String,
+        options: JsReconnectOptions,
+        on_event: Option<OnReconnectEvent>,
+        protocols: Option<Vec<String>>,
+    ) -> Result<WsClient, JsValue> {
+        let options = ConnectOptions {
+            protocols: protocols.unwrap_or_default(),
+            reconnect: Some(Reconnect::from_js(&options)),
+            ..ConnectOptions::default()
+        };
+        WsClient::connect_with_js(endpoint, options, on_event, None).await
+    }
+
+    /// Connects to the WebSocket `endpoint` as set by the `protocols`, `reconnect`, `heartbeat`,
+    /// `handshake`, `sendQueue` and `retry` of `options`, the objects among them being like the
+    /// options of `connectReconnecting`, `{ message, reply, intervalMs, deadlineMs }`,
+    /// `{ pid, secretKey }` and the retry policy of `wsPing`, which applies to the first
+    /// connection.
+    ///
+    /// A message larger than `maxMessageSize` bytes, 16 MiB by default, fails the connection with
+    /// a `MessageTooLarge` error.
+    ///
+    /// With a handshake, the client resolves only once the server acknowledged its proof, and
+    /// proves itself again on every reconnection. `onEvent` is called with the events of
+    /// reconnecting, if the client does.
+    ///
+    /// Aborting the `signal` of `options` while connecting, e.g. while the server is slow to
+    /// acknowledge the proof, closes the connection and rejects with a `Cancelled` error.
+    #[wasm_bindgen(js_name = connectWith)]
+    pub async fn connect_with_options_js(
+        endpoint: String,
+        options: JsConnectOptions,
+        on_event: Option<OnReconnectEvent>,
+    ) -> Result<WsClient, JsValue> {
+        let signal = js_property(&options, "signal").and_then(|signal| signal.dyn_into().ok());
+        let options = ConnectOptions::from_js(&options)?;
+        WsClient::connect_with_js(endpoint, options, on_event, signal).await
+    }
+
+    /// Subprotocol picked by the server among the ones offered, empty if none was or while
+    /// reconnecting.
+    #[wasm_bindgen(getter = protocol)]
+    pub fn protocol_js(&self) -> String {
+        self.protocol()
+    }
+
+    /// Sends a string as a text message, or a `Uint8Array` or an `ArrayBuffer` as a binary one.
+    #[wasm_bindgen(js_name = send)]
+    pub fn send_js(&self, message: JsData) -> Result<(), JsValue> {
+        Ok(self.send(&Message::from_js(&message)?)?)
+    }
+
+    /// Resolves with the next message received, a string or a `Uint8Array`, and rejects once the
+    /// connection failed or was closed.
+    #[wasm_bindgen(js_name = nextMessage)]
+    pub fn next_message_js(&self) -> MessagePromise {
+        let incoming = self.incoming.clone();
+        let message = future_to_promise(async move {
+            let message = next_message(&incoming).await?;
+            Ok(message.into_js())
+        });
+        message.unchecked_into()
+    }
+
+    /// Calls `onMessage` with every message received, a string or a `Uint8Array`, then `onClose`
+    /// with the error once the connection failed or was closed.
+    ///
+    /// The messages are read from the same queue as `nextMessage`, which gets none of those
+    /// handed to the callback.
+    #[wasm_bindgen(js_name = onMessage)]
+    pub fn on_message_js(&self, on_message: OnMessage, on_close: Option<OnError>) {
+        let (on_message, on_close): (Function, Option<Function>) = (
+            on_message.unchecked_into(),
+            on_close.map(JsCast::unchecked_into),
+        );
+        let messages = self.messages();
+        wasm_bindgen_futures::spawn_local(async move {
+            let mut messages = pin!(messages);
+            while let Some(message) = messages.next().await {
+                // The messages keep coming whatever the callbacks throw
+                match message {
+                    Ok(message) => {
+                        let _ = on_message.call1(&JsValue::NULL, &message.into_js());
+                    }
+                    Err(err) => {
+                        if let Some(on_close) = &on_close {
+                            let _ = on_close.call1(&JsValue::NULL, &err.into());
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Calls `onOpen` whenever the client reconnected, the first connection being told by the
+    /// client resolving.
+    #[wasm_bindgen(js_name = onOpen)]
+    pub fn on_open_js(&self, on_open: OnOpen) {
+        let on_open: Function = on_open.unchecked_into();
+        self.on_open(move || {
+            // The client goes on whatever the callbacks throw
+            let _ = on_open.call0(&JsValue::NULL);
+        });
+    }
+
+    /// Calls `onClose` with the code and the reason whenever the connection closes, whether the
+    /// client reconnects or not, a connection that failed being closed with 1006.
+    #[wasm_bindgen(js_name = onClose)]
+    pub fn on_close_js(&self, on_close: OnClose) {
+        let on_close: Function = on_close.unchecked_into();
+        self.on_close(move |code, reason| {
+            let _ = on_close.call2(&JsValue::NULL, &code.into(), &reason.into());
+        });
+    }
+
+    /// Calls `onError` with a `WsClientError` whenever the connection fails, e.g. when the
+    /// heartbeat found it dead, right before `onClose`.
+    #[wasm_bindgen(js_name = onError)]
+    pub fn on_error_js(&self, on_error: OnError) {
+        let on_error: Function = on_error.unchecked_into();
+        self.on_error(move |err| {
+            let _ = on_error.call1(&JsValue::NULL, &err.clone().into());
+        });
+    }
+
+    /// Closes the connection, dropping the messages not read yet, and stops reconnecting.
+    #[wasm_bindgen(js_name = close)]
+    pub fn close_js(&self) {
+        self.close();
+    }
+}
+
+impl WsClient {
+    async fn connect_with_js(
+        endpoint: String,
+        options: ConnectOptions,
+        on_event: Option<OnReconnectEvent>,
+        signal: Option<AbortSignal>,
+    ) -> Result<WsClient, JsValue> {
+        let on_event = on_event.map(JsCast::unchecked_into::<Function>);
+        let client = WsClient::connect_with(&endpoint, options, move |event| {
+            if let Some(on_event) = &on_event {
+                // The client keeps reconnecting whatever the callback throws
+                let _ = on_event.call1(&JsValue::NULL, &event.into_js());
+            }
+        });
+        let client = match &signal {
+            Some(signal) => cancellable(aborted(signal), client).await?,
+            None => client.await?,
+        };
+        Ok(client)
+    }
+}
+
+#[wasm_bindgen(js_class = RpcClient)]
+impl RpcClient {
+    /// Connects to the WebSocket `endpoint` as set by the options of `WsClient.connectWith`, if
+    /// any, and resolves with a client making calls once the connection is open.
+    #[wasm_bindgen(js_name = connect)]
+    pub async fn connect_js(
+        endpoint: String,
+        options: Option<JsConnectOptions>,
+    ) -> Result<RpcClient, JsValue> {
+        let options = options.map_or(JsValue::UNDEFINED, JsValue::from);
+        let signal = js_property(&options, "signal").and_then(|signal| signal.dyn_into().ok());
+        let options = ConnectOptions::from_js(&options)?;
+        let client = WsClient::connect_with_js(endpoint, options, None, signal).await?;
+        Ok(RpcClient::new(client)?)
+    }
+
+    /// Sends `value` serialized as JSON and resolves with the payload of its reply, parsed as
+    /// JSON, while other calls may be awaiting theirs.
+    ///
+    /// The call is given up after `timeoutMs` or once `signal` is aborted, and rejected with an
+    /// `InvalidJson` error like `wsRequestJson`. It's also rejected once the connection drops,
+    /// even if the client reconnects.
+    #[wasm_bindgen(js_name = call)]
+    pub fn call_js(
+        &self,
+        value: JsValue,
+        timeout_ms: Option<u32>,
+        signal: Option<AbortSignal>,
+    ) -> Promise {
+        let calls = self.calls.clone();
+        future_to_promise(async move {
+            let invalid = |err: serde_wasm_bindgen::Error| WsError::Json(err.to_string());
+            let request: serde_json::Value =
+                serde_wasm_bindgen::from_value(value).map_err(invalid)?;
+            let reply: serde_json::Value =
+                bounded(calls.call(&request), timeout_ms, signal).await?;
+            Ok(reply
+                .serialize(&Serializer::json_compatible())
+                .map_err(invalid)?)
+        })
+    }
+
+    /// Closes the connection, rejecting the calls awaiting their reply.
+    #[wasm_bindgen(js_name = close)]
+    pub fn close_js(&self) {
+        self.close();
+    }
+}
+
+/// Connects to the WebSocket `endpoint` offering the subprotocols `protocols`, returning the open
+/// connection with the messages it receives, up to `max_message_size` bytes each.
+pub(crate) async fn open(
+    endpoint: &str,
+    protocols: &[String],
+    max_message_size: usize,
+) -> Result<(Connection, Incoming), WsError> {
+    let ws = if protocols.is_empty() {
+        WebSocket::new(endpoint)
+    } else {
+        let protocols: Array = protocols
+            .iter()
+            .map(|protocol| JsValue::from_str(protocol))
+            .collect();
+        WebSocket::new_with_str_sequence(endpoint, &protocols)
+    }
+    .map_err(|err| WsError::InvalidEndpoint(describe(&err)))?;
+    // Binary messages are otherwise received as blobs, which can only be read asynchronously,
+    // though runtimes ignoring it are served all the same
+    ws.set_binary_type(BinaryType::Arraybuffer);
+
+    let (opened, open) = oneshot::channel();
+    let (forwarded, mut incoming) = mpsc::unbounded();
+    // The events go through a queue of their own, so that the messages read asynchronously aren't
+    // overtaken by the ones received after them
+    let (received, read) = mpsc::unbounded();
+    wasm_bindgen_futures::spawn_local(forward_in_order(read, forwarded));
+    let onopen = Closure::once(move || {
+        // Fails only if connecting was given up, leaving no one to tell
+        let _ = opened.send(());
+    });
+    let onerror = Closure::<dyn FnMut(_)>::new({
+        let received = received.clone();
+        move |_: Event| {
+            let _ = received.unbounded_send(Box::pin(future::ready(Err(WsError::Connection))));
+        }
+    });
+    let onmessage = Closure::<dyn FnMut(_)>::new({
+        let received = received.clone();
+        move |e: MessageEvent| {
+            let _ = received.unbounded_send(Message::read(e.data(), max_message_size));
+        }
+    });
+    let onclose = Closure::<dyn FnMut(_)>::new(move |e: CloseEvent| {
+        let _ = received.unbounded_send(Box::pin(future::ready(Err(WsError::Closed {
+            code: e.code(),
+            reason: e.reason(),
+        }))));
+        received.close_channel();
+    });
+    let connection = Connection::new(ws, onopen, onerror, onclose, onmessage);
+
+    // Nothing but a failure can be received before the connection is open
+    match future::select(open, incoming.next()).await {
+        Either::Left((Ok(()), _)) => Ok((connection, incoming)),
+        Either::Right((Some(Err(err)), _)) => Err(err),
+        _ => Err(WsError::Connection),
+    }
+}
+
+/// Message or failure received, once read.
+type Reading = LocalBoxFuture<'static, Result<Message, WsError>>;
+
+/// Hands the messages and failures `read` to `received` in the order they were received, each one
+/// once read, until the connection closes.
+async fn forward_in_order(
+    mut read: mpsc::UnboundedReceiver<Reading>,
+    received: mpsc::UnboundedSender<Result<Message, WsError>>,
+) {
+    while let Some(reading) = read.next().await {
+        if received.unbounded_send(reading.await).is_err() {
+            return;
+        }
+    }
+}
+
+/// Resolves after `duration`, through `setTimeout`.
+pub(crate) fn sleep(duration: Duration) -> impl Future<Output = ()> + Unpin {
+    // setTimeout fires at once past 2^31 - 1 ms, about 24 days, which is as good as never
+    TimeoutFuture::new(duration.as_millis().min(i32::MAX as u128) as u32)
+}
+
+/// Time elapsed since the page or the worker started, through `performance.now()`.
+pub(crate) fn now() -> Duration {
+    Duration::from_secs_f64(performance_now() / 1000.0)
+}
+
+/// Spawns `future` on the event loop, which always runs in browsers.
+pub(crate) fn spawn_local(future: impl Future<Output = ()> + 'static) -> Result<(), WsError> {
+    wasm_bindgen_futures::spawn_local(future);
+    Ok(())
+}
+
+/// Random number from 0 included to 1 excluded, through `Math.random`.
+pub(crate) fn random() -> f64 {
+    Math::random()
+}
+
+/// WebSocket along with its callbacks, which are unset before they're freed and the connection
+/// closed when it's dropped.
+pub(crate) struct Connection {
+    ws: WebSocket,
+    _onopen: Closure<dyn FnMut()>,
+    _onerror: Closure<dyn FnMut(Event)>,
+    _onclose: Closure<dyn FnMut(CloseEvent)>,
+    _onmessage: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl Connection {
+    fn new(
+        ws: WebSocket,
+        onopen: Closure<dyn FnMut()>,
+        onerror: Closure<dyn FnMut(Event)>,
+        onclose: Closure<dyn FnMut(CloseEvent)>,
+        onmessage: Closure<dyn FnMut(MessageEvent)>,
+    ) -> Self {
+        ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+        ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+        ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+        ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        Connection {
+            ws,
+            _onopen: onopen,
+            _onerror: onerror,
+            _onclose: onclose,
+            _onmessage: onmessage,
+        }
+    }
+
+    /// Sends `message` as a text or a binary message.
+    pub(crate) fn send(&self, message: &Message) -> Result<(), WsError> {
+        match message {
+            Message::Text(text) => self.ws.send_with_str(text),
+            Message::Binary(bytes) => self.ws.send_with_u8_array(bytes),
+        }
+        .map_err(|err| WsError::Send(describe(&err)))
+    }
+
+    /// Subprotocol picked by the server, empty if none was.
+    pub(crate) fn protocol(&self) -> String {
+        self.ws.protocol()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.ws.set_onopen(None);
+        self.ws.set_onerror(None);
+        self.ws.set_onclose(None);
+        self.ws.set_onmessage(None);
+        // We close the connection silently
+        let _ = self.ws.close();
+    }
+}
+
+/// Resolves once `signal` is aborted, at once if it is already.
+fn aborted(signal: &AbortSignal) -> Aborted {
+    let (abort, aborted) = oneshot::channel();
+    let onabort = Closure::once(move || {
+        let _ = abort.send(());
+    });
+    // Fails only on a signal that isn't an event target, which an `AbortSignal` always is
+    let _ = signal.add_event_listener_with_callback("abort", onabort.as_ref().unchecked_ref());
+    Aborted {
+        signal: signal.clone(),
+        onabort,
+        aborted,
+    }
+}
+
+/// Abort of a signal awaited, whose listener is removed before it's freed when it's dropped.
+struct Aborted {
+    signal: AbortSignal,
+    onabort: Closure<dyn FnMut()>,
+    aborted: oneshot::Receiver<()>,
+}
+
+impl Future for Aborted {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.signal.aborted() {
+            return Poll::Ready(());
+        }
+        self.aborted.poll_unpin(cx).map(|_| ())
+    }
+}
+
+impl Drop for Aborted {
+    fn drop(&mut self) {
+        let _ = self
+            .signal
+            .remove_event_listener_with_callback("abort", self.onabort.as_ref().unchecked_ref());
+    }
+}
+
+/// Property `name` of the JS object `object`, unless it's missing, `undefined` or `null`.
+fn js_property(object: &JsValue, name: &str) -> Option<JsValue> {
+    Reflect::get(object, &name.into())
+        .ok()
+        .filter(|value| !value.is_undefined() && !value.is_null())
+}
+
+/// Message of a JS error, or the value itself for anything else thrown.
+fn describe(err: &JsValue) -> String {
+    match err.dyn_ref::<Error>() {
+        Some(err) => String::from(err.message()),
+        None => err.as_string().unwrap_or_else(|| format!("{err:?}")),
+    }
+}
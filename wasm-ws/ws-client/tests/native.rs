@@ -0,0 +1,418 @@
+//! Native client against the server of the workspace, each test serving it on a port of its own.
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::{cell::RefCell, future::Future, rc::Rc, time::Duration};
+
+use futures_channel::oneshot;
+use futures_util::{future, StreamExt};
+use k256::{
+    elliptic_curve::{rand_core::OsRng, Field},
+    Scalar,
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::{net::TcpListener, task::LocalSet};
+use ws_client::{
+    measure_latency, ping, ping_binary, request_json, retrying, within, Backoff, ConnectOptions,
+    Handshake, Heartbeat, Message, Reconnect, ReconnectEvent, RetryPolicy, RpcClient, WsClient,
+    WsError,
+};
+
+/// Serves the server on a free port, returning its base URL.
+async fn serve() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(ws_server::serve(listener));
+    format!("ws://{address}")
+}
+
+/// Runs `test` on a `LocalSet`, which the client spawns its tasks on.
+async fn local<F: Future>(test: F) -> F::Output {
+    LocalSet::new().run_until(test).await
+}
+
+#[tokio::test]
+async fn pings_are_echoed() {
+    local(async {
+        let endpoint = format!("{}/ws", serve().await);
+
+        assert_eq!(ping(&endpoint, "hello").await.unwrap(), "hello");
+        assert_eq!(ping_binary(&endpoint, &[1, 2, 3]).await.unwrap(), [1, 2, 3]);
+        let request = json!({ "method": "echo", "params": [1, 2] });
+        let reply: serde_json::Value = request_json(&endpoint, &request).await.unwrap();
+        assert_eq!(reply, request);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn clients_fail_outside_of_a_local_set() {
+    let endpoint = format!("{}/ws", serve().await);
+
+    assert_eq!(
+        WsClient::connect(&endpoint).await.err(),
+        Some(WsError::NoLocalSet)
+    );
+    assert_eq!(ping(&endpoint, "hello").await, Err(WsError::NoLocalSet));
+}
+
+#[tokio::test]
+async fn latency_is_measured_over_the_samples() {
+    local(async {
+        let endpoint = format!("{}/ws", serve().await);
+
+        let latency = measure_latency(&endpoint, 20).await.unwrap();
+        assert!(latency.min <= latency.avg);
+        assert!(latency.min <= latency.p95);
+        assert!(matches!(
+            measure_latency(&endpoint, 0).await,
+            Err(WsError::InvalidOptions(_))
+        ));
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn binary_messages_are_received_in_order() {
+    local(async {
+        let endpoint = format!("{}/ws", serve().await);
+        let client = WsClient::connect(&endpoint).await.unwrap();
+
+        let sent: Vec<_> = (0..50u8)
+            .map(|i| Message::Binary(vec![i; usize::from(i) + 1]))
+            .collect();
+        for message in &sent {
+            client.send(message).unwrap();
+        }
+        let received: Vec<_> = client
+            .messages()
+            .take(sent.len())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(received, sent);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn subprotocols_are_negotiated() {
+    local(async {
+        let endpoint = format!("{}/ws", serve().await);
+
+        let options = ConnectOptions {
+            protocols: vec!["echo.v2".to_owned(), "echo.v1".to_owned()],
+            ..ConnectOptions::default()
+        };
+        let client = WsClient::connect_with(&endpoint, options, |_| {})
+            .await
+            .unwrap();
+        assert_eq!(client.protocol(), "echo.v1");
+
+        // Unlike browsers, tungstenite fails when the server picks none of the protocols
+        let options = ConnectOptions {
+            protocols: vec!["echo.v2".to_owned()],
+            ..ConnectOptions::default()
+        };
+        let connected = WsClient::connect_with(&endpoint, options, |_| {}).await;
+        assert_eq!(connected.err(), Some(WsError::Connection));
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn client_reconnects_once_the_server_closed_the_connection() {
+    local(async {
+        let endpoint = format!("{}/rpc", serve().await);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let options = ConnectOptions {
+            reconnect: Some(Reconnect {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(10),
+                jitter: 0.0,
+            }),
+            ..ConnectOptions::default()
+        };
+        let client = WsClient::connect_with(&endpoint, options, {
+            let events = events.clone();
+            move |event| events.borrow_mut().push(event)
+        })
+        .await
+        .unwrap();
+        let closes = Rc::new(RefCell::new(Vec::new()));
+        client.on_close({
+            let closes = closes.clone();
+            move |code, reason| closes.borrow_mut().push((code, reason.to_owned()))
+        });
+        let (opened, reopened) = oneshot::channel();
+        let opened = RefCell::new(Some(opened));
+        client.on_open(move || {
+            if let Some(opened) = opened.take() {
+                let _ = opened.send(());
+            }
+        });
+
+        // The route closes the connection on anything but a call
+        client
+            .send(&Message::Text("not a call".to_owned()))
+            .unwrap();
+        reopened.await.unwrap();
+        let call = r#"{"id":1,"payload":"again"}"#;
+        client.send(&Message::Text(call.to_owned())).unwrap();
+
+        assert_eq!(
+            client.next_message().await,
+            Ok(Message::Text(call.to_owned()))
+        );
+        assert_eq!(*closes.borrow(), [(1007, "invalid call".to_owned())]);
+        assert_eq!(
+            *events.borrow(),
+            [
+                ReconnectEvent::Reconnecting {
+                    attempt: 1,
+                    delay: Duration::from_millis(10),
+                },
+                ReconnectEvent::Reconnected { attempt: 1 },
+            ]
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn waits_time_out() {
+    local(async {
+        let endpoint = format!("{}/ws", serve().await);
+        let client = WsClient::connect(&endpoint).await.unwrap();
+
+        let timeout = Duration::from_millis(50);
+        assert_eq!(
+            within(timeout, client.next_message()).await,
+            Err(WsError::Timeout(timeout))
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn heartbeat_replies_are_kept_from_the_messages() {
+    local(async {
+        let endpoint = format!("{}/ws", serve().await);
+        let options = ConnectOptions {
+            heartbeat: Some(Heartbeat {
+                interval: Duration::from_millis(10),
+                deadline: Duration::from_millis(500),
+                ..Heartbeat::default()
+            }),
+            ..ConnectOptions::default()
+        };
+        let client = WsClient::connect_with(&endpoint, options, |_| {})
+            .await
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.send(&Message::Text("hello".to_owned())).unwrap();
+        assert_eq!(
+            client.next_message().await,
+            Ok(Message::Text("hello".to_owned()))
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn failed_connections_are_retried() {
+    local(async {
+        // A port nothing listens on anymore
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("ws://{}/ws", listener.local_addr().unwrap());
+        drop(listener);
+
+        let policy = RetryPolicy {
+            retries: 2,
+            per_attempt_timeout: Some(Duration::from_secs(1)),
+            backoff: Backoff {
+                base_delay: Duration::from_millis(10),
+                jitter: 0.0,
+            },
+        };
+        let attempts = RefCell::new(0);
+        let connected = retrying(&policy, || {
+            *attempts.borrow_mut() += 1;
+            WsClient::connect(&endpoint)
+        })
+        .await;
+        assert_eq!(connected.err(), Some(WsError::Connection));
+        assert_eq!(*attempts.borrow(), 3);
+
+        // Invalid endpoints would fail again, so they aren't retried
+        *attempts.borrow_mut() = 0;
+        let connected = retrying(&policy, || {
+            *attempts.borrow_mut() += 1;
+            WsClient::connect("not a url")
+        })
+        .await;
+        assert!(matches!(connected, Err(WsError::InvalidEndpoint(_))));
+        assert_eq!(*attempts.borrow(), 1);
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn larger_messages_than_the_limit_close_the_connection() {
+    local(async {
+        let endpoint = format!("{}/ws", serve().await);
+        let options = ConnectOptions {
+            max_message_size: 1024,
+            ..ConnectOptions::default()
+        };
+        let client = WsClient::connect_with(&endpoint, options, |_| {})
+            .await
+            .unwrap();
+
+        let at_limit = Message::Text("a".repeat(1024));
+        client.send(&at_limit).unwrap();
+        assert_eq!(client.next_message().await, Ok(at_limit));
+
+        client.send(&Message::Binary(vec![0; 1025])).unwrap();
+        assert_eq!(
+            client.next_message().await,
+            Err(WsError::MessageTooLarge(1024))
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn larger_messages_than_the_limit_are_not_reconnected_from() {
+    local(async {
+        let endpoint = format!("{}/ws", serve().await);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let options = ConnectOptions {
+            reconnect: Some(Reconnect {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(10),
+                jitter: 0.0,
+            }),
+            max_message_size: 1024,
+            ..ConnectOptions::default()
+        };
+        let client = WsClient::connect_with(&endpoint, options, {
+            let events = events.clone();
+            move |event| events.borrow_mut().push(event)
+        })
+        .await
+        .unwrap();
+
+        client.send(&Message::Binary(vec![0; 1025])).unwrap();
+        assert_eq!(
+            client.next_message().await,
+            Err(WsError::MessageTooLarge(1024))
+        );
+        assert_eq!(
+            client.send(&Message::Text("again".to_owned())),
+            Err(WsError::MessageTooLarge(1024))
+        );
+        assert!(events.borrow().is_empty());
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn rpc_replies_go_to_their_call() {
+    local(async {
+        let endpoint = format!("{}/rpc", serve().await);
+        let rpc = RpcClient::connect(&endpoint).await.unwrap();
+
+        let requests: Vec<u64> = (0..20).collect();
+        let calls = requests.iter().map(|request| rpc.call::<u64>(request));
+        let replies: Vec<_> = future::join_all(calls).await;
+        assert_eq!(replies, requests.into_iter().map(Ok).collect::<Vec<_>>());
+
+        let echoed: String = rpc.call(&"echo").await.unwrap();
+        assert_eq!(echoed, "echo");
+
+        rpc.close();
+        assert!(matches!(
+            rpc.call::<u64>(&0).await,
+            Err(WsError::Closed { code: 1000, .. })
+        ));
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn handshake_proves_the_identity_of_the_client() {
+    local(async {
+        let endpoint = format!("{}/auth", serve().await);
+        let options = ConnectOptions {
+            handshake: Some(Handshake {
+                pid: 1,
+                secret_key: Scalar::random(&mut OsRng),
+            }),
+            ..ConnectOptions::default()
+        };
+        let client = WsClient::connect_with(&endpoint, options, |_| {})
+            .await
+            .unwrap();
+
+        // The server echoes the messages once authenticated
+        client.send(&Message::Text("hello".to_owned())).unwrap();
+        assert_eq!(
+            client.next_message().await,
+            Ok(Message::Text("hello".to_owned()))
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn clients_without_a_proof_are_rejected() {
+    local(async {
+        let endpoint = format!("{}/auth", serve().await);
+        let client = WsClient::connect(&endpoint).await.unwrap();
+
+        let Ok(Message::Text(nonce)) = client.next_message().await else {
+            panic!("the server should open the handshake with a nonce");
+        };
+        assert_eq!(nonce.len(), 64);
+        client
+            .send(&Message::Text("not a proof".to_owned()))
+            .unwrap();
+        assert_eq!(
+            client.next_message().await,
+            Err(WsError::Closed {
+                code: 1008,
+                reason: "authentication failed".to_owned(),
+            })
+        );
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn relay_pairs_the_parties_presenting_the_token_of_the_room() {
+    local(async {
+        let server = serve().await;
+        let token = "8f14e45fceea167a5a36dedd4bea2543";
+        let room = format!("{server}/relay/{:x}", Sha256::digest(token));
+
+        let claimed = WsClient::connect(&format!("{room}?token=not-the-token")).await;
+        assert_eq!(claimed.err(), Some(WsError::Connection));
+
+        let first = WsClient::connect(&format!("{room}?token={token}"))
+            .await
+            .unwrap();
+        let second = WsClient::connect(&format!("{room}?token={token}"))
+            .await
+            .unwrap();
+        first.send(&Message::Text("hello".to_owned())).unwrap();
+        assert_eq!(
+            second.next_message().await,
+            Ok(Message::Text("hello".to_owned()))
+        );
+        second.send(&Message::Binary(vec![1, 2])).unwrap();
+        assert_eq!(first.next_message().await, Ok(Message::Binary(vec![1, 2])));
+    })
+    .await;
+}
@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        ConnectInfo, Path, Query, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::any,
+    Router,
+};
+use dlog_proof::DLogProof;
+use futures_util::{SinkExt, StreamExt};
+use k256::{
+    elliptic_curve::rand_core::{OsRng, RngCore},
+    AffinePoint,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{net::TcpListener, sync::oneshot, time::timeout};
+use tracing::{error, info, warn};
+
+/// Subprotocols of `/ws` by order of preference, picked among the ones the client offers.
+const PROTOCOLS: [&str; 1] = ["echo.v1"];
+
+/// Acknowledgement of a valid proof in the handshake of `/auth`.
+const AUTHENTICATED: &str = "authenticated";
+
+/// How long a client of `/auth` has to answer the nonce with its proof.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the first party of a room waits for the other one before giving up.
+const ROOM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Rooms with a party waiting for the other one, by id.
+type Rooms = Arc<Mutex<HashMap<String, Room>>>;
+
+/// Room of the relay with its first party waiting, which the second one is handed to.
+struct Room {
+    joined: oneshot::Sender<WebSocket>,
+}
+
+#[derive(Deserialize)]
+struct RoomQuery {
+    /// Token of the match, as handed out by sync-point along with the room.
+    token: String,
+}
+
+/// Answer of a client of `/auth` to the nonce, proving the knowledge of the secret key of
+/// `public_key` for the nonce as the session id and `pid` as the participant id.
+#[derive(Deserialize)]
+struct Authentication {
+    pid: u32,
+    public_key: AffinePoint,
+    proof: DLogProof,
+}
+
+/// Call of a client of `/rpc`, or its reply, tagged with the id the client matches them by.
+#[derive(Deserialize, Serialize)]
+struct Envelope {
+    id: u64,
+    payload: serde_json::Value,
+}
+
+/// Serves the routes of the server over `listener` until it fails.
+pub async fn serve(listener: TcpListener) -> io::Result<()> {
+    let app = Router::new()
+        .route("/ws", any(ws_handler))
+        .route("/auth", any(auth_handler))
+        .route("/rpc", any(rpc_handler))
+        .route("/relay/:room", any(relay_handler))
+        .with_state(Rooms::default());
+
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    info!(who = %addr, "New connection");
+    // Clients offering none of the protocols are served all the same, without any
+    ws.protocols(PROTOCOLS)
+        .on_upgrade(move |socket| handle_socket(socket, addr))
+}
+
+/// Echoes the messages like `/ws` once the client proved its identity in the handshake.
+async fn auth_handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    info!(who = %addr, "New connection to authenticate");
+    ws.protocols(PROTOCOLS)
+        .on_upgrade(move |mut socket| async move {
+            if authenticate(&mut socket, addr).await {
+                handle_socket(socket, addr).await;
+            }
+        })
+}
+
+/// Sends a fresh nonce to the client and verifies the DLog proof it answers with for the nonce as
+/// the session id, acknowledging it or closing the connection with a policy violation.
+///
+/// Any key is accepted as long as the client proves it holds its secret, so the pid is only
+/// trusted as far as the public key is known to belong to it.
+async fn authenticate(socket: &mut WebSocket, who: SocketAddr) -> bool {
+    let mut nonce = [0; 32];
+    OsRng.fill_bytes(&mut nonce);
+    let nonce: String = nonce.iter().map(|byte| format!("{byte:02x}")).collect();
+    if socket.send(Message::Text(nonce.clone())).await.is_err() {
+        return false;
+    }
+
+    let authentication = match timeout(HANDSHAKE_TIMEOUT, socket.recv()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<Authentication>(&text).ok(),
+        _ => None,
+    };
+    match authentication.filter(|authentication| {
+        let public_key = authentication.public_key.into();
+        authentication
+            .proof
+            .verify(&nonce, authentication.pid, public_key)
+    }) {
+        Some(authentication) => {
+            info!(%who, pid = authentication.pid, "Authenticated");
+            socket
+                .send(Message::Text(AUTHENTICATED.to_owned()))
+                .await
+                .is_ok()
+        }
+        None => {
+            warn!(%who, "Authentication failed");
+            let _ = socket
+                .send(Message::Close(Some(CloseFrame {
+                    code: close_code::POLICY,
+                    reason: "authentication failed".into(),
+                })))
+                .await;
+            false
+        }
+    }
+}
+
+async fn handle_socket(mut socket: WebSocket, who: SocketAddr) {
+    if let Some(protocol) = socket
+        .protocol()
+        .and_then(|protocol| protocol.to_str().ok())
+    {
+        info!(%who, protocol, "Negotiated protocol");
+    }
+    loop {
+        match socket.recv().await {
+            Some(Ok(Message::Text(txt))) => {
+                info!(%who, message = %txt, "Received message");
+                if let Err(err) = socket.send(Message::Text(txt)).await {
+                    error!(%who, %err, "Failed to respond");
+                } else {
+                    info!(%who, "Sent response");
+                }
+            }
+            Some(Ok(Message::Binary(bytes))) => {
+                info!(%who, bytes = bytes.len(), "Received binary message");
+                if let Err(err) = socket.send(Message::Binary(bytes)).await {
+                    error!(%who, %err, "Failed to respond");
+                } else {
+                    info!(%who, "Sent response");
+                }
+            }
+            Some(Ok(Message::Close(_))) | None => {
+                info!(%who, "Connection closed");
+                return;
+            }
+            Some(Ok(_)) => {
+                warn!(%who, "Received unsupported message format");
+            }
+            Some(Err(err)) => {
+                error!(%who, %err, "Connection error");
+                return;
+            }
+        }
+    }
+}
+
+/// Answers the calls of the client, each tagged with its id in an envelope.
+async fn rpc_handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> impl IntoResponse {
+    info!(who = %addr, "New RPC connection");
+    ws.on_upgrade(move |socket| handle_calls(socket, addr))
+}
+
+/// Replies to every call with its payload, like `/ws` echoes the messages, under the id of the
+/// call, closing the connection with an invalid payload error on a message that isn't a call.
+async fn handle_calls(mut socket: WebSocket, who: SocketAddr) {
+    loop {
+        match socket.recv().await {
+            Some(Ok(Message::Text(txt))) => {
+                let Ok(call) = serde_json::from_str::<Envelope>(&txt) else {
+                    warn!(%who, message = %txt, "Received an invalid call");
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::INVALID,
+                            reason: "invalid call".into(),
+                        })))
+                        .await;
+                    return;
+                };
+                info!(%who, id = call.id, "Received call");
+                // A JSON value always serializes
+                let reply = serde_json::to_string(&call).unwrap();
+                if let Err(err) = socket.send(Message::Text(reply)).await {
+                    error!(%who, %err, "Failed to reply");
+                } else {
+                    info!(%who, id = call.id, "Sent reply");
+                }
+            }
+            Some(Ok(Message::Close(_))) | None => {
+                info!(%who, "Connection closed");
+                return;
+            }
+            Some(Ok(_)) => {
+                warn!(%who, "Received unsupported message format");
+            }
+            Some(Err(err)) => {
+                error!(%who, %err, "Connection error");
+                return;
+            }
+        }
+    }
+}
+
+/// Pairs the two parties of a match joining the same room, then relays the messages of each one
+/// to the other.
+///
+/// Rooms are named after the SHA-256 hash of the token of their match, which only its parties
+/// know: connections presenting a token of another room are rejected.
+async fn relay_handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(room): Path<String>,
+    Query(RoomQuery { token }): Query<RoomQuery>,
+    State(rooms): State<Rooms>,
+) -> Response {
+    if format!("{:x}", Sha256::digest(token.as_bytes())) != room {
+        warn!(who = %addr, %room, "Rejected a party with the wrong token");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // The room is only joined once upgraded, so that failed upgrades leave nothing behind
+    ws.on_upgrade(move |socket| join(socket, addr, room, rooms))
+}
+
+/// Hands `socket` to the party waiting in `room`, or waits there for the other party and relays
+/// their messages once it joins.
+async fn join(mut socket: WebSocket, who: SocketAddr, room: String, rooms: Rooms) {
+    let second = loop {
+        let mut waiting = rooms.lock().unwrap();
+        let Some(Room { joined }) = waiting.remove(&room) else {
+            info!(%who, %room, "First party joined the room");
+            let (joined, second) = oneshot::channel();
+            waiting.insert(room.clone(), Room { joined });
+            break second;
+        };
+        drop(waiting);
+
+        match joined.send(socket) {
+            Ok(()) => {
+                info!(%who, %room, "Second party joined the room");
+                return;
+            }
+            // The first party gave up in the meantime, so this one waits in its place
+            Err(returned) => socket = returned,
+        }
+    };
+
+    match timeout(ROOM_TIMEOUT, second).await {
+        Ok(Ok(second)) => relay(socket, second, &room).await,
+        _ => {
+            info!(%room, "No second party joined the room");
+            let mut waiting = rooms.lock().unwrap();
+            // Unless another party took the room over since
+            if waiting
+                .get(&room)
+                .is_some_and(|Room { joined }| joined.is_closed())
+            {
+                waiting.remove(&room);
+            }
+        }
+    }
+}
+
+/// Forwards the messages of each party to the other until one of them leaves.
+async fn relay(first: WebSocket, second: WebSocket, room: &str) {
+    let (mut first_tx, mut first_rx) = first.split();
+    let (mut second_tx, mut second_rx) = second.split();
+
+    let first_to_second = async {
+        while let Some(Ok(message)) = first_rx.next().await {
+            if second_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+        let _ = second_tx.close().await;
+    };
+    let second_to_first = async {
+        while let Some(Ok(message)) = second_rx.next().await {
+            if first_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+        let _ = first_tx.close().await;
+    };
+
+    tokio::select! {
+        _ = first_to_second => {}
+        _ = second_to_first => {}
+    }
+    info!(%room, "Room closed");
+}